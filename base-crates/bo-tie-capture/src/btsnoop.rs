@@ -0,0 +1,278 @@
+//! The btsnoop file format
+//!
+//! A btsnoop file starts with a 16 byte header followed by the records of the captured packets.
+//! Every field is big endian. Files written by this crate use the *HCI UART (H4)* datalink type,
+//! so the data of every record starts with the H4 packet indicator. Files with either the H4 or
+//! the *HCI unencapsulated* datalink type can be read.
+
+use crate::{Direction, Record};
+use bo_tie_h4::packet::{Packet, PacketIndicator};
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The identification pattern at the start of the file
+pub const IDENTIFICATION: [u8;8] = *b"btsnoop\0";
+
+pub const VERSION: u32 = 1;
+
+/// The datalink type for HCI packets without a packet indicator
+pub const DATALINK_UNENCAPSULATED: u32 = 1001;
+
+/// The datalink type for HCI UART (H4)
+pub const DATALINK_H4: u32 = 1002;
+
+/// Microseconds between midnight, January 1st, 0 AD (the btsnoop epoch) and the Unix epoch
+const UNIX_EPOCH_OFFSET: i64 = 0x00dc_ddb3_0f2f_8000;
+
+/// Packet flag for a packet received by the host
+const FLAG_RECEIVED: u32 = 1 << 0;
+
+/// Packet flag for a command or event packet
+const FLAG_COMMAND_OR_EVENT: u32 = 1 << 1;
+
+/// Convert a time into a btsnoop timestamp
+fn timestamp(time: SystemTime) -> i64 {
+    let since_unix_epoch = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_micros() as i64,
+        Err(e) => -(e.duration().as_micros() as i64),
+    };
+
+    since_unix_epoch + UNIX_EPOCH_OFFSET
+}
+
+/// Convert a btsnoop timestamp into a time
+fn time(timestamp: i64) -> SystemTime {
+    let since_unix_epoch = timestamp - UNIX_EPOCH_OFFSET;
+
+    if since_unix_epoch >= 0 {
+        UNIX_EPOCH + Duration::from_micros(since_unix_epoch as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_micros(since_unix_epoch.unsigned_abs())
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+pub(crate) fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(&IDENTIFICATION)?;
+    writer.write_all(&VERSION.to_be_bytes())?;
+    writer.write_all(&DATALINK_H4.to_be_bytes())
+}
+
+pub(crate) fn write_record<W: Write>(writer: &mut W, record: &Record) -> io::Result<()> {
+    let data = record.packet.as_bytes();
+
+    let mut flags = 0;
+
+    if let Direction::ControllerToHost = record.direction {
+        flags |= FLAG_RECEIVED;
+    }
+
+    if let PacketIndicator::Command | PacketIndicator::Event = record.packet.indicator {
+        flags |= FLAG_COMMAND_OR_EVENT;
+    }
+
+    writer.write_all(&(data.len() as u32).to_be_bytes())?; // original length
+    writer.write_all(&(data.len() as u32).to_be_bytes())?; // included length
+    writer.write_all(&flags.to_be_bytes())?;
+    writer.write_all(&0u32.to_be_bytes())?; // cumulative drops
+    writer.write_all(&timestamp(record.timestamp).to_be_bytes())?;
+    writer.write_all(&data)
+}
+
+/// A reader of the records in a btsnoop file
+pub struct Reader<R: Read> {
+    reader: R,
+    datalink: u32,
+}
+
+impl<R: Read> Reader<R> {
+
+    /// Create a new `Reader`
+    ///
+    /// The file header is read and validated before this returns.
+    ///
+    /// # Error
+    /// An error is returned if the header cannot be read, it is not the header of a btsnoop file,
+    /// or the datalink type of the file is not supported.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8;16];
+
+        reader.read_exact(&mut header)?;
+
+        if header[..8] != IDENTIFICATION {
+            return Err(invalid_data("not a btsnoop file"));
+        }
+
+        let version = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+
+        if version != VERSION {
+            return Err(invalid_data(format!("unsupported btsnoop version {}", version)));
+        }
+
+        let datalink = u32::from_be_bytes([header[12], header[13], header[14], header[15]]);
+
+        match datalink {
+            DATALINK_UNENCAPSULATED | DATALINK_H4 => Ok(Reader { reader, datalink }),
+            _ => Err(invalid_data(format!("unsupported btsnoop datalink type {}", datalink))),
+        }
+    }
+
+    /// Read the next record
+    ///
+    /// `None` is returned once the end of the file is reached.
+    ///
+    /// The packet type of a record in a file with the HCI unencapsulated datalink type is
+    /// determined from the flags of the record. As the flags cannot distinguish between ACL and
+    /// synchronous data, data packets are read as ACL data.
+    ///
+    /// # Error
+    /// An error is returned if the reader fails, the file ends in the middle of a record, or the
+    /// record is invalid.
+    pub fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0u8;24];
+
+        // The end of the file is only valid at the start of a record
+        match self.reader.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut header[1..])?,
+        }
+
+        let field = |i: usize| u32::from_be_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+
+        let original_len = field(0);
+        let included_len = field(4);
+        let flags = field(8);
+
+        let mut timestamp = [0u8;8];
+
+        timestamp.copy_from_slice(&header[16..]);
+
+        if included_len != original_len {
+            return Err(invalid_data("the captured packet is truncated"));
+        }
+
+        let mut data = vec![0u8; included_len as usize];
+
+        self.reader.read_exact(&mut data)?;
+
+        let direction = if flags & FLAG_RECEIVED != 0 {
+            Direction::ControllerToHost
+        } else {
+            Direction::HostToController
+        };
+
+        let packet = match self.datalink {
+            DATALINK_H4 => match data.split_first() {
+                Some((&raw, rest)) => PacketIndicator::try_from(raw)
+                    .map(|indicator| Packet::new(indicator, rest.to_vec()))
+                    .map_err(|raw| invalid_data(format!("invalid packet indicator {:#x}", raw)))?,
+                None => return Err(invalid_data("empty record")),
+            },
+            _ => {
+                let indicator = match (flags & FLAG_COMMAND_OR_EVENT != 0, direction) {
+                    (true, Direction::HostToController) => PacketIndicator::Command,
+                    (true, Direction::ControllerToHost) => PacketIndicator::Event,
+                    (false, _) => PacketIndicator::AclData,
+                };
+
+                Packet::new(indicator, data)
+            },
+        };
+
+        Ok(Some(Record { direction, timestamp: time(i64::from_be_bytes(timestamp)), packet }))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_write_test() {
+        let records = vec![
+            Record {
+                direction: Direction::HostToController,
+                timestamp: UNIX_EPOCH + Duration::from_micros(1_571_000_000_123_456),
+                packet: Packet::new(PacketIndicator::Command, vec![0x03, 0x0C, 0x00]),
+            },
+            Record {
+                direction: Direction::ControllerToHost,
+                timestamp: UNIX_EPOCH + Duration::from_micros(1_571_000_000_124_000),
+                packet: Packet::new(PacketIndicator::Event, vec![0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00]),
+            },
+            Record {
+                direction: Direction::ControllerToHost,
+                timestamp: UNIX_EPOCH + Duration::from_micros(1_571_000_000_200_000),
+                packet: Packet::new(PacketIndicator::AclData, vec![0x40, 0x20, 0x01, 0x00, 0xFF]),
+            },
+        ];
+
+        let mut file = Vec::new();
+
+        write_header(&mut file).unwrap();
+
+        records.iter().for_each(|record| write_record(&mut file, record).unwrap());
+
+        let read = Reader::new(file.as_slice()).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+
+        assert_eq!(records, read);
+
+        // A file that ends in the middle of a record
+        assert!(Reader::new(&file[..(file.len() - 1)]).unwrap().last().unwrap().is_err());
+
+        assert!(Reader::new(&b"btsnoop\0\0\0\0\x01\0\0\x07\xD1"[..]).is_err());
+    }
+
+    #[test]
+    fn unencapsulated_test() {
+        let file = [
+            &b"btsnoop\0"[..],
+            &[0, 0, 0, 1, 0, 0, 0x03, 0xE9],
+            // Command
+            &[0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0],
+            &0x00dc_ddb3_0f2f_8000i64.to_be_bytes(),
+            &[0x03, 0x0C, 0x00],
+            // Event
+            &[0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0],
+            &0x00dc_ddb3_0f2f_8000i64.to_be_bytes(),
+            &[0x1A, 0x00],
+            // ACL data
+            &[0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, 0],
+            &0x00dc_ddb3_0f2f_8000i64.to_be_bytes(),
+            &[0x40, 0x20, 0x00, 0x00],
+        ].concat();
+
+        let read = Reader::new(file.as_slice()).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+
+        let expected = vec![
+            Record {
+                direction: Direction::HostToController,
+                timestamp: UNIX_EPOCH,
+                packet: Packet::new(PacketIndicator::Command, vec![0x03, 0x0C, 0x00]),
+            },
+            Record {
+                direction: Direction::ControllerToHost,
+                timestamp: UNIX_EPOCH,
+                packet: Packet::new(PacketIndicator::Event, vec![0x1A, 0x00]),
+            },
+            Record {
+                direction: Direction::ControllerToHost,
+                timestamp: UNIX_EPOCH,
+                packet: Packet::new(PacketIndicator::AclData, vec![0x40, 0x20, 0x00, 0x00]),
+            },
+        ];
+
+        assert_eq!(expected, read);
+    }
+}
@@ -0,0 +1,407 @@
+//! HCI traffic capture for bo-tie
+//!
+//! [`Capture`] wraps the host controller interface of another base crate and records every
+//! command, event, ACL data, and synchronous data packet that passes through it. The packets are written with a
+//! timestamp in either the btsnoop or the pcap format so that the capture can be opened in
+//! Wireshark or any other tool that understands these formats.
+//!
+//! Packets received from the controller are taken from the wrapped interface through the
+//! [`ReceivedPacketSource`] trait, so that the events are recorded exactly as they were sent by
+//! the controller.
+//!
+//! A btsnoop capture can be played back with a [`Replay`](replay::Replay) to run the host against
+//! the recorded controller.
+
+pub mod btsnoop;
+pub mod pcap;
+pub mod replay;
+
+use bo_tie::hci::{
+    events,
+    common::ConnectionHandle,
+    CommandParameter,
+    EventMatcher,
+    HciAclData,
+    HciAclDataInterface,
+    HciPacketType,
+    HciSyncData,
+    HciSyncDataInterface,
+    HostControllerInterface,
+    ReceivedPacketListener,
+    ReceivedPacketSource,
+};
+use bo_tie_h4::packet::{Packet, PacketIndicator};
+use std::io::{self, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::time::{Duration, SystemTime};
+
+/// The direction of a captured packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    HostToController,
+    ControllerToHost,
+}
+
+/// The file format of a capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The btsnoop format with the HCI UART (H4) datalink type
+    Btsnoop,
+    /// The pcap format with the `LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR` link type
+    Pcap,
+}
+
+/// A captured packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    pub packet: Packet,
+}
+
+impl Record {
+
+    /// Create a record of a packet with the current time as the timestamp
+    pub fn now(direction: Direction, packet: Packet) -> Self {
+        Record { direction, timestamp: SystemTime::now(), packet }
+    }
+}
+
+/// A writer of captured packets
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    format: Format,
+}
+
+impl<W: Write> CaptureWriter<W> {
+
+    /// Create a new `CaptureWriter`
+    ///
+    /// The file header of `format` is immediately written to `writer`.
+    pub fn new(mut writer: W, format: Format) -> io::Result<Self> {
+        match format {
+            Format::Btsnoop => btsnoop::write_header(&mut writer)?,
+            Format::Pcap => pcap::write_header(&mut writer)?,
+        }
+
+        writer.flush()?;
+
+        Ok(CaptureWriter { writer, format })
+    }
+
+    /// Write a record
+    ///
+    /// The writer is flushed after every record so that a capture is complete up to the last
+    /// record even if the program aborts.
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Btsnoop => btsnoop::write_record(&mut self.writer, record)?,
+            Format::Pcap => pcap::write_record(&mut self.writer, record)?,
+        }
+
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// The recorder shared between a `Capture` and the listener set on the wrapped interface
+struct Recorder {
+    writer: Mutex<CaptureWriter<Box<dyn Write + Send>>>,
+    /// The listener set on the `Capture`
+    listener: Mutex<Option<Arc<dyn ReceivedPacketListener>>>,
+}
+
+impl Recorder {
+
+    fn record(&self, direction: Direction, packet: Packet) {
+        let record = Record::now(direction, packet);
+
+        if let Err(e) = self.writer.lock().expect("Failed to acquire capture writer").write_record(&record) {
+            log::error!("Failed to write captured packet: {}", e);
+        }
+    }
+}
+
+impl ReceivedPacketListener for Recorder {
+    fn on_packet(&self, packet_type: HciPacketType, packet: &[u8]) {
+        self.record(Direction::ControllerToHost, Packet::new(packet_type.into(), packet.to_vec()));
+
+        let listener = self.listener.lock().expect("Failed to acquire listener").clone();
+
+        if let Some(listener) = listener { listener.on_packet(packet_type, packet) }
+    }
+}
+
+/// A host controller interface that captures all HCI traffic
+///
+/// Commands and data sent by the host are recorded just before they are given to the wrapped
+/// interface. If the wrapped interface cannot send a command yet (`send_command` returns
+/// `Ok(false)`) the command will be recorded again when it is resent. A failure to write to the
+/// capture is logged and does not affect the HCI traffic.
+///
+/// Received packets are passed on to the listener set on the `Capture` after they are recorded, so
+/// a `Capture` can be used as the interface of anything that needs the received packets (such as
+/// the event streams of a `HostInterface`).
+///
+/// Clones of a `Capture` record to the same capture.
+#[derive(Clone)]
+pub struct Capture<I> {
+    interface: I,
+    recorder: Arc<Recorder>,
+}
+
+impl<I> Capture<I> where I: ReceivedPacketSource {
+
+    /// Create a new `Capture`
+    ///
+    /// The capture is written to `writer` in the format `format`. This replaces any listener of
+    /// received packets that was set on `interface`.
+    pub fn new<W>(interface: I, writer: W, format: Format) -> io::Result<Self>
+    where W: Write + Send + 'static
+    {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+
+        let recorder = Arc::new(Recorder {
+            writer: Mutex::new(CaptureWriter::new(writer, format)?),
+            listener: Mutex::new(None),
+        });
+
+        interface.set_received_packet_listener(Some(recorder.clone()));
+
+        Ok(Capture { interface, recorder })
+    }
+
+    /// Create a new `Capture` that is written to the file at `path`
+    ///
+    /// The file is created if it doesn't exist and truncated if it does.
+    pub fn create<P: AsRef<Path>>(interface: I, path: P, format: Format) -> io::Result<Self> {
+        let file = io::BufWriter::new(std::fs::File::create(path)?);
+
+        Self::new(interface, file, format)
+    }
+
+    /// Stop capturing and return the wrapped interface
+    pub fn into_inner(self) -> I {
+        self.interface.set_received_packet_listener(None);
+
+        self.interface
+    }
+}
+
+impl<I> HostControllerInterface for Capture<I> where I: HostControllerInterface {
+
+    type SendCommandError = I::SendCommandError;
+    type ReceiveEventError = I::ReceiveEventError;
+
+    fn send_command<D,W>(&self, cmd_data: &D, waker: W) -> Result<bool, Self::SendCommandError>
+    where D: CommandParameter,
+          W: Into<Option<Waker>>
+    {
+        self.recorder.record(
+            Direction::HostToController,
+            Packet::new(PacketIndicator::Command, cmd_data.as_command_packet().into_vec())
+        );
+
+        self.interface.send_command(cmd_data, waker)
+    }
+
+    fn receive_event<P>(&self,
+        event: events::Events,
+        waker: &Waker,
+        matcher: Pin<Arc<P>>,
+        timeout: Option<Duration>)
+    -> Option<Result<events::EventsData, Self::ReceiveEventError>>
+    where P: EventMatcher + Send + Sync + 'static
+    {
+        self.interface.receive_event(event, waker, matcher, timeout)
+    }
+}
+
+impl<I> ReceivedPacketSource for Capture<I> {
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>) {
+        *self.recorder.listener.lock().expect("Failed to acquire listener") = listener;
+    }
+}
+
+impl<I> HciAclDataInterface for Capture<I> where I: HciAclDataInterface {
+
+    type SendAclDataError = I::SendAclDataError;
+    type ReceiveAclDataError = I::ReceiveAclDataError;
+
+    fn send(&self, data: HciAclData) -> Result<usize, Self::SendAclDataError> {
+        self.recorder.record(Direction::HostToController, Packet::new(PacketIndicator::AclData, data.get_packet()));
+
+        self.interface.send(data)
+    }
+
+    fn start_receiver(&self, handle: ConnectionHandle) {
+        self.interface.start_receiver(handle)
+    }
+
+    fn stop_receiver(&self, handle: &ConnectionHandle) {
+        self.interface.stop_receiver(handle)
+    }
+
+    fn receive(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciAclData>, Self::ReceiveAclDataError>>
+    {
+        self.interface.receive(handle, waker)
+    }
+}
+
+impl<I> HciSyncDataInterface for Capture<I> where I: HciSyncDataInterface {
+
+    type SendSyncDataError = I::SendSyncDataError;
+    type ReceiveSyncDataError = I::ReceiveSyncDataError;
+
+    fn send_sync(&self, data: HciSyncData) -> Result<usize, Self::SendSyncDataError> {
+        self.recorder.record(Direction::HostToController, Packet::new(PacketIndicator::SyncData, data.get_packet()));
+
+        self.interface.send_sync(data)
+    }
+
+    fn start_sync_receiver(&self, handle: ConnectionHandle) {
+        self.interface.start_sync_receiver(handle)
+    }
+
+    fn stop_sync_receiver(&self, handle: &ConnectionHandle) {
+        self.interface.stop_sync_receiver(handle)
+    }
+
+    fn receive_sync(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciSyncData>, Self::ReceiveSyncDataError>>
+    {
+        self.interface.receive_sync(handle, waker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use bo_tie::hci::HostInterface;
+    use bo_tie::hci::le::mandatory::{read_bd_addr, reset};
+    use bo_tie::hci::testing::VirtualController;
+    use futures::executor::block_on;
+
+    const ADDRESS: [u8;6] = [1, 2, 3, 4, 5, 6];
+
+    /// A writer to a buffer that can still be read after the writer is given to a `Capture`
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run the Reset and Read BD_ADDR commands and return the capture
+    fn capture_commands(format: Format) -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+
+        let capture = Capture::new(VirtualController::new(ADDRESS), buffer.clone(), format).unwrap();
+
+        let hi = HostInterface::from(capture);
+
+        block_on(reset::send(&hi)).unwrap();
+
+        assert_eq!(ADDRESS, block_on(read_bd_addr::send(&hi)).unwrap());
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        bytes
+    }
+
+    #[test]
+    fn listener_test() {
+        let controller = VirtualController::new(ADDRESS);
+
+        let capture = Capture::new(controller.clone(), SharedBuffer::default(), Format::Btsnoop).unwrap();
+
+        let hi = HostInterface::from(capture);
+
+        let mut stream = hi.subscribe(events::Events::DisconnectionComplete, 1);
+
+        controller.inject_event(events::Events::DisconnectionComplete, &[0, 0x40, 0, 0x13]).unwrap();
+
+        match block_on(stream.next_event()) {
+            Ok(events::EventsData::DisconnectionComplete(data)) =>
+                assert_eq!(0x40, data.connection_handle.get_raw_handle()),
+            _ => panic!("Expected disconnection complete event"),
+        }
+    }
+
+    /// Split the records of a capture into the flags (or direction) and the data of each record
+    fn records(mut bytes: &[u8], split: fn(&[u8]) -> (usize, u32, usize)) -> Vec<(u32, Vec<u8>)> {
+        let mut records = Vec::new();
+
+        while !bytes.is_empty() {
+            let (len, flags, data_start) = split(bytes);
+
+            records.push((flags, bytes[data_start..(data_start + len)].to_vec()));
+
+            bytes = &bytes[(data_start + len)..];
+        }
+
+        records
+    }
+
+    #[test]
+    fn btsnoop_test() {
+        let bytes = capture_commands(Format::Btsnoop);
+
+        assert_eq!(b"btsnoop\0", &bytes[..8]);
+        assert_eq!([0, 0, 0, 1], bytes[8..12]);
+        assert_eq!([0, 0, 0x03, 0xEA], bytes[12..16]);
+
+        let records = records(&bytes[16..], |record| {
+            let len = u32::from_be_bytes([record[0], record[1], record[2], record[3]]) as usize;
+            let flags = u32::from_be_bytes([record[8], record[9], record[10], record[11]]);
+
+            (len, flags, 24)
+        });
+
+        let expected = vec![
+            (2, vec![0x01, 0x03, 0x0C, 0x00]),
+            (3, vec![0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00]),
+            (2, vec![0x01, 0x09, 0x10, 0x00]),
+            (3, [&[0x04, 0x0E, 0x0A, 0x01, 0x09, 0x10, 0x00][..], &ADDRESS].concat()),
+        ];
+
+        assert_eq!(expected, records);
+    }
+
+    #[test]
+    fn pcap_test() {
+        let bytes = capture_commands(Format::Pcap);
+
+        assert_eq!([0xd4, 0xc3, 0xb2, 0xa1], bytes[..4]);
+        assert_eq!([201, 0, 0, 0], bytes[20..24]);
+
+        let records = records(&bytes[24..], |record| {
+            let len = u32::from_le_bytes([record[8], record[9], record[10], record[11]]) as usize;
+            let direction = u32::from_be_bytes([record[16], record[17], record[18], record[19]]);
+
+            (len - 4, direction, 20)
+        });
+
+        let expected = vec![
+            (0, vec![0x01, 0x03, 0x0C, 0x00]),
+            (1, vec![0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00]),
+            (0, vec![0x01, 0x09, 0x10, 0x00]),
+            (1, [&[0x04, 0x0E, 0x0A, 0x01, 0x09, 0x10, 0x00][..], &ADDRESS].concat()),
+        ];
+
+        assert_eq!(expected, records);
+    }
+}
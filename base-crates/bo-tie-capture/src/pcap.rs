@@ -0,0 +1,53 @@
+//! The pcap file format
+//!
+//! Files are written with the link type `LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR`, the data of every
+//! record is a four byte (big endian) direction pseudo header followed by the H4 packet indicator
+//! and the HCI packet.
+
+use crate::{Direction, Record};
+use std::io::{self, Write};
+use std::time::UNIX_EPOCH;
+
+pub const MAGIC: u32 = 0xa1b2_c3d4;
+
+pub const VERSION_MAJOR: u16 = 2;
+
+pub const VERSION_MINOR: u16 = 4;
+
+/// The maximum number of bytes of a packet that are saved
+///
+/// This is larger than the largest HCI packet so packets are never truncated.
+pub const SNAPSHOT_LENGTH: u32 = 0x0004_0000;
+
+/// The link type for HCI H4 with a direction pseudo header
+pub const LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR: u32 = 201;
+
+pub(crate) fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(&MAGIC.to_le_bytes())?;
+    writer.write_all(&VERSION_MAJOR.to_le_bytes())?;
+    writer.write_all(&VERSION_MINOR.to_le_bytes())?;
+    writer.write_all(&0i32.to_le_bytes())?; // GMT to local correction
+    writer.write_all(&0u32.to_le_bytes())?; // accuracy of timestamps
+    writer.write_all(&SNAPSHOT_LENGTH.to_le_bytes())?;
+    writer.write_all(&LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR.to_le_bytes())
+}
+
+pub(crate) fn write_record<W: Write>(writer: &mut W, record: &Record) -> io::Result<()> {
+    let data = record.packet.as_bytes();
+
+    let since_epoch = record.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let direction: u32 = match record.direction {
+        Direction::HostToController => 0,
+        Direction::ControllerToHost => 1,
+    };
+
+    let len = data.len() as u32 + 4;
+
+    writer.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+    writer.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?; // included length
+    writer.write_all(&len.to_le_bytes())?; // original length
+    writer.write_all(&direction.to_be_bytes())?;
+    writer.write_all(&data)
+}
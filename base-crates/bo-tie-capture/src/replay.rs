@@ -0,0 +1,452 @@
+//! Replay of a recorded controller
+//!
+//! A [`Replay`] is a host controller interface that plays back the controller's side of a
+//! recording. Every command and data packet sent by the host is checked against the next
+//! packet sent by the host in the recording, and the packets sent by the controller are delivered
+//! to the host once every packet the host sent before them in the recording has been sent again.
+//! The timestamps of the recording are not used, so a replay is deterministic and runs as fast as
+//! the host can go.
+//!
+//! This makes it possible to record a session with real hardware once (using
+//! [`Capture`](crate::Capture)) and then use the recording as a regression test that does not
+//! need a controller.
+
+use crate::{btsnoop, Direction, Record};
+use bo_tie::hci::{
+    events,
+    common::ConnectionHandle,
+    CommandParameter,
+    EventMatcher,
+    HciAclData,
+    HciAclDataInterface,
+    HciSyncData,
+    HciSyncDataInterface,
+    HostControllerInterface,
+    ReceivedPacketListener,
+    ReceivedPacketSource,
+};
+use bo_tie_h4::H4Interface;
+use bo_tie_h4::packet::{Packet, PacketIndicator};
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
+use std::time::Duration;
+
+/// A packet sent by the host that is not the next packet sent by the host in the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The packet in the recording, this is `None` if the recording has ended
+    pub expected: Option<Packet>,
+    pub sent: Packet,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.expected {
+            Some(ref expected) => write!(f, "Expected the host to send {:?}, but {:?} was sent", expected, self.sent),
+            None => write!(f, "The recording has ended, but the host sent {:?}", self.sent),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Mismatch(Mismatch),
+    Interface(bo_tie_h4::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        write!(f, "(from base-crate: bo-tie-capture) ")?;
+
+        match *self {
+            Error::Mismatch(ref mismatch) => write!(f, "Replay mismatch: {}", mismatch),
+
+            Error::Interface(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Mismatch(_) => None,
+            Error::Interface(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<bo_tie_h4::Error> for Error {
+    fn from(e: bo_tie_h4::Error) -> Self {
+        Error::Interface(e)
+    }
+}
+
+#[derive(Default)]
+struct PipeState {
+    buffer: VecDeque<u8>,
+    closed: bool,
+}
+
+/// An in memory byte stream from the player to the `H4Interface` of a `Replay`
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    condvar: Condvar,
+}
+
+impl Pipe {
+
+    fn write(&self, bytes: &[u8]) {
+        self.state.lock().expect("Couldn't acquire lock").buffer.extend(bytes);
+
+        self.condvar.notify_all();
+    }
+
+    /// Close the pipe
+    ///
+    /// The reader still reads the bytes already written to the pipe before it reaches the end of
+    /// the stream.
+    fn close(&self) {
+        self.state.lock().expect("Couldn't acquire lock").closed = true;
+
+        self.condvar.notify_all();
+    }
+}
+
+struct PipeReader(Arc<Pipe>);
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.0.state.lock().expect("Couldn't acquire lock");
+
+        while state.buffer.is_empty() && !state.closed {
+            state = self.0.condvar.wait(state).expect("Couldn't acquire lock");
+        }
+
+        let len = buf.len().min(state.buffer.len());
+
+        buf.iter_mut().zip(state.buffer.drain(..len)).for_each(|(b, byte)| *b = byte);
+
+        Ok(len)
+    }
+}
+
+/// The player of the recording
+struct Player {
+    records: VecDeque<Record>,
+    mismatch: Option<Mismatch>,
+    pipe: Arc<Pipe>,
+}
+
+impl Player {
+
+    /// Deliver the controller's packets up to the next packet sent by the host
+    ///
+    /// The pipe is closed once the end of the recording is reached.
+    fn deliver(&mut self) {
+        while let Some(record) = self.records.front() {
+            if record.direction == Direction::HostToController {
+                return
+            }
+
+            self.pipe.write(&record.packet.as_bytes());
+
+            self.records.pop_front();
+        }
+
+        log::info!("End of the recording reached");
+
+        self.pipe.close();
+    }
+
+    /// Check a packet sent by the host
+    ///
+    /// After the first mismatch every packet sent by the host results in the same mismatch.
+    fn host_packet(&mut self, sent: Packet) -> Result<(), Error> {
+        if let Some(ref mismatch) = self.mismatch {
+            return Err(Error::Mismatch(mismatch.clone()));
+        }
+
+        match self.records.front() {
+            Some(record) if record.packet == sent => {
+                self.records.pop_front();
+
+                self.deliver();
+
+                Ok(())
+            },
+            next => {
+                let mismatch = Mismatch { expected: next.map(|record| record.packet.clone()), sent };
+
+                log::error!("Replay mismatch: {}", mismatch);
+
+                self.mismatch = Some(mismatch.clone());
+
+                self.pipe.close();
+
+                Err(Error::Mismatch(mismatch))
+            }
+        }
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.pipe.close()
+    }
+}
+
+/// A host controller interface that replays a recording
+///
+/// The packets sent by the controller in the recording are given to a [`H4Interface`], so events
+/// and data are received the same way as from a controller on a H4 transport. When the
+/// recording ends or a packet sent by the host does not match the recording, the replayed
+/// controller is closed. Anything still waiting on an event or data that was not delivered
+/// will then receive an error instead of waiting forever.
+///
+/// Clones of a `Replay` are handles to the same replay.
+#[derive(Clone)]
+pub struct Replay {
+    interface: H4Interface,
+    player: Arc<Mutex<Player>>,
+}
+
+impl Replay {
+
+    /// Create a new `Replay` of the records of a recording
+    ///
+    /// The packets sent by the controller before the first packet sent by the host are delivered
+    /// immediately.
+    pub fn new<I>(records: I) -> Self where I: IntoIterator<Item = Record> {
+        let pipe = Arc::new(Pipe::default());
+
+        let interface = H4Interface::from_split(PipeReader(pipe.clone()), io::sink());
+
+        let mut player = Player { records: records.into_iter().collect(), mismatch: None, pipe };
+
+        player.deliver();
+
+        Replay { interface, player: Arc::new(Mutex::new(player)) }
+    }
+
+    /// Create a new `Replay` of the btsnoop file at `path`
+    ///
+    /// # Error
+    /// An error is returned if the file cannot be read or it is not a valid btsnoop file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = io::BufReader::new(std::fs::File::open(path)?);
+
+        let records = btsnoop::Reader::new(file)?.collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self::new(records))
+    }
+
+    /// Check if the entire recording was replayed
+    pub fn is_complete(&self) -> bool {
+        let player = self.player.lock().expect("Couldn't acquire lock");
+
+        player.records.is_empty() && player.mismatch.is_none()
+    }
+
+    /// Get the first mismatch between the packets sent by the host and the recording
+    pub fn get_mismatch(&self) -> Option<Mismatch> {
+        self.player.lock().expect("Couldn't acquire lock").mismatch.clone()
+    }
+
+    fn host_packet(&self, packet: Packet) -> Result<(), Error> {
+        self.player.lock().expect("Couldn't acquire lock").host_packet(packet)
+    }
+}
+
+impl HostControllerInterface for Replay {
+
+    type SendCommandError = Error;
+    type ReceiveEventError = Error;
+
+    fn send_command<D,W>(&self, cmd_data: &D, _: W) -> Result<bool, Self::SendCommandError>
+    where D: CommandParameter,
+          W: Into<Option<Waker>>
+    {
+        self.host_packet(Packet::new(PacketIndicator::Command, cmd_data.as_command_packet().into_vec()))
+            .map(|_| true)
+    }
+
+    fn receive_event<P>(&self,
+        event: events::Events,
+        waker: &Waker,
+        matcher: Pin<Arc<P>>,
+        timeout: Option<Duration>)
+    -> Option<Result<events::EventsData, Self::ReceiveEventError>>
+    where P: EventMatcher + Send + Sync + 'static
+    {
+        self.interface.receive_event(event, waker, matcher, timeout)
+            .map(|result| result.map_err(Error::from))
+    }
+}
+
+impl HciAclDataInterface for Replay {
+
+    type SendAclDataError = Error;
+    type ReceiveAclDataError = Error;
+
+    fn send(&self, data: HciAclData) -> Result<usize, Self::SendAclDataError> {
+        let len = data.get_payload().len() + 1;
+
+        self.host_packet(Packet::new(PacketIndicator::AclData, data.get_packet()))
+            .map(|_| len)
+    }
+
+    fn start_receiver(&self, handle: ConnectionHandle) {
+        self.interface.start_receiver(handle)
+    }
+
+    fn stop_receiver(&self, handle: &ConnectionHandle) {
+        self.interface.stop_receiver(handle)
+    }
+
+    fn receive(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciAclData>, Self::ReceiveAclDataError>>
+    {
+        self.interface.receive(handle, waker)
+            .map(|result| result.map_err(Error::from))
+    }
+}
+
+impl HciSyncDataInterface for Replay {
+
+    type SendSyncDataError = Error;
+    type ReceiveSyncDataError = Error;
+
+    fn send_sync(&self, data: HciSyncData) -> Result<usize, Self::SendSyncDataError> {
+        let len = data.get_payload().len() + 1;
+
+        self.host_packet(Packet::new(PacketIndicator::SyncData, data.get_packet()))
+            .map(|_| len)
+    }
+
+    fn start_sync_receiver(&self, handle: ConnectionHandle) {
+        self.interface.start_sync_receiver(handle)
+    }
+
+    fn stop_sync_receiver(&self, handle: &ConnectionHandle) {
+        self.interface.stop_sync_receiver(handle)
+    }
+
+    fn receive_sync(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciSyncData>, Self::ReceiveSyncDataError>>
+    {
+        self.interface.receive_sync(handle, waker)
+            .map(|result| result.map_err(Error::from))
+    }
+}
+
+impl ReceivedPacketSource for Replay {
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>) {
+        self.interface.set_received_packet_listener(listener)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use bo_tie::hci::{AclBroadcastFlag, AclPacketBoundary, HostInterface};
+    use bo_tie::hci::le::mandatory::{read_bd_addr, reset};
+    use futures::executor::block_on;
+    use futures::future::poll_fn;
+    use std::task::Poll;
+    use std::time::SystemTime;
+
+    const ADDRESS: [u8;6] = [1, 2, 3, 4, 5, 6];
+
+    fn record(direction: Direction, indicator: PacketIndicator, data: &[u8]) -> Record {
+        Record { direction, timestamp: SystemTime::now(), packet: Packet::new(indicator, data.to_vec()) }
+    }
+
+    fn command(data: &[u8]) -> Record {
+        record(Direction::HostToController, PacketIndicator::Command, data)
+    }
+
+    fn event(data: &[u8]) -> Record {
+        record(Direction::ControllerToHost, PacketIndicator::Event, data)
+    }
+
+    fn recording() -> Vec<Record> {
+        vec![
+            command(&[0x03, 0x0C, 0x00]),
+            event(&[0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00]),
+            command(&[0x09, 0x10, 0x00]),
+            event(&[0x0E, 0x0A, 0x01, 0x09, 0x10, 0x00, 1, 2, 3, 4, 5, 6]),
+        ]
+    }
+
+    #[test]
+    fn replay_test() {
+        let replay = Replay::new(recording());
+
+        let hi = HostInterface::from(replay.clone());
+
+        block_on(reset::send(&hi)).unwrap();
+
+        assert!(!replay.is_complete());
+
+        assert_eq!(ADDRESS, block_on(read_bd_addr::send(&hi)).unwrap());
+
+        assert!(replay.is_complete());
+
+        // The recording has ended
+        assert!(block_on(reset::send(&hi)).is_err());
+
+        assert_eq!(None, replay.get_mismatch().unwrap().expected);
+    }
+
+    #[test]
+    fn mismatch_test() {
+        let replay = Replay::new(recording());
+
+        let hi = HostInterface::from(replay.clone());
+
+        assert!(block_on(read_bd_addr::send(&hi)).is_err());
+
+        let mismatch = replay.get_mismatch().unwrap();
+
+        assert_eq!(recording()[0].packet, mismatch.expected.unwrap());
+        assert_eq!(Packet::new(PacketIndicator::Command, vec![0x09, 0x10, 0x00]), mismatch.sent);
+    }
+
+    #[test]
+    fn acl_data_test() {
+        let handle = ConnectionHandle::try_from(0x40).unwrap();
+
+        let replay = Replay::new(vec![
+            record(Direction::HostToController, PacketIndicator::AclData, &[0x40, 0x00, 0x02, 0x00, 0xA, 0xB]),
+            record(Direction::ControllerToHost, PacketIndicator::AclData, &[0x40, 0x20, 0x01, 0x00, 0xC]),
+        ]);
+
+        replay.start_receiver(handle);
+
+        let sent = HciAclData::new(handle, AclPacketBoundary::FirstNonFlushable, AclBroadcastFlag::NoBroadcast, vec![0xA, 0xB]);
+
+        replay.send(sent).unwrap();
+
+        let receive = || block_on(poll_fn(|cx| match replay.receive(&handle, cx.waker()) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }));
+
+        let received = receive().unwrap();
+
+        assert_eq!(1, received.len());
+        assert_eq!(&[0xC], received[0].get_payload());
+
+        // Nothing more is received as the recording has ended
+        assert!(receive().is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use bo_tie::hci::{events, EventMatcher};
+use crate::timeout::Timer;
+use crate::Error;
+use std::collections::{BTreeMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+/// The maximum number of received events that are kept while nothing is waiting for them
+///
+/// Events are buffered so that an event sent by the controller before the host starts waiting for
+/// it (e.g. the Command Complete event of a fast controller) isn't lost. Once the buffer is full
+/// the oldest event is dropped.
+const RECEIVED_CAPACITY: usize = 64;
+
+struct Expectation {
+    /// The matcher is kept so its address (used in the key of the expectation) cannot be reused
+    /// while the expectation exists
+    _matcher: Pin<Arc<dyn EventMatcher>>,
+    waker: Waker,
+    deadline: Option<Instant>,
+}
+
+/// The key of an expectation is the event and the address of the matcher
+type ExpectationKey = (events::Events, usize);
+
+/// Expected event manager
+#[derive(Default)]
+pub struct EventExpecter {
+    received: VecDeque<events::EventsData>,
+    expected: BTreeMap<ExpectationKey, Expectation>,
+    closed: bool,
+}
+
+impl EventExpecter {
+
+    pub fn expect_event<P>(
+        mutex: &Arc<Mutex<Self>>,
+        event: events::Events,
+        waker: &Waker,
+        matcher: Pin<Arc<P>>,
+        timeout: Option<Duration>,
+        timer: &Timer,
+    ) -> Option<Result<events::EventsData, Error>>
+    where P: EventMatcher + 'static
+    {
+        let key = (event, &*matcher as *const P as usize);
+
+        let mut guard = mutex.lock().expect("Couldn't acquire lock");
+
+        let position = guard.received.iter()
+            .position(|data| data.get_enum_name() == event && matcher.match_event(data));
+
+        if let Some(index) = position {
+            log::debug!("Retrieving data for event {:?}", event);
+
+            guard.expected.remove(&key);
+
+            return guard.received.remove(index).map(Ok);
+        }
+
+        if guard.closed {
+            guard.expected.remove(&key);
+
+            return Some(Err(Error::Closed));
+        }
+
+        match guard.expected.get_mut(&key) {
+            Some(expectation) => {
+                if expectation.deadline.map(|deadline| deadline <= Instant::now()).unwrap_or_default() {
+                    guard.expected.remove(&key);
+
+                    Some(Err(Error::Timeout))
+                } else {
+                    expectation.waker = waker.clone();
+
+                    None
+                }
+            },
+            None => {
+                log::debug!("Setting up expectation for event {:?}", event);
+
+                let deadline = timeout.map(|duration| Instant::now() + duration);
+
+                if let Some(deadline) = deadline {
+                    let mutex = mutex.clone();
+
+                    timer.add(deadline, move || {
+                        let waker = mutex.lock().expect("Couldn't acquire lock")
+                            .expected.get(&key)
+                            .map(|expectation| expectation.waker.clone());
+
+                        if let Some(waker) = waker { waker.wake() }
+                    });
+                }
+
+                guard.expected.insert(key, Expectation { _matcher: matcher, waker: waker.clone(), deadline });
+
+                None
+            }
+        }
+    }
+
+    /// Process an event packet received from the controller
+    ///
+    /// The return is the wakers of the contexts waiting on the received event.
+    pub fn process(&mut self, packet: &[u8]) -> Vec<Waker> {
+        match events::EventsData::from_packet(packet) {
+            Ok(event_data) => {
+                let received_event = event_data.get_enum_name();
+
+                if self.received.len() >= RECEIVED_CAPACITY {
+                    if let Some(dropped) = self.received.pop_front() {
+                        log::debug!("Dropped unclaimed event {:?}", dropped.get_enum_name());
+                    }
+                }
+
+                self.received.push_back(event_data);
+
+                self.expected.iter()
+                    .filter(|((event, _), _)| *event == received_event)
+                    .map(|(_, expectation)| expectation.waker.clone())
+                    .collect()
+            },
+            Err(e) => {
+                log::error!("HCI Event Error: {}", e);
+
+                Vec::new()
+            },
+        }
+    }
+
+    /// Mark the interface as closed
+    ///
+    /// Every context waiting on an event is woken to receive the error that the interface is
+    /// closed.
+    pub fn close(&mut self) -> Vec<Waker> {
+        self.closed = true;
+
+        self.expected.values().map(|expectation| expectation.waker.clone()).collect()
+    }
+}
@@ -0,0 +1,702 @@
+//! H4 (UART) transport for bo-tie
+//!
+//! This is a base crate for communicating with a Bluetooth controller that uses the UART transport
+//! layer (v5.0 | Vol 4, Part A), more commonly known as H4. Controllers running firmware such as
+//! Zephyr's `hci_uart` or the nRF HCI firmware are connected to the host over a serial line and
+//! use this transport.
+//!
+//! The transport itself is nothing more than a byte stream where every HCI packet is preceded by
+//! a packet indicator, so [`H4Interface`] works with any byte stream. A serial device opened as a
+//! `File`, a `TcpStream`, or a `UnixStream` can be used directly with
+//! [`H4Interface::new`], any other reader and writer can be used with
+//! [`H4Interface::from_split`].
+//!
+//! # Serial Configuration
+//! The serial device must be configured (baud rate, flow control, raw mode) before it is used to
+//! create a `H4Interface`.
+
+use bo_tie::hci::{
+    events,
+    common::ConnectionHandle,
+    CommandParameter,
+    EventMatcher,
+    HciAclData,
+    HciSyncData,
+    ReceivedPacketListener,
+};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::thread;
+use std::time::Duration;
+
+mod event;
+pub mod packet;
+mod timeout;
+
+use packet::{Packet, PacketIndicator};
+
+#[derive(Debug)]
+pub enum Error {
+    IOError(io::Error),
+    /// The byte stream is closed
+    Closed,
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        write!(f, "(from base-crate: bo-tie-h4) ")?;
+
+        match *self {
+            Error::IOError(ref e) => write!(f, "IO error: {}", e),
+
+            Error::Closed => write!(f, "The byte stream to the controller is closed"),
+
+            Error::Timeout => write!(f, "Timeout Occurred"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::IOError(ref e) => Some(e),
+            Error::Closed => None,
+            Error::Timeout => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::IOError(e)
+    }
+}
+
+/// A byte stream that can be used by one thread for reading while another thread is writing
+pub trait Stream: Read + Write + Send + Sized + 'static {
+
+    /// Create another handle to the same stream
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl Stream for std::fs::File {
+    fn try_clone(&self) -> io::Result<Self> { std::fs::File::try_clone(self) }
+}
+
+impl Stream for std::net::TcpStream {
+    fn try_clone(&self) -> io::Result<Self> { std::net::TcpStream::try_clone(self) }
+}
+
+#[cfg(unix)]
+impl Stream for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> io::Result<Self> { std::os::unix::net::UnixStream::try_clone(self) }
+}
+
+/// Data received from the controller for a connection
+///
+/// This is implemented for the kinds of HCI data that are buffered per connection handle.
+trait ReceivedData: Sized {
+    /// The name of the kind of data used for logging
+    const KIND: &'static str;
+
+    fn connection_handle(&self) -> ConnectionHandle;
+}
+
+impl ReceivedData for HciAclData {
+    const KIND: &'static str = "ACL data";
+
+    fn connection_handle(&self) -> ConnectionHandle { *self.get_handle() }
+}
+
+impl ReceivedData for HciSyncData {
+    const KIND: &'static str = "synchronous data";
+
+    fn connection_handle(&self) -> ConnectionHandle { *self.get_handle() }
+}
+
+/// Received data for a connection handle
+struct DataBuffer<D> {
+    packets: Vec<D>,
+    waker: Option<Waker>,
+    /// A receiver is started for the connection handle
+    started: bool,
+}
+
+impl<D> Default for DataBuffer<D> {
+    fn default() -> Self {
+        DataBuffer { packets: Vec::new(), waker: None, started: false }
+    }
+}
+
+impl<D> DataBuffer<D> where D: ReceivedData {
+
+    /// The maximum number of packets buffered for a connection handle without a receiver
+    ///
+    /// Data can be received for a connection before its receiver is started, but a connection
+    /// handle may also never get a receiver. Once this limit is reached the oldest packet is
+    /// dropped for every new packet.
+    const UNSTARTED_CAPACITY: usize = 100;
+
+    fn push(&mut self, data: D) {
+        if !self.started && self.packets.len() >= Self::UNSTARTED_CAPACITY {
+            log::warn!("Dropped {} for connection handle {} as it has no receiver", D::KIND, data.connection_handle());
+
+            self.packets.remove(0);
+        }
+
+        self.packets.push(data);
+    }
+}
+
+/// The buffers of the received data of a kind for every connection handle
+struct DataBuffers<D>(Mutex<HashMap<ConnectionHandle, DataBuffer<D>>>);
+
+impl<D> DataBuffers<D> where D: ReceivedData {
+
+    fn new() -> Self {
+        DataBuffers(Mutex::new(HashMap::new()))
+    }
+
+    /// Buffer data received from the controller
+    fn push(&self, data: D) {
+        let waker = {
+            let mut buffers = self.0.lock().expect("Couldn't acquire lock");
+
+            let buffer = buffers.entry(data.connection_handle()).or_default();
+
+            buffer.push(data);
+
+            buffer.waker.take()
+        };
+
+        if let Some(waker) = waker { waker.wake() }
+    }
+
+    fn start(&self, handle: ConnectionHandle) {
+        self.0.lock().expect("Couldn't acquire lock").entry(handle).or_default().started = true;
+    }
+
+    fn stop(&self, handle: &ConnectionHandle) {
+        self.0.lock().expect("Couldn't acquire lock").remove(handle);
+    }
+
+    fn receive(&self, handle: &ConnectionHandle, waker: &Waker, closed: &AtomicBool)
+    -> Option<Result<Vec<D>, Error>>
+    {
+        let mut buffers = self.0.lock().expect("Couldn't acquire lock");
+
+        let buffer = buffers.entry(*handle).or_default();
+
+        if !buffer.packets.is_empty() {
+            Some(Ok(std::mem::take(&mut buffer.packets)))
+        } else if closed.load(Ordering::Relaxed) {
+            Some(Err(Error::Closed))
+        } else {
+            buffer.waker = Some(waker.clone());
+
+            None
+        }
+    }
+
+    /// Wake everything waiting to receive data
+    fn wake_all(&self) {
+        let wakers = self.0.lock().expect("Couldn't acquire lock")
+            .values_mut()
+            .filter_map(|buffer| buffer.waker.take())
+            .collect::<Vec<_>>();
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+}
+
+type PacketListener = Arc<Mutex<Option<Arc<dyn ReceivedPacketListener>>>>;
+
+/// The thread for reading packets from the controller
+struct ReaderThread<R> {
+    reader: R,
+    event_expecter: Arc<Mutex<event::EventExpecter>>,
+    acl_buffers: Arc<DataBuffers<HciAclData>>,
+    sync_buffers: Arc<DataBuffers<HciSyncData>>,
+    packet_listener: PacketListener,
+    closed: Arc<AtomicBool>,
+}
+
+impl<R> ReaderThread<R> where R: Read + Send + 'static {
+
+    fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            self.task();
+        })
+    }
+
+    /// Task for processing packets from the controller
+    ///
+    /// This task runs until the byte stream ends, an error occurs reading from the stream, or
+    /// every `H4Interface` for the stream is dropped (checked after every packet). When the task
+    /// ends anything waiting on an event or data is woken to receive the error that the
+    /// interface is closed.
+    fn task(mut self) {
+        while !self.closed.load(Ordering::Relaxed) {
+            match Packet::read_from(&mut self.reader) {
+                Ok(Some(packet)) => self.process(packet),
+                Ok(None) => {
+                    log::info!("The byte stream to the controller has ended");
+                    break
+                },
+                Err(e) => {
+                    log::error!("Cannot read from the byte stream to the controller: {}", e);
+                    break
+                },
+            }
+        }
+
+        self.closed.store(true, Ordering::Relaxed);
+
+        let wakers = self.event_expecter.lock().expect("Couldn't acquire lock").close();
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+
+        self.acl_buffers.wake_all();
+
+        self.sync_buffers.wake_all();
+    }
+
+    fn process(&self, packet: Packet) {
+        let listener = self.packet_listener.lock().expect("Couldn't acquire lock").clone();
+
+        if let Some(listener) = listener {
+            listener.on_packet(packet.indicator.into(), &packet.data);
+        }
+
+        match packet.indicator {
+            PacketIndicator::Event => {
+                log::trace!("Processing received HCI data, type:'Event'");
+
+                let wakers = self.event_expecter.lock().expect("Couldn't acquire lock").process(&packet.data);
+
+                wakers.into_iter().for_each(|waker| waker.wake());
+            },
+            PacketIndicator::AclData => {
+                log::trace!("Processing received HCI data, type:'ACL DATA'");
+
+                match HciAclData::from_packet(&packet.data) {
+                    Ok(data) => self.acl_buffers.push(data),
+                    Err(e) => log::error!("Failed to process hci acl packet: {}", e),
+                }
+            },
+            PacketIndicator::SyncData => {
+                log::trace!("Processing received HCI data, type:'SYNC DATA'");
+
+                match HciSyncData::from_packet(&packet.data) {
+                    Ok(data) => self.sync_buffers.push(data),
+                    Err(e) => log::error!("Failed to process hci synchronous data packet: {}", e),
+                }
+            },
+            PacketIndicator::Command => log::error!("Received a command packet from the controller"),
+        }
+    }
+}
+
+/// The state shared by the clones of a `H4Interface`
+struct Shared {
+    writer: Mutex<Box<dyn Write + Send>>,
+    event_expecter: Arc<Mutex<event::EventExpecter>>,
+    acl_buffers: Arc<DataBuffers<HciAclData>>,
+    sync_buffers: Arc<DataBuffers<HciSyncData>>,
+    packet_listener: PacketListener,
+    timer: timeout::Timer,
+    closed: Arc<AtomicBool>,
+}
+
+impl Shared {
+    fn write_packet(&self, packet: Packet) -> Result<(), Error> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(Error::Closed);
+        }
+
+        packet.write_to(&mut *self.writer.lock().expect("Couldn't acquire lock"))
+            .map_err(Error::from)
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A host controller interface over a H4 byte stream
+///
+/// Clones of a `H4Interface` are handles to the same interface. A thread is spawned to read the
+/// packets sent by the controller, this thread ends when the byte stream ends or the next packet
+/// is received after every handle to the interface is dropped.
+#[derive(Clone)]
+pub struct H4Interface {
+    shared: Arc<Shared>,
+}
+
+impl H4Interface {
+
+    /// Create a new `H4Interface` from a byte stream
+    ///
+    /// # Error
+    /// An error is returned if the stream cannot be cloned for the reading thread.
+    pub fn new<S>(stream: S) -> io::Result<Self> where S: Stream {
+        let reader = stream.try_clone()?;
+
+        Ok(Self::from_split(reader, stream))
+    }
+
+    /// Create a new `H4Interface` from the reading and writing halves of a byte stream
+    pub fn from_split<R, W>(reader: R, writer: W) -> Self
+    where R: Read + Send + 'static,
+          W: Write + Send + 'static,
+    {
+        let event_expecter = Arc::new(Mutex::new(event::EventExpecter::default()));
+
+        let acl_buffers = Arc::new(DataBuffers::new());
+
+        let sync_buffers = Arc::new(DataBuffers::new());
+
+        let packet_listener = PacketListener::default();
+
+        let closed = Arc::new(AtomicBool::new(false));
+
+        ReaderThread {
+            reader,
+            event_expecter: event_expecter.clone(),
+            acl_buffers: acl_buffers.clone(),
+            sync_buffers: sync_buffers.clone(),
+            packet_listener: packet_listener.clone(),
+            closed: closed.clone(),
+        }
+        .spawn();
+
+        let shared = Shared {
+            writer: Mutex::new(Box::new(writer)),
+            event_expecter,
+            acl_buffers,
+            sync_buffers,
+            packet_listener,
+            timer: timeout::Timer::new(),
+            closed,
+        };
+
+        H4Interface { shared: Arc::new(shared) }
+    }
+}
+
+impl bo_tie::hci::HostControllerInterface for H4Interface {
+
+    type SendCommandError = Error;
+    type ReceiveEventError = Error;
+
+    /// Send a command to the controller
+    ///
+    /// The command is written to the byte stream before this returns, so the return is always
+    /// true if there is no error.
+    fn send_command<D,W>(&self, cmd_data: &D, _: W) -> Result<bool, Self::SendCommandError>
+    where D: CommandParameter,
+          W: Into<Option<Waker>>
+    {
+        log::debug!("Sending command {:?}", D::COMMAND);
+
+        let packet = Packet::new(PacketIndicator::Command, cmd_data.as_command_packet().into_vec());
+
+        self.shared.write_packet(packet).map(|_| true)
+    }
+
+    fn receive_event<P>(&self,
+        event: events::Events,
+        waker: &Waker,
+        matcher: Pin<Arc<P>>,
+        timeout: Option<Duration>)
+    -> Option<Result<events::EventsData, Self::ReceiveEventError>>
+    where P: EventMatcher + Send + Sync + 'static
+    {
+        event::EventExpecter::expect_event(
+            &self.shared.event_expecter,
+            event,
+            waker,
+            matcher,
+            timeout,
+            &self.shared.timer,
+        )
+    }
+}
+
+impl bo_tie::hci::HciAclDataInterface for H4Interface {
+
+    type SendAclDataError = Error;
+    type ReceiveAclDataError = Error;
+
+    fn send(&self, data: HciAclData) -> Result<usize, Self::SendAclDataError> {
+        let len = data.get_payload().len() + 1;
+
+        self.shared.write_packet(Packet::new(PacketIndicator::AclData, data.get_packet()))?;
+
+        Ok(len)
+    }
+
+    fn start_receiver(&self, handle: ConnectionHandle) {
+        self.shared.acl_buffers.start(handle)
+    }
+
+    fn stop_receiver(&self, handle: &ConnectionHandle) {
+        self.shared.acl_buffers.stop(handle)
+    }
+
+    /// Receive ACL data
+    ///
+    /// ACL data received for a connection handle without a receiver is still buffered (up to a
+    /// limited number of packets), a receiver only needs to be stopped to drop the buffer for the
+    /// connection handle.
+    fn receive(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciAclData>, Self::ReceiveAclDataError>>
+    {
+        self.shared.acl_buffers.receive(handle, waker, &self.shared.closed)
+    }
+}
+
+impl bo_tie::hci::HciSyncDataInterface for H4Interface {
+
+    type SendSyncDataError = Error;
+    type ReceiveSyncDataError = Error;
+
+    fn send_sync(&self, data: HciSyncData) -> Result<usize, Self::SendSyncDataError> {
+        let len = data.get_payload().len() + 1;
+
+        self.shared.write_packet(Packet::new(PacketIndicator::SyncData, data.get_packet()))?;
+
+        Ok(len)
+    }
+
+    fn start_sync_receiver(&self, handle: ConnectionHandle) {
+        self.shared.sync_buffers.start(handle)
+    }
+
+    fn stop_sync_receiver(&self, handle: &ConnectionHandle) {
+        self.shared.sync_buffers.stop(handle)
+    }
+
+    /// Receive synchronous data
+    ///
+    /// Synchronous data is buffered the same way as ACL data, data received for a connection
+    /// handle without a receiver is still buffered (up to a limited number of packets).
+    fn receive_sync(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciSyncData>, Self::ReceiveSyncDataError>>
+    {
+        self.shared.sync_buffers.receive(handle, waker, &self.shared.closed)
+    }
+}
+
+impl bo_tie::hci::ReceivedPacketSource for H4Interface {
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>) {
+        *self.shared.packet_listener.lock().expect("Couldn't acquire lock") = listener;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+
+    use super::*;
+    use bo_tie::hci::{
+        AclBroadcastFlag,
+        AclPacketBoundary,
+        HciAclDataInterface,
+        HciSyncDataInterface,
+        HostInterface,
+        SyncPacketStatus,
+    };
+    use futures::executor::block_on;
+    use std::fs::File;
+    use std::task::Poll;
+
+    /// Open a pseudo terminal in raw mode
+    ///
+    /// The return is the master and slave ends of the pseudo terminal.
+    fn pty_pair() -> (File, File) {
+        use nix::pty::openpty;
+        use nix::sys::termios;
+        use std::os::unix::io::FromRawFd;
+
+        let pty = openpty(None, None).unwrap();
+
+        let mut attributes = termios::tcgetattr(pty.slave).unwrap();
+
+        termios::cfmakeraw(&mut attributes);
+
+        termios::tcsetattr(pty.slave, termios::SetArg::TCSANOW, &attributes).unwrap();
+
+        unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) }
+    }
+
+    /// Run a fake controller on the master end of the pseudo terminal
+    ///
+    /// The script is a list of the packets expected from the host, each with the packets the
+    /// controller sends in response. The master end is returned by the thread as closing it
+    /// before the host has read the last responses would discard them.
+    fn fake_controller(mut master: File, script: Vec<(Packet, Vec<Packet>)>) -> thread::JoinHandle<File> {
+        thread::spawn(move || {
+            for (expected, responses) in script {
+                assert_eq!(Some(expected), Packet::read_from(&mut master).unwrap());
+
+                responses.into_iter().for_each(|response| response.write_to(&mut master).unwrap());
+            }
+
+            master
+        })
+    }
+
+    fn event(data: &[u8]) -> Packet {
+        Packet::new(PacketIndicator::Event, data.to_vec())
+    }
+
+    fn command(data: &[u8]) -> Packet {
+        Packet::new(PacketIndicator::Command, data.to_vec())
+    }
+
+    #[test]
+    fn command_test() {
+        use bo_tie::hci::le::mandatory::{read_bd_addr, reset};
+
+        let (master, slave) = pty_pair();
+
+        let controller = fake_controller(master, vec![
+            (command(&[0x03, 0x0C, 0x00]), vec![event(&[0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00])]),
+            (command(&[0x09, 0x10, 0x00]), vec![
+                // an event nobody waits for is sent before the Command Complete event
+                event(&[0x08, 0x04, 0x00, 0x40, 0x00, 0x01]),
+                event(&[0x0E, 0x0A, 0x01, 0x09, 0x10, 0x00, 1, 2, 3, 4, 5, 6]),
+            ]),
+        ]);
+
+        let hi = HostInterface::from(H4Interface::new(slave).unwrap());
+
+        block_on(reset::send(&hi)).unwrap();
+
+        assert_eq!([1, 2, 3, 4, 5, 6], block_on(read_bd_addr::send(&hi)).unwrap());
+
+        controller.join().unwrap();
+    }
+
+    #[test]
+    fn acl_data_test() {
+        let (master, slave) = pty_pair();
+
+        let handle = ConnectionHandle::try_from(0x40).unwrap();
+
+        let controller = fake_controller(master, vec![
+            (
+                Packet::new(PacketIndicator::AclData, vec![0x40, 0x00, 0x02, 0x00, 0xAA, 0xBB]),
+                vec![Packet::new(PacketIndicator::AclData, vec![0x40, 0x20, 0x01, 0x00, 0xCC])]
+            ),
+        ]);
+
+        let interface = H4Interface::new(slave).unwrap();
+
+        interface.start_receiver(handle);
+
+        interface.send(HciAclData::new(
+            handle,
+            AclPacketBoundary::FirstNonFlushable,
+            AclBroadcastFlag::NoBroadcast,
+            vec![0xAA, 0xBB]
+        )).unwrap();
+
+        let received = block_on(futures::future::poll_fn(|cx| match interface.receive(&handle, cx.waker()) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        })).unwrap();
+
+        assert_eq!(&[0xCC], received[0].get_payload());
+
+        controller.join().unwrap();
+    }
+
+    #[test]
+    fn sync_data_test() {
+        let (master, slave) = pty_pair();
+
+        let handle = ConnectionHandle::try_from(0x40).unwrap();
+
+        let controller = fake_controller(master, vec![
+            (
+                Packet::new(PacketIndicator::SyncData, vec![0x40, 0x00, 0x02, 0xAA, 0xBB]),
+                vec![
+                    // data for another connection handle is not received for `handle`
+                    Packet::new(PacketIndicator::SyncData, vec![0x41, 0x00, 0x01, 0xDD]),
+                    Packet::new(PacketIndicator::SyncData, vec![0x40, 0x10, 0x01, 0xCC]),
+                ]
+            ),
+        ]);
+
+        let interface = H4Interface::new(slave).unwrap();
+
+        interface.start_sync_receiver(handle);
+
+        interface.send_sync(HciSyncData::new(handle, SyncPacketStatus::CorrectlyReceived, vec![0xAA, 0xBB]))
+            .unwrap();
+
+        let received = block_on(futures::future::poll_fn(|cx| match interface.receive_sync(&handle, cx.waker()) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        })).unwrap();
+
+        assert_eq!(1, received.len());
+        assert_eq!(&[0xCC], received[0].get_payload());
+        assert_eq!(SyncPacketStatus::PossiblyInvalid, received[0].get_packet_status_flag());
+
+        controller.join().unwrap();
+    }
+
+    #[test]
+    fn unstarted_acl_buffer_test() {
+        let handle = ConnectionHandle::try_from(0x40).unwrap();
+
+        let data = |byte: u8| HciAclData::new(
+            handle,
+            AclPacketBoundary::FirstNonFlushable,
+            AclBroadcastFlag::NoBroadcast,
+            vec![byte]
+        );
+
+        let mut buffer = DataBuffer::<HciAclData>::default();
+
+        for byte in 0..=DataBuffer::<HciAclData>::UNSTARTED_CAPACITY as u8 {
+            buffer.push(data(byte));
+        }
+
+        // The oldest packet is dropped when there is no receiver
+        assert_eq!(DataBuffer::<HciAclData>::UNSTARTED_CAPACITY, buffer.packets.len());
+        assert_eq!(&[1], buffer.packets[0].get_payload());
+
+        buffer.started = true;
+
+        buffer.push(data(0xFF));
+
+        assert_eq!(DataBuffer::<HciAclData>::UNSTARTED_CAPACITY + 1, buffer.packets.len());
+        assert_eq!(&[1], buffer.packets[0].get_payload());
+    }
+
+    #[test]
+    fn timeout_test() {
+        let (master, slave) = pty_pair();
+
+        let hi = HostInterface::from(H4Interface::new(slave).unwrap());
+
+        let result = block_on(hi.wait_for_event(events::Events::HardwareError, Duration::from_millis(10)));
+
+        assert!(matches!(result, Err(Error::Timeout)));
+
+        drop(master);
+    }
+}
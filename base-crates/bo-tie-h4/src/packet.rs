@@ -0,0 +1,202 @@
+//! H4 packet framing
+//!
+//! With the H4 transport every HCI packet is preceded by a one byte packet indicator that tells
+//! the receiver what kind of HCI packet follows (v5.0 | Vol 4, Part A, Section 2). The length of
+//! the packet is then determined from the header of the HCI packet.
+
+use bo_tie::hci::HciPacketType;
+use std::io::{self, Read, Write};
+
+/// The H4 packet indicator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketIndicator {
+    Command,
+    AclData,
+    SyncData,
+    Event,
+}
+
+impl PacketIndicator {
+
+    pub fn val(&self) -> u8 {
+        match self {
+            PacketIndicator::Command => 0x01,
+            PacketIndicator::AclData => 0x02,
+            PacketIndicator::SyncData => 0x03,
+            PacketIndicator::Event => 0x04,
+        }
+    }
+
+    /// Try to convert a raw packet indicator
+    ///
+    /// The raw value is returned as the error if it is not a valid packet indicator.
+    pub fn try_from(raw: u8) -> Result<Self, u8> {
+        match raw {
+            0x01 => Ok(PacketIndicator::Command),
+            0x02 => Ok(PacketIndicator::AclData),
+            0x03 => Ok(PacketIndicator::SyncData),
+            0x04 => Ok(PacketIndicator::Event),
+            _ => Err(raw),
+        }
+    }
+
+    /// The size of the header of the HCI packet
+    fn header_len(&self) -> usize {
+        match self {
+            PacketIndicator::Command => 3,
+            PacketIndicator::AclData => 4,
+            PacketIndicator::SyncData => 3,
+            PacketIndicator::Event => 2,
+        }
+    }
+
+    /// Get the length of the rest of the HCI packet from the header
+    fn parameter_len(&self, header: &[u8]) -> usize {
+        match self {
+            PacketIndicator::Command => header[2] as usize,
+            PacketIndicator::AclData => <u16>::from_le_bytes([header[2], header[3]]) as usize,
+            PacketIndicator::SyncData => header[2] as usize,
+            PacketIndicator::Event => header[1] as usize,
+        }
+    }
+}
+
+impl From<PacketIndicator> for HciPacketType {
+    fn from(indicator: PacketIndicator) -> Self {
+        match indicator {
+            PacketIndicator::Command => HciPacketType::Command,
+            PacketIndicator::AclData => HciPacketType::AclData,
+            PacketIndicator::SyncData => HciPacketType::SyncData,
+            PacketIndicator::Event => HciPacketType::Event,
+        }
+    }
+}
+
+impl From<HciPacketType> for PacketIndicator {
+    fn from(packet_type: HciPacketType) -> Self {
+        match packet_type {
+            HciPacketType::Command => PacketIndicator::Command,
+            HciPacketType::AclData => PacketIndicator::AclData,
+            HciPacketType::SyncData => PacketIndicator::SyncData,
+            HciPacketType::Event => PacketIndicator::Event,
+        }
+    }
+}
+
+/// A HCI packet with its H4 packet indicator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub indicator: PacketIndicator,
+    /// The HCI packet (without the packet indicator)
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+
+    pub fn new(indicator: PacketIndicator, data: Vec<u8>) -> Self {
+        Packet { indicator, data }
+    }
+
+    /// Read the next packet from a byte stream
+    ///
+    /// This blocks until an entire packet is read from `reader`. Any byte that is not a valid
+    /// packet indicator where a packet indicator is expected is logged and discarded. `None` is
+    /// returned if the stream ended before the start of a packet.
+    ///
+    /// # Error
+    /// An error is returned if the stream returns an error or ends in the middle of a packet.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let indicator = loop {
+            let mut raw = [0u8;1];
+
+            match reader.read(&mut raw) {
+                Ok(0) => return Ok(None),
+                Ok(_) => match PacketIndicator::try_from(raw[0]) {
+                    Ok(indicator) => break indicator,
+                    Err(raw) => log::warn!("Discarded byte '{:#x}', it is not a packet indicator", raw),
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        };
+
+        let mut data = vec![0u8; indicator.header_len()];
+
+        reader.read_exact(&mut data)?;
+
+        let header_len = data.len();
+
+        data.resize(header_len + indicator.parameter_len(&data), 0);
+
+        reader.read_exact(&mut data[header_len..])?;
+
+        Ok(Some(Packet { indicator, data }))
+    }
+
+    /// Write the packet to a byte stream
+    ///
+    /// The packet indicator and the HCI packet are written with a single write and then the
+    /// stream is flushed.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.as_bytes())?;
+
+        writer.flush()
+    }
+
+    /// Get the packet as it is sent over the H4 transport
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 1);
+
+        bytes.push(self.indicator.val());
+        bytes.extend_from_slice(&self.data);
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reader that returns at most one byte per read
+    struct ByteReader<'a>(&'a [u8]);
+
+    impl Read for ByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((byte, rest)) if !buf.is_empty() => {
+                    buf[0] = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                },
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn read_packets_test() {
+        let stream = [
+            0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00, // Command Complete for Reset
+            0xFF, // not a packet indicator
+            0x02, 0x40, 0x20, 0x03, 0x00, 0x01, 0x02, 0x03, // ACL data
+        ];
+
+        let mut reader = ByteReader(&stream);
+
+        assert_eq!(
+            Some(Packet::new(PacketIndicator::Event, vec![0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00])),
+            Packet::read_from(&mut reader).unwrap()
+        );
+
+        assert_eq!(
+            Some(Packet::new(PacketIndicator::AclData, vec![0x40, 0x20, 0x03, 0x00, 0x01, 0x02, 0x03])),
+            Packet::read_from(&mut reader).unwrap()
+        );
+
+        assert_eq!(None, Packet::read_from(&mut reader).unwrap());
+
+        // The stream ends in the middle of a packet
+        assert!(Packet::read_from(&mut ByteReader(&[0x04, 0x0E, 0x04, 0x01])).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+//! Timeouts for waiting on events
+//!
+//! A single thread is used to call the callbacks of every timeout of an interface. The thread
+//! sleeps until the next timeout or until a new timeout is added.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct TimerState {
+    timeouts: Vec<(Instant, Callback)>,
+    exit: bool,
+}
+
+pub struct Timer {
+    shared: Arc<(Mutex<TimerState>, Condvar)>,
+}
+
+impl Timer {
+
+    /// Create a new timer
+    ///
+    /// This spawns the thread for the timer, the thread exits when the timer is dropped.
+    pub fn new() -> Self {
+        let shared = Arc::new((Mutex::new(TimerState::default()), Condvar::new()));
+
+        let thread_shared = shared.clone();
+
+        thread::spawn(move || Self::task(thread_shared));
+
+        Timer { shared }
+    }
+
+    /// Add a timeout
+    ///
+    /// The `callback` is called (from the thread of the timer) once `deadline` is reached.
+    pub fn add<F>(&self, deadline: Instant, callback: F) where F: FnOnce() + Send + 'static {
+        let (state, condvar) = &*self.shared;
+
+        state.lock().expect("Timer lock poisoned").timeouts.push((deadline, Box::new(callback)));
+
+        condvar.notify_one();
+    }
+
+    fn task(shared: Arc<(Mutex<TimerState>, Condvar)>) {
+        let (state, condvar) = &*shared;
+
+        let mut guard = state.lock().expect("Timer lock poisoned");
+
+        while !guard.exit {
+            let now = Instant::now();
+
+            let (expired, pending) = guard.timeouts.drain(..).partition(|(deadline, _)| *deadline <= now);
+
+            guard.timeouts = pending;
+
+            if !expired.is_empty() {
+                // The lock is released so that callbacks can add new timeouts
+                drop(guard);
+
+                expired.into_iter().for_each(|(_, callback): (Instant, Callback)| callback());
+
+                guard = state.lock().expect("Timer lock poisoned");
+            } else {
+                guard = match guard.timeouts.iter().map(|(deadline, _)| *deadline).min() {
+                    Some(next) => condvar.wait_timeout(guard, next - now).expect("Timer lock poisoned").0,
+                    None => condvar.wait(guard).expect("Timer lock poisoned"),
+                };
+            }
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let (state, condvar) = &*self.shared;
+
+        if let Ok(mut guard) = state.lock() {
+            guard.exit = true;
+        }
+
+        condvar.notify_one();
+    }
+}
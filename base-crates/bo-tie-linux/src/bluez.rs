@@ -17,12 +17,25 @@ pub const BTPROTO_HCI: i32 = 1;
 
 pub const HCI_MAX_DEV: usize = 16;
 
+/// The device index used when a socket is not bound to a device
+pub const HCI_DEV_NONE: u16 = 0xFFFF;
+
 // pub const HCI_CHANNEL_RAW: i32 = 0; // A raw channel works with the linux hci implementation
 pub const HCI_CHANNEL_USER: i32 = 1; // User channel gives total control, but requires hci
+pub const HCI_CHANNEL_MONITOR: i32 = 2; // Monitor channel receives a copy of all HCI traffic
+
+// Bit positions of the device flags (`hci_dev_info::flags` and `hci_dev_req::dev_opt`)
+pub const HCI_UP: u32 = 0;
+
+// Opcodes of the monitor channel messages
+pub const HCI_MON_CTRL_OPEN: u16 = 14;
+pub const HCI_MON_CTRL_CLOSE: u16 = 15;
+
+/// Size of the header of a monitor channel message
+pub const HCI_MON_HDR_SIZE: usize = 6;
 
 #[link(name = "bluetooth")]
 extern "C" {
-    pub fn hci_get_route(bt_dev_addr: *mut bo_tie::BluetoothDeviceAddress) -> i32;
     pub fn hci_send_cmd(dev: i32, ogf: u16, ocf: u16, parameter_len: u8, parameter: *mut c_void) -> i32;
 }
 
@@ -45,14 +58,14 @@ pub struct sockaddr_hci {
 #[repr(C)]
 #[derive(Default)]
 pub struct hci_dev_req {
-    dev_id: u16,
-    dev_opt: u32,
+    pub dev_id: u16,
+    pub dev_opt: u32,
 }
 
 #[repr(C)]
 pub struct hci_dev_list_req {
-    dev_num: u16,
-    dev_req: [hci_dev_req; HCI_MAX_DEV],
+    pub dev_num: u16,
+    pub dev_req: [hci_dev_req; HCI_MAX_DEV],
 }
 
 impl Default for hci_dev_list_req {
@@ -64,13 +77,61 @@ impl Default for hci_dev_list_req {
     }
 }
 
+#[repr(C)]
+#[derive(Default)]
+pub struct hci_dev_stats {
+    pub err_rx: u32,
+    pub err_tx: u32,
+    pub cmd_tx: u32,
+    pub evt_rx: u32,
+    pub acl_tx: u32,
+    pub acl_rx: u32,
+    pub sco_tx: u32,
+    pub sco_rx: u32,
+    pub byte_rx: u32,
+    pub byte_tx: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct hci_dev_info {
+    pub dev_id: u16,
+    pub name: [u8; 8],
+    pub bdaddr: bo_tie::BluetoothDeviceAddress,
+    pub flags: u32,
+    /// The bus is the lower nibble, the device type is the upper nibble
+    pub type_: u8,
+    pub features: [u8; 8],
+    pub pkt_type: u32,
+    pub link_policy: u32,
+    pub link_mode: u32,
+    pub acl_mtu: u16,
+    pub acl_pkts: u16,
+    pub sco_mtu: u16,
+    pub sco_pkts: u16,
+    pub stat: hci_dev_stats,
+}
+
 // ioclt workarounds
 const HCI_IOC_MAGIC:u8 = b'H';
 
 const HCI_IOC_HCIDEVUP: u8 = 201;
 const HCI_IOC_HCIDEVDOWN: u8 = 202;
 const HCI_IOC_HCIGETDEVLIST: u8 = 210;
+const HCI_IOC_HCIGETDEVINFO: u8 = 211;
 
 nix::ioctl_write_int!(hci_dev_up, HCI_IOC_MAGIC, HCI_IOC_HCIDEVUP);
 nix::ioctl_write_int!(hci_dev_down, HCI_IOC_MAGIC, HCI_IOC_HCIDEVDOWN);
-nix::ioctl_read!(hci_get_dev_list, HCI_IOC_MAGIC, HCI_IOC_HCIGETDEVLIST, hci_dev_list_req);
+
+// The request codes of these are defined by bluez with the size of an `int`, not the size of the
+// structure that is read.
+nix::ioctl_read_bad!(
+    hci_get_dev_list,
+    nix::request_code_read!(HCI_IOC_MAGIC, HCI_IOC_HCIGETDEVLIST, std::mem::size_of::<i32>()),
+    hci_dev_list_req
+);
+nix::ioctl_read_bad!(
+    hci_get_dev_info,
+    nix::request_code_read!(HCI_IOC_MAGIC, HCI_IOC_HCIGETDEVINFO, std::mem::size_of::<i32>()),
+    hci_dev_info
+);
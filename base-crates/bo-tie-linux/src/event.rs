@@ -74,6 +74,8 @@ struct ExpEventInfo {
 #[derive(Debug)]
 pub struct EventExpecter {
     expected: BTreeMap<events::Events, BTreeMap<DynEventMatcher, ExpEventInfo>>,
+    /// No more events will be received from the adapter
+    closed: bool,
 }
 
 impl EventExpecter {
@@ -131,7 +133,11 @@ impl EventExpecter {
 
         let mut gaurd = mutex.lock().expect("Couldn't acquire lock");
 
-        match gaurd.expected.get(&event).and_then(|map| map.get(&pat_key) )
+        if gaurd.closed && gaurd.expected.get(&event).and_then(|map| map.get(&pat_key) ).is_none() {
+            return Some(Err(crate::Error::AdapterClosed))
+        }
+
+        match gaurd.expected.get_mut(&event).and_then(|map| map.get_mut(&pat_key) )
         {
             None => {
                 log::debug!("Seting up expectation for event {:?}", event);
@@ -176,7 +182,7 @@ impl EventExpecter {
 
                 None
             }
-            Some(ref val) => {
+            Some(val) => {
                 log::debug!("Retreiving data for event {:?}", event);
 
                 if val.waker_token.triggered() {
@@ -192,6 +198,10 @@ impl EventExpecter {
                     expected.data
 
                 } else {
+                    // The expectation may be polled by a different task than the one that set it
+                    // up (such as the response to a dropped command)
+                    val.waker_token.waker = Some(waker.clone());
+
                     None
                 }
             }
@@ -234,6 +244,27 @@ impl EventProcessor {
             Err(e) => log::error!("HCI Event Error: {}", e),
         }
     }
+
+    /// Close the processor
+    ///
+    /// This is called when no more events will be received from the adapter. Every expected event
+    /// that has not been received is completed with the error `AdapterClosed`, and so are any
+    /// events expected after this is called.
+    pub fn close(&mut self) {
+        let mut gaurd = self.expected_events.lock().expect("Couldn't acquire mutex");
+
+        gaurd.closed = true;
+
+        for exp_event_info in gaurd.expected.values_mut().flat_map(|map| map.values_mut()) {
+            if exp_event_info.data.is_none() {
+                exp_event_info.data = Some(Err(crate::Error::AdapterClosed));
+
+                exp_event_info.stop_timer = None;
+
+                exp_event_info.waker_token.trigger();
+            }
+        }
+    }
 }
 
 pub struct EventSetup;
@@ -244,6 +275,7 @@ impl EventSetup {
 
         let expecter = Arc::new(Mutex::new(EventExpecter {
             expected: BTreeMap::new(),
+            closed: false,
         }));
 
         let processor = EventProcessor {
@@ -4,6 +4,7 @@ use bo_tie::hci::{
     events,
     common::ConnectionHandle,
     HciAclData,
+    HciSyncData,
 };
 use std::collections::HashMap;
 use std::default;
@@ -12,8 +13,8 @@ use std::fmt;
 use std::ops::Drop;
 use std::option::Option;
 use std::pin::Pin;
-use std::ptr;
 use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::task;
 use std::thread;
 use std::time::Duration;
@@ -30,6 +31,7 @@ macro_rules! log_error_and_panic {
 }
 
 mod bluez;
+pub mod monitor;
 mod timeout;
 
 #[derive(Debug,PartialEq,Eq,Clone)]
@@ -60,6 +62,33 @@ impl ArcFileDesc {
 
 mod event;
 
+/// Opcode of the *Reset* command
+const RESET_OPCODE: u16 = 0x0C03;
+
+/// Opcode of the *Set Controller To Host Flow Control* command
+const SET_CONTROLLER_TO_HOST_FLOW_CONTROL_OPCODE: u16 = 0x0C31;
+
+/// Send a command packet to the controller
+///
+/// The `packet` is a command packet as created by `as_command_packet` of `CommandParameter`.
+fn send_command_packet(adapter_fd: &ArcFileDesc, packet: &[u8]) -> Result<(), Error> {
+    use nix::errno::Errno;
+
+    let opcode = <u16>::from_le_bytes([packet[0], packet[1]]);
+
+    let parameter = &packet[3..];
+
+    Errno::result( unsafe { bluez::hci_send_cmd(
+        adapter_fd.raw_fd(),
+        opcode >> 10,
+        opcode & 0x3FF,
+        parameter.len() as u8,
+        parameter.as_ptr() as *mut ::std::os::raw::c_void
+    )})
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
 /// For Epoll, a value is assigned to signify what file descriptor had an event occur.
 /// * 0 -> BluetoothController,
 /// * 1 -> TaskExit,
@@ -105,6 +134,16 @@ pub enum Error {
     IOError(nix::Error),
     MPSCError(String),
     Timeout,
+    /// There is no Bluetooth adapter available
+    NoAdapter,
+    /// There is no Bluetooth adapter with the identifier
+    AdapterNotFound(u16),
+    /// The user channel of the Bluetooth adapter with the identifier is used by another socket
+    AdapterBusy(u16),
+    /// The adapter was closed because of an error, no more events will be received from it
+    AdapterClosed,
+    /// A message of the monitor channel could not be decoded
+    InvalidMonitorFrame(String),
     Other(String),
 }
 
@@ -123,6 +162,16 @@ impl fmt::Display for Error  {
 
             Error::Timeout => write!(f, "Timeout Occurred"),
 
+            Error::NoAdapter => write!(f, "No Bluetooth adapter is available"),
+
+            Error::AdapterNotFound(id) => write!(f, "No Bluetooth adapter with id {} exists", id),
+
+            Error::AdapterBusy(id) => write!(f, "The user channel of Bluetooth adapter {} is in use", id),
+
+            Error::AdapterClosed => write!(f, "The Bluetooth adapter was closed"),
+
+            Error::InvalidMonitorFrame(ref reason) => write!(f, "Invalid monitor frame, {}", reason),
+
             Error::Other( ref msg) => write!(f, "{}", msg),
         }
     }
@@ -135,6 +184,11 @@ impl error::Error for Error  {
             Error::IOError(ref errno) => errno.source().clone(),
             Error::MPSCError(_) => None,
             Error::Timeout => None,
+            Error::NoAdapter => None,
+            Error::AdapterNotFound(_) => None,
+            Error::AdapterBusy(_) => None,
+            Error::AdapterClosed => None,
+            Error::InvalidMonitorFrame(_) => None,
             Error::Other(_) => None,
         }
     }
@@ -152,9 +206,25 @@ impl From<nix::errno::Errno> for Error {
     }
 }
 
+impl Error {
+
+    /// Create an error from a failed operation on the adapter with the identifier `adapter_id`
+    fn from_adapter_error(adapter_id: u16, e: nix::Error) -> Self {
+        use nix::errno::Errno;
+
+        match e {
+            nix::Error::Sys(Errno::ENODEV) => Error::AdapterNotFound(adapter_id),
+            nix::Error::Sys(Errno::EBUSY) |
+            nix::Error::Sys(Errno::EUSERS) => Error::AdapterBusy(adapter_id),
+            e => Error::IOError(e),
+        }
+    }
+}
+
 /// Controller Message type
 ///
 /// The way to differentiate between messages over the HCI
+#[derive(Clone, Copy)]
 enum CtrlMsgType {
     Command,
     Event,
@@ -188,6 +258,35 @@ impl From<CtrlMsgType> for u8 {
 }
 
 
+impl From<CtrlMsgType> for bo_tie::hci::HciPacketType {
+    fn from(msg: CtrlMsgType) -> Self {
+        match msg {
+            CtrlMsgType::Command => bo_tie::hci::HciPacketType::Command,
+            CtrlMsgType::Event => bo_tie::hci::HciPacketType::Event,
+            CtrlMsgType::ACLData => bo_tie::hci::HciPacketType::AclData,
+            CtrlMsgType::SyncData => bo_tie::hci::HciPacketType::SyncData,
+        }
+    }
+}
+
+/// The listener for the packets received from the controller
+#[derive(Clone, Default)]
+struct PacketListener(Arc<Mutex<Option<Arc<dyn bo_tie::hci::ReceivedPacketListener>>>>);
+
+impl PacketListener {
+    fn notify(&self, msg: CtrlMsgType, packet: &[u8]) {
+        let listener = self.0.lock().expect("Couldn't acquire lock").clone();
+
+        if let Some(listener) = listener { listener.on_packet(msg.into(), packet) }
+    }
+}
+
+impl fmt::Debug for PacketListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PacketListener")
+    }
+}
+
 struct AdapterThread {
     adapter_fd: ArcFileDesc,
     exit_fd: ArcFileDesc,
@@ -195,6 +294,8 @@ struct AdapterThread {
     event_processor: event::EventProcessor,
     timeout_manager: Arc<Mutex<timeout::TimeoutManager>>,
     hci_data_recv: RcvHciAclData,
+    hci_sync_recv: RcvHciSyncData,
+    packet_listener: PacketListener,
 }
 
 impl AdapterThread {
@@ -256,7 +357,10 @@ impl AdapterThread {
 
             }) {
                 Ok(size) => size,
-                Err(e) => panic!("Epoll Error: {}", Error::from(e)),
+                Err(e) => {
+                    log::error!("Epoll Error: {}", e);
+                    break 'task;
+                },
             };
 
             for epoll_event in epoll_events[..event_count].iter() {
@@ -268,39 +372,14 @@ impl AdapterThread {
                             read( self.adapter_fd.raw_fd(), &mut buffer).map_err( |e| { Error::from(e) })
                         }) {
                             Ok(val) => val,
-                            Err(e)  => panic!("Cannot read from Bluetooth Controller file descriptor: {}", Error::from(e)),
+                            Err(e)  => {
+                                // This is also how the removal of the adapter is seen
+                                log::error!("Cannot read from Bluetooth Controller file descriptor: {}", e);
+                                break 'task;
+                            },
                         };
 
-                        // The first byte is the indicator of the mssage type, next byte is the length of the
-                        // message, the rest is the hci message
-                        //
-                        // Any other values are logged (debug level) and then ignored (including
-                        // the sometimes manufacture specific 0xFF value)
-                        if let Ok(msg) = core::convert::TryInto::try_into(buffer[0])
-                        {
-                            match msg {
-                                CtrlMsgType::Command => {
-                                    panic!("Received a command message, the HCI adapter task should \
-                                        only receive ACL, Syncronous, or Event Data from a controller")
-                                },
-                                CtrlMsgType::Event => {
-                                    log::trace!("Processing received HCI data, type:'Event'");
-                                    self.event_processor.process(&buffer[1..len])
-                                },
-                                CtrlMsgType::ACLData => {
-                                    log::trace!("Processing received HCI data, type:'ACL DATA'");
-                                    match HciAclData::from_packet(&buffer[1..len]) {
-                                        Ok(hci_acl_data) => self.hci_data_recv.add_received(hci_acl_data),
-                                        Err(e) => log::error!("Failed to process hci acl packet: {}", e),
-                                    }
-                                },
-                                CtrlMsgType::SyncData => { log::error!("SCO data unimplemented")},
-                            }
-
-                            std::thread::yield_now();
-                        } else {
-                            log::warn!("Received unknown packet indicator type '{:#x}", buffer[0])
-                        }
+                        self.process_packet(&buffer[..len]);
                     },
 
                     EPollResult::TaskExit => {
@@ -317,6 +396,179 @@ impl AdapterThread {
                 }
             }
         }
+
+        self.event_processor.close();
+    }
+
+    /// Process a packet read from the adapter
+    ///
+    /// The first byte of `packet` is the packet indicator, the rest of it is the HCI packet.
+    /// Events are given to the event processor and data is added to the received data of its
+    /// kind.
+    fn process_packet(&mut self, packet: &[u8]) {
+        // The first byte is the indicator of the mssage type, next byte is the length of the
+        // message, the rest is the hci message
+        //
+        // Any other values are logged (debug level) and then ignored (including
+        // the sometimes manufacture specific 0xFF value)
+        let (indicator, hci_packet) = match packet.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+
+        if let Ok(msg) = core::convert::TryInto::try_into(*indicator)
+        {
+            self.packet_listener.notify(msg, hci_packet);
+
+            match msg {
+                CtrlMsgType::Command => {
+                    log::error!("Received a command message, the HCI adapter task should \
+                        only receive ACL, Syncronous, or Event Data from a controller")
+                },
+                CtrlMsgType::Event => {
+                    log::trace!("Processing received HCI data, type:'Event'");
+                    self.event_processor.process(hci_packet)
+                },
+                CtrlMsgType::ACLData => {
+                    log::trace!("Processing received HCI data, type:'ACL DATA'");
+                    match HciAclData::from_packet(hci_packet) {
+                        Ok(hci_acl_data) => self.hci_data_recv.add_received(hci_acl_data),
+                        Err(e) => log::error!("Failed to process hci acl packet: {}", e),
+                    }
+                },
+                CtrlMsgType::SyncData => {
+                    log::trace!("Processing received HCI data, type:'SYNC DATA'");
+                    match HciSyncData::from_packet(hci_packet) {
+                        Ok(hci_sync_data) => self.hci_sync_recv.add_received(hci_sync_data),
+                        Err(e) => log::error!("Failed to process hci synchronous packet: {}", e),
+                    }
+                },
+            }
+
+            std::thread::yield_now();
+        } else {
+            log::warn!("Received unknown packet indicator type '{:#x}", indicator)
+        }
+    }
+}
+
+/// The bus of a Bluetooth adapter
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Bus {
+    Virtual,
+    Usb,
+    PcCard,
+    Uart,
+    Rs232,
+    Pci,
+    Sdio,
+    Spi,
+    I2c,
+    Smd,
+    Virtio,
+    Unknown(u8),
+}
+
+impl From<u8> for Bus {
+    fn from(raw: u8) -> Self {
+        match raw {
+            0 => Bus::Virtual,
+            1 => Bus::Usb,
+            2 => Bus::PcCard,
+            3 => Bus::Uart,
+            4 => Bus::Rs232,
+            5 => Bus::Pci,
+            6 => Bus::Sdio,
+            7 => Bus::Spi,
+            8 => Bus::I2c,
+            9 => Bus::Smd,
+            10 => Bus::Virtio,
+            _ => Bus::Unknown(raw),
+        }
+    }
+}
+
+/// Information on a Bluetooth adapter of the system
+///
+/// This is returned by [`HCIAdapter::list`](HCIAdapter::list).
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct AdapterInfo {
+    /// The identifier of the adapter, this is the number *N* of the name `hciN`
+    pub id: u16,
+    /// The name of the adapter
+    pub name: String,
+    /// The address of the adapter
+    ///
+    /// This is the address last read by the kernel, it is all zeros if the kernel has never
+    /// initialized the adapter.
+    pub address: bo_tie::BluetoothDeviceAddress,
+    pub bus: Bus,
+    /// The adapter is up
+    pub is_up: bool,
+    /// A socket is bound to the user channel of the adapter
+    ///
+    /// An adapter cannot be opened while its user channel is in use. This is found from the
+    /// sockets opened to the Bluetooth subsystem of the kernel, which requires the capability
+    /// `CAP_NET_RAW`. Without this capability only the adapters opened by this process are known
+    /// to be in use.
+    pub user_channel_in_use: bool,
+}
+
+/// The identifiers of the adapters opened by this process
+static OPENED_ADAPTERS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+
+/// An adapter opened by this process
+///
+/// The identifier of the adapter is in `OPENED_ADAPTERS` until this is dropped.
+#[derive(Debug)]
+struct OpenedAdapter(u16);
+
+impl OpenedAdapter {
+
+    /// Claim the adapter with the identifier `adapter_id`
+    ///
+    /// The error [`AdapterBusy`](Error::AdapterBusy) is returned if the adapter is already opened
+    /// by this process.
+    fn claim(adapter_id: u16) -> Result<Self, Error> {
+        let mut opened = lock!(OPENED_ADAPTERS);
+
+        if opened.contains(&adapter_id) {
+            Err(Error::AdapterBusy(adapter_id))
+        } else {
+            opened.push(adapter_id);
+
+            Ok(OpenedAdapter(adapter_id))
+        }
+    }
+}
+
+impl Drop for OpenedAdapter {
+    fn drop(&mut self) {
+        if let Ok(mut opened) = OPENED_ADAPTERS.lock() {
+            opened.retain(|id| *id != self.0);
+        }
+    }
+}
+
+/// The exit of the adapter task
+///
+/// The task is signaled to exit when this is dropped, which is when the last clone of a
+/// `HCIAdapter` is dropped.
+#[derive(Debug)]
+struct AdapterExit {
+    adapter_id: u16,
+    exit_fd: ArcFileDesc,
+    _opened: OpenedAdapter,
+}
+
+impl Drop for AdapterExit {
+
+    fn drop(&mut self) {
+        // Send the exit signal.
+        // The value sent doesn't really matter (just that it is 8 bytes, not 0, and not !0 )
+        if let Err(e) = nix::unistd::write( self.exit_fd.raw_fd(), &[1u8;8]) {
+            log::error!("Failed to signal the task of adapter {} to exit: {}", self.adapter_id, e);
+        }
     }
 }
 
@@ -327,26 +579,111 @@ impl AdapterThread {
 /// Bluetooth specification.
 ///
 /// Each Bluetooth adapter (if there is any) is assigned an identifier (just a number) by your
-/// system.
+/// system. The adapters of the system are listed by [`list`](HCIAdapter::list), and an adapter is
+/// opened with [`open`](HCIAdapter::open) or [`open_first`](HCIAdapter::open_first). Any number
+/// of adapters can be opened at the same time, but an adapter cannot be opened again until every
+/// clone of the `HCIAdapter` it was opened as is dropped.
+///
+/// An adapter is opened through the user channel of the Linux Bluetooth subsystem. This takes the
+/// adapter away from the kernel (and BlueZ) until the adapter is dropped, and it requires the
+/// capability `CAP_NET_ADMIN`.
+///
+/// If the adapter is removed from the system (or otherwise fails) while it is open, every event
+/// waited on by the host fails with the error [`AdapterClosed`](Error::AdapterClosed) and sending
+/// to the adapter returns an error.
+///
+/// # Controller To Host Flow Control
+/// Received ACL and synchronous data is buffered by the adapter until it is read by the host. When
+/// flow control of either kind of data is turned on with the command
+/// [`set_controller_to_host_flow_control`](bo_tie::hci::cb::set_controller_to_host_flow_control),
+/// the adapter reports the data packets read by the host (or dropped by the adapter) to the
+/// controller with the *Host Number Of Completed Packets* command. Flow control is considered to
+/// be off again after the controller is reset. The buffer size of the host must still be given to
+/// the controller with [`host_buffer_size`](bo_tie::hci::cb::host_buffer_size).
 #[derive(Clone,Debug)]
 pub struct HCIAdapter {
     adapter_fd: ArcFileDesc,
-    exit_fd: ArcFileDesc,
+    exit: Arc<AdapterExit>,
     epoll_fd: ArcFileDesc,
     event_expecter: Arc<Mutex<event::EventExpecter>>,
     timeout_manager: Arc<Mutex<timeout::TimeoutManager>>,
     hci_data_recv: RcvHciAclData,
+    hci_sync_recv: RcvHciSyncData,
+    packet_listener: PacketListener,
 }
 
-impl From<i32> for HCIAdapter {
+impl HCIAdapter {
 
-    /// Create a HCIAdapter with the given bluetooth adapter id if an adapter exists
+    /// List the Bluetooth adapters of the system
+    pub fn list() -> Result<Vec<AdapterInfo>, Error> {
+        use nix::errno::Errno;
+        use nix::libc;
+
+        let ctl_fd = unsafe { libc::socket(libc::AF_BLUETOOTH, libc::SOCK_RAW | libc::SOCK_CLOEXEC, bluez::BTPROTO_HCI) };
+
+        if ctl_fd < 0 {
+            // Without Bluetooth support in the kernel there cannot be any adapters
+            return match Errno::last() {
+                Errno::EAFNOSUPPORT => Ok(Vec::new()),
+                errno => Err(errno.into()),
+            }
+        }
+
+        let ctl_fd = ArcFileDesc::from(ctl_fd);
+
+        let mut dev_list = bluez::hci_dev_list_req::default();
+
+        unsafe { bluez::hci_get_dev_list(ctl_fd.raw_fd(), &mut dev_list) }?;
+
+        let in_use = match monitor::user_channel_adapters() {
+            Ok(in_use) => in_use,
+            Err(e) => {
+                log::debug!("Cannot get the user channels from the monitor channel: {}", e);
+
+                lock!(OPENED_ADAPTERS).clone()
+            }
+        };
+
+        let dev_count = std::cmp::min(dev_list.dev_num as usize, bluez::HCI_MAX_DEV);
+
+        let mut adapters = Vec::with_capacity(dev_count);
+
+        for dev_req in dev_list.dev_req[..dev_count].iter() {
+            let mut dev_info = bluez::hci_dev_info { dev_id: dev_req.dev_id, .. Default::default() };
+
+            match unsafe { bluez::hci_get_dev_info(ctl_fd.raw_fd(), &mut dev_info) } {
+                Ok(_) => (),
+                // The adapter was removed after the list was read
+                Err(nix::Error::Sys(Errno::ENODEV)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            let name_len = dev_info.name.iter().position(|c| *c == 0).unwrap_or(dev_info.name.len());
+
+            adapters.push(AdapterInfo {
+                id: dev_info.dev_id,
+                name: String::from_utf8_lossy(&dev_info.name[..name_len]).into_owned(),
+                address: dev_info.bdaddr,
+                bus: Bus::from(dev_info.type_ & 0xF),
+                is_up: dev_info.flags & (1 << bluez::HCI_UP) != 0,
+                user_channel_in_use: in_use.contains(&dev_info.dev_id),
+            });
+        }
+
+        Ok(adapters)
+    }
+
+    /// Open the Bluetooth adapter with the identifier `adapter_id`
     ///
-    /// Call "default" if the device id is unknown or any adapter is acceptable
+    /// The identifier is the number *N* of the adapter name `hciN`.
     ///
-    /// # Panics
-    /// There is no Bluetooth Adapter with the given device id
-    fn from( adapter_id: i32 ) -> Self {
+    /// # Errors
+    /// * [`AdapterNotFound`](Error::AdapterNotFound) if there is no adapter with the identifier
+    /// * [`AdapterBusy`](Error::AdapterBusy) if the user channel of the adapter is in use or the
+    ///   adapter is already opened by this process
+    /// * [`IOError`](Error::IOError) for any other failure of the system, such as not having the
+    ///   permission to open the adapter
+    pub fn open(adapter_id: u16) -> Result<Self, Error> {
 
         use nix::sys::eventfd::{EfdFlags, eventfd};
         use nix::libc;
@@ -359,67 +696,66 @@ impl From<i32> for HCIAdapter {
             EpollFlags,
         };
 
-        use std::convert::TryInto;
-
-        if adapter_id < 0 { panic!("Invalid adapter id, cannot be a negative number") }
+        // The adapter must not be taken down while it is opened by this process
+        let opened = OpenedAdapter::claim(adapter_id)?;
 
         let device_fd = unsafe{ libc::socket(libc::AF_BLUETOOTH, libc::SOCK_RAW | libc::SOCK_CLOEXEC, bluez::BTPROTO_HCI) };
 
         if device_fd < 0 {
-            panic!("No Bluetooth Adapter with device id {} exists", adapter_id);
+            return match nix::errno::Errno::last() {
+                nix::errno::Errno::EAFNOSUPPORT => Err(Error::AdapterNotFound(adapter_id)),
+                errno => Err(errno.into()),
+            }
         }
 
+        let arc_adapter_fd = ArcFileDesc::from(device_fd);
+
         let sa_p = &bluez::sockaddr_hci {
             hci_family: libc::AF_BLUETOOTH as u16,
-            hci_dev: adapter_id as u16,
+            hci_dev: adapter_id,
             hci_channel: bluez::HCI_CHANNEL_USER as u16,
         } as *const bluez::sockaddr_hci as *const libc::sockaddr;
 
         let sa_len = std::mem::size_of::<bluez::sockaddr_hci>() as libc::socklen_t;
 
-        if let Err(e) = unsafe{ bluez::hci_dev_down(device_fd, adapter_id.try_into().unwrap() ) } {
-            panic!("Failed to close hci device '{}', {}", adapter_id, e );
-        }
+        let dev_id = adapter_id.into();
 
-        if let Err(e) = unsafe{ bluez::hci_dev_up(device_fd, adapter_id.try_into().unwrap() ) } {
-            panic!("Failed to open hci device '{}', {}", adapter_id, e );
-        }
-
-        if let Err(e) = unsafe{ bluez::hci_dev_down(device_fd, adapter_id.try_into().unwrap() ) } {
-            panic!("Failed to close hci device '{}', {}", adapter_id, e );
-        }
+        unsafe{ bluez::hci_dev_down(device_fd, dev_id) }
+            .and_then(|_| unsafe{ bluez::hci_dev_up(device_fd, dev_id) })
+            .and_then(|_| unsafe{ bluez::hci_dev_down(device_fd, dev_id) })
+            .map_err(|e| Error::from_adapter_error(adapter_id, e))?;
 
         if unsafe{ libc::bind(device_fd, sa_p, sa_len) } < 0 {
-            panic!("Failed to bind to HCI: {}", nix::errno::Errno::last() );
+            return Err(Error::from_adapter_error(adapter_id, nix::Error::last()));
         }
 
-        let exit_evt_fd = eventfd(0, EfdFlags::EFD_CLOEXEC).expect("eventfd failed");
+        let arc_exit_fd = ArcFileDesc::from(eventfd(0, EfdFlags::EFD_CLOEXEC)?);
 
-        let epoll_fd = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).expect("epoll_create1 failed");
+        let arc_epoll_fd = ArcFileDesc::from(epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?);
 
         epoll_ctl(
-            epoll_fd,
+            arc_epoll_fd.raw_fd(),
             EpollOp::EpollCtlAdd,
             device_fd,
             &mut EpollEvent::new(EpollFlags::EPOLLIN, EPollResult::BluetoothController.into())
-        ).expect("epoll_ctl failed");
+        )?;
 
         epoll_ctl(
-            epoll_fd,
+            arc_epoll_fd.raw_fd(),
             EpollOp::EpollCtlAdd,
-            exit_evt_fd,
+            arc_exit_fd.raw_fd(),
             &mut EpollEvent::new(EpollFlags::EPOLLIN, EPollResult::TaskExit.into())
-        ).expect("epoll_ctl failed");
-
-        let arc_adapter_fd = ArcFileDesc::from(device_fd);
-        let arc_exit_fd = ArcFileDesc::from(exit_evt_fd);
-        let arc_epoll_fd = ArcFileDesc::from(epoll_fd);
+        )?;
 
         let (event_expecter, event_processor) = event::EventSetup::setup();
 
         let to_manager = Arc::new(Mutex::new(timeout::TimeoutManager::new()));
 
-        let data_receiver = RcvHciAclData::new();
+        let data_receiver = RcvHciAclData::new(arc_adapter_fd.clone());
+
+        let sync_receiver = RcvHciSyncData::new(arc_adapter_fd.clone());
+
+        let packet_listener = PacketListener::default();
 
         AdapterThread {
             adapter_fd: arc_adapter_fd.clone(),
@@ -428,45 +764,77 @@ impl From<i32> for HCIAdapter {
             event_processor,
             timeout_manager: to_manager.clone(),
             hci_data_recv: data_receiver.clone(),
+            hci_sync_recv: sync_receiver.clone(),
+            packet_listener: packet_listener.clone(),
         }
         .spawn();
 
-        HCIAdapter {
+        Ok(HCIAdapter {
             adapter_fd: arc_adapter_fd,
-            exit_fd: arc_exit_fd,
+            exit: Arc::new(AdapterExit { adapter_id, exit_fd: arc_exit_fd, _opened: opened }),
             epoll_fd: arc_epoll_fd,
             event_expecter,
             timeout_manager: to_manager,
             hci_data_recv: data_receiver,
+            hci_sync_recv: sync_receiver,
+            packet_listener,
+        })
+    }
+
+    /// Open the first Bluetooth adapter that is not in use
+    ///
+    /// The adapters are tried in the order returned by [`list`](HCIAdapter::list), skipping the
+    /// adapters whose user channel is in use. The error [`NoAdapter`](Error::NoAdapter) is returned
+    /// if there is no adapter to open.
+    pub fn open_first() -> Result<Self, Error> {
+        for info in Self::list()?.into_iter().filter(|info| !info.user_channel_in_use) {
+            match Self::open(info.id) {
+                Ok(adapter) => return Ok(adapter),
+                Err(Error::AdapterNotFound(_)) |
+                Err(Error::AdapterBusy(_)) => continue,
+                Err(e) => return Err(e),
+            }
         }
+
+        Err(Error::NoAdapter)
     }
-}
 
-/// Create a HCIAdapter object with the first bluetooth adapter returned by the system
-///
-/// # Panics
-/// * No bluetooth adapter exists on the system
-/// * The system couldn't allocate another file descriptor for the device
-impl default::Default for HCIAdapter {
+    /// Get the identifier of the adapter
+    pub fn get_id(&self) -> u16 {
+        self.exit.adapter_id
+    }
+}
 
-    fn default() -> Self {
+impl From<i32> for HCIAdapter {
 
-        let adapter_id = unsafe { bluez::hci_get_route(ptr::null_mut()) };
+    /// Create a HCIAdapter with the given bluetooth adapter id if an adapter exists
+    ///
+    /// Call "default" if the device id is unknown or any adapter is acceptable. Use
+    /// [`open`](HCIAdapter::open) to not panic when the adapter cannot be opened.
+    ///
+    /// # Panics
+    /// The adapter cannot be opened, see the errors of [`open`](HCIAdapter::open)
+    fn from( adapter_id: i32 ) -> Self {
+        use std::convert::TryFrom;
 
-        if adapter_id < 0 {
-            panic!("No bluetooth adapter on this system");
-        }
+        let adapter_id = <u16>::try_from(adapter_id)
+            .unwrap_or_else(|_| log_error_and_panic!("Invalid adapter id {}", adapter_id));
 
-        HCIAdapter::from(adapter_id)
+        HCIAdapter::open(adapter_id).unwrap_or_else(|e| log_error_and_panic!("{}", e))
     }
 }
 
-impl Drop for HCIAdapter {
+/// Create a HCIAdapter object with the first bluetooth adapter that is not in use
+///
+/// Use [`open_first`](HCIAdapter::open_first) to not panic when there is no adapter to open.
+///
+/// # Panics
+/// * No bluetooth adapter can be opened
+/// * The system couldn't allocate another file descriptor for the device
+impl default::Default for HCIAdapter {
 
-    fn drop(&mut self) {
-        // Send the exit signal.
-        // The value sent doesn't really matter (just that it is 8 bytes, not 0, and not !0 )
-        nix::unistd::write( self.exit_fd.raw_fd(), &[1u8;8]).unwrap();
+    fn default() -> Self {
+        HCIAdapter::open_first().unwrap_or_else(|e| log_error_and_panic!("{}", e))
     }
 }
 
@@ -479,34 +847,41 @@ impl bo_tie::hci::HostControllerInterface for HCIAdapter {
     ///
     /// If there is no error, this function always returns true, which is why the waker parameter
     /// isn't used. Overflowing the Bluetooth Controller buffer is sort of an accomplishment on linux...
+    ///
+    /// The commands *Set Controller To Host Flow Control* and *Reset* are also used to determine
+    /// if received ACL and synchronous data is flow controlled by the host. See the documentation of
+    /// [`HCIAdapter`](HCIAdapter) for details.
     fn send_command<D,W>(&self, cmd_data: &D, _: W) -> Result<bool, Self::SendCommandError>
     where D: bo_tie::hci::CommandParameter,
           W: Into<Option<std::task::Waker>>
     {
-        use nix::errno::Errno;
-        use std::mem::size_of;
-
         log::debug!("Sending command {:?}", D::COMMAND);
 
-        let oc_pair = D::COMMAND.as_opcode_pair();
-
-        log::trace!("ogf: {}", oc_pair.get_ogf());
-        log::trace!("ocf: {}", oc_pair.get_ocf());
-        log::trace!("parameter size: {}", size_of::<D::Parameter>());
-        log::trace!("parameter: {:x?}", unsafe { std::slice::from_raw_parts(&cmd_data.get_parameter() as *const D::Parameter as *mut u8, size_of::<D::Parameter>()) });
-
-        // send the command
-        if let Err(err) = Errno::result( unsafe { bluez::hci_send_cmd(
-            self.adapter_fd.raw_fd(),
-            oc_pair.get_ogf(),
-            oc_pair.get_ocf(),
-            size_of::<D::Parameter>() as u8,
-            &mut cmd_data.get_parameter() as *mut D::Parameter as *mut ::std::os::raw::c_void
-        )}){
-            Err(Error::from(err))
-        } else {
-            Ok(true)
+        let packet = cmd_data.as_command_packet();
+
+        // The opcode is taken from the packet as it is not within `D::COMMAND` for a raw command
+        let opcode = <u16>::from_le_bytes([packet[0], packet[1]]);
+
+        log::trace!("ogf: {}", opcode >> 10);
+        log::trace!("ocf: {}", opcode & 0x3FF);
+        log::trace!("parameter size: {}", packet[2]);
+        log::trace!("parameter: {:x?}", &packet[3..]);
+
+        send_command_packet(&self.adapter_fd, &packet)?;
+
+        match opcode {
+            RESET_OPCODE => {
+                self.hci_data_recv.set_host_flow_control(false);
+                self.hci_sync_recv.set_host_flow_control(false);
+            },
+            SET_CONTROLLER_TO_HOST_FLOW_CONTROL_OPCODE => {
+                self.hci_data_recv.set_host_flow_control(packet[3] & 0x1 != 0);
+                self.hci_sync_recv.set_host_flow_control(packet[3] & 0x2 != 0);
+            },
+            _ => (),
         }
+
+        Ok(true)
     }
 
     fn receive_event<P>(&self,
@@ -574,9 +949,72 @@ impl bo_tie::hci::HciAclDataInterface for HCIAdapter {
     }
 }
 
+impl bo_tie::hci::HciSyncDataInterface for HCIAdapter {
+
+    type SendSyncDataError = nix::Error;
+    type ReceiveSyncDataError = String;
+
+    fn send_sync(&self, data: HciSyncData) -> Result<usize, Self::SendSyncDataError> {
+        use nix::sys::uio;
+        use nix::sys::socket;
+
+        let packet_indicator = &[ CtrlMsgType::SyncData.into() ];
+
+        let packet_data = &data.get_packet();
+
+        let io_vec = &[uio::IoVec::from_slice(packet_indicator), uio::IoVec::from_slice(packet_data)];
+
+        let flags = socket::MsgFlags::MSG_DONTWAIT;
+
+        socket::sendmsg(self.adapter_fd.raw_fd(), io_vec, &[], flags, None)
+    }
+
+    fn start_sync_receiver(&self, handle: ConnectionHandle) {
+        self.hci_sync_recv.add_connection_handle(handle, StayAround::Unlimited);
+    }
+
+    fn stop_sync_receiver(&self, handle: &ConnectionHandle) {
+        self.hci_sync_recv.remove_connection_handle(handle);
+    }
+
+    fn receive_sync(&self, handle: &ConnectionHandle, waker: &task::Waker)
+    -> Option<Result<Vec<HciSyncData>, Self::ReceiveSyncDataError>>
+    {
+        self.hci_sync_recv.get_received(handle, waker)
+    }
+}
+
+impl bo_tie::hci::ReceivedPacketSource for HCIAdapter {
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn bo_tie::hci::ReceivedPacketListener>>) {
+        *self.packet_listener.0.lock().expect("Couldn't acquire lock") = listener;
+    }
+}
+
+/// Data received from the controller for a connection
+///
+/// This is implemented for the kinds of HCI data that are buffered per connection handle.
+trait ReceivedData: fmt::Debug {
+    /// The name of the kind of data used for logging
+    const KIND: &'static str;
+
+    fn connection_handle(&self) -> ConnectionHandle;
+}
+
+impl ReceivedData for HciAclData {
+    const KIND: &'static str = "ACL data";
+
+    fn connection_handle(&self) -> ConnectionHandle { *self.get_handle() }
+}
+
+impl ReceivedData for HciSyncData {
+    const KIND: &'static str = "synchronous data";
+
+    fn connection_handle(&self) -> ConnectionHandle { *self.get_handle() }
+}
+
 /// Stay around flag for received data
 ///
-/// This is used to determine the state of the received ACL data. There are 3 states
+/// This is used to determine the state of the received data. There are 3 states
 /// - `Unlimited`: Their is an 'unlimited' number of *saved* packets
 /// - `Limited`: A limited number of received packets are saved, after the limit is reached, a
 ///              new packet causes the oldest packet to be dropped. This is a ring buffer, but
@@ -592,16 +1030,16 @@ enum StayAround {
 }
 
 #[derive(Debug)]
-enum PacketBuffer {
+enum PacketBuffer<T> {
     /// A circle buffer for limited storage of packets
     ///
     /// The associated `usize` is the index of the start of the ring.
-    Limited(Vec<HciAclData>, usize),
+    Limited(Vec<T>, usize),
     /// Unlimited storage
-    Unlimited(Vec<HciAclData>),
+    Unlimited(Vec<T>),
 }
 
-impl PacketBuffer {
+impl<T> PacketBuffer<T> {
 
     fn make_unlimited(&mut self) {
         match self {
@@ -626,22 +1064,28 @@ impl PacketBuffer {
         self
     }
 
-    fn add(&mut self, data: HciAclData) {
+    /// Add data
+    ///
+    /// True is returned if the oldest packet of a `Limited` buffer was dropped to make room for
+    /// `data`.
+    fn add(&mut self, data: T) -> bool {
         match self {
-            Self::Unlimited(ref mut v) => v.push(data),
+            Self::Unlimited(ref mut v) => { v.push(data); false },
             Self::Limited(ref mut v, ref mut size) => {
                 if v.capacity() == v.len() {
                     v[*size] = data;
-                   *size = (*size + 1) % v.len()
+                   *size = (*size + 1) % v.len();
+                   true
                 } else {
                     v.push(data);
+                    false
                 }
             }
         }
     }
 
     /// Get the data
-    fn get(&mut self) -> Vec<HciAclData> {
+    fn get(&mut self) -> Vec<T> {
         use std::mem::replace;
 
         match self {
@@ -655,12 +1099,12 @@ impl PacketBuffer {
 }
 
 #[derive(Debug)]
-struct ConnectionRecvInfo {
-    received_packets: PacketBuffer,
+struct ConnectionRecvInfo<T> {
+    received_packets: PacketBuffer<T>,
     waker: Option<task::Waker>,
 }
 
-impl ConnectionRecvInfo {
+impl<T> ConnectionRecvInfo<T> {
 
     const LIMITED_CAPACITY:usize = 100;
 
@@ -668,7 +1112,7 @@ impl ConnectionRecvInfo {
     ///
     /// The `stay` input is used to indicate if this should continue to exist after data is taken
     /// from it. The `stay` flag is used by
-    /// [`RcvHciAclData`](RcvHciAclData).
+    /// [`RcvHciData`](RcvHciData).
     fn new(stay_around: StayAround) -> Self {
         ConnectionRecvInfo {
             received_packets: match stay_around {
@@ -692,7 +1136,7 @@ impl ConnectionRecvInfo {
     /// This either returns all received packets or sets the waker and returns None.
     ///
     /// Regardless of the operation, the packet buffer is upgraded to `Unlimited`
-    fn get_data_or_set_waker(&mut self, waker: &task::Waker ) -> Option<Vec<HciAclData>> {
+    fn get_data_or_set_waker(&mut self, waker: &task::Waker ) -> Option<Vec<T>> {
 
         let recv_packs = match core::mem::replace( 
             &mut self.received_packets, 
@@ -710,11 +1154,15 @@ impl ConnectionRecvInfo {
     }
 
     /// Add data
-    fn add(&mut self, data: HciAclData ) {
+    ///
+    /// True is returned if a previously received packet was dropped
+    fn add(&mut self, data: T ) -> bool {
 
-        self.received_packets.add(data);
+        let dropped = self.received_packets.add(data);
 
         if let Some(w) = self.waker.take() { w.wake(); }
+
+        dropped
     }
 
     fn using_limited_buffer(&self) -> bool {
@@ -722,23 +1170,81 @@ impl ConnectionRecvInfo {
     }
 }
 
-type AclDataChannels = HashMap<ConnectionHandle, ConnectionRecvInfo>;
+type DataChannels<T> = HashMap<ConnectionHandle, ConnectionRecvInfo<T>>;
 
-/// A structure for managing the reception of hci acl data packets for use with futures
-#[derive(Debug,Clone)]
-struct RcvHciAclData {
-    receive_channels: Arc<Mutex<AclDataChannels>>
+/// A structure for managing the reception of hci data packets for use with futures
+///
+/// When controller to host flow control is on for the kind of data, packets are reported to the
+/// controller as completed once they are taken from the buffer by `get_received` (or dropped from
+/// a `Limited` buffer).
+#[derive(Debug)]
+struct RcvHciData<T> {
+    receive_channels: Arc<Mutex<DataChannels<T>>>,
+    host_flow_control: Arc<AtomicBool>,
+    adapter_fd: ArcFileDesc,
 }
 
-impl RcvHciAclData {
+impl<T> Clone for RcvHciData<T> {
+    fn clone(&self) -> Self {
+        RcvHciData {
+            receive_channels: self.receive_channels.clone(),
+            host_flow_control: self.host_flow_control.clone(),
+            adapter_fd: self.adapter_fd.clone(),
+        }
+    }
+}
+
+type RcvHciAclData = RcvHciData<HciAclData>;
+
+type RcvHciSyncData = RcvHciData<HciSyncData>;
+
+impl<T> RcvHciData<T> where T: ReceivedData {
+
+    fn new(adapter_fd: ArcFileDesc) -> Self {
+        RcvHciData {
+            receive_channels: Arc::new( Mutex::new( DataChannels::new() )),
+            host_flow_control: Arc::new( AtomicBool::new(false) ),
+            adapter_fd,
+        }
+    }
 
-    fn new() -> Self {
-        RcvHciAclData { receive_channels: Arc::new( Mutex::new( AclDataChannels::new() )) }
+    /// Set if the controller to host flow control is on for this kind of data
+    fn set_host_flow_control(&self, enabled: bool) {
+        self.host_flow_control.store(enabled, Ordering::Relaxed)
     }
 
-    /// Add a buffer to associated with `handle` for receiving ACL data
+    /// Report completed packets to the controller
     ///
-    /// This will create a buffer that will stay around to collect ACL data packets even when there
+    /// Nothing is sent if `count` is zero or controller to host flow control is off.
+    fn report_completed(&self, handle: ConnectionHandle, count: usize) {
+        use bo_tie::hci::CommandParameter;
+        use bo_tie::hci::cb::host_number_of_completed_packets::{CompletedPackets, Parameter};
+
+        if count == 0 || !self.host_flow_control.load(Ordering::Relaxed) { return }
+
+        // The controller cannot send more packets than the number of buffers of the host, which
+        // is a 16 bit value
+        let completed = CompletedPackets {
+            connection_handle: handle,
+            host_num_completed_packets: std::convert::TryFrom::try_from(count).unwrap_or(<u16>::MAX),
+        };
+
+        let packet = match Parameter::new(&[completed]) {
+            Ok(parameter) => parameter.as_command_packet(),
+            Err(e) => {
+                log::error!("Failed to report completed packets for connection handle {}: {}", handle, e);
+                return
+            }
+        };
+
+        if let Err(e) = send_command_packet(&self.adapter_fd, &packet) {
+            log::error!("Failed to report completed packets for connection handle {}: {}", handle, e);
+        }
+    }
+
+    /// Add a buffer to associated with `handle` for receiving data
+    ///
+    /// This will create a buffer that will stay around to collect data packets even when there
     /// is no waker to wake a pending task to receive the received data.
     ///
     /// Only one buffer is set per handle. Calling this multiple of times doesn't drop the buffer,
@@ -777,13 +1283,13 @@ impl RcvHciAclData {
 
     /// Try to get a received packet
     ///
-    /// If there is a packet to be received for the provided connection handle, then an HciAclData
-    /// packet will be returned. If there are no packets to be received, then no packet is returned
+    /// If there is a packet to be received for the provided connection handle, then the received
+    /// packets will be returned. If there are no packets to be received, then no packet is returned
     /// but the provided waker will be used when a packet is ready to be received. Whoever is woken
     /// will need to call this function again to get the received data. If data is returned, the
     /// provided waker is ignored.
     fn get_received( &self, handle: &ConnectionHandle, waker: &task::Waker )
-    -> Option< Result<Vec<HciAclData>, String> >
+    -> Option< Result<Vec<T>, String> >
     {
         let mut rc_gaurd = match self.receive_channels.lock() {
             Ok(gaurd) => gaurd,
@@ -815,6 +1321,12 @@ impl RcvHciAclData {
         // The handle is associated with a temporary buffer so it should be deleted
         if remove_cri { rc_gaurd.remove(handle); }
 
+        drop(rc_gaurd);
+
+        if let Some(Ok(ref data)) = ret {
+            self.report_completed(*handle, data.len());
+        }
+
         ret
     }
 
@@ -822,26 +1334,34 @@ impl RcvHciAclData {
     ///
     /// This is used by the
     /// [`AdapterThread`](AdapterThread)
-    /// to add ACL Data packets that were received from the controller.
-    fn add_received( &self, packet: HciAclData ) {
-        match self.receive_channels.lock().as_mut()
+    /// to add data packets that were received from the controller.
+    fn add_received( &self, packet: T ) {
+        let handle = packet.connection_handle();
+
+        let dropped = match self.receive_channels.lock().as_mut()
         {
             Ok( rc ) => {
 
-                let handle = *packet.get_handle();
-
                 if let Some(recv_info) = rc.get_mut(&handle) {
                     recv_info.add( packet )
                 } else {
                     let mut recv_info = ConnectionRecvInfo::new(StayAround::Limited);
 
-                    recv_info.add( packet );
+                    let dropped = recv_info.add( packet );
 
                     rc.insert(handle, recv_info);
+
+                    dropped
                 }
             }
             Err(lock_e) =>
                 log_error_and_panic!("Failed to acquire lock: {}", lock_e),
+        };
+
+        if dropped {
+            log::warn!("Dropped the oldest unread {} packet of connection handle {}", T::KIND, handle);
+
+            self.report_completed(handle, 1);
         }
     }
 }
@@ -889,3 +1409,145 @@ impl From<task::Waker> for WakerToken {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use bo_tie::hci::{AclBroadcastFlag, AclPacketBoundary, HciPacketType, SyncPacketStatus};
+
+    struct NoopWaker;
+
+    impl task::Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// A listener that records the type of every received packet
+    #[derive(Default)]
+    struct Listener(Mutex<Vec<HciPacketType>>);
+
+    impl bo_tie::hci::ReceivedPacketListener for Listener {
+        fn on_packet(&self, packet_type: HciPacketType, _: &[u8]) {
+            self.0.lock().unwrap().push(packet_type)
+        }
+    }
+
+    /// A file descriptor that is not used by the test
+    fn unused_fd() -> ArcFileDesc {
+        let (read_fd, write_fd) = nix::unistd::pipe().unwrap();
+
+        nix::unistd::close(write_fd).unwrap();
+
+        ArcFileDesc::from(read_fd)
+    }
+
+    fn adapter_thread() -> AdapterThread {
+        let adapter_fd = unused_fd();
+
+        let (_, event_processor) = event::EventSetup::setup();
+
+        AdapterThread {
+            adapter_fd: adapter_fd.clone(),
+            exit_fd: unused_fd(),
+            epoll_fd: unused_fd(),
+            event_processor,
+            timeout_manager: Arc::new(Mutex::new(timeout::TimeoutManager::new())),
+            hci_data_recv: RcvHciAclData::new(adapter_fd.clone()),
+            hci_sync_recv: RcvHciSyncData::new(adapter_fd),
+            packet_listener: PacketListener::default(),
+        }
+    }
+
+    #[test]
+    fn sync_data_routing_test() {
+        let mut adapter_thread = adapter_thread();
+
+        let listener = Arc::new(Listener::default());
+
+        *adapter_thread.packet_listener.0.lock().unwrap() = Some(listener.clone());
+
+        let handle = ConnectionHandle::try_from(0x40).unwrap();
+
+        let waker = task::Waker::from(Arc::new(NoopWaker));
+
+        let sync_data = HciSyncData::new(handle, SyncPacketStatus::PartiallyLost, vec![1, 2, 3]);
+
+        let mut packet = vec![CtrlMsgType::SyncData.into()];
+
+        packet.extend_from_slice(&sync_data.get_packet());
+
+        adapter_thread.process_packet(&packet);
+
+        let acl_data = HciAclData::new(handle, AclPacketBoundary::FirstNonFlushable, AclBroadcastFlag::NoBroadcast, vec![4, 5]);
+
+        let mut packet = vec![CtrlMsgType::ACLData.into()];
+
+        packet.extend_from_slice(&acl_data.get_packet());
+
+        adapter_thread.process_packet(&packet);
+
+        // A synchronous data packet shorter than its length field is dropped
+        adapter_thread.process_packet(&[CtrlMsgType::SyncData.into(), 0x40, 0x00, 5, 1]);
+
+        let received = adapter_thread.hci_sync_recv.get_received(&handle, &waker).unwrap().unwrap();
+
+        assert_eq!(1, received.len());
+        assert_eq!(handle, *received[0].get_handle());
+        assert_eq!(SyncPacketStatus::PartiallyLost, received[0].get_packet_status_flag());
+        assert_eq!(&[1, 2, 3], received[0].get_payload());
+
+        let received = adapter_thread.hci_data_recv.get_received(&handle, &waker).unwrap().unwrap();
+
+        assert_eq!(1, received.len());
+        assert_eq!(&[4, 5], received[0].get_payload());
+
+        assert_eq!(
+            vec![HciPacketType::SyncData, HciPacketType::AclData, HciPacketType::SyncData],
+            *listener.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn bus_from_test() {
+        assert_eq!(Bus::Virtual, Bus::from(0));
+        assert_eq!(Bus::Usb, Bus::from(1));
+        assert_eq!(Bus::Uart, Bus::from(3));
+        assert_eq!(Bus::Virtio, Bus::from(10));
+        assert_eq!(Bus::Unknown(11), Bus::from(11));
+        assert_eq!(Bus::Unknown(0xFF), Bus::from(0xFF));
+    }
+
+    #[test]
+    fn from_adapter_error_test() {
+        use nix::errno::Errno;
+
+        assert_eq!(Error::AdapterNotFound(3), Error::from_adapter_error(3, nix::Error::Sys(Errno::ENODEV)));
+        assert_eq!(Error::AdapterBusy(3), Error::from_adapter_error(3, nix::Error::Sys(Errno::EBUSY)));
+        assert_eq!(Error::AdapterBusy(3), Error::from_adapter_error(3, nix::Error::Sys(Errno::EUSERS)));
+        assert_eq!(
+            Error::IOError(nix::Error::Sys(Errno::EPERM)),
+            Error::from_adapter_error(3, nix::Error::Sys(Errno::EPERM))
+        );
+    }
+
+    #[test]
+    fn double_open_test() {
+        // An identifier that is not used by any other test
+        let adapter_id = 0xFFF0;
+
+        let opened = OpenedAdapter::claim(adapter_id).unwrap();
+
+        assert_eq!(Error::AdapterBusy(adapter_id), OpenedAdapter::claim(adapter_id).unwrap_err());
+
+        assert!(matches!(HCIAdapter::open(adapter_id), Err(Error::AdapterBusy(id)) if id == adapter_id));
+
+        // Other adapters can still be opened
+        drop(OpenedAdapter::claim(adapter_id + 1).unwrap());
+
+        drop(opened);
+
+        drop(OpenedAdapter::claim(adapter_id).unwrap());
+
+        assert!(!OPENED_ADAPTERS.lock().unwrap().contains(&adapter_id));
+    }
+}
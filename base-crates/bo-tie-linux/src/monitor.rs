@@ -0,0 +1,457 @@
+//! The HCI monitor channel
+//!
+//! The monitor channel of the Linux kernel receives a copy of the HCI traffic of every adapter
+//! along with messages about the adapters and the sockets opened to the Bluetooth subsystem. It is
+//! only an observer, nothing can be sent to an adapter through it. This is the channel used by
+//! `btmon`.
+//!
+//! A [`Monitor`] is opened to receive the messages of the monitor channel. Every message is
+//! decoded into a [`MonitorPacket`] with the types of bo-tie. Binding to the monitor channel
+//! requires the capability `CAP_NET_RAW`.
+//!
+//! ```no_run
+//! use bo_tie_linux::monitor::{Monitor, MonitorData};
+//!
+//! for packet in Monitor::open().unwrap() {
+//!     match packet {
+//!         Ok(packet) => if let MonitorData::Event(event) = packet.data {
+//!             println!("hci{:?}: {:?}", packet.index, event.get_enum_name());
+//!         },
+//!         Err(e) => eprintln!("{}", e),
+//!     }
+//! }
+//! ```
+
+use crate::{bluez, ArcFileDesc, Bus, Error};
+use bo_tie::hci::{events, HciAclData, HciSyncData};
+use std::collections::HashMap;
+
+/// The maximum number of messages read from the monitor channel by `user_channel_adapters`
+///
+/// The replay of the opened sockets is received before any live traffic, so this is only a limit
+/// for when the monitor receives a lot of traffic while the replay is read.
+const REPLAY_READ_LIMIT: usize = 4096;
+
+/// The format of a `HCI_MON_CTRL_OPEN` message for a user channel socket
+const USER_CHANNEL_FORMAT: u16 = 0x0001;
+
+/// The size of the buffer used to receive a message
+///
+/// This is large enough for the header and the largest HCI packet.
+const RECEIVE_BUFFER_SIZE: usize = bluez::HCI_MON_HDR_SIZE + 4 + <u16>::MAX as usize;
+
+// Opcodes of the monitor channel messages
+const NEW_INDEX: u16 = 0;
+const DEL_INDEX: u16 = 1;
+const COMMAND_PKT: u16 = 2;
+const EVENT_PKT: u16 = 3;
+const ACL_TX_PKT: u16 = 4;
+const ACL_RX_PKT: u16 = 5;
+const SCO_TX_PKT: u16 = 6;
+const SCO_RX_PKT: u16 = 7;
+const OPEN_INDEX: u16 = 8;
+const CLOSE_INDEX: u16 = 9;
+const INDEX_INFO: u16 = 10;
+const SYSTEM_NOTE: u16 = 12;
+
+/// Open a socket bound to the monitor channel
+fn open_socket(non_blocking: bool) -> Result<ArcFileDesc, Error> {
+    use nix::libc;
+
+    let flags = if non_blocking { libc::SOCK_NONBLOCK } else { 0 };
+
+    let fd = unsafe {
+        libc::socket(libc::AF_BLUETOOTH, libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags, bluez::BTPROTO_HCI)
+    };
+
+    if fd < 0 { return Err(nix::errno::Errno::last().into()) }
+
+    let socket = ArcFileDesc::from(fd);
+
+    let sa_p = &bluez::sockaddr_hci {
+        hci_family: libc::AF_BLUETOOTH as u16,
+        hci_dev: bluez::HCI_DEV_NONE,
+        hci_channel: bluez::HCI_CHANNEL_MONITOR as u16,
+    } as *const bluez::sockaddr_hci as *const libc::sockaddr;
+
+    let sa_len = std::mem::size_of::<bluez::sockaddr_hci>() as libc::socklen_t;
+
+    if unsafe { libc::bind(socket.raw_fd(), sa_p, sa_len) } < 0 {
+        return Err(nix::errno::Errno::last().into())
+    }
+
+    Ok(socket)
+}
+
+/// Get the adapters that have a socket bound to the user channel
+///
+/// When a socket is bound to the monitor channel, the kernel sends a message for every socket
+/// already opened to the Bluetooth subsystem. The user channel sockets are picked out from these
+/// messages.
+pub(crate) fn user_channel_adapters() -> Result<Vec<u16>, Error> {
+    use nix::errno::Errno;
+    use nix::sys::socket::{recv, MsgFlags};
+
+    let socket = open_socket(true)?;
+
+    // The opened user channel sockets mapped by their cookie
+    let mut user_channels = HashMap::new();
+
+    let mut buffer = [0u8; 1024];
+
+    for _ in 0..REPLAY_READ_LIMIT {
+        let len = match recv(socket.raw_fd(), &mut buffer, MsgFlags::MSG_DONTWAIT) {
+            Ok(len) => len,
+            Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        if len < bluez::HCI_MON_HDR_SIZE + 4 { continue }
+
+        let opcode = <u16>::from_le_bytes([buffer[0], buffer[1]]);
+        let index = <u16>::from_le_bytes([buffer[2], buffer[3]]);
+        let data = &buffer[bluez::HCI_MON_HDR_SIZE..len];
+
+        let cookie = <u32>::from_le_bytes([data[0], data[1], data[2], data[3]]);
+
+        match opcode {
+            bluez::HCI_MON_CTRL_OPEN if data.len() >= 6 => {
+                let format = <u16>::from_le_bytes([data[4], data[5]]);
+
+                if format == USER_CHANNEL_FORMAT && index != bluez::HCI_DEV_NONE {
+                    user_channels.insert(cookie, index);
+                }
+            },
+            bluez::HCI_MON_CTRL_CLOSE => {
+                user_channels.remove(&cookie);
+            },
+            _ => (),
+        }
+    }
+
+    let mut adapters = user_channels.into_values().collect::<Vec<_>>();
+
+    adapters.sort_unstable();
+    adapters.dedup();
+
+    Ok(adapters)
+}
+
+/// The direction of a packet
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Direction {
+    HostToController,
+    ControllerToHost,
+}
+
+/// The data of a message of the monitor channel
+#[derive(Debug)]
+pub enum MonitorData {
+    /// An adapter was added to the system
+    NewIndex {
+        bus: Bus,
+        address: bo_tie::BluetoothDeviceAddress,
+        name: String,
+    },
+    /// An adapter was removed from the system
+    DeleteIndex,
+    /// An adapter was opened (brought up or bound to a user channel)
+    OpenIndex,
+    /// An adapter was closed
+    CloseIndex,
+    /// Information on an adapter
+    IndexInfo {
+        address: bo_tie::BluetoothDeviceAddress,
+        manufacturer: u16,
+    },
+    /// A command sent to the controller
+    ///
+    /// The parameter is the raw parameter of the command.
+    Command {
+        opcode: u16,
+        parameter: Vec<u8>,
+    },
+    /// An event sent by the controller
+    Event(Box<events::EventsData>),
+    /// ACL data
+    AclData {
+        direction: Direction,
+        data: HciAclData,
+    },
+    /// Synchronous data
+    SyncData {
+        direction: Direction,
+        data: HciSyncData,
+    },
+    /// A note from the system, such as the version of the Bluetooth subsystem
+    SystemNote(String),
+    /// Any other message
+    Other {
+        opcode: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// A message of the monitor channel
+#[derive(Debug)]
+pub struct MonitorPacket {
+    /// The identifier of the adapter of the message
+    ///
+    /// This is `None` for messages that are not for an adapter.
+    pub index: Option<u16>,
+    pub data: MonitorData,
+}
+
+impl MonitorPacket {
+
+    /// Decode a monitor frame
+    ///
+    /// A monitor frame is a message of the monitor channel as it is received from the socket,
+    /// starting with the header of the message.
+    pub fn from_frame(frame: &[u8]) -> Result<Self, Error> {
+        use std::convert::TryFrom;
+
+        if frame.len() < bluez::HCI_MON_HDR_SIZE {
+            return Err(Error::InvalidMonitorFrame(format!("header is too short ({} bytes)", frame.len())))
+        }
+
+        let opcode = <u16>::from_le_bytes([frame[0], frame[1]]);
+        let index = <u16>::from_le_bytes([frame[2], frame[3]]);
+        let len = <u16>::from_le_bytes([frame[4], frame[5]]) as usize;
+
+        let data = frame[bluez::HCI_MON_HDR_SIZE..].get(..len)
+            .ok_or_else(|| Error::InvalidMonitorFrame(format!("data is shorter than its length of {}", len)))?;
+
+        let too_short = || Error::InvalidMonitorFrame(format!("data of opcode {} is too short", opcode));
+
+        let address = |raw: &[u8]| -> Result<bo_tie::BluetoothDeviceAddress, Error> {
+            <bo_tie::BluetoothDeviceAddress>::try_from(raw.get(..6).ok_or_else(too_short)?).map_err(|_| too_short())
+        };
+
+        let monitor_data = match opcode {
+            NEW_INDEX => {
+                // the data is the type, bus, address, and name of the adapter
+                let name = data.get(8..16).ok_or_else(too_short)?;
+
+                let name_len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+
+                MonitorData::NewIndex {
+                    bus: Bus::from(data[1]),
+                    address: address(&data[2..])?,
+                    name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                }
+            },
+            DEL_INDEX => MonitorData::DeleteIndex,
+            COMMAND_PKT => {
+                if data.len() < 3 { return Err(too_short()) }
+
+                MonitorData::Command {
+                    opcode: <u16>::from_le_bytes([data[0], data[1]]),
+                    parameter: data[3..].to_vec(),
+                }
+            },
+            EVENT_PKT => {
+                // The conversion of an event expects the parameter to be complete
+                if data.len() < 2 || data.len() != 2 + data[1] as usize {
+                    return Err(Error::InvalidMonitorFrame("event length does not match its data".to_string()))
+                }
+
+                MonitorData::Event(Box::new(
+                    events::EventsData::from_packet(data)
+                        .map_err(|e| Error::InvalidMonitorFrame(format!("invalid event, {}", e)))?
+                ))
+            },
+            ACL_TX_PKT | ACL_RX_PKT => MonitorData::AclData {
+                direction: if opcode == ACL_TX_PKT {
+                    Direction::HostToController
+                } else {
+                    Direction::ControllerToHost
+                },
+                data: HciAclData::from_packet(data)
+                    .map_err(|e| Error::InvalidMonitorFrame(format!("invalid ACL data, {}", e)))?,
+            },
+            SCO_TX_PKT | SCO_RX_PKT => MonitorData::SyncData {
+                direction: if opcode == SCO_TX_PKT {
+                    Direction::HostToController
+                } else {
+                    Direction::ControllerToHost
+                },
+                data: HciSyncData::from_packet(data)
+                    .map_err(|e| Error::InvalidMonitorFrame(format!("invalid synchronous data, {}", e)))?,
+            },
+            OPEN_INDEX => MonitorData::OpenIndex,
+            CLOSE_INDEX => MonitorData::CloseIndex,
+            INDEX_INFO => {
+                let manufacturer = data.get(6..8).ok_or_else(too_short)?;
+
+                MonitorData::IndexInfo {
+                    address: address(data)?,
+                    manufacturer: <u16>::from_le_bytes([manufacturer[0], manufacturer[1]]),
+                }
+            },
+            SYSTEM_NOTE => {
+                let note_len = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+
+                MonitorData::SystemNote(String::from_utf8_lossy(&data[..note_len]).into_owned())
+            },
+            _ => MonitorData::Other { opcode, data: data.to_vec() },
+        };
+
+        Ok(MonitorPacket {
+            index: if index == bluez::HCI_DEV_NONE { None } else { Some(index) },
+            data: monitor_data,
+        })
+    }
+}
+
+/// A receiver of the monitor channel
+///
+/// A `Monitor` receives the messages of the monitor channel for every adapter of the system. When
+/// it is opened the kernel first sends messages for the adapters that already exist, then every
+/// message is sent as it occurs.
+///
+/// Messages are received with the blocking [`receive`](Monitor::receive) or by using the
+/// `Monitor` as an iterator. The iterator never ends, but it returns an error for every message
+/// that could not be decoded (or received).
+pub struct Monitor {
+    socket: ArcFileDesc,
+    buffer: Vec<u8>,
+}
+
+impl Monitor {
+
+    /// Open the monitor channel
+    pub fn open() -> Result<Self, Error> {
+        Ok(Monitor {
+            socket: open_socket(false)?,
+            buffer: vec![0u8; RECEIVE_BUFFER_SIZE],
+        })
+    }
+
+    /// Receive the next message
+    ///
+    /// This blocks until a message is received.
+    pub fn receive(&mut self) -> Result<MonitorPacket, Error> {
+        use nix::errno::Errno;
+        use nix::sys::socket::{recv, MsgFlags};
+
+        let len = loop {
+            match recv(self.socket.raw_fd(), &mut self.buffer, MsgFlags::empty()) {
+                Ok(len) => break len,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        MonitorPacket::from_frame(&self.buffer[..len])
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = Result<MonitorPacket, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.receive())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn decode_test() {
+        // frames as they are received from the monitor channel
+
+        let new_index = [
+            0x00, 0x00, 0x00, 0x00, 0x10, 0x00,
+            0x00, 0x01, 0x21, 0x43, 0x65, 0x87, 0xa9, 0xcb, b'h', b'c', b'i', b'0', 0, 0, 0, 0,
+        ];
+
+        match MonitorPacket::from_frame(&new_index) {
+            Ok(MonitorPacket { index: Some(0), data: MonitorData::NewIndex { bus, address, name } }) => {
+                assert_eq!(Bus::Usb, bus);
+                assert_eq!([0x21, 0x43, 0x65, 0x87, 0xa9, 0xcb], address);
+                assert_eq!("hci0", name);
+            },
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+
+        let reset = [0x02, 0x00, 0x01, 0x00, 0x03, 0x00, 0x03, 0x0c, 0x00];
+
+        match MonitorPacket::from_frame(&reset) {
+            Ok(MonitorPacket { index: Some(1), data: MonitorData::Command { opcode, parameter } }) => {
+                assert_eq!(0x0C03, opcode);
+                assert!(parameter.is_empty());
+            },
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+
+        let reset_complete = [0x03, 0x00, 0x01, 0x00, 0x06, 0x00, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00];
+
+        match MonitorPacket::from_frame(&reset_complete) {
+            Ok(MonitorPacket { index: Some(1), data: MonitorData::Event(event) }) => match *event {
+                events::EventsData::CommandComplete(data) => assert_eq!(Some(0x0C03), data.command_opcode),
+                event => panic!("Unexpected event {:?}", event),
+            },
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+
+        // an ATT exchange MTU request received on connection handle 0x40
+        let acl_rx = [
+            0x05, 0x00, 0x00, 0x00, 0x0b, 0x00,
+            0x40, 0x20, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x02, 0x00, 0x02,
+        ];
+
+        match MonitorPacket::from_frame(&acl_rx) {
+            Ok(MonitorPacket { index: Some(0), data: MonitorData::AclData { direction, data } }) => {
+                assert_eq!(Direction::ControllerToHost, direction);
+                assert_eq!(0x40, data.get_handle().get_raw_handle());
+                assert_eq!(&[0x03, 0x00, 0x04, 0x00, 0x02, 0x00, 0x02], data.get_payload());
+            },
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+
+        // a SCO packet sent on connection handle 0x6
+        let sco_tx = [0x06, 0x00, 0x00, 0x00, 0x07, 0x00, 0x06, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04];
+
+        match MonitorPacket::from_frame(&sco_tx) {
+            Ok(MonitorPacket { index: Some(0), data: MonitorData::SyncData { direction, data } }) => {
+                assert_eq!(Direction::HostToController, direction);
+                assert_eq!(0x6, data.get_handle().get_raw_handle());
+                assert_eq!(bo_tie::hci::SyncPacketStatus::CorrectlyReceived, data.get_packet_status_flag());
+                assert_eq!(&[0x01, 0x02, 0x03, 0x04], data.get_payload());
+            },
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+
+        let note = [
+            0x0c, 0x00, 0xff, 0xff, 0x1a, 0x00,
+            b'B', b'l', b'u', b'e', b't', b'o', b'o', b't', b'h', b' ', b's', b'u', b'b', b's', b'y', b's', b't',
+            b'e', b'm', b' ', b'v', b'e', b'r', b'.', b' ', 0,
+        ];
+
+        match MonitorPacket::from_frame(&note) {
+            Ok(MonitorPacket { index: None, data: MonitorData::SystemNote(note) }) =>
+                assert_eq!("Bluetooth subsystem ver. ", note),
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn decode_invalid_test() {
+        // header is too short
+        assert!(MonitorPacket::from_frame(&[0x03, 0x00, 0x00]).is_err());
+
+        // the length is larger than the data
+        assert!(MonitorPacket::from_frame(&[0x03, 0x00, 0x00, 0x00, 0x06, 0x00, 0x0e, 0x04]).is_err());
+
+        // the event is incomplete
+        assert!(MonitorPacket::from_frame(&[0x03, 0x00, 0x00, 0x00, 0x02, 0x00, 0x0e, 0x04]).is_err());
+
+        // the synchronous data is shorter than its length
+        assert!(MonitorPacket::from_frame(&[0x07, 0x00, 0x00, 0x00, 0x04, 0x00, 0x06, 0x00, 0x04, 0x01]).is_err());
+    }
+}
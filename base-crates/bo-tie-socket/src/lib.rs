@@ -0,0 +1,211 @@
+//! HCI over a socket for bo-tie
+//!
+//! Emulated controllers, such as Bumble or a Zephyr `native_posix` build, expose their HCI on a
+//! TCP or Unix socket. This base crate connects to such a socket so that the rest of bo-tie can be
+//! run against a software controller.
+//!
+//! The packets sent over the socket are framed the same way as the H4 (UART) transport, a packet
+//! indicator followed by the HCI packet. The framing is done by the
+//! [`bo-tie-h4`](bo_tie_h4) base crate.
+
+use bo_tie::hci::{
+    events,
+    common::ConnectionHandle,
+    CommandParameter,
+    EventMatcher,
+    HciAclData,
+    HciAclDataInterface,
+    HciSyncData,
+    HciSyncDataInterface,
+    HostControllerInterface,
+    ReceivedPacketListener,
+    ReceivedPacketSource,
+};
+use bo_tie_h4::H4Interface;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Waker;
+use std::time::Duration;
+
+pub use bo_tie_h4::Error;
+
+/// A host controller interface to a controller on a socket
+///
+/// Clones of a `SocketInterface` are handles to the same connection.
+#[derive(Clone)]
+pub struct SocketInterface {
+    interface: H4Interface,
+}
+
+impl SocketInterface {
+
+    /// Connect to a controller on a TCP socket
+    ///
+    /// Nagle's algorithm is disabled for the connection as HCI packets are small and should be
+    /// sent immediately.
+    pub fn connect_tcp<A>(address: A) -> io::Result<Self> where A: ToSocketAddrs {
+        let stream = TcpStream::connect(address)?;
+
+        stream.set_nodelay(true)?;
+
+        log::info!("Connected to controller at {}", stream.peer_addr()?);
+
+        Ok(SocketInterface { interface: H4Interface::new(stream)? })
+    }
+
+    /// Connect to a controller on a Unix domain socket
+    #[cfg(unix)]
+    pub fn connect_unix<P>(path: P) -> io::Result<Self> where P: AsRef<std::path::Path> {
+        let stream = std::os::unix::net::UnixStream::connect(path.as_ref())?;
+
+        log::info!("Connected to controller at {}", path.as_ref().display());
+
+        Ok(SocketInterface { interface: H4Interface::new(stream)? })
+    }
+}
+
+impl HostControllerInterface for SocketInterface {
+
+    type SendCommandError = Error;
+    type ReceiveEventError = Error;
+
+    fn send_command<D,W>(&self, cmd_data: &D, waker: W) -> Result<bool, Self::SendCommandError>
+    where D: CommandParameter,
+          W: Into<Option<Waker>>
+    {
+        self.interface.send_command(cmd_data, waker)
+    }
+
+    fn receive_event<P>(&self,
+        event: events::Events,
+        waker: &Waker,
+        matcher: Pin<Arc<P>>,
+        timeout: Option<Duration>)
+    -> Option<Result<events::EventsData, Self::ReceiveEventError>>
+    where P: EventMatcher + Send + Sync + 'static
+    {
+        self.interface.receive_event(event, waker, matcher, timeout)
+    }
+}
+
+impl HciAclDataInterface for SocketInterface {
+
+    type SendAclDataError = Error;
+    type ReceiveAclDataError = Error;
+
+    fn send(&self, data: HciAclData) -> Result<usize, Self::SendAclDataError> {
+        self.interface.send(data)
+    }
+
+    fn start_receiver(&self, handle: ConnectionHandle) {
+        self.interface.start_receiver(handle)
+    }
+
+    fn stop_receiver(&self, handle: &ConnectionHandle) {
+        self.interface.stop_receiver(handle)
+    }
+
+    fn receive(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciAclData>, Self::ReceiveAclDataError>>
+    {
+        self.interface.receive(handle, waker)
+    }
+}
+
+impl HciSyncDataInterface for SocketInterface {
+
+    type SendSyncDataError = Error;
+    type ReceiveSyncDataError = Error;
+
+    fn send_sync(&self, data: HciSyncData) -> Result<usize, Self::SendSyncDataError> {
+        self.interface.send_sync(data)
+    }
+
+    fn start_sync_receiver(&self, handle: ConnectionHandle) {
+        self.interface.start_sync_receiver(handle)
+    }
+
+    fn stop_sync_receiver(&self, handle: &ConnectionHandle) {
+        self.interface.stop_sync_receiver(handle)
+    }
+
+    fn receive_sync(&self, handle: &ConnectionHandle, waker: &Waker)
+    -> Option<Result<Vec<HciSyncData>, Self::ReceiveSyncDataError>>
+    {
+        self.interface.receive_sync(handle, waker)
+    }
+}
+
+impl ReceivedPacketSource for SocketInterface {
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>) {
+        self.interface.set_received_packet_listener(listener)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use bo_tie::hci::HostInterface;
+    use bo_tie::hci::le::mandatory::{read_bd_addr, reset};
+    use bo_tie_h4::packet::{Packet, PacketIndicator};
+    use futures::executor::block_on;
+    use std::io::{Read, Write};
+    use std::thread;
+
+    const ADDRESS: [u8;6] = [1, 2, 3, 4, 5, 6];
+
+    /// Answer the Reset and Read BD_ADDR commands like an emulated controller
+    fn emulated_controller<S: Read + Write>(mut stream: S) {
+        let responses = [
+            ([0x03, 0x0C, 0x00], vec![0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00]),
+            ([0x09, 0x10, 0x00], [&[0x0E, 0x0A, 0x01, 0x09, 0x10, 0x00][..], &ADDRESS].concat()),
+        ];
+
+        for (command, event) in responses.iter() {
+            assert_eq!(
+                Some(Packet::new(PacketIndicator::Command, command.to_vec())),
+                Packet::read_from(&mut stream).unwrap()
+            );
+
+            Packet::new(PacketIndicator::Event, event.clone()).write_to(&mut stream).unwrap();
+        }
+    }
+
+    fn run_commands(hi: HostInterface<SocketInterface>) {
+        block_on(reset::send(&hi)).unwrap();
+
+        assert_eq!(ADDRESS, block_on(read_bd_addr::send(&hi)).unwrap());
+    }
+
+    #[test]
+    fn tcp_test() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let address = listener.local_addr().unwrap();
+
+        let controller = thread::spawn(move || emulated_controller(listener.accept().unwrap().0));
+
+        run_commands(HostInterface::from(SocketInterface::connect_tcp(address).unwrap()));
+
+        controller.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_test() {
+        let path = std::env::temp_dir().join(format!("bo-tie-socket-test-{}", std::process::id()));
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let controller = thread::spawn(move || emulated_controller(listener.accept().unwrap().0));
+
+        run_commands(HostInterface::from(SocketInterface::connect_unix(&path).unwrap()));
+
+        controller.join().unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -230,7 +230,7 @@ fn main() {
 
             std::thread::spawn( move || {
 
-                let connection_channel = interface_clone.new_le_acl_connection_channel(&event_data);
+                let connection_channel = interface_clone.new_subscribed_le_acl_connection_channel(&event_data);
 
                 let server = gatt_server_init(&connection_channel, local_name);
 
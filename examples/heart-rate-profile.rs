@@ -335,7 +335,7 @@ fn main() {
 
                 let hrm = heart_rate_service::characteristics::HeartRateMeasurement::new(heart_rate.clone());
 
-                let connection_channel = interface.new_le_acl_connection_channel(&connection_complete_event);
+                let connection_channel = interface.new_subscribed_le_acl_connection_channel(&connection_complete_event);
 
                 let server = heart_rate_service::build_server(hrm, &connection_channel, None);
 
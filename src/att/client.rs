@@ -8,7 +8,11 @@ use alloc::{
     boxed::Box,
     format,
 };
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 use crate::l2cap;
+use crate::timer::{Timed, Timer};
 use super::server::ServerPduName;
 
 #[derive(Debug,Clone,Copy,PartialEq,PartialOrd,Eq)]
@@ -120,6 +124,58 @@ where F: FnOnce(&[u8]) -> Result<R, super::Error>
             Err( super::Error::IncorrectChannelId )
         }
     }
+
+    /// Receive and process the response
+    ///
+    /// The returned future receives from `channel` until an attribute protocol packet is
+    /// received, and then processes it as the response. ACL data received for other protocols is
+    /// discarded. An `Error::Timeout` is returned if the response is not received within the
+    /// [`TRANSACTION_TIMEOUT`](super::TRANSACTION_TIMEOUT). Per the specification, no more
+    /// requests can be sent to the server after a transaction times out.
+    pub fn receive<'z, C, T>(self, channel: &'z C, timer: &T) -> ResponseFuture<'z, C, F, R>
+    where C: l2cap::ConnectionChannel,
+          T: Timer + ?Sized,
+    {
+        ResponseFuture {
+            processor: Some(self),
+            receiver: Timed::new(timer, super::TRANSACTION_TIMEOUT, channel.future_receiver()),
+        }
+    }
+}
+
+/// The future returned by [`ResponseProcessor::receive`]
+pub struct ResponseFuture<'z, C, F, R>
+where C: l2cap::ConnectionChannel,
+      F: FnOnce(&[u8]) -> Result<R, super::Error>,
+{
+    processor: Option<ResponseProcessor<F,R>>,
+    receiver: Timed<l2cap::ConChanFutureRx<'z, C>>,
+}
+
+impl<C, F, R> Future for ResponseFuture<'_, C, F, R>
+where C: l2cap::ConnectionChannel,
+      F: FnOnce(&[u8]) -> Result<R, super::Error> + Unpin,
+{
+    type Output = Result<R, super::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            match Pin::new(&mut this.receiver).poll(cx) {
+                Poll::Pending => break Poll::Pending,
+                Poll::Ready(Err(_)) => break Poll::Ready(Err(super::Error::Timeout)),
+                Poll::Ready(Ok(Err(e))) => break Poll::Ready(Err(super::Error::Receive(e))),
+                Poll::Ready(Ok(Ok(acl_data))) => {
+                    if let Some(response) = acl_data.iter().find(|d| d.get_channel_id() == super::L2CAP_CHANNEL_ID) {
+                        let processor = this.processor.take().expect("ResponseFuture polled after completion");
+
+                        break Poll::Ready(processor.process_response(response))
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub struct Client<'c, C>
@@ -30,6 +30,9 @@ pub const MIN_ATT_MTU_LE: u16 = 23;
 /// The minimum number of data bytes in an attribute protocol based packet for bluetooth BR/EDR
 pub const MIN_ATT_MTU_BR_EDR: u16 = 48;
 
+/// The time a client waits for the response to a request (v5.0 | Vol 3, Part F, 3.3.3)
+pub const TRANSACTION_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(30);
+
 /// Avanced Encryption Standard (AES) key sizes
 #[derive(Clone,Copy,Debug,PartialEq,Eq)]
 pub enum EncryptionKeySize {
@@ -187,7 +190,11 @@ pub enum Error {
     /// Incorrect Channel Identifier
     IncorrectChannelId,
     /// Pdu Error
-    PduError(pdu::Error)
+    PduError(pdu::Error),
+    /// The response to a request was not received within the transaction timeout
+    Timeout,
+    /// Failed to receive ACL data
+    Receive(l2cap::AclDataError),
 }
 
 impl core::fmt::Display for Error{
@@ -210,6 +217,10 @@ impl core::fmt::Display for Error{
                     number for the Attribute Protocol"),
             Error::PduError(err) =>
                 write!(f, "Attribute PDU error '{}'", err),
+            Error::Timeout =>
+                write!(f, "Attribute transaction timed out"),
+            Error::Receive(err) =>
+                write!(f, "Failed to receive ACL data, {}", err),
         }
     }
 }
@@ -590,8 +601,9 @@ mod test {
     }
 
     impl l2cap::ConnectionChannel for Channel1 {
+        type SendError = core::convert::Infallible;
 
-        fn send<Pdu>(&self, data: Pdu) where Pdu: Into<crate::l2cap::L2capPdu>{
+        fn send<Pdu>(&self, data: Pdu) -> l2cap::ConChanFutureTx<'_, Self> where Pdu: Into<crate::l2cap::L2capPdu>{
             let mut gaurd = self.two_way.lock().expect("Failed to acquire lock");
 
             gaurd.b1 = Some(data.into().into_data());
@@ -599,6 +611,8 @@ mod test {
             if let Some(waker) = gaurd.w1.take() {
                 waker.wake();
             }
+
+            l2cap::ConChanFutureTx::new(self)
         }
 
         fn receive(&self, waker: &Waker) -> Option<Vec<crate::l2cap::AclDataFragment>> {
@@ -616,8 +630,9 @@ mod test {
     }
 
     impl l2cap::ConnectionChannel for Channel2 {
+        type SendError = core::convert::Infallible;
 
-        fn send<Pdu>(&self, data: Pdu) where Pdu: Into<crate::l2cap::L2capPdu>{
+        fn send<Pdu>(&self, data: Pdu) -> l2cap::ConChanFutureTx<'_, Self> where Pdu: Into<crate::l2cap::L2capPdu>{
             let mut gaurd = self.two_way.lock().expect("Failed to acquire lock");
 
             gaurd.b2 = Some(data.into().into_data());
@@ -625,6 +640,8 @@ mod test {
             if let Some(waker) = gaurd.w2.take() {
                 waker.wake();
             }
+
+            l2cap::ConChanFutureTx::new(self)
         }
 
         fn receive(&self, waker: &Waker) -> Option<Vec<crate::l2cap::AclDataFragment>> {
@@ -1078,3 +1078,66 @@ impl ::core::fmt::Display for DataTooLargeError {
         write!(f, "Advertising Data Too Large")
     }
 }
+
+/// A packer of AD structures
+///
+/// This packs the raw form of advertising data types one after the other into a buffer. It is the
+/// builder used by the HCI commands that set the advertising data or the scan response data.
+#[derive(Debug,Clone,Copy)]
+pub(crate) struct ADStructPacker<B> {
+    length: usize,
+    buffer: B,
+}
+
+impl<B> ADStructPacker<B> where B: AsRef<[u8]> + AsMut<[u8]> {
+
+    /// Create a new packer
+    ///
+    /// The entire `buffer` is used for packing AD structures.
+    pub(crate) fn new(buffer: B) -> Self {
+        ADStructPacker { length: 0, buffer }
+    }
+
+    /// Add the raw form of `data` to the packed AD structures
+    ///
+    /// # Error
+    /// `data` in its transmission form is too large for the remaining space of the buffer.
+    pub(crate) fn try_push<T>(&mut self, data: T) -> Result<(), DataTooLargeError> where T: IntoRaw {
+        let raw_data = data.into_raw();
+
+        let capacity = self.buffer.as_ref().len();
+
+        if raw_data.len() + self.length <= capacity {
+            let old_len = self.length;
+
+            self.length += raw_data.len();
+
+            self.buffer.as_mut()[old_len..self.length].copy_from_slice(&raw_data);
+
+            Ok(())
+        }
+        else {
+            Err(DataTooLargeError {
+                overflow: raw_data.len() + self.length - capacity,
+                remaining: capacity - self.length,
+            })
+        }
+    }
+
+    /// Get the remaining amount of space within the buffer
+    pub(crate) fn remaining_space(&self) -> usize {
+        self.buffer.as_ref().len() - self.length
+    }
+
+    /// Get the number of bytes of the packed AD structures
+    pub(crate) fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Get the buffer
+    ///
+    /// Only the first [`len`](ADStructPacker::len) bytes of the buffer are packed AD structures.
+    pub(crate) fn get_buffer(&self) -> &B {
+        &self.buffer
+    }
+}
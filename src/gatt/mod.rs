@@ -595,7 +595,11 @@ mod tests {
     struct DummyConnection;
 
     impl ConnectionChannel for DummyConnection {
-        fn send<Pdu>(&self, _: Pdu) where Pdu: Into<crate::l2cap::L2capPdu>{}
+        type SendError = core::convert::Infallible;
+
+        fn send<Pdu>(&self, _: Pdu) -> crate::l2cap::ConChanFutureTx<'_, Self> where Pdu: Into<crate::l2cap::L2capPdu> {
+            crate::l2cap::ConChanFutureTx::new(self)
+        }
         fn receive(&self, _: &core::task::Waker) -> Option<Vec<crate::l2cap::AclDataFragment>> { None }
     }
 
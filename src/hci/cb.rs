@@ -21,7 +21,7 @@ pub mod reset {
         fn get_parameter(&self) -> Self::Parameter { *self }
     }
 
-    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> ) -> impl Future<Output=Result<(), impl Display + Debug>> + 'a where T: HostControllerInterface
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> ) -> impl Future<Output=Result<(), impl CommandError>> + 'a where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
     }
@@ -188,7 +188,7 @@ pub mod set_event_mask {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, events: &[EventMask] )
-    -> impl Future<Output=Result<(), impl Display + Debug>> + 'a where T: HostControllerInterface
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a where T: HostControllerInterface
     {
         let parameter = Parameter { mask: EventMask::to_val(events).to_le_bytes() };
 
@@ -274,9 +274,294 @@ pub mod read_transmit_power_level {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, parameter: Parameter )
-                                 -> impl Future<Output=Result<TransmitPowerLevel, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<TransmitPowerLevel, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
     }
-}
\ No newline at end of file
+}
+
+/// Turn flow control on or off for data sent from the controller to the host
+///
+/// When flow control is on, the controller does not send more data packets to the host than the
+/// host has buffers for. The size and number of the host's data buffers must be given to the
+/// controller with the [`host_buffer_size`](super::host_buffer_size) command, and the host must
+/// report the packets it has processed with the
+/// [`host_number_of_completed_packets`](super::host_number_of_completed_packets) command.
+pub mod set_controller_to_host_flow_control {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::ControllerAndBaseband(opcodes::ControllerAndBaseband::SetControllerToHostFlowControl);
+
+    /// The data flow controlled by the host
+    #[derive(Clone,Copy,Debug,PartialEq,Eq)]
+    pub enum FlowControlEnable {
+        /// Flow control is off for all data (this is the default after a reset)
+        Off,
+        /// Flow control is on for ACL data and off for synchronous data
+        Acl,
+        /// Flow control is off for ACL data and on for synchronous data
+        Synchronous,
+        /// Flow control is on for both ACL and synchronous data
+        AclAndSynchronous,
+    }
+
+    impl FlowControlEnable {
+        fn into_val(self) -> u8 {
+            match self {
+                FlowControlEnable::Off => 0x0,
+                FlowControlEnable::Acl => 0x1,
+                FlowControlEnable::Synchronous => 0x2,
+                FlowControlEnable::AclAndSynchronous => 0x3,
+            }
+        }
+    }
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone,Copy)]
+    struct Parameter {
+        flow_control_enable: u8,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = u8;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { self.flow_control_enable }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: FlowControlEnable )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a where T: HostControllerInterface
+    {
+        let parameter = Parameter { flow_control_enable: enable.into_val() };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Give the size and number of the host's data buffers to the controller
+///
+/// The controller uses these to determine the size of the data packets it sends to the host, and
+/// the number of data packets it may send to the host when controller to host flow control is on.
+pub mod host_buffer_size {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::ControllerAndBaseband(opcodes::ControllerAndBaseband::HostBufferSize);
+
+    #[repr(packed)]
+    #[derive(Clone,Copy)]
+    struct CmdParameter {
+        _acl_data_packet_length: u16,
+        _synchronous_data_packet_length: u8,
+        _total_num_acl_data_packets: u16,
+        _total_num_synchronous_data_packets: u16,
+    }
+
+    /// The data buffers of the host
+    #[derive(Clone,Copy,Debug,PartialEq,Eq)]
+    pub struct BufferSize {
+        /// The maximum size of the data portion of an ACL data packet
+        pub acl_data_packet_length: u16,
+        /// The maximum size of the data portion of a synchronous data packet
+        pub synchronous_data_packet_length: u8,
+        /// The total number of ACL data packets that can be stored by the host
+        pub total_num_acl_data_packets: u16,
+        /// The total number of synchronous data packets that can be stored by the host
+        pub total_num_synchronous_data_packets: u16,
+    }
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone,Copy)]
+    struct Parameter(BufferSize);
+
+    impl CommandParameter for Parameter {
+        type Parameter = CmdParameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {
+            CmdParameter {
+                _acl_data_packet_length: self.0.acl_data_packet_length,
+                _synchronous_data_packet_length: self.0.synchronous_data_packet_length,
+                _total_num_acl_data_packets: self.0.total_num_acl_data_packets,
+                _total_num_synchronous_data_packets: self.0.total_num_synchronous_data_packets,
+            }
+        }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, buffer_size: BufferSize )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter(buffer_size), events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Report the data packets processed by the host
+///
+/// When controller to host flow control is on, this is used to free the host's data buffers for
+/// the controller. Every data packet received from the controller must eventually be reported as
+/// completed, except for the packets of a connection that was disconnected (the controller assumes
+/// that all buffers of a disconnected connection are freed).
+///
+/// This command can be sent even when the controller cannot accept any other command, and the
+/// controller does not respond to this command (unless there is an error with the parameters). The
+/// future returned by `send` is ready once the command is sent.
+pub mod host_number_of_completed_packets {
+
+    use crate::hci::*;
+    use crate::hci::common::ConnectionHandle;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::ControllerAndBaseband(opcodes::ControllerAndBaseband::HostNumberOfCompletedPackets);
+
+    /// The maximum number of connection handles within one command
+    ///
+    /// This is limited by the maximum size of a command parameter. The function `send` sends more
+    /// than one command when more connection handles are given to it.
+    pub const MAX_HANDLES: usize = 63;
+
+    /// The number of completed packets for a connection
+    #[derive(Clone,Copy,Debug,PartialEq,Eq)]
+    pub struct CompletedPackets {
+        pub connection_handle: ConnectionHandle,
+        pub host_num_completed_packets: u16,
+    }
+
+    /// The parameter of a *Host Number Of Completed Packets* command
+    ///
+    /// This is public so that an interface can report completed packets without going through a
+    /// `HostInterface`. The parameter is of variable length, so the command packet must be created
+    /// with [`as_command_packet`](CommandParameter::as_command_packet).
+    pub struct Parameter {
+        completed: Vec<CompletedPackets>,
+    }
+
+    impl Parameter {
+
+        /// Create a new `Parameter`
+        ///
+        /// An error is returned if there are more than `MAX_HANDLES` entries in `completed`.
+        pub fn new(completed: &[CompletedPackets]) -> Result<Self, &'static str> {
+            if completed.len() <= MAX_HANDLES {
+                Ok(Parameter { completed: completed.to_vec() })
+            } else {
+                Err("Too many connection handles for one command")
+            }
+        }
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = ();
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {}
+
+        fn as_command_packet<'a>(&self) -> alloc::boxed::Box<[u8]> {
+            let parameter_len = 1 + self.completed.len() * 4;
+
+            let mut packet = Vec::with_capacity(parameter_len + 3);
+
+            packet.extend_from_slice(&COMMAND.as_opcode_pair().as_opcode().to_le_bytes());
+
+            packet.push(parameter_len as u8);
+
+            packet.push(self.completed.len() as u8);
+
+            self.completed.iter().for_each(|completed| {
+                packet.extend_from_slice(&completed.connection_handle.get_raw_handle().to_le_bytes());
+                packet.extend_from_slice(&completed.host_num_completed_packets.to_le_bytes());
+            });
+
+            packet.into_boxed_slice()
+        }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, completed: &[CompletedPackets] )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a where T: HostControllerInterface
+    {
+        hci.send_command_without_response(
+            completed.chunks(MAX_HANDLES).map(|chunk| Parameter { completed: chunk.to_vec() })
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::hci::common::ConnectionHandle;
+    use crate::hci::testing::fixture::host_interface;
+    use futures::executor::block_on;
+
+    #[test]
+    fn host_flow_control_test() {
+        use crate::hci::cb::{
+            host_buffer_size,
+            host_number_of_completed_packets::{self, CompletedPackets},
+            reset,
+            set_controller_to_host_flow_control::{self, FlowControlEnable},
+        };
+
+        let (hi, controller) = host_interface();
+
+        block_on(set_controller_to_host_flow_control::send(&hi, FlowControlEnable::Acl)).unwrap();
+
+        let buffer_size = host_buffer_size::BufferSize {
+            acl_data_packet_length: 0x100,
+            synchronous_data_packet_length: 0,
+            total_num_acl_data_packets: 8,
+            total_num_synchronous_data_packets: 0,
+        };
+
+        block_on(host_buffer_size::send(&hi, buffer_size)).unwrap();
+
+        assert_eq!(1, controller.get_controller_to_host_flow_control());
+        assert_eq!(Some([0x00, 0x01, 0x00, 0x08, 0x00, 0x00, 0x00]), controller.get_host_buffer_size());
+
+        let handle_1 = ConnectionHandle::try_from(0x40).unwrap();
+        let handle_2 = ConnectionHandle::try_from(0x41).unwrap();
+
+        let completed = [
+            CompletedPackets { connection_handle: handle_1, host_num_completed_packets: 3 },
+            CompletedPackets { connection_handle: handle_2, host_num_completed_packets: 1 },
+        ];
+
+        block_on(host_number_of_completed_packets::send(&hi, &completed)).unwrap();
+
+        assert_eq!(vec![(handle_1, 3), (handle_2, 1)], controller.take_host_completed_packets());
+
+        block_on(reset::send(&hi)).unwrap();
+
+        assert_eq!(0, controller.get_controller_to_host_flow_control());
+        assert_eq!(None, controller.get_host_buffer_size());
+    }
+
+    #[test]
+    fn host_number_of_completed_packets_packet_test() {
+        use crate::hci::CommandParameter;
+        use crate::hci::cb::host_number_of_completed_packets::{CompletedPackets, Parameter, MAX_HANDLES};
+
+        let completed = |raw_handle: u16, count: u16| CompletedPackets {
+            connection_handle: ConnectionHandle::try_from(raw_handle).unwrap(),
+            host_num_completed_packets: count,
+        };
+
+        let packet = Parameter::new(&[]).unwrap().as_command_packet();
+
+        assert_eq!(&[0x35, 0x0C, 1, 0][..], &*packet);
+
+        let packet = Parameter::new(&[completed(0x40, 3), completed(0x0EFF, 0x1234)]).unwrap()
+            .as_command_packet();
+
+        assert_eq!(&[0x35, 0x0C, 9, 2, 0x40, 0x00, 3, 0x00, 0xFF, 0x0E, 0x34, 0x12][..], &*packet);
+
+        let all = vec![completed(0x40, 1); MAX_HANDLES];
+
+        let packet = Parameter::new(&all).unwrap().as_command_packet();
+
+        assert_eq!(3 + 1 + MAX_HANDLES * 4, packet.len());
+        assert_eq!((1 + MAX_HANDLES * 4) as u8, packet[2]);
+        assert_eq!(MAX_HANDLES as u8, packet[3]);
+        assert_eq!(&[0x40, 0x00, 1, 0x00], &packet[(packet.len() - 4)..]);
+
+        assert!(Parameter::new(&vec![completed(0x40, 1); MAX_HANDLES + 1]).is_err());
+    }
+}
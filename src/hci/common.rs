@@ -264,6 +264,7 @@ impl SupervisionTimeout {
     }
 }
 
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
 pub enum EncryptionLevel {
     Off,
     E0,
@@ -664,6 +665,14 @@ impl ExtendedAdvertisingAndScanResponseDataItr {
             indexer: 0,
         }
     }
+
+    /// Get the raw advertising data
+    ///
+    /// This is all of the data of the report, it is not affected by iterating over the AD
+    /// structures.
+    pub fn get_raw_data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
 impl Iterator for ExtendedAdvertisingAndScanResponseDataItr {
@@ -0,0 +1,150 @@
+//! Controller Information
+//!
+//! The capabilities of a controller are spread over a number of informational commands. The
+//! function [`init`] resets the controller and then reads all of them into one [`ControllerInfo`].
+//! It also sets the supported commands of the host interface (see
+//! [`set_supported_commands`](crate::hci::HostInterface::set_supported_commands)), so that a
+//! command not supported by the controller fails immediately instead of waiting for a response
+//! that will never come.
+
+use crate::hci::*;
+use crate::hci::common::{Features, LEFeatures};
+use crate::hci::info_params::read_local_supported_commands::SupportedCommands;
+use crate::hci::info_params::read_local_version_information::VersionInformation;
+use crate::hci::le::mandatory::read_buffer_size::BufferSize;
+use crate::hci::le::mandatory::read_supported_states::StatesAndRoles;
+
+/// The information of a controller
+#[derive(Debug)]
+pub struct ControllerInfo {
+    /// The version information of the controller
+    pub version: VersionInformation,
+    /// The LMP features supported by the controller
+    pub features: Vec<Features>,
+    /// The LE features supported by the controller
+    pub le_features: Vec<LEFeatures>,
+    /// The HCI commands supported by the controller
+    pub supported_commands: Vec<SupportedCommands>,
+    /// The LE states and roles supported by the controller
+    pub supported_states: Vec<StatesAndRoles>,
+    /// The LE ACL data buffer size of the controller
+    pub buffer_size: BufferSize,
+}
+
+impl ControllerInfo {
+    /// Check if a command is supported by the controller
+    pub fn is_supported(&self, command: SupportedCommands) -> bool {
+        self.supported_commands.contains(&command)
+    }
+
+    /// Check if a LE feature is supported by the controller
+    pub fn is_le_feature_supported(&self, feature: LEFeatures) -> bool {
+        self.le_features.contains(&feature)
+    }
+}
+
+/// Initialize the controller and read its information
+///
+/// This sends the commands *Reset*, *Read Local Version Information*, *Read Local Supported
+/// Features*, *LE Read Local Supported Features*, *Read Local Supported Commands*, *LE Read
+/// Supported States* and *LE Read Buffer Size* (in that order) and returns the information
+/// returned by them.
+///
+/// Once the supported commands are read, they are set as the supported commands of `hi`. Any
+/// command that is not supported by the controller will then fail with the error kind
+/// [`NotSupported`](crate::hci::CommandErrorKind::NotSupported). The flow control of LE ACL data
+/// is also set up by the *LE Read Buffer Size* command.
+pub fn init<'a, T: 'static>(hi: &'a HostInterface<T>) -> impl Future<Output=Result<ControllerInfo, ProcedureError>> + 'a
+where T: HostControllerInterface
+{
+    use crate::hci::{cb, info_params, le};
+
+    async move {
+        // Any previously set supported commands may not be for this controller
+        hi.set_supported_commands(None);
+
+        cb::reset::send(hi).await.map_err(|e| ProcedureError::new("Reset", e))?;
+
+        let version = info_params::read_local_version_information::send(hi).await
+            .map_err(|e| ProcedureError::new("Read Local Version Information", e))?;
+
+        let features = info_params::read_local_supported_features::send(hi).await
+            .map_err(|e| ProcedureError::new("Read Local Supported Features", e))?
+            .collect();
+
+        let le_features = le::mandatory::read_local_supported_features::send(hi).await
+            .map_err(|e| ProcedureError::new("LE Read Local Supported Features", e))?
+            .collect();
+
+        let supported_commands = info_params::read_local_supported_commands::send(hi).await
+            .map_err(|e| ProcedureError::new("Read Local Supported Commands", e))?;
+
+        hi.set_supported_commands(Some(&supported_commands));
+
+        let supported_states = le::mandatory::read_supported_states::send(hi).await
+            .map_err(|e| ProcedureError::new("LE Read Supported States", e))?;
+
+        let buffer_size = le::mandatory::read_buffer_size::send(hi).await
+            .map_err(|e| ProcedureError::new("LE Read Buffer Size", e))?;
+
+        Ok(ControllerInfo {
+            version,
+            features,
+            le_features,
+            supported_commands,
+            supported_states,
+            buffer_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hci::info_params::read_bd_addr;
+    use crate::hci::testing::fixture::{host_interface, ADDRESS};
+    use crate::hci::testing::{
+        HCI_VERSION,
+        LE_ACL_DATA_PACKET_LENGTH,
+        MANUFACTURER_NAME,
+        TOTAL_NUM_LE_ACL_DATA_PACKETS,
+    };
+    use futures::executor::block_on;
+
+    #[test]
+    fn controller_info_test() {
+        let (hi, _) = host_interface();
+
+        let info = block_on(init(&hi)).unwrap();
+
+        assert_eq!(HCI_VERSION, info.version.hci_version);
+        assert_eq!(MANUFACTURER_NAME, info.version.manufacturer_name);
+        assert_eq!(Some(LE_ACL_DATA_PACKET_LENGTH), info.buffer_size.packet_len);
+        assert_eq!(Some(TOTAL_NUM_LE_ACL_DATA_PACKETS), info.buffer_size.packet_cnt);
+        assert!(info.is_supported(SupportedCommands::ReadBDADDR));
+        assert!(!info.is_supported(SupportedCommands::Inquiry));
+
+        assert_eq!(ADDRESS, block_on(read_bd_addr::send(&hi)).unwrap());
+
+        // A command not supported by the controller fails without being sent to the controller
+        hi.set_supported_commands(Some(&[SupportedCommands::Reset]));
+
+        let err = block_on(read_bd_addr::send(&hi)).unwrap_err();
+
+        let read_bd_addr_command = opcodes::HCICommand::InformationParameters(
+            opcodes::InformationParameters::ReadBD_ADDR
+        );
+
+        assert_eq!(CommandErrorKind::NotSupported(read_bd_addr_command), err.kind());
+
+        // Raw commands are not checked
+        let raw_read_bd_addr = hi.send_raw_command(0x1009, &[], Duration::from_secs(1));
+
+        assert!(block_on(raw_read_bd_addr).is_ok());
+
+        hi.set_supported_commands(None);
+
+        assert_eq!(ADDRESS, block_on(read_bd_addr::send(&hi)).unwrap());
+    }
+}
@@ -0,0 +1,371 @@
+//! Subscriptions to events
+//!
+//! A subscription receives a copy of every event sent by the controller that matches it. The
+//! events are taken from the interface through the
+//! [`ReceivedPacketSource`](super::ReceivedPacketSource) trait as they are
+//! received, so no event is missed between the polls of an [`EventStream`]. Subscriptions are
+//! only observers, an event received by a subscription can still be waited on with
+//! `wait_for_event` or be the response to a command.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use super::{events, EventMatcher, HciPacketType, ReceivedPacketListener, ReceivedPacketSource};
+use super::spin_lock::SpinLock;
+
+/// The indication that events were dropped by an [`EventStream`]
+///
+/// Events are dropped when they are received while the buffer of the stream is full. The
+/// indication is returned by the stream in place of the dropped events, so the events returned
+/// before it were received before the dropped events and the events returned after it were
+/// received after the dropped events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow {
+    /// The number of dropped events
+    pub dropped: usize,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} events were dropped because the event stream buffer was full", self.dropped)
+    }
+}
+
+struct Buffer {
+    entries: VecDeque<Result<events::EventsData, Overflow>>,
+    /// The number of events in `entries`
+    event_count: usize,
+    waker: Option<Waker>,
+}
+
+struct Subscription {
+    event: events::Events,
+    matcher: Box<dyn EventMatcher>,
+    capacity: usize,
+    /// The oldest event is dropped instead of the received event when the buffer is full
+    keep_latest: bool,
+    buffer: SpinLock<Buffer>,
+}
+
+impl Subscription {
+
+    /// Add a received event to the buffer
+    ///
+    /// The return is the waker of the stream.
+    fn push(&self, event_data: events::EventsData) -> Option<Waker> {
+        let mut buffer = self.buffer.lock();
+
+        if self.keep_latest && buffer.event_count != 0 && buffer.event_count >= self.capacity {
+            EventStream::pop_entry(&mut buffer);
+        }
+
+        if buffer.event_count < self.capacity {
+            buffer.entries.push_back(Ok(event_data));
+
+            buffer.event_count += 1;
+        } else if let Some(Err(overflow)) = buffer.entries.back_mut() {
+            overflow.dropped += 1;
+        } else {
+            log::debug!("Event stream for {:?} is full", self.event);
+
+            buffer.entries.push_back(Err(Overflow { dropped: 1 }));
+        }
+
+        buffer.waker.take()
+    }
+}
+
+/// The subscriptions of a `HostInterface`
+///
+/// This is the listener for the received packets set on the interface. The listener set on the
+/// `HostInterface` is passed every received packet before it is given to the subscriptions.
+pub(super) struct Subscriptions {
+    subscriptions: SpinLock<Vec<Arc<Subscription>>>,
+    /// The listener set on the `HostInterface`
+    listener: SpinLock<Option<Arc<dyn ReceivedPacketListener>>>,
+    /// This is set as the listener of the interface
+    installed: AtomicBool,
+}
+
+impl Subscriptions {
+
+    pub(super) fn new() -> Self {
+        Subscriptions {
+            subscriptions: SpinLock::new(Vec::new()),
+            listener: SpinLock::new(None),
+            installed: AtomicBool::new(false),
+        }
+    }
+
+    /// Set this as the listener for the received packets of `interface`
+    ///
+    /// This is only done the first time this is called.
+    pub(super) fn install<I>(self: &Arc<Self>, interface: &I) where I: ReceivedPacketSource {
+        if !self.installed.swap(true, Ordering::AcqRel) {
+            interface.set_received_packet_listener(Some(self.clone()));
+        }
+    }
+
+    /// Set the listener that every received packet is passed on to
+    pub(super) fn set_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>) {
+        *self.listener.lock() = listener;
+    }
+
+    fn remove(&self, subscription: &Arc<Subscription>) {
+        self.subscriptions.lock().retain(|s| !Arc::ptr_eq(s, subscription))
+    }
+}
+
+impl ReceivedPacketListener for Subscriptions {
+    fn on_packet(&self, packet_type: HciPacketType, packet: &[u8]) {
+        let listener = self.listener.lock().clone();
+
+        if let Some(listener) = listener { listener.on_packet(packet_type, packet) }
+
+        if packet_type != HciPacketType::Event { return }
+
+        let subscriptions = self.subscriptions.lock().clone();
+
+        if subscriptions.is_empty() { return }
+
+        let event_data = match events::EventsData::from_packet(packet) {
+            Ok(event_data) => event_data,
+            Err(e) => {
+                log::error!("Event stream failed to convert event: {}", e);
+                return
+            }
+        };
+
+        let event = event_data.get_enum_name();
+
+        let wakers = subscriptions.iter()
+            .filter(|subscription| subscription.event == event && subscription.matcher.match_event(&event_data))
+            .filter_map(|subscription| subscription.push(event_data.clone()))
+            .collect::<Vec<_>>();
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+}
+
+/// A stream of events
+///
+/// An `EventStream` is created by
+/// [`subscribe`](super::HostInterface::subscribe) or
+/// [`subscribe_with_matcher`](super::HostInterface::subscribe_with_matcher) of `HostInterface`.
+/// It returns every event received from the controller that matches the subscription, from the
+/// time it was created until it is dropped.
+///
+/// Received events are buffered until they are returned by the stream. The number of buffered
+/// events is limited by the capacity given when subscribing, when the buffer is full any further
+/// received events are dropped and an [`Overflow`] is returned by the stream in their place.
+///
+/// This has the same interface as a `Stream` of the futures crate, `poll_next` never returns
+/// `Ready(None)` as the stream does not end while it exists.
+pub struct EventStream {
+    subscription: Arc<Subscription>,
+    subscriptions: Arc<Subscriptions>,
+}
+
+impl EventStream {
+
+    pub(super) fn new<P>(
+        subscriptions: Arc<Subscriptions>,
+        event: events::Events,
+        matcher: P,
+        capacity: usize,
+        keep_latest: bool,
+    ) -> Self
+    where P: EventMatcher + 'static
+    {
+        let subscription = Arc::new(Subscription {
+            event,
+            matcher: Box::new(matcher),
+            capacity,
+            keep_latest,
+            buffer: SpinLock::new(Buffer { entries: VecDeque::new(), event_count: 0, waker: None }),
+        });
+
+        subscriptions.subscriptions.lock().push(subscription.clone());
+
+        EventStream { subscription, subscriptions }
+    }
+
+    /// Get the event that is subscribed to
+    pub fn get_event(&self) -> events::Events {
+        self.subscription.event
+    }
+
+    /// Take the next buffered event without waiting
+    ///
+    /// `None` is returned when there are no buffered events.
+    pub fn try_next_event(&self) -> Option<Result<events::EventsData, Overflow>> {
+        Self::pop_entry(&mut self.subscription.buffer.lock())
+    }
+
+    /// Poll for the next event
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<events::EventsData, Overflow>>> {
+        let mut buffer = self.subscription.buffer.lock();
+
+        match Self::pop_entry(&mut buffer) {
+            None => {
+                buffer.waker = Some(cx.waker().clone());
+
+                Poll::Pending
+            },
+            entry => Poll::Ready(entry),
+        }
+    }
+
+    fn pop_entry(buffer: &mut Buffer) -> Option<Result<events::EventsData, Overflow>> {
+        let entry = buffer.entries.pop_front();
+
+        if let Some(Ok(_)) = entry {
+            buffer.event_count -= 1;
+        }
+
+        entry
+    }
+
+    /// Get a future for the next event
+    pub fn next_event(&mut self) -> EventStreamNext<'_> {
+        EventStreamNext { stream: self }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.subscriptions.remove(&self.subscription)
+    }
+}
+
+/// The future returned by [`EventStream::next_event`]
+pub struct EventStreamNext<'a> {
+    stream: &'a mut EventStream,
+}
+
+impl Future for EventStreamNext<'_> {
+    type Output = Result<events::EventsData, Overflow>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut *self.get_mut().stream).poll_next(cx) {
+            Poll::Ready(Some(item)) => Poll::Ready(item),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hci::testing::fixture::host_interface;
+    use futures::executor::block_on;
+
+    #[test]
+    fn event_stream_test() {
+        let (hi, controller) = host_interface();
+
+        let mut stream = hi.subscribe(events::Events::DisconnectionComplete, 2);
+
+        let waker = futures::task::noop_waker();
+
+        let mut context = core::task::Context::from_waker(&waker);
+
+        let mut poll_next = |stream: &mut crate::hci::EventStream| {
+            match Pin::new(stream).poll_next(&mut context) {
+                core::task::Poll::Ready(Some(Ok(events::EventsData::DisconnectionComplete(data)))) =>
+                    Some(Ok(data.connection_handle.get_raw_handle())),
+                core::task::Poll::Ready(Some(Ok(_))) => panic!("Expected disconnection complete event"),
+                core::task::Poll::Ready(Some(Err(overflow))) => Some(Err(overflow)),
+                core::task::Poll::Ready(None) => panic!("Event stream ended"),
+                core::task::Poll::Pending => None,
+            }
+        };
+
+        assert_eq!(None, poll_next(&mut stream));
+
+        for raw_handle in 1..=4u8 {
+            controller.inject_event(events::Events::DisconnectionComplete, &[0, raw_handle, 0, 0x13]).unwrap();
+        }
+
+        assert_eq!(Some(Ok(1)), poll_next(&mut stream));
+        assert_eq!(Some(Ok(2)), poll_next(&mut stream));
+        assert_eq!(Some(Err(Overflow { dropped: 2 })), poll_next(&mut stream));
+        assert_eq!(None, poll_next(&mut stream));
+
+        controller.inject_event(events::Events::DisconnectionComplete, &[0, 5, 0, 0x13]).unwrap();
+
+        assert_eq!(Some(Ok(5)), poll_next(&mut stream));
+
+        // The stream is only an observer, the events can still be waited for
+        match block_on(hi.wait_for_event(events::Events::DisconnectionComplete, None)) {
+            Ok(events::EventsData::DisconnectionComplete(data)) =>
+                assert_eq!(1, data.connection_handle.get_raw_handle()),
+            _ => panic!("Expected disconnection complete event"),
+        }
+
+        drop(stream);
+
+        let mut stream = hi.subscribe_with_matcher(
+            events::Events::DisconnectionComplete,
+            4,
+            |data: &events::EventsData| match data {
+                events::EventsData::DisconnectionComplete(data) => data.connection_handle.get_raw_handle() == 7,
+                _ => false,
+            }
+        );
+
+        controller.inject_event(events::Events::DisconnectionComplete, &[0, 6, 0, 0x13]).unwrap();
+        controller.inject_event(events::Events::DisconnectionComplete, &[0, 7, 0, 0x13]).unwrap();
+
+        match block_on(stream.next_event()) {
+            Ok(events::EventsData::DisconnectionComplete(data)) =>
+                assert_eq!(7, data.connection_handle.get_raw_handle()),
+            _ => panic!("Expected disconnection complete event"),
+        }
+
+        assert!(Pin::new(&mut stream.next_event()).poll(&mut context).is_pending());
+    }
+
+    #[test]
+    fn listener_test() {
+        use crate::hci::ReceivedPacketSource;
+
+        struct Counter(SpinLock<usize>);
+
+        impl ReceivedPacketListener for Counter {
+            fn on_packet(&self, _: HciPacketType, _: &[u8]) { *self.0.lock() += 1 }
+        }
+
+        let (hi, controller) = host_interface();
+
+        let counter = Arc::new(Counter(SpinLock::new(0)));
+
+        hi.set_received_packet_listener(Some(counter.clone()));
+
+        // Subscribing does not replace the listener set on the host interface
+        let mut first = hi.subscribe(events::Events::DisconnectionComplete, 2);
+        let mut second = hi.subscribe(events::Events::DisconnectionComplete, 2);
+
+        controller.inject_event(events::Events::DisconnectionComplete, &[0, 1, 0, 0x13]).unwrap();
+
+        assert_eq!(1, *counter.0.lock());
+
+        assert!(block_on(first.next_event()).is_ok());
+        assert!(block_on(second.next_event()).is_ok());
+
+        hi.set_received_packet_listener(None);
+
+        controller.inject_event(events::Events::DisconnectionComplete, &[0, 2, 0, 0x13]).unwrap();
+
+        assert_eq!(1, *counter.0.lock());
+
+        assert!(block_on(first.next_event()).is_ok());
+    }
+}
@@ -165,11 +165,27 @@ macro_rules! chew_handle {
 
 type BufferType<T> = ::alloc::boxed::Box<T>;
 
-#[derive(Clone)]
+/// Event data for events that contain a list of items
+///
+/// The items are accessed by dereferencing to the slice of the items.
 pub struct Multiple<T: ?Sized> {
     data: BufferType<T>
 }
 
+impl<T: Clone> Clone for Multiple<[T]> {
+    fn clone(&self) -> Self {
+        Multiple { data: self.data.clone() }
+    }
+}
+
+impl<T: ?Sized> core::ops::Deref for Multiple<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
 #[derive(Clone)]
 pub enum PageScanRepitionMode {
     R0,
@@ -2828,7 +2844,7 @@ impl LEDirectedAdvertisingReportData {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
 pub enum LEPhy {
     _1M,
     _2M,
@@ -2836,7 +2852,7 @@ pub enum LEPhy {
 }
 
 impl LEPhy {
-    fn try_from( raw: u8 ) -> Result<Self, alloc::string::String> {
+    pub(crate) fn try_from( raw: u8 ) -> Result<Self, alloc::string::String> {
         match raw {
             0x01 => Ok(LEPhy::_1M),
             0x02 => Ok(LEPhy::_2M),
@@ -2844,6 +2860,14 @@ impl LEPhy {
             _ => Err(alloc::format!("Unknown LE Phy: {}", raw)),
         }
     }
+
+    pub(crate) fn into_val(self) -> u8 {
+        match self {
+            LEPhy::_1M => 0x01,
+            LEPhy::_2M => 0x02,
+            LEPhy::Coded => 0x03,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -3154,8 +3178,12 @@ impl LEPeriodicAdvertisingReportData {
                     None
                 }
             },
-            // There is a unused byte here, so the next chew needs to account for that
-            data_status: LEDataStatus::try_from(chew!(packet,1,1)[0])?,
+            // There is a unused byte before the data status
+            data_status: {
+                chew!(packet);
+
+                LEDataStatus::try_from(chew!(packet))?
+            },
             data: {
                 let len = chew!(packet) as usize;
                 packet[..len].to_vec().into_boxed_slice()
@@ -3166,7 +3194,7 @@ impl LEPeriodicAdvertisingReportData {
 
 #[derive(Clone)]
 pub struct LEPeriodicAdvertisingSyncLostData {
-    sync_handle: ConnectionHandle,
+    pub sync_handle: ConnectionHandle,
 }
 
 impl LEPeriodicAdvertisingSyncLostData {
@@ -3204,9 +3232,9 @@ impl LEAdvertisingSetTerminatedData {
 
 #[derive(Clone)]
 pub struct LEScanRequestReceivedData {
-    advertising_handle: u8,
-    scanner_address_type: LEAddressType,
-    scanner_address: BluetoothDeviceAddress,
+    pub advertising_handle: u8,
+    pub scanner_address_type: LEAddressType,
+    pub scanner_address: BluetoothDeviceAddress,
 }
 
 impl LEScanRequestReceivedData {
@@ -3326,6 +3354,32 @@ impl LEMeta {
             _    => Err(alloc::format!("Unknown LE Meta: {}", raw)),
         }
     }
+
+    /// Get the subevent code of the LE Meta event
+    pub fn get_val(&self) -> u8 {
+        match *self {
+            LEMeta::ConnectionComplete => 0x01,
+            LEMeta::AdvertisingReport => 0x02,
+            LEMeta::ConnectionUpdateComplete => 0x03,
+            LEMeta::ReadRemoteFeaturesComplete => 0x04,
+            LEMeta::LongTermKeyRequest => 0x05,
+            LEMeta::RemoteConnectionParameterRequest => 0x06,
+            LEMeta::DataLengthChange => 0x07,
+            LEMeta::ReadLocalP256PublicKeyComplete => 0x08,
+            LEMeta::GenerateDHKeyComplete => 0x09,
+            LEMeta::EnhancedConnectionComplete => 0x0A,
+            LEMeta::DirectedAdvertisingReport => 0x0B,
+            LEMeta::PHYUpdateComplete => 0x0C,
+            LEMeta::ExtendedAdvertisingReport => 0x0D,
+            LEMeta::PeriodicAdvertisingSyncEstablished => 0x0E,
+            LEMeta::PeriodicAdvertisingReport => 0x0F,
+            LEMeta::PeriodicAdvertisingSyncLost => 0x10,
+            LEMeta::ScanTimeout => 0x11,
+            LEMeta::AdvertisingSetTerminated => 0x12,
+            LEMeta::ScanRequestReceived => 0x13,
+            LEMeta::ChannelSelectionAlgorithm => 0x14,
+        }
+    }
 }
 
 impl LEMetaData {
@@ -3513,6 +3567,23 @@ impl_try_from_for_raw_packet! {
     }
 }
 
+/// The data of a vendor specific event
+///
+/// The contents of a vendor specific event are defined by the manufacturer of the controller, so
+/// the event parameter is kept as is.
+#[derive(Clone)]
+pub struct VendorSpecificData {
+    pub data: alloc::vec::Vec<u8>,
+}
+
+impl_try_from_for_raw_packet! {
+    VendorSpecificData,
+    packet,
+    {
+        Ok(VendorSpecificData { data: packet.to_vec() })
+    }
+}
+
 macro_rules! put_ { ( $t:tt ) => { _ } }
 
 macro_rules! data_into_simple { ($unused_rpt:tt, $data_var:expr) => { $data_var.into_simple() } }
@@ -3524,7 +3595,7 @@ macro_rules! events_markup {
 
         enumerate_split! {
             #[derive(Debug,Hash,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
-            pub enum $EnumName ( enum $EnumDataName ){
+            pub enum $EnumName ( #[derive(Clone)] enum $EnumDataName ){
                 $( $name $(( $($enum_val),* ))* {$data $(< $type >)*}, )*
             }
         }
@@ -3682,5 +3753,6 @@ events_markup! {
         InquiryResponseNotification{InquiryResponseNotificationData} -> 0x56,
         AuthenticatedPayloadTimeoutExpired{AuthenticatedPayloadTimeoutExpiredData} -> 0x57,
         SAMStatusChange{SAMStatusChangeData} -> 0x58,
+        VendorSpecific{VendorSpecificData} -> 0xFF,
     }
 }
@@ -0,0 +1,1050 @@
+//! Flow control of the packets sent from the host to the controller
+//!
+//! The controller tells the host how many commands it can accept with the Num_HCI_Command_Packets
+//! field of the *Command Complete* and *Command Status* events (v5.0 | Vol 2, Part E, 4.4). The
+//! host starts out assuming it can send one command, and the count of commands that can be sent
+//! (the command *credits*) is set to the value in the response of every command. The controller
+//! can also change the count without responding to a command by sending a *Command Complete*
+//! event with the opcode 0x0000 (no operation).
+//!
+//! ACL data is flow controlled by the number of data buffers of the controller (v5.0 | Vol 2,
+//! Part E, 4.1.1). Every ACL data packet sent to the controller takes one buffer and the buffers
+//! are returned to the host by the *Number Of Completed Packets* event. The buffers of the packets
+//! not yet completed when a connection is disconnected are freed by the controller, these are
+//! returned by the *Disconnection Complete* event.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use super::{common::ConnectionHandle, events, EventMatcher, EventStream, HciAclData, HostControllerInterface};
+use crate::timer::Timeout;
+use super::spin_lock::SpinLock;
+
+/// A command waiting for a credit
+struct Queued {
+    ticket: usize,
+    opcode: u16,
+    waker: Waker,
+}
+
+/// Matcher for the response to a command
+///
+/// The *Command Complete* event used by the controller to only update the number of credits has
+/// the opcode 0x0000, so it is matched with an `opcode` of zero.
+pub(super) struct CommandMatcher {
+    pub(super) opcode: u16,
+    /// Set once the response was received, after this no event is matched
+    received: AtomicBool,
+}
+
+impl CommandMatcher {
+
+    pub(super) fn new(opcode: u16) -> Self {
+        CommandMatcher { opcode, received: AtomicBool::new(false) }
+    }
+
+    /// Stop matching events
+    ///
+    /// This is for a response that is waited for as either of two events. The interface still has
+    /// the expectation for the other event, and it must not take the response to a later command
+    /// with the same opcode.
+    pub(super) fn set_received(&self) {
+        self.received.store(true, Ordering::Relaxed)
+    }
+}
+
+impl EventMatcher for CommandMatcher {
+    fn match_event(&self, event_data: &events::EventsData) -> bool {
+        if self.received.load(Ordering::Relaxed) { return false }
+
+        match event_data {
+            events::EventsData::CommandComplete(data) => data.command_opcode.unwrap_or_default() == self.opcode,
+            events::EventsData::CommandStatus(data) => data.command_opcode.unwrap_or_default() == self.opcode,
+            _ => false,
+        }
+    }
+}
+
+/// An expectation of a command response that the command no longer waits for
+///
+/// This is a command that was dropped while waiting for its response. The opcode of the command
+/// stays reserved until the response is received (or the response times out), otherwise the
+/// response could be taken as the response to the next command with the same opcode.
+///
+/// The response to a command sent by its opcode is waited for as either a *Command Complete* or a
+/// *Command Status* event. Once it is received the expectation of the other event is also kept
+/// here (with `reserved` set to false) until the interface times it out.
+pub(super) struct Abandoned {
+    pub(super) event: events::Events,
+    /// The other event the response can be received as
+    pub(super) alternate_event: Option<events::Events>,
+    pub(super) matcher: Pin<Arc<CommandMatcher>>,
+    pub(super) timeout: Option<Duration>,
+    pub(super) response_timeout: Option<Timeout>,
+    /// The opcode of the command is reserved
+    pub(super) reserved: bool,
+}
+
+impl Abandoned {
+    /// Create the expectation of the event the response was not received as
+    ///
+    /// After the response to a command is received as `received`, the matcher is set to no longer
+    /// match any event so the expectation of the other event cannot take the response to a later
+    /// command with the same opcode. `None` is returned if there is no other event or no timeout
+    /// for the interface to remove the expectation with.
+    pub(super) fn other_event(
+        received: events::Events,
+        event: events::Events,
+        alternate_event: Option<events::Events>,
+        matcher: &Pin<Arc<CommandMatcher>>,
+        timeout: Option<Duration>,
+        response_timeout: &mut Option<Timeout>,
+    ) -> Option<Self>
+    {
+        let alternate_event = alternate_event?;
+
+        matcher.set_received();
+
+        timeout?;
+
+        Some(Abandoned {
+            event: if received == event { alternate_event } else { event },
+            alternate_event: None,
+            matcher: matcher.clone(),
+            timeout,
+            response_timeout: response_timeout.take(),
+            reserved: false,
+        })
+    }
+}
+
+/// Command flow control
+///
+/// Besides waiting for a credit, a command is also held back while another command with the same
+/// opcode is waiting for its response. The response events only identify the command by the
+/// opcode, so this makes sure every response is routed to the command that it is for. Commands
+/// are sent in the order they first tried to be sent, unless the command before them is held back
+/// by its opcode.
+pub(super) struct CommandFlowControl {
+    credits: usize,
+    /// The opcodes of the commands waiting for their response
+    pending: Vec<u16>,
+    queue: VecDeque<Queued>,
+    next_ticket: usize,
+    /// The commands dropped while waiting for their response
+    abandoned: Vec<Abandoned>,
+    /// The matcher for the *Command Complete* events that only update the number of credits
+    nop_matcher: Pin<Arc<CommandMatcher>>,
+}
+
+impl CommandFlowControl {
+
+    pub(super) fn new() -> Self {
+        CommandFlowControl {
+            credits: 1,
+            pending: Vec::new(),
+            queue: VecDeque::new(),
+            next_ticket: 0,
+            abandoned: Vec::new(),
+            nop_matcher: Arc::pin(CommandMatcher::new(0)),
+        }
+    }
+
+    /// Try to acquire a credit to send a command
+    ///
+    /// The command is queued under the returned ticket the first time this is called. If true is
+    /// returned the command can be sent, then either [`sent`](CommandFlowControl::sent) or
+    /// [`unsent`](CommandFlowControl::unsent) must be called depending on whether the interface
+    /// sent the command. Otherwise the waker is woken when the command should try again.
+    pub(super) fn acquire(&mut self, ticket: &mut Option<usize>, opcode: u16, waker: &Waker) -> bool {
+        let ticket = match *ticket {
+            Some(ticket) => ticket,
+            None => {
+                let new_ticket = self.next_ticket;
+
+                self.queue.push_back(Queued { ticket: new_ticket, opcode, waker: waker.clone() });
+
+                self.next_ticket = self.next_ticket.wrapping_add(1);
+
+                *ticket = Some(new_ticket);
+
+                new_ticket
+            },
+        };
+
+        let pending = &self.pending;
+
+        let blocked_by_queue = self.queue.iter()
+            .take_while(|queued| queued.ticket != ticket)
+            .any(|queued| !pending.contains(&queued.opcode));
+
+        if self.credits > 0 && !self.pending.contains(&opcode) && !blocked_by_queue {
+            self.credits -= 1;
+
+            self.pending.push(opcode);
+
+            true
+        } else {
+            self.queue.iter_mut()
+                .filter(|queued| queued.ticket == ticket)
+                .for_each(|queued| queued.waker = waker.clone());
+
+            false
+        }
+    }
+
+    /// Remove a command that was sent from the queue
+    ///
+    /// [`release`](CommandFlowControl::release) must be called once the response is received or
+    /// the command is abandoned.
+    pub(super) fn sent(&mut self, ticket: usize) {
+        self.queue.retain(|queued| queued.ticket != ticket);
+    }
+
+    /// Return the credit of a command that the interface did not send
+    ///
+    /// The command keeps its place in the queue.
+    pub(super) fn unsent(&mut self, opcode: u16) {
+        self.remove_pending(opcode);
+
+        self.credits += 1;
+    }
+
+    /// Release the credit of a command
+    ///
+    /// The input `credits` is the Num_HCI_Command_Packets field of the response to the command. If
+    /// the response does not contain this field (or the command was abandoned) the credit used to
+    /// send the command is returned instead.
+    ///
+    /// The returned wakers are for the queued commands and must be woken by the caller.
+    #[must_use]
+    pub(super) fn release(&mut self, opcode: u16, credits: Option<u8>) -> Vec<Waker> {
+        self.remove_pending(opcode);
+
+        match credits {
+            Some(credits) => self.credits = credits.into(),
+            None => self.credits += 1,
+        }
+
+        self.wakers()
+    }
+
+    /// Set the number of credits from a *Command Complete* event with the opcode 0x0000
+    ///
+    /// The returned wakers are for the queued commands and must be woken by the caller.
+    #[must_use]
+    pub(super) fn update_credits(&mut self, credits: u8) -> Vec<Waker> {
+        self.credits = credits.into();
+
+        self.wakers()
+    }
+
+    /// Get the matcher for the *Command Complete* events with the opcode 0x0000
+    pub(super) fn get_nop_matcher(&self) -> Pin<Arc<CommandMatcher>> {
+        self.nop_matcher.clone()
+    }
+
+    /// Keep the opcode of a command dropped while waiting for its response reserved
+    pub(super) fn abandon(&mut self, command: Abandoned) {
+        self.abandoned.push(command)
+    }
+
+    /// Take the commands dropped while waiting for their response
+    ///
+    /// Every command that is still waiting for its response must be given back with
+    /// [`abandon`](CommandFlowControl::abandon).
+    pub(super) fn take_abandoned(&mut self) -> Vec<Abandoned> {
+        core::mem::take(&mut self.abandoned)
+    }
+
+    /// Remove a queued command
+    ///
+    /// A queued command may be holding back the commands queued after it, so the returned wakers
+    /// must be woken by the caller.
+    #[must_use]
+    pub(super) fn dequeue(&mut self, ticket: usize) -> Vec<Waker> {
+        self.queue.retain(|queued| queued.ticket != ticket);
+
+        self.wakers()
+    }
+
+    fn remove_pending(&mut self, opcode: u16) {
+        if let Some(index) = self.pending.iter().position(|pending| *pending == opcode) {
+            self.pending.swap_remove(index);
+        }
+    }
+
+    fn wakers(&self) -> Vec<Waker> {
+        self.queue.iter().map(|queued| queued.waker.clone()).collect()
+    }
+}
+
+/// ACL data flow control
+///
+/// Flow control is disabled until the buffer size of the controller is known. Until then ACL data
+/// is sent to the controller as soon as it is queued.
+pub(super) struct AclFlowControl {
+    /// The maximum length of the payload of an ACL data packet
+    packet_len: Option<usize>,
+    /// The number of free data buffers of the controller, this is `None` while flow control is
+    /// disabled
+    credits: Option<usize>,
+    /// The number of packets sent for each connection that are not yet completed
+    outstanding: BTreeMap<ConnectionHandle, usize>,
+    queue: VecDeque<HciAclData>,
+}
+
+impl AclFlowControl {
+
+    fn new() -> Self {
+        AclFlowControl {
+            packet_len: None,
+            credits: None,
+            outstanding: BTreeMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Enable flow control for the buffer size of the controller
+    ///
+    /// Packets already sent to the controller are taken from the number of free buffers.
+    pub(super) fn enable(&mut self, packet_len: u16, packet_count: u16) {
+        let outstanding: usize = self.outstanding.values().sum();
+
+        self.packet_len = Some(packet_len.into());
+
+        self.credits = Some(usize::from(packet_count).saturating_sub(outstanding));
+    }
+
+    pub(super) fn get_packet_len(&self) -> Option<usize> {
+        self.packet_len
+    }
+
+    pub(super) fn push(&mut self, packet: HciAclData) {
+        self.queue.push_back(packet)
+    }
+
+    /// Get the next queued packet that can be sent to the controller
+    pub(super) fn next(&mut self) -> Option<HciAclData> {
+        if let Some(0) = self.credits {
+            return None;
+        }
+
+        let packet = self.queue.pop_front()?;
+
+        if let Some(ref mut credits) = self.credits {
+            *credits -= 1;
+        }
+
+        *self.outstanding.entry(*packet.get_handle()).or_default() += 1;
+
+        Some(packet)
+    }
+
+    /// Return the buffers of completed packets
+    ///
+    /// The number of completed packets is capped to the number of packets sent for the connection
+    /// so that buffers are not returned twice for a connection that was already removed.
+    pub(super) fn complete(&mut self, handle: ConnectionHandle, completed: u16) {
+        if let Some(outstanding) = self.outstanding.get_mut(&handle) {
+            let completed = core::cmp::min(*outstanding, completed.into());
+
+            *outstanding -= completed;
+
+            if let Some(ref mut credits) = self.credits {
+                *credits += completed;
+            }
+        }
+    }
+
+    /// Return the buffer taken by a packet that could not be sent to the controller
+    pub(super) fn unsent(&mut self, handle: ConnectionHandle) {
+        self.complete(handle, 1)
+    }
+
+    /// Check if there is a queued packet that can be sent to the controller
+    pub(super) fn can_send(&self) -> bool {
+        !self.queue.is_empty() && self.credits != Some(0)
+    }
+
+    /// Remove a disconnected connection
+    ///
+    /// The controller frees the buffers of a connection that is disconnected, so the buffers used
+    /// by the packets of the connection are returned and any packets still queued are dropped.
+    pub(super) fn disconnected(&mut self, handle: ConnectionHandle) {
+        if let Some(outstanding) = self.outstanding.remove(&handle) {
+            if let Some(ref mut credits) = self.credits {
+                *credits += outstanding;
+            }
+        }
+
+        self.drop_queued(handle);
+    }
+
+    /// Drop the packets of a connection that are still queued
+    ///
+    /// The packets already sent keep their buffers until they are completed or the connection is
+    /// disconnected.
+    pub(super) fn drop_queued(&mut self, handle: ConnectionHandle) {
+        self.queue.retain(|packet| *packet.get_handle() != handle);
+    }
+
+    /// Check if there are any packets of a connection that are queued
+    pub(super) fn is_queued(&self, handle: ConnectionHandle) -> bool {
+        self.queue.iter().any(|packet| *packet.get_handle() == handle)
+    }
+}
+
+/// The number of *Number Of Completed Packets* events buffered for ACL data flow control
+///
+/// Every event completes at least one packet and the controller cannot have more than 255 LE ACL
+/// data packets outstanding, so the buffer can only overflow while flow control is disabled.
+pub(super) const COMPLETED_PACKETS_CAPACITY: usize = 256;
+
+/// The number of *Disconnection Complete* events buffered for ACL data flow control
+pub(super) const DISCONNECTIONS_CAPACITY: usize = 16;
+
+/// A matcher for any event
+struct MatchAll;
+
+impl EventMatcher for MatchAll {
+    fn match_event(&self, _: &events::EventsData) -> bool { true }
+}
+
+/// The subscriptions to the events used for ACL data flow control
+pub(super) struct FlowEvents {
+    pub(super) completed_packets: EventStream,
+    pub(super) disconnections: EventStream,
+}
+
+/// The shared state of ACL data flow control
+///
+/// This is also the waker used for receiving *Number Of Completed Packets* events. When the event
+/// is received every task waiting on the queue of ACL data is woken.
+pub(super) struct AclFlow {
+    pub(super) control: SpinLock<AclFlowControl>,
+    wakers: SpinLock<Vec<Waker>>,
+    /// The subscriptions to the flow control events, these are created with the first connection
+    /// channel made from a host interface that is a received packet source
+    pub(super) events: SpinLock<Option<FlowEvents>>,
+    /// The matcher for the *Number Of Completed Packets* events taken from the interface when
+    /// there are no subscriptions
+    matcher: Pin<Arc<MatchAll>>,
+    /// Set while a connection channel is sending the queued packets
+    pub(super) sending: AtomicBool,
+}
+
+impl AclFlow {
+
+    pub(super) fn new() -> Self {
+        AclFlow {
+            control: SpinLock::new(AclFlowControl::new()),
+            wakers: SpinLock::new(Vec::new()),
+            events: SpinLock::new(None),
+            matcher: Arc::pin(MatchAll),
+            sending: AtomicBool::new(false),
+        }
+    }
+
+    /// Return the buffers of the packets completed by the received *Number Of Completed Packets*
+    /// events and of the connections disconnected by the received *Disconnection Complete* events
+    ///
+    /// Without the subscriptions the *Number Of Completed Packets* events are taken from
+    /// `interface`, and disconnections are not seen. The waiting tasks are woken when the next
+    /// event is received.
+    pub(super) fn receive_completed<I>(self: &Arc<Self>, interface: &I) where I: HostControllerInterface {
+        let waker = Waker::from(self.clone());
+
+        let mut context = Context::from_waker(&waker);
+
+        let mut flow_events = self.events.lock();
+
+        match flow_events.as_mut() {
+            Some(flow_events) => {
+                while let Poll::Ready(Some(entry)) = Pin::new(&mut flow_events.completed_packets).poll_next(&mut context) {
+                    match entry {
+                        Ok(event_data) => self.process(event_data),
+                        Err(overflow) => log::error!("Number Of Completed Packets events: {}", overflow),
+                    }
+                }
+
+                while let Poll::Ready(Some(entry)) = Pin::new(&mut flow_events.disconnections).poll_next(&mut context) {
+                    match entry {
+                        Ok(event_data) => self.process(event_data),
+                        Err(overflow) => log::error!("Disconnection Complete events: {}", overflow),
+                    }
+                }
+            },
+            None => while let Some(result) = interface.receive_event(
+                events::Events::NumberOfCompletedPackets,
+                &waker,
+                self.matcher.clone(),
+                None)
+            {
+                match result {
+                    Ok(event_data) => self.process(event_data),
+                    Err(e) => {
+                        log::error!("Failed to receive Number Of Completed Packets event: {}", e);
+                        break
+                    }
+                }
+            },
+        }
+    }
+
+    fn process(&self, event_data: events::EventsData) {
+        match event_data {
+            events::EventsData::NumberOfCompletedPackets(completed) => {
+                let mut control = self.control.lock();
+
+                completed.iter()
+                    .for_each(|data| control.complete(data.connection_handle, data.number_of_completed_packets))
+            },
+            events::EventsData::DisconnectionComplete(data) if matches!(data.status, super::error::Error::NoError) =>
+                self.control.lock().disconnected(data.connection_handle),
+            _ => (),
+        }
+    }
+
+    /// Add a waker to be woken when buffers are returned by the controller
+    pub(super) fn add_waker(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone())
+        }
+    }
+
+    pub(super) fn remove_waker(&self, waker: &Waker) {
+        self.wakers.lock().retain(|w| !w.will_wake(waker))
+    }
+}
+
+impl Wake for AclFlow {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = core::mem::take(&mut *self.wakers.lock());
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hci::{events, CommandParameter, EventMatcher, HostControllerInterface, HostInterface};
+    use crate::hci::cb::reset;
+    use crate::hci::info_params::read_bd_addr;
+    use alloc::sync::Arc;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::Context;
+    use core::time::Duration;
+    use futures::task::noop_waker;
+    use crate::hci::testing::fixture::{host_interface, CONNECTION_COMPLETE};
+    use crate::hci::testing::{
+        ACL_DATA_PACKET_LENGTH,
+        LE_ACL_DATA_PACKET_LENGTH,
+        TOTAL_NUM_ACL_DATA_PACKETS,
+        TOTAL_NUM_LE_ACL_DATA_PACKETS,
+    };
+    use futures::executor::block_on;
+    use std::sync::Mutex;
+
+    const RESET: u16 = 0x0C03;
+    const READ_BD_ADDR: u16 = 0x1009;
+
+    /// A controller that only answers commands when told to by the test
+    #[derive(Default)]
+    struct Controller {
+        sent: Mutex<Vec<u16>>,
+        events: Mutex<Vec<events::EventsData>>,
+        /// Commands are not sent while this is true
+        busy: Mutex<bool>,
+    }
+
+    impl Controller {
+        fn take_sent(&self) -> Vec<u16> {
+            core::mem::take(&mut *self.sent.lock().unwrap())
+        }
+
+        fn command_complete(&self, credits: u8, opcode: u16, return_parameter: &[u8]) {
+            let mut packet = vec![0x0E, 3 + return_parameter.len() as u8, credits];
+
+            packet.extend_from_slice(&opcode.to_le_bytes());
+            packet.extend_from_slice(return_parameter);
+
+            self.events.lock().unwrap().push(events::EventsData::from_packet(&packet).unwrap());
+        }
+    }
+
+    impl HostControllerInterface for Controller {
+        type SendCommandError = &'static str;
+        type ReceiveEventError = &'static str;
+
+        fn send_command<D,W>(&self, cmd_data: &D, _: W) -> Result<bool, Self::SendCommandError>
+        where D: CommandParameter,
+              W: Into<Option<Waker>>
+        {
+            if *self.busy.lock().unwrap() {
+                return Ok(false)
+            }
+
+            let packet = cmd_data.as_command_packet();
+
+            self.sent.lock().unwrap().push(u16::from_le_bytes([packet[0], packet[1]]));
+
+            Ok(true)
+        }
+
+        fn receive_event<P>(&self, event: events::Events, _: &Waker, matcher: Pin<Arc<P>>, _: Option<Duration>)
+        -> Option<Result<events::EventsData, Self::ReceiveEventError>>
+        where P: EventMatcher + Send + Sync + 'static
+        {
+            let mut events = self.events.lock().unwrap();
+
+            events.iter()
+                .position(|data| data.get_enum_name() == event && matcher.match_event(data))
+                .map(|index| Ok(events.remove(index)))
+        }
+    }
+
+    #[test]
+    fn command_flow_control_test() {
+        let hi = HostInterface::from(Controller::default());
+
+        let controller = hi.get_native_interface();
+
+        let waker = noop_waker();
+
+        let mut cx = Context::from_waker(&waker);
+
+        let mut first_reset = Box::pin(reset::send(&hi));
+        let mut read_address = Box::pin(read_bd_addr::send(&hi));
+        let mut second_reset = Box::pin(reset::send(&hi));
+
+        // There is only one credit to start with
+        assert!(first_reset.as_mut().poll(&mut cx).is_pending());
+        assert!(read_address.as_mut().poll(&mut cx).is_pending());
+        assert!(second_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET], controller.take_sent());
+
+        controller.command_complete(2, RESET, &[0]);
+
+        assert!(first_reset.as_mut().poll(&mut cx).is_ready());
+
+        // Queued commands are sent in order
+        assert!(second_reset.as_mut().poll(&mut cx).is_pending());
+        assert!(controller.take_sent().is_empty());
+
+        assert!(read_address.as_mut().poll(&mut cx).is_pending());
+        assert!(second_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![READ_BD_ADDR, RESET], controller.take_sent());
+
+        controller.command_complete(3, READ_BD_ADDR, &[0, 1, 2, 3, 4, 5, 6]);
+
+        assert!(read_address.as_mut().poll(&mut cx).is_ready());
+
+        // A command is not sent while a command with the same opcode waits for its response
+        let mut third_reset = Box::pin(reset::send(&hi));
+
+        assert!(third_reset.as_mut().poll(&mut cx).is_pending());
+        assert!(controller.take_sent().is_empty());
+
+        controller.command_complete(1, RESET, &[0]);
+
+        assert!(second_reset.as_mut().poll(&mut cx).is_ready());
+        assert!(third_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET], controller.take_sent());
+    }
+
+    #[test]
+    fn abandoned_command_test() {
+        let hi = HostInterface::from(Controller::default());
+
+        let waker = noop_waker();
+
+        let mut cx = Context::from_waker(&waker);
+
+        let mut sent = Box::pin(reset::send(&hi));
+        let mut queued = Box::pin(read_bd_addr::send(&hi));
+        let mut last = Box::pin(read_bd_addr::send(&hi));
+
+        assert!(sent.as_mut().poll(&mut cx).is_pending());
+        assert!(queued.as_mut().poll(&mut cx).is_pending());
+        assert!(last.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET], hi.get_native_interface().take_sent());
+
+        // The place of a queued command is given up when it is dropped, but the credit of a sent
+        // command is kept until its response is received
+        drop(sent);
+        drop(queued);
+
+        assert!(last.as_mut().poll(&mut cx).is_pending());
+
+        assert!(hi.get_native_interface().take_sent().is_empty());
+
+        hi.get_native_interface().command_complete(1, RESET, &[0]);
+
+        assert!(last.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![READ_BD_ADDR], hi.get_native_interface().take_sent());
+
+        // The response to a dropped command is not taken as the response to the next command with
+        // the same opcode
+        let mut dropped = Box::pin(reset::send(&hi));
+        let mut next = Box::pin(reset::send(&hi));
+
+        hi.get_native_interface().command_complete(1, READ_BD_ADDR, &[0, 1, 2, 3, 4, 5, 6]);
+
+        assert!(last.as_mut().poll(&mut cx).is_ready());
+        assert!(dropped.as_mut().poll(&mut cx).is_pending());
+        assert!(next.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET], hi.get_native_interface().take_sent());
+
+        drop(dropped);
+
+        hi.get_native_interface().command_complete(1, RESET, &[0]);
+
+        assert!(next.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET], hi.get_native_interface().take_sent());
+    }
+
+    #[test]
+    fn credit_update_test() {
+        let hi = HostInterface::from(Controller::default());
+
+        let controller = hi.get_native_interface();
+
+        let waker = noop_waker();
+
+        let mut cx = Context::from_waker(&waker);
+
+        let mut first_reset = Box::pin(reset::send(&hi));
+
+        assert!(first_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET], controller.take_sent());
+
+        controller.command_complete(0, RESET, &[0]);
+
+        assert!(first_reset.as_mut().poll(&mut cx).is_ready());
+
+        let mut read_address = Box::pin(read_bd_addr::send(&hi));
+        let mut second_reset = Box::pin(reset::send(&hi));
+
+        assert!(read_address.as_mut().poll(&mut cx).is_pending());
+        assert!(second_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert!(controller.take_sent().is_empty());
+
+        // No operation (opcode 0x0000) Command Complete event giving two credits
+        controller.command_complete(2, 0, &[]);
+
+        assert!(read_address.as_mut().poll(&mut cx).is_pending());
+        assert!(second_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![READ_BD_ADDR, RESET], controller.take_sent());
+    }
+
+    #[test]
+    fn unsent_command_test() {
+        let hi = HostInterface::from(Controller::default());
+
+        let controller = hi.get_native_interface();
+
+        let waker = noop_waker();
+
+        let mut cx = Context::from_waker(&waker);
+
+        let mut first_reset = Box::pin(reset::send(&hi));
+
+        assert!(first_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET], controller.take_sent());
+
+        controller.command_complete(2, RESET, &[0]);
+
+        assert!(first_reset.as_mut().poll(&mut cx).is_ready());
+
+        *controller.busy.lock().unwrap() = true;
+
+        let mut read_address = Box::pin(read_bd_addr::send(&hi));
+        let mut second_reset = Box::pin(reset::send(&hi));
+
+        assert!(read_address.as_mut().poll(&mut cx).is_pending());
+
+        *controller.busy.lock().unwrap() = false;
+
+        // The command not sent by the interface keeps both its credit and its place in the queue
+        assert!(second_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert!(controller.take_sent().is_empty());
+
+        assert!(read_address.as_mut().poll(&mut cx).is_pending());
+        assert!(second_reset.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![READ_BD_ADDR, RESET], controller.take_sent());
+    }
+
+    #[test]
+    fn command_timeout_test() {
+        use crate::timer::ManualClock;
+
+        let clock = ManualClock::new();
+
+        let hi = HostInterface::from(Controller::default());
+
+        hi.set_timer(clock.clone());
+
+        let waker = noop_waker();
+
+        let mut cx = Context::from_waker(&waker);
+
+        let mut timed_out = Box::pin(reset::send(&hi));
+        let mut queued = Box::pin(read_bd_addr::send(&hi));
+
+        assert!(timed_out.as_mut().poll(&mut cx).is_pending());
+        assert!(queued.as_mut().poll(&mut cx).is_pending());
+
+        clock.advance(Duration::from_millis(999));
+
+        assert!(timed_out.as_mut().poll(&mut cx).is_pending());
+
+        clock.advance(Duration::from_millis(1));
+
+        match timed_out.as_mut().poll(&mut cx) {
+            core::task::Poll::Ready(Err(_)) => (),
+            _ => panic!("Expected the command to time out"),
+        }
+
+        // The credit of the timed out command is given to the queued command
+        assert!(queued.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(vec![RESET, READ_BD_ADDR], hi.get_native_interface().take_sent());
+    }
+
+    #[test]
+    fn acl_flow_control_test() {
+        use crate::hci::le::mandatory::read_buffer_size;
+        use crate::l2cap::{AclData, ChannelIdentifier, ConnectionChannel, LeUserChannelIdentifier};
+        use core::future::Future;
+
+        let (hi, controller) = host_interface();
+
+        let buffer_size = block_on(read_buffer_size::send(&hi)).unwrap();
+
+        assert_eq!(Some(LE_ACL_DATA_PACKET_LENGTH), buffer_size.packet_len);
+        assert_eq!(Some(TOTAL_NUM_LE_ACL_DATA_PACKETS), buffer_size.packet_cnt);
+
+        controller.inject_le_meta_event(events::LEMeta::ConnectionComplete, &CONNECTION_COMPLETE).unwrap();
+
+        let data = match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+            Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) => data,
+            _ => panic!("Expected LE connection complete event"),
+        };
+
+        let channel = hi.new_subscribed_le_acl_connection_channel(&data);
+
+        let waker = futures::task::noop_waker();
+
+        let mut context = core::task::Context::from_waker(&waker);
+
+        let pdu = || AclData::new(vec![1, 2, 3], ChannelIdentifier::LE(LeUserChannelIdentifier::AttributeProtocol));
+
+        for _ in 0..5 { channel.send(pdu()); }
+
+        let mut flush = channel.send(pdu());
+
+        assert_eq!(4, controller.take_sent_acl_data().len());
+        assert!(Pin::new(&mut flush).poll(&mut context).is_pending());
+
+        // Number Of Completed Packets event freeing two buffers of connection handle 0x40
+        controller.inject_event(events::Events::NumberOfCompletedPackets, &[1, 0x40, 0x00, 2, 0]).unwrap();
+
+        assert!(matches!(Pin::new(&mut flush).poll(&mut context), core::task::Poll::Ready(Ok(()))));
+        assert_eq!(2, controller.take_sent_acl_data().len());
+
+        // The events used for flow control are still given to the host
+        assert!(block_on(hi.wait_for_event(events::Events::NumberOfCompletedPackets, None)).is_ok());
+    }
+
+    #[test]
+    fn shared_acl_flow_control_test() {
+        use crate::hci::le::mandatory::read_buffer_size;
+        use crate::l2cap::{AclData, ChannelIdentifier, ConnectionChannel, LeUserChannelIdentifier};
+
+        let (hi, controller) = host_interface();
+
+        controller.set_shared_data_buffers(true);
+
+        let buffer_size = block_on(read_buffer_size::send(&hi)).unwrap();
+
+        assert_eq!(None, buffer_size.packet_len);
+        assert_eq!(None, buffer_size.packet_cnt);
+
+        controller.inject_le_meta_event(events::LEMeta::ConnectionComplete, &CONNECTION_COMPLETE).unwrap();
+
+        let data = match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+            Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) => data,
+            _ => panic!("Expected LE connection complete event"),
+        };
+
+        let channel = hi.new_subscribed_le_acl_connection_channel(&data);
+
+        let waker = futures::task::noop_waker();
+
+        let mut context = core::task::Context::from_waker(&waker);
+
+        let channel_id = ChannelIdentifier::LE(LeUserChannelIdentifier::AttributeProtocol);
+
+        // The ACL data buffers of the *Read Buffer Size* command are used for flow control
+        let mut flush = channel.send(AclData::new(vec![0; 100], channel_id));
+
+        let sent = controller.take_sent_acl_data();
+
+        assert_eq!(2, sent.len());
+        assert_eq!(ACL_DATA_PACKET_LENGTH as usize, sent[0].get_payload().len());
+        assert!(Pin::new(&mut flush).poll(&mut context).is_ready());
+
+        for _ in TOTAL_NUM_ACL_DATA_PACKETS..4 {
+            channel.send(AclData::new(vec![1, 2, 3], channel_id));
+        }
+
+        let mut flush = channel.send(AclData::new(vec![1, 2, 3], channel_id));
+
+        assert_eq!(1, controller.take_sent_acl_data().len());
+        assert!(Pin::new(&mut flush).poll(&mut context).is_pending());
+
+        controller.inject_event(events::Events::NumberOfCompletedPackets, &[1, 0x40, 0x00, 3, 0]).unwrap();
+
+        assert!(matches!(Pin::new(&mut flush).poll(&mut context), core::task::Poll::Ready(Ok(()))));
+        assert_eq!(1, controller.take_sent_acl_data().len());
+    }
+
+    #[test]
+    fn disconnection_acl_flow_control_test() {
+        use crate::hci::le::mandatory::read_buffer_size;
+        use crate::l2cap::{AclData, ChannelIdentifier, ConnectionChannel, LeUserChannelIdentifier};
+
+        let (hi, controller) = host_interface();
+
+        block_on(read_buffer_size::send(&hi)).unwrap();
+
+        let mut connection_complete = CONNECTION_COMPLETE;
+
+        let mut connect = |raw_handle: u8| {
+            connection_complete[1] = raw_handle;
+
+            controller.inject_le_meta_event(events::LEMeta::ConnectionComplete, &connection_complete).unwrap();
+
+            match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+                Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) => data,
+                _ => panic!("Expected LE connection complete event"),
+            }
+        };
+
+        let data_1 = connect(0x40);
+        let data_2 = connect(0x41);
+
+        let waker = futures::task::noop_waker();
+
+        let mut context = core::task::Context::from_waker(&waker);
+
+        let pdu = || AclData::new(vec![1, 2, 3], ChannelIdentifier::LE(LeUserChannelIdentifier::AttributeProtocol));
+
+        let channel_1 = hi.new_subscribed_le_acl_connection_channel(&data_1);
+
+        for _ in 0..TOTAL_NUM_LE_ACL_DATA_PACKETS { channel_1.send(pdu()); }
+
+        channel_1.send(pdu());
+
+        assert_eq!(usize::from(TOTAL_NUM_LE_ACL_DATA_PACKETS), controller.take_sent_acl_data().len());
+
+        // The connection may still be up when the channel is dropped, so only the queued packet is
+        // dropped and the buffers of the sent packets are not returned
+        drop(channel_1);
+
+        let channel_2 = hi.new_subscribed_le_acl_connection_channel(&data_2);
+
+        let mut flush = channel_2.send(pdu());
+
+        assert!(controller.take_sent_acl_data().is_empty());
+        assert!(Pin::new(&mut flush).poll(&mut context).is_pending());
+
+        // The buffers are returned once the connection is disconnected
+        controller.inject_event(events::Events::DisconnectionComplete, &[0, 0x40, 0x00, 0x13]).unwrap();
+
+        assert!(matches!(Pin::new(&mut flush).poll(&mut context), core::task::Poll::Ready(Ok(()))));
+
+        let sent = controller.take_sent_acl_data();
+
+        assert_eq!(1, sent.len());
+        assert_eq!(data_2.connection_handle, *sent[0].get_handle());
+
+        assert!(block_on(hi.wait_for_event(events::Events::DisconnectionComplete, None)).is_ok());
+    }
+
+    #[test]
+    fn interface_acl_flow_control_test() {
+        use crate::hci::le::mandatory::read_buffer_size;
+        use crate::l2cap::{AclData, ChannelIdentifier, ConnectionChannel, LeUserChannelIdentifier};
+
+        let (hi, controller) = host_interface();
+
+        block_on(read_buffer_size::send(&hi)).unwrap();
+
+        controller.inject_le_meta_event(events::LEMeta::ConnectionComplete, &CONNECTION_COMPLETE).unwrap();
+
+        let data = match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+            Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) => data,
+            _ => panic!("Expected LE connection complete event"),
+        };
+
+        let waker = futures::task::noop_waker();
+
+        let mut context = core::task::Context::from_waker(&waker);
+
+        let pdu = || AclData::new(vec![1, 2, 3], ChannelIdentifier::LE(LeUserChannelIdentifier::AttributeProtocol));
+
+        let channel = hi.new_le_acl_connection_channel(&data);
+
+        for _ in 0..TOTAL_NUM_LE_ACL_DATA_PACKETS { channel.send(pdu()); }
+
+        let mut flush = channel.send(pdu());
+
+        assert_eq!(usize::from(TOTAL_NUM_LE_ACL_DATA_PACKETS), controller.take_sent_acl_data().len());
+        assert!(Pin::new(&mut flush).poll(&mut context).is_pending());
+
+        // The Number Of Completed Packets event is taken from the interface
+        controller.inject_event(events::Events::NumberOfCompletedPackets, &[1, 0x40, 0x00, 1, 0]).unwrap();
+
+        assert!(matches!(Pin::new(&mut flush).poll(&mut context), core::task::Poll::Ready(Ok(()))));
+        assert_eq!(1, controller.take_sent_acl_data().len());
+
+        drop(channel);
+
+        // A new connection with the same handle means the connection of the dropped channel was
+        // disconnected
+        let channel = hi.new_le_acl_connection_channel(&data);
+
+        for _ in 0..TOTAL_NUM_LE_ACL_DATA_PACKETS { channel.send(pdu()); }
+
+        assert_eq!(usize::from(TOTAL_NUM_LE_ACL_DATA_PACKETS), controller.take_sent_acl_data().len());
+    }
+}
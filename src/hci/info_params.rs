@@ -8,7 +8,6 @@ pub mod read_bd_addr {
 
     use crate::BluetoothDeviceAddress;
     use crate::hci::*;
-    use core::fmt::{Display, Debug};
 
     const COMMAND: opcodes::HCICommand = opcodes::HCICommand::InformationParameters(opcodes::InformationParameters::ReadBD_ADDR);
 
@@ -54,7 +53,7 @@ pub mod read_bd_addr {
 
     /// Returns the bluetooth device address for the device
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-    -> impl Future<Output=Result<BluetoothDeviceAddress, impl Display + Debug>> + 'a where T: HostControllerInterface
+    -> impl Future<Output=Result<BluetoothDeviceAddress, impl CommandError>> + 'a where T: HostControllerInterface
     {
         use events::Events::CommandComplete;
 
@@ -113,7 +112,7 @@ pub mod read_local_supported_features {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-    -> impl Future<Output=Result<EnabledFeaturesIter, impl Display + Debug>> + 'a
+    -> impl Future<Output=Result<EnabledFeaturesIter, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -188,7 +187,81 @@ pub mod read_local_version_information {
     }
 
     pub fn send<'a, T: 'static>(hci: &'a HostInterface<T>)
-                                -> impl Future<Output=Result<VersionInformation, impl Display + Debug>> + 'a
+                                -> impl Future<Output=Result<VersionInformation, impl CommandError>> + 'a
+        where T: HostControllerInterface
+    {
+        ReturnedFuture(hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1)))
+    }
+}
+
+/// Read Buffer Size Command
+///
+/// This returns the size of the ACL and synchronous data buffers of the controller. For a LE
+/// controller, these are also the buffers for LE ACL data when the *LE Read Buffer Size* command
+/// returns zero for the buffer size (the buffers are shared between LE and BR/EDR).
+pub mod read_buffer_size {
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::InformationParameters(opcodes::InformationParameters::ReadBufferSize);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        acl_data_packet_length: u16,
+        synchronous_data_packet_length: u8,
+        total_num_acl_data_packets: u16,
+        total_num_synchronous_data_packets: u16,
+    }
+
+    #[derive(Debug)]
+    pub struct BufferSize {
+        /// The maximum length of the payload of an ACL data packet
+        pub acl_data_packet_length: u16,
+        /// The maximum length of the payload of a synchronous data packet
+        pub synchronous_data_packet_length: u8,
+        /// The number of ACL data packets that the controller can hold
+        pub total_num_acl_data_packets: u16,
+        /// The number of synchronous data packets that the controller can hold
+        pub total_num_synchronous_data_packets: u16,
+    }
+
+    impl BufferSize {
+        fn try_from(packed: CmdReturn) -> Result<Self, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(Self {
+                    acl_data_packet_length: <u16>::from_le(packed.acl_data_packet_length),
+                    synchronous_data_packet_length: packed.synchronous_data_packet_length,
+                    total_num_acl_data_packets: <u16>::from_le(packed.total_num_acl_data_packets),
+                    total_num_synchronous_data_packets: <u16>::from_le(packed.total_num_synchronous_data_packets),
+                })
+            } else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        BufferSize,
+        error::Error
+    );
+
+    impl_command_data_future!(BufferSize, error::Error);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>(hci: &'a HostInterface<T>)
+                                -> impl Future<Output=Result<BufferSize, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture(hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1)))
@@ -211,8 +284,7 @@ pub mod read_local_supported_commands {
         supported_commands: [u8;64],
     }
 
-    #[cfg_attr(test,derive(Debug))]
-    #[derive(PartialEq)]
+    #[derive(Clone,Copy,Debug,PartialEq,Eq)]
     pub enum SupportedCommands {
         Inquiry,
         InquiryCancel,
@@ -502,6 +574,122 @@ pub mod read_local_supported_commands {
 
     impl SupportedCommands {
 
+        /// Get the supported command of a HCI command
+        ///
+        /// `None` is returned for the commands that are not within the supported commands bitmask
+        /// (*Read Local Supported Commands* and the vendor specific and raw commands).
+        pub(crate) fn try_from_command( command: opcodes::HCICommand ) -> Option<SupportedCommands> {
+            use self::SupportedCommands::*;
+            use crate::hci::opcodes::{
+                HCICommand,
+                LinkControl,
+                ControllerAndBaseband,
+                InformationParameters,
+                StatusParameters,
+                LEController,
+            };
+
+            match command {
+                HCICommand::LinkControl(ocf) => match ocf {
+                    LinkControl::Disconnect => Some(Disconnect),
+                    LinkControl::ReadRemoteVersionInformation => Some(ReadRemoteVersionInformation),
+                },
+                HCICommand::ControllerAndBaseband(ocf) => match ocf {
+                    ControllerAndBaseband::SetEventMask => Some(SetEventMask),
+                    ControllerAndBaseband::Reset => Some(Reset),
+                    ControllerAndBaseband::ReadTransmitPowerLevel => Some(ReadTransmitPowerLevel),
+                    ControllerAndBaseband::SetControllerToHostFlowControl => Some(SetConrollerToHostFlowControl),
+                    ControllerAndBaseband::HostBufferSize => Some(HostBufferSize),
+                    ControllerAndBaseband::HostNumberOfCompletedPackets => Some(HostNumberOfCompletedPackets),
+                },
+                HCICommand::InformationParameters(ocf) => match ocf {
+                    InformationParameters::ReadLocalSupportedVersionInformation => Some(ReadLocalVersionInformation),
+                    InformationParameters::ReadLocalSupportedCommands => None,
+                    InformationParameters::ReadLocalSupportedFeatures => Some(ReadLocalSupportedFeatures),
+                    InformationParameters::ReadBufferSize => Some(ReadBufferSize),
+                    InformationParameters::ReadBD_ADDR => Some(ReadBDADDR),
+                },
+                HCICommand::StatusParameters(ocf) => match ocf {
+                    StatusParameters::ReadRSSI => Some(ReadRSSI),
+                },
+                HCICommand::LEController(ocf) => match ocf {
+                    LEController::SetEventMask => Some(LESetEventMask),
+                    LEController::ReadBufferSize => Some(LEReadBufferSize),
+                    LEController::ReadLocalSupportedFeatures => Some(LEReadLocalSupportedFeatures),
+                    LEController::SetRandomAddress => Some(LESetRandomAddress),
+                    LEController::SetAdvertisingParameters => Some(LESetAdvertisingParameters),
+                    LEController::ReadAdvertisingChannelTxPower => Some(LEReadAdvertisingChannelTXPower),
+                    LEController::SetAdvertisingData => Some(LESetAdvertisingData),
+                    LEController::SetScanResponseData => Some(LESetScanResponseData),
+                    LEController::SetAdvertisingEnable => Some(LESetAdvertisingEnable),
+                    LEController::SetScanParameters => Some(LESetScanParameters),
+                    LEController::SetScanEnable => Some(LESetScanEnable),
+                    LEController::CreateConnection => Some(LECreateConnection),
+                    LEController::CreateConnectionCancel => Some(LECreateConnectionCancel),
+                    LEController::ReadWhiteListSize => Some(LEReadWhiteListSize),
+                    LEController::ClearWhiteList => Some(LEClearWhiteList),
+                    LEController::AddDeviceToWhiteList => Some(LEAddDeviceToWhiteList),
+                    LEController::RemoveDeviceFromWhiteList => Some(LERemoveDeviceFromWhiteList),
+                    LEController::ConnectionUpdate => Some(LEConnectionUpdate),
+                    LEController::SetHostChannelClassification => Some(LESetHostChannelClassification),
+                    LEController::ReadChannelMap => Some(LEReadChannelMap),
+                    LEController::ReadRemoteFeatures => Some(LEReadRemoteFeatures),
+                    LEController::Encrypt => Some(LEEncrypt),
+                    LEController::Rand => Some(LERand),
+                    LEController::StartEncryption => Some(LEStartEncryption),
+                    LEController::LongTermKeyRequestReply => Some(LELongTermKeyRequestReply),
+                    LEController::LongTermKeyRequestNegativeReply => Some(LELongTermKeyRequestNegativeReply),
+                    LEController::ReadSupportedStates => Some(LEReadSupportedStates),
+                    LEController::ReceiverTest => Some(LEReceiverTest),
+                    LEController::TransmitterTest => Some(LETransmitterTest),
+                    LEController::TestEnd => Some(LETestEnd),
+                    LEController::ReadConnectionParameterRequestReply =>
+                        Some(LERemoteConnectionParameterRequestReply),
+                    LEController::ReadConnectionParameterRequestNegativeReply =>
+                        Some(LERemoteConnectionParameterREquestNegativeReply),
+                    LEController::SetDataLength => Some(LESetDataLength),
+                    LEController::ReadSuggestedDefaultDataLength => Some(LEReadSuggestedDefaultDataLength),
+                    LEController::WriteSuggestedDefaultDataLength => Some(LEWriteSuggestedDefaultDataLength),
+                    LEController::AddDeviceToResolvingList => Some(LEAddDeviceToResolvingList),
+                    LEController::RemoveDeviceFromResolvingList => Some(LERemoveDeviceFromResolvingList),
+                    LEController::ClearResolvingList => Some(LEClearResolvingList),
+                    LEController::ReadResolvingListSize => Some(LEReadResolvingListSize),
+                    LEController::ReadPeerResolvableAddress => Some(LEReadPeerResolvableAddress),
+                    LEController::ReadLocalResolvableAddress => Some(LEReadLocalResolvableAddress),
+                    LEController::SetAddressResolutionEnable => Some(LESetAddressResolutionEnable),
+                    LEController::SetResolvablePrivateAddressTimeout => Some(LESetResolvablePrivateAddressTimeout),
+                    LEController::ReadMaximumDataLength => Some(LEReadMaximumDataLength),
+                    LEController::ReadPHY => Some(LEReadPHYCommand),
+                    LEController::SetDefaultPHY => Some(LESetDefaultPHYCommand),
+                    LEController::SetPHY => Some(LESetPHYCommand),
+                    LEController::SetAdvertisingSetRandomAddress => Some(LESetAdvertisingSetRandomAddressCommand),
+                    LEController::SetExtendedAdvertisingParameters => Some(LESetExtendedAdvertisingParametersCommand),
+                    LEController::SetExtendedAdvertisingData => Some(LESetExtendedAdvertisingDataCommand),
+                    LEController::SetExtendedScanResponseData => Some(LESetExtendedScanResponseDataCommand),
+                    LEController::SetExtendedAdvertisingEnable => Some(LESetExtendedAdvertisingEnableCommand),
+                    LEController::ReadMaximumAdvertisingDataLength => Some(LEReadMaximumAdvertisingDataLengthCommand),
+                    LEController::ReadNumberOfSupportedAdvertisingSets => Some(LEReadNumberOfSupportedAdvertisingSetCommand),
+                    LEController::RemoveAdvertisingSet => Some(LERemoveAdvertisingSetCommand),
+                    LEController::ClearAdvertisingSets => Some(LEClearAdvertisingSetsCommand),
+                    LEController::SetPeriodicAdvertisingParameters => Some(LESetPeriodicAdvertisingParametersCommand),
+                    LEController::SetPeriodicAdvertisingData => Some(LESetPeriodicAdvertisingDataCommand),
+                    LEController::SetPeriodicAdvertisingEnable => Some(LESetPeriodicAdvertisingEnableCommand),
+                    LEController::SetExtendedScanParameters => Some(LESetExtendedScanParametersCommand),
+                    LEController::SetExtendedScanEnable => Some(LESetExtendedScanEnableCommand),
+                    LEController::ExtendedCreateConnection => Some(LEExtendedCreateConnectionCommand),
+                    LEController::PeriodicAdvertisingCreateSync => Some(LEPeriodicAdvertisingCreateSyncCommand),
+                    LEController::PeriodicAdvertisingCreateSyncCancel => Some(LEPeriodicAdvertisingCreateSyncCancelCommand),
+                    LEController::PeriodicAdvertisingTerminateSync => Some(LEPeriodicAdvertisingTerminateSyncCommand),
+                    LEController::AddDeviceToPeriodicAdvertiserList => Some(LEAddDeviceToPeriodicAdvertiserListCommand),
+                    LEController::RemoveDeviceFromPeriodicAdvertiserList => Some(LERemoveDeviceFromPeriodicAdvertiserListCommand),
+                    LEController::ClearPeriodicAdvertiserList => Some(LEClearPeriodicAdvertiserListCommand),
+                    LEController::ReadPeriodicAdvertiserListSize => Some(LEReadPeriodicAdvertiserListSizeCommand),
+                    LEController::SetPrivacyMode => Some(LESetPrivacyMode),
+                },
+                HCICommand::VendorSpecific(_) | HCICommand::Raw => None,
+            }
+        }
+
         fn from_bit_pos( pos: (usize, usize) ) -> Option<SupportedCommands> {
             use self::SupportedCommands::*;
 
@@ -834,7 +1022,7 @@ pub mod read_local_supported_commands {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-                                 -> impl Future<Output=Result<alloc::vec::Vec<SupportedCommands>, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<alloc::vec::Vec<SupportedCommands>, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -92,7 +92,7 @@ pub mod remote_connection_parameter_request_reply {
     impl_command_data_future!(Return, error::Error);
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, parameter: CommandParameters)
-    -> impl Future<Output=Result<Return, impl Display + Debug>> + 'a
+    -> impl Future<Output=Result<Return, impl CommandError>> + 'a
     where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(
@@ -166,7 +166,7 @@ pub mod remote_connection_parameter_request_negative_reply {
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>,
         handle: ConnectionHandle,
         reason: error::Error
-    ) -> impl Future<Output=Result<Return, impl Display + Debug>> + 'a
+    ) -> impl Future<Output=Result<Return, impl CommandError>> + 'a
     where T: HostControllerInterface
     {
         let parameter = Parameter {
@@ -1,11 +1,36 @@
+use core::fmt::{self, Debug, Display};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use crate::hci::{
+    error,
+    events,
+    spin_lock::SpinLock,
+    CommandError,
+    CommandErrorKind,
+    EventStream,
+    HostControllerInterface,
+    HostInterface,
+    ReceivedPacketSource,
+};
+use crate::hci::common::{
+    self,
+    ConnectionHandle,
+    ConnectionLatency,
+    EnabledLEFeaturesItr,
+    EncryptionLevel,
+    SupervisionTimeout,
+};
+use crate::hci::le::common::ConnectionEventLength;
 
 interval!( #[derive(Clone, Copy)] ConnectionInterval, 0x0006, 0x0C80, ApiDef, 0x0006, 1250);
 
 /// ConnectionUpdateInterval contaings the minimum and maximum connection intervals for
 /// the le connection update
 pub struct ConnectionIntervalBounds {
-    min: ConnectionInterval,
-    max: ConnectionInterval,
+    pub(super) min: ConnectionInterval,
+    pub(super) max: ConnectionInterval,
 }
 
 impl ConnectionIntervalBounds {
@@ -121,7 +146,7 @@ pub mod disconnect {
     impl_command_status_future!();
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, dp: DisconnectParameters )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(dp, events::Events::CommandStatus, Duration::from_secs(1) ) )
@@ -177,22 +202,34 @@ pub mod connection_update {
         }
     }
 
-    impl_returned_future!(
-            crate::hci::events::LEConnectionUpdateCompleteData,
-            events::EventsData::LEMeta,
-            events::LEMetaData::ConnectionUpdateComplete(data),
-            &'static str, // useless type that has both Display + Debug
-            {
-                core::task::Poll::Ready(Ok(data))
-            }
-        );
+    impl_command_status_future!();
 
-    /// The event expected to be returned is the LEMeta event carrying a Connection Update
-    /// Complete lE event
+    /// The returned future completes when the LEMeta event carrying a Connection Update Complete
+    /// LE event for the connection is received. The `timeout` is used for both the Command Status
+    /// event and the Connection Update Complete event.
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, cu: ConnectionUpdate, timeout: Duration)
-                                 -> impl Future<Output=Result<crate::hci::events::LEConnectionUpdateCompleteData, impl Display + Debug>> + 'a where T: HostControllerInterface
+                                 -> impl Future<Output=Result<crate::hci::events::LEConnectionUpdateCompleteData, impl CommandError>> + 'a where T: HostControllerInterface
     {
-        ReturnedFuture( hci.send_command( cu, events::Events::LEMeta( events::LEMeta::ConnectionUpdateComplete ), timeout ) )
+        let handle = cu.handle;
+
+        let matcher = move |ed: &events::EventsData| match ed {
+            events::EventsData::LEMeta(events::LEMetaData::ConnectionUpdateComplete(data)) =>
+                data.connection_handle == handle,
+            _ => false,
+        };
+
+        StatusThenEventFuture::new(
+            ReturnedFuture( hci.send_command( cu, events::Events::CommandStatus, timeout ) ),
+            hci.wait_for_event_with_matcher(
+                events::Events::LEMeta( events::LEMeta::ConnectionUpdateComplete ),
+                timeout,
+                matcher
+            ),
+            |ed| match ed {
+                events::EventsData::LEMeta(events::LEMetaData::ConnectionUpdateComplete(data)) => Some(data),
+                _ => None,
+            },
+        )
     }
 
 }
@@ -215,7 +252,7 @@ pub mod create_connection_cancel {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>)
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command( Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -246,7 +283,7 @@ pub mod create_connection {
     }
 
     impl InitiatorFilterPolicy {
-        fn val(&self) -> u8 {
+        pub(crate) fn val(&self) -> u8 {
             match *self {
                 InitiatorFilterPolicy::DoNotUseWhiteList => 0x00,
                 InitiatorFilterPolicy::UseWhiteList => 0x01,
@@ -362,7 +399,7 @@ pub mod create_connection {
     impl_command_status_future!();
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, cp: ConnectionParameters )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(cp, events::Events::CommandStatus , Duration::from_secs(1) ) )
@@ -449,7 +486,7 @@ pub mod read_channel_map {
     impl_command_data_future!(ChannelMapInfo, error::Error);
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: ConnectionHandle )
-                                 -> impl Future<Output=Result<ChannelMapInfo, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<ChannelMapInfo, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
 
@@ -484,7 +521,7 @@ pub mod read_remote_features {
     impl_command_status_future!();
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: ConnectionHandle )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
 
@@ -565,7 +602,7 @@ pub mod set_host_channel_classification {
     impl_status_return!(COMMAND);
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, map: ChannelMap )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command( map, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -574,4 +611,475 @@ pub mod set_host_channel_classification {
 
 pub use super::super::cb::read_transmit_power_level;
 pub use super::super::status_prams::read_rssi;
-pub use super::super::link_control::read_remote_version_information;
\ No newline at end of file
+pub use super::super::link_control::read_remote_version_information;
+
+/// The state of a connection that can be changed by the controller
+struct ConnectionState {
+    interval: common::ConnectionInterval,
+    latency: ConnectionLatency,
+    supervision_timeout: SupervisionTimeout,
+    encryption: EncryptionLevel,
+    disconnect_reason: Option<error::Error>,
+}
+
+impl ConnectionState {
+
+    fn process_event(&mut self, event_data: events::EventsData) {
+        use events::{EventsData, LEMetaData};
+
+        match event_data {
+            EventsData::LEMeta(LEMetaData::ConnectionUpdateComplete(data))
+            if data.status == error::Error::NoError => {
+                self.interval = data.connection_interval;
+                self.latency = data.connection_latency;
+                self.supervision_timeout = data.supervision_timeout;
+            },
+            EventsData::EncryptionChange(data) if data.status == error::Error::NoError => {
+                self.encryption = data.encryption_enabled.get_for_le();
+            },
+            EventsData::DisconnectionComplete(data) if data.status == error::Error::NoError => {
+                self.disconnect_reason = Some(error::Error::from(data.reason));
+            },
+            _ => (),
+        }
+    }
+}
+
+/// A LE connection
+///
+/// A `Connection` is created from the *LE Connection Complete* (or *LE Enhanced Connection
+/// Complete*) event sent by the controller when a connection is established. It keeps the role
+/// of the device within the connection along with the connection parameters, encryption, and
+/// whether the connection is disconnected. These are kept up to date from the *LE Connection
+/// Update Complete*, *Encryption Change*, and *Disconnection Complete* events for the connection
+/// handle, so the state returned by the getters reflects every one of these events received
+/// before the getter is called.
+///
+/// The events are received with [event streams](crate::hci::EventStream), which means that the
+/// events received before the `Connection` is created are not seen by it. A `Connection` should
+/// be created as soon as the connection complete event is received. Each stream only keeps the
+/// latest event, as every event replaces the state set by the events before it.
+///
+/// The methods for the commands sent over a connection only fill in the connection handle, the
+/// commands are sent to the controller in the same way as using the command modules directly.
+pub struct Connection<'a, I> {
+    hi: &'a HostInterface<I>,
+    handle: ConnectionHandle,
+    role: events::LERole,
+    state: SpinLock<ConnectionState>,
+    update_events: EventStream,
+    encryption_events: EventStream,
+    disconnection_events: EventStream,
+}
+
+impl<'a, I> Connection<'a, I>
+where I: HostControllerInterface + ReceivedPacketSource + 'static
+{
+    fn new(
+        hi: &'a HostInterface<I>,
+        handle: ConnectionHandle,
+        role: events::LERole,
+        state: ConnectionState,
+    ) -> Self
+    {
+        use events::{Events, EventsData, LEMeta, LEMetaData};
+
+        let update_events = hi.subscribe_latest(
+            Events::LEMeta(LEMeta::ConnectionUpdateComplete),
+            move |ed: &EventsData| matches!(ed,
+                EventsData::LEMeta(LEMetaData::ConnectionUpdateComplete(data)) if data.connection_handle == handle
+            )
+        );
+
+        let encryption_events = hi.subscribe_latest(
+            Events::EncryptionChange,
+            move |ed: &EventsData| matches!(ed,
+                EventsData::EncryptionChange(data) if data.connection_handle == handle
+            )
+        );
+
+        let disconnection_events = hi.subscribe_latest(
+            Events::DisconnectionComplete,
+            move |ed: &EventsData| matches!(ed,
+                EventsData::DisconnectionComplete(data) if data.connection_handle == handle
+            )
+        );
+
+        Connection {
+            hi,
+            handle,
+            role,
+            state: SpinLock::new(state),
+            update_events,
+            encryption_events,
+            disconnection_events,
+        }
+    }
+
+    /// Create a `Connection` from the data of a *LE Connection Complete* event
+    ///
+    /// # Error
+    /// The status of the event is returned if it is not `NoError` (a connection was not
+    /// established).
+    pub fn try_from_connection_complete(
+        hi: &'a HostInterface<I>,
+        data: &events::LEConnectionCompleteData
+    ) -> Result<Self, error::Error>
+    {
+        if data.status != error::Error::NoError {
+            return Err(data.status)
+        }
+
+        let state = ConnectionState {
+            interval: data.connection_interval.clone(),
+            latency: data.connection_latency.clone(),
+            supervision_timeout: data.supervision_timeout.clone(),
+            encryption: EncryptionLevel::Off,
+            disconnect_reason: None,
+        };
+
+        Ok(Self::new(hi, data.connection_handle, data.role.clone(), state))
+    }
+
+    /// Create a `Connection` from the data of a *LE Enhanced Connection Complete* event
+    ///
+    /// # Error
+    /// The status of the event is returned if it is not `NoError` (a connection was not
+    /// established). `InvalidLMPParametersOrInvalidLLParameters` is returned if the connection
+    /// interval of the event is not valid.
+    pub fn try_from_enhanced_connection_complete(
+        hi: &'a HostInterface<I>,
+        data: &events::LEEnhancedConnectionCompleteData
+    ) -> Result<Self, error::Error>
+    {
+        if data.status != error::Error::NoError {
+            return Err(data.status)
+        }
+
+        // Both connection interval types have the same range of valid values
+        let interval = common::ConnectionInterval::try_from(data.connection_interval.get_raw_val())
+            .map_err(|_| error::Error::InvalidLMPParametersOrInvalidLLParameters)?;
+
+        let state = ConnectionState {
+            interval,
+            latency: data.connection_latency.clone(),
+            supervision_timeout: data.supervision_timeout.clone(),
+            encryption: EncryptionLevel::Off,
+            disconnect_reason: None,
+        };
+
+        Ok(Self::new(hi, data.connection_handle, data.role.clone(), state))
+    }
+
+    /// Get the state after processing the events received for the connection
+    fn get_state(&self) -> crate::hci::spin_lock::SpinLockGuard<'_, ConnectionState> {
+        let mut state = self.state.lock();
+
+        for events in [&self.update_events, &self.encryption_events, &self.disconnection_events].iter() {
+            while let Some(entry) = events.try_next_event() {
+                match entry {
+                    Ok(event_data) => state.process_event(event_data),
+                    Err(overflow) => log::warn!("Connection {}: {}", self.handle, overflow),
+                }
+            }
+        }
+
+        state
+    }
+
+    /// Get the connection handle
+    pub fn get_handle(&self) -> ConnectionHandle {
+        self.handle
+    }
+
+    /// Get the role of this device within the connection
+    pub fn get_role(&self) -> events::LERole {
+        self.role.clone()
+    }
+
+    /// Get the connection interval
+    pub fn get_interval(&self) -> common::ConnectionInterval {
+        self.get_state().interval.clone()
+    }
+
+    /// Get the connection latency
+    pub fn get_latency(&self) -> ConnectionLatency {
+        self.get_state().latency.clone()
+    }
+
+    /// Get the supervision timeout
+    pub fn get_supervision_timeout(&self) -> SupervisionTimeout {
+        self.get_state().supervision_timeout.clone()
+    }
+
+    /// Get the encryption of the connection
+    ///
+    /// This is either `EncryptionLevel::Off` or `EncryptionLevel::AESCCM`
+    pub fn get_encryption(&self) -> EncryptionLevel {
+        self.get_state().encryption
+    }
+
+    /// Check if the connection is still connected
+    pub fn is_connected(&self) -> bool {
+        self.get_state().disconnect_reason.is_none()
+    }
+
+    /// Get the reason for the disconnection
+    ///
+    /// `None` is returned if the connection is still connected.
+    pub fn get_disconnect_reason(&self) -> Option<error::Error> {
+        self.get_state().disconnect_reason
+    }
+
+    /// Update the connection parameters
+    ///
+    /// This sends the *LE Connection Update* command, the returned future completes when the
+    /// *LE Connection Update Complete* event is received.
+    pub fn update(
+        &self,
+        interval: ConnectionIntervalBounds,
+        latency: ConnectionLatency,
+        supervision_timeout: SupervisionTimeout,
+        connection_event_len: ConnectionEventLength,
+        timeout: Duration,
+    ) -> impl Future<Output=Result<events::LEConnectionUpdateCompleteData, impl CommandError>> + 'a
+    {
+        let parameter = connection_update::ConnectionUpdate {
+            handle: self.handle,
+            interval,
+            latency: latency.get_latency(),
+            supervision_timeout,
+            connection_event_len,
+        };
+
+        connection_update::send(self.hi, parameter, timeout)
+    }
+
+    /// Disconnect
+    ///
+    /// The returned future completes when the controller has started the disconnection, the
+    /// connection is disconnected once the *Disconnection Complete* event is received.
+    pub fn disconnect(&self, reason: disconnect::DisconnectReason)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        let parameter = disconnect::DisconnectParameters {
+            connection_handle: self.handle,
+            disconnect_reason: reason,
+        };
+
+        disconnect::send(self.hi, parameter)
+    }
+
+    /// Read the RSSI of the connection
+    pub fn read_rssi(&self) -> impl Future<Output=Result<read_rssi::RSSIInfo, impl CommandError>> + 'a {
+        read_rssi::send(self.hi, self.handle)
+    }
+
+    /// Read the channel map of the connection
+    pub fn read_channel_map(&self)
+    -> impl Future<Output=Result<read_channel_map::ChannelMapInfo, impl CommandError>> + 'a
+    {
+        read_channel_map::send(self.hi, self.handle)
+    }
+
+    /// Read the LE features supported by the peer device
+    ///
+    /// Unlike `read_remote_features::send`, the returned future completes when the *LE Read
+    /// Remote Features Complete* event is received.
+    pub fn read_remote_features(&self)
+    -> impl Future<Output=Result<EnabledLEFeaturesItr, impl CommandError>> + 'a
+    {
+        use events::{Events, EventsData, LEMeta, LEMetaData};
+
+        let handle = self.handle;
+
+        // The subscription must be made before the command is sent so that the event cannot be
+        // received before there is a stream to receive it.
+        let events = self.hi.subscribe_with_matcher(
+            Events::LEMeta(LEMeta::ReadRemoteFeaturesComplete),
+            1,
+            move |ed: &EventsData| matches!(ed,
+                EventsData::LEMeta(LEMetaData::ReadRemoteFeaturesComplete(data)) if data.connection_handle == handle
+            )
+        );
+
+        RemoteFeaturesFuture {
+            command: Some(read_remote_features::send(self.hi, self.handle)),
+            events,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum RemoteFeaturesError<E> {
+    Command(E),
+    Status(error::Error),
+}
+
+impl<E: Display> Display for RemoteFeaturesError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteFeaturesError::Command(e) => Display::fmt(e, f),
+            RemoteFeaturesError::Status(status) => write!(f, "Read remote features failed, {}", status),
+        }
+    }
+}
+
+impl<E: CommandError> CommandError for RemoteFeaturesError<E> {
+    fn kind(&self) -> CommandErrorKind {
+        match self {
+            RemoteFeaturesError::Command(e) => e.kind(),
+            RemoteFeaturesError::Status(status) => CommandErrorKind::Status(*status),
+        }
+    }
+}
+
+struct RemoteFeaturesFuture<F> {
+    command: Option<F>,
+    events: EventStream,
+}
+
+impl<F, E> Future for RemoteFeaturesFuture<F>
+where F: Future<Output=Result<(), E>> + Unpin
+{
+    type Output = Result<EnabledLEFeaturesItr, RemoteFeaturesError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        use events::{EventsData, LEMetaData};
+
+        let this = self.get_mut();
+
+        if let Some(command) = this.command.as_mut() {
+            match Pin::new(command).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(RemoteFeaturesError::Command(e))),
+                Poll::Ready(Ok(())) => this.command = None,
+            }
+        }
+
+        loop {
+            match Pin::new(&mut this.events).poll_next(cx) {
+                Poll::Ready(Some(Ok(EventsData::LEMeta(LEMetaData::ReadRemoteFeaturesComplete(data))))) =>
+                    break if data.status == error::Error::NoError {
+                        Poll::Ready(Ok(data.features))
+                    } else {
+                        Poll::Ready(Err(RemoteFeaturesError::Status(data.status)))
+                    },
+                Poll::Ready(_) => continue,
+                Poll::Pending => break Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::hci::testing::fixture::connect_linked;
+    use crate::hci::testing::{VirtualController, RSSI};
+    use crate::hci::{error, events, HostInterface};
+    use core::time::Duration;
+    use futures::executor::block_on;
+
+    #[test]
+    fn connection_object_test() {
+        use crate::hci::common::{
+            ConnectionLatency, EncryptionLevel, LEFeatures, SupervisionTimeout
+        };
+        use crate::hci::le::common::ConnectionEventLength;
+        use crate::hci::le::connection::{
+            disconnect, Connection, ConnectionInterval, ConnectionIntervalBounds
+        };
+
+        let slave_address = [2, 2, 2, 2, 2, 2];
+
+        let master_controller = VirtualController::new([1, 1, 1, 1, 1, 1]);
+        let slave_controller = VirtualController::new(slave_address);
+
+        master_controller.link(&slave_controller);
+
+        let master = HostInterface::from(master_controller.clone());
+        let slave = HostInterface::from(slave_controller.clone());
+
+        let (master_data, slave_data) = connect_linked(&master, &slave, slave_address);
+
+        let master_connection = Connection::try_from_connection_complete(&master, &master_data).unwrap();
+        let slave_connection = Connection::try_from_connection_complete(&slave, &slave_data).unwrap();
+
+        let handle = master_connection.get_handle();
+
+        assert_eq!(master_data.connection_handle, handle);
+        assert!(matches!(master_connection.get_role(), events::LERole::Master));
+        assert!(matches!(slave_connection.get_role(), events::LERole::Slave));
+        assert_eq!(0x20, master_connection.get_interval().get_interval());
+        assert_eq!(EncryptionLevel::Off, master_connection.get_encryption());
+        assert!(master_connection.is_connected());
+
+        let update = master_connection.update(
+            ConnectionIntervalBounds::try_from(
+                ConnectionInterval::try_from_raw(0x30).unwrap(),
+                ConnectionInterval::try_from_raw(0x40).unwrap(),
+            ).unwrap(),
+            ConnectionLatency::try_from(2).unwrap(),
+            SupervisionTimeout::try_from_raw(0x200).unwrap(),
+            ConnectionEventLength::default(),
+            Duration::from_secs(1),
+        );
+
+        block_on(update).unwrap();
+
+        for connection in [&master_connection, &slave_connection].iter() {
+            assert_eq!(0x40, connection.get_interval().get_interval());
+            assert_eq!(2, connection.get_latency().get_latency());
+            assert_eq!(0x200, connection.get_supervision_timeout().get_timeout());
+        }
+
+        // Only the latest event sets the state, no matter how many are received between the calls
+        // to the getters
+        for raw_interval in 0x41..=0x50u16 {
+            let mut update_complete = alloc::vec![error::Error::NoError.into()];
+
+            update_complete.extend_from_slice(&handle.get_raw_handle().to_le_bytes());
+            update_complete.extend_from_slice(&raw_interval.to_le_bytes());
+            update_complete.extend_from_slice(&[3, 0, 0x00, 0x03]);
+
+            master_controller.inject_le_meta_event(events::LEMeta::ConnectionUpdateComplete, &update_complete)
+                .unwrap();
+        }
+
+        assert_eq!(0x50, master_connection.get_interval().get_interval());
+        assert_eq!(3, master_connection.get_latency().get_latency());
+        assert_eq!(0x300, master_connection.get_supervision_timeout().get_timeout());
+
+        let mut encryption_change = alloc::vec![error::Error::NoError.into()];
+
+        encryption_change.extend_from_slice(&handle.get_raw_handle().to_le_bytes());
+        encryption_change.push(1);
+
+        master_controller.inject_event(events::Events::EncryptionChange, &encryption_change).unwrap();
+
+        assert_eq!(EncryptionLevel::AESCCM, master_connection.get_encryption());
+        assert_eq!(EncryptionLevel::Off, slave_connection.get_encryption());
+
+        assert_eq!(RSSI, block_on(master_connection.read_rssi()).unwrap().rssi);
+        assert_eq!(37, block_on(master_connection.read_channel_map()).unwrap().channel_map.len());
+
+        let features = block_on(master_connection.read_remote_features()).unwrap().collect::<Vec<_>>();
+
+        assert!(features.contains(&LEFeatures::LEEncryption));
+        assert!(features.contains(&LEFeatures::ConectionParametersRequestProcedure));
+
+        block_on(master_connection.disconnect(disconnect::DisconnectReason::RemoteUserTerminatedConnection))
+            .unwrap();
+
+        assert!(!master_connection.is_connected());
+        assert_eq!(
+            Some(error::Error::ConnectionTerminatedByLocalHost),
+            master_connection.get_disconnect_reason()
+        );
+        assert_eq!(
+            Some(error::Error::RemoteUserTerminatedConnection),
+            slave_connection.get_disconnect_reason()
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! LE Data Packet Length Extension
+//!
+//! These are the commands for changing the maximum size of the payload of the Link Layer data
+//! PDUs of a connection. Without the data packet length extension the payload of a Link Layer
+//! PDU is at most 27 bytes, with it the payload can be up to 251 bytes.
+//!
+//! The length of a connection is changed with [`set_data_length`]. When the length used by the
+//! Link Layer changes the controller sends the *LE Data Length Change* event to the host. The
+//! ACL connection channel of the connection takes this event to use the new maximum transmit
+//! length for fragmenting L2CAP PDUs (see
+//! [`new_le_acl_connection_channel`](crate::hci::HostInterface::new_le_acl_connection_channel)).
+
+/// The maximum number of payload octets and the maximum transmission time of a Link Layer data
+/// PDU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataLength {
+    octets: u16,
+    time: u16,
+}
+
+impl DataLength {
+    /// The minimum data length (and the data length used when a connection is created)
+    pub const MINIMUM: DataLength = DataLength { octets: 0x1B, time: 0x148 };
+
+    /// The largest data length for the LE 1M PHY
+    pub const MAXIMUM: DataLength = DataLength { octets: 0xFB, time: 0x848 };
+
+    const OCTETS_RANGE: core::ops::RangeInclusive<u16> = 0x1B..=0xFB;
+
+    const TIME_RANGE: core::ops::RangeInclusive<u16> = 0x148..=0x4290;
+
+    /// Create a new `DataLength`
+    ///
+    /// The input `octets` is the number of payload octets and `time` is the transmission time in
+    /// microseconds.
+    ///
+    /// # Error
+    /// `octets` is not within the range 27..=251 or `time` is not within the range 328..=17040
+    pub fn try_new(octets: u16, time: u16) -> Result<Self, &'static str> {
+        if !Self::OCTETS_RANGE.contains(&octets) {
+            Err("Octets out of range: 27..=251")
+        } else if !Self::TIME_RANGE.contains(&time) {
+            Err("Time out of range: 328..=17040")
+        } else {
+            Ok(DataLength { octets, time })
+        }
+    }
+
+    /// Create a `DataLength` from values returned by the controller
+    fn from_raw(octets: u16, time: u16) -> Self {
+        DataLength { octets: <u16>::from_le(octets), time: <u16>::from_le(time) }
+    }
+
+    /// Get the maximum number of payload octets
+    pub fn get_octets(&self) -> u16 { self.octets }
+
+    /// Get the maximum transmission time in microseconds
+    pub fn get_time(&self) -> u16 { self.time }
+}
+
+impl Default for DataLength {
+    fn default() -> Self {
+        DataLength::MINIMUM
+    }
+}
+
+/// Set the data length of a connection
+///
+/// This suggests the maximum transmit data length to the controller, the controller may use a
+/// smaller length. The *LE Data Length Change* event is sent by the controller once the data
+/// length used by the Link Layer changes.
+pub mod set_data_length {
+
+    use crate::hci::*;
+    use crate::hci::common::ConnectionHandle;
+    use super::DataLength;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetDataLength);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        _connection_handle: u16,
+    }
+
+    pub struct Return;
+
+    impl Return {
+        fn try_from(packed: CmdReturn) -> Result<(), error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(())
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        Return,
+        (),
+        error::Error
+    );
+
+    impl_command_data_future!(Return, (), error::Error);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _handle: u16,
+        _tx_octets: u16,
+        _tx_time: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Parameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: ConnectionHandle, tx_length: DataLength )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _handle: handle.get_raw_handle().to_le(),
+            _tx_octets: tx_length.get_octets().to_le(),
+            _tx_time: tx_length.get_time().to_le(),
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Read the suggested default data length
+///
+/// The suggested default data length is the maximum transmit data length the controller uses for
+/// new connections.
+pub mod read_suggested_default_data_length {
+
+    use crate::hci::*;
+    use super::DataLength;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadSuggestedDefaultDataLength);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        max_tx_octets: u16,
+        max_tx_time: u16,
+    }
+
+    impl DataLength {
+        fn try_from(packed: CmdReturn) -> Result<Self, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(DataLength::from_raw(packed.max_tx_octets, packed.max_tx_time))
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        DataLength,
+        error::Error
+    );
+
+    impl_command_data_future!(DataLength, error::Error);
+
+    #[derive(Clone,Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {*self}
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<DataLength, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Write the suggested default data length
+///
+/// This sets the maximum transmit data length the controller uses for new connections.
+pub mod write_suggested_default_data_length {
+
+    use crate::hci::*;
+    use super::DataLength;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::WriteSuggestedDefaultDataLength);
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _tx_octets: u16,
+        _tx_time: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Parameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, tx_length: DataLength )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _tx_octets: tx_length.get_octets().to_le(),
+            _tx_time: tx_length.get_time().to_le(),
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Read the maximum data length supported by the controller
+pub mod read_maximum_data_length {
+
+    use crate::hci::*;
+    use super::DataLength;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadMaximumDataLength);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        supported_max_tx_octets: u16,
+        supported_max_tx_time: u16,
+        supported_max_rx_octets: u16,
+        supported_max_rx_time: u16,
+    }
+
+    /// The maximum data lengths supported by the controller
+    #[derive(Debug, Clone, Copy)]
+    pub struct MaximumDataLength {
+        /// The maximum transmit data length
+        pub tx: DataLength,
+        /// The maximum receive data length
+        pub rx: DataLength,
+    }
+
+    impl MaximumDataLength {
+        fn try_from(packed: CmdReturn) -> Result<Self, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(MaximumDataLength {
+                    tx: DataLength::from_raw(packed.supported_max_tx_octets, packed.supported_max_tx_time),
+                    rx: DataLength::from_raw(packed.supported_max_rx_octets, packed.supported_max_rx_time),
+                })
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        MaximumDataLength,
+        error::Error
+    );
+
+    impl_command_data_future!(MaximumDataLength, error::Error);
+
+    #[derive(Clone,Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {*self}
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<MaximumDataLength, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hci::events;
+    use crate::hci::testing::fixture::{host_interface, CONNECTION_COMPLETE};
+    use futures::executor::block_on;
+
+    #[test]
+    fn data_length_test() {
+        use crate::l2cap::{AclData, ChannelIdentifier, ConnectionChannel, LeUserChannelIdentifier};
+
+        let (hi, controller) = host_interface();
+
+        let maximum = block_on(read_maximum_data_length::send(&hi)).unwrap();
+
+        assert_eq!(DataLength::MAXIMUM, maximum.tx);
+        assert_eq!(DataLength::MAXIMUM, maximum.rx);
+
+        assert_eq!(DataLength::MINIMUM, block_on(read_suggested_default_data_length::send(&hi)).unwrap());
+
+        block_on(write_suggested_default_data_length::send(&hi, DataLength::MAXIMUM)).unwrap();
+
+        assert_eq!(DataLength::MAXIMUM, block_on(read_suggested_default_data_length::send(&hi)).unwrap());
+
+        assert!(DataLength::try_new(26, 0x148).is_err());
+        assert!(DataLength::try_new(251, 0x4291).is_err());
+
+        controller.inject_le_meta_event(events::LEMeta::ConnectionComplete, &CONNECTION_COMPLETE).unwrap();
+
+        let data = match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+            Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) => data,
+            _ => panic!("Expected LE connection complete event"),
+        };
+
+        let channel = hi.new_subscribed_le_acl_connection_channel(&data);
+
+        let pdu = || (
+            AclData::new(vec![0; 100], ChannelIdentifier::LE(LeUserChannelIdentifier::AttributeProtocol)),
+            251usize
+        );
+
+        // Fragmented to the minimum data length of 27 bytes
+        channel.send(pdu());
+
+        assert_eq!(4, controller.take_sent_acl_data().len());
+
+        block_on(set_data_length::send(&hi, data.connection_handle, DataLength::MAXIMUM)).unwrap();
+
+        // The change event is received by both the host and the channel
+        assert!(block_on(hi.wait_for_event(events::LEMeta::DataLengthChange.into(), None)).is_ok());
+
+        channel.send(pdu());
+
+        assert_eq!(1, controller.take_sent_acl_data().len());
+    }
+}
@@ -58,7 +58,7 @@ pub mod encrypt {
     ///
     /// Both the `key` and `plain-text` should be in native endian.
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, key: u128, plain_text: [u8;16])
-                                 -> impl Future<Output=Result<Cypher, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<Cypher, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         let parameter = Parameter {
@@ -140,7 +140,7 @@ pub mod long_term_key_request_reply {
         hci: &'a HostInterface<T>,
         connection_handle: ConnectionHandle,
         long_term_key: u128,
-    ) -> impl Future<Output=Result<Return, impl Display + Debug>> + 'a
+    ) -> impl Future<Output=Result<Return, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         let parameter = Parameter {
@@ -217,7 +217,7 @@ pub mod long_term_key_request_negative_reply {
     pub fn send<'a, T: 'static>(
         hci: &'a HostInterface<T>,
         connection_handle: ConnectionHandle,
-    ) -> impl Future<Output=Result<Return, impl Display + Debug>> + 'a
+    ) -> impl Future<Output=Result<Return, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         let parameter = Parameter {
@@ -282,7 +282,7 @@ pub mod rand {
     impl_command_data_future!(Return, error::Error);
 
     pub fn send<'a, T: 'static>(hci: &'a HostInterface<T>)
-    -> impl Future<Output=Result<Return, impl Display + Debug>> + 'a
+    -> impl Future<Output=Result<Return, impl CommandError>> + 'a
     where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(
@@ -344,7 +344,7 @@ pub mod start_encryption {
     impl_command_status_future!();
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, parameter: Parameter)
-    -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
     where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(
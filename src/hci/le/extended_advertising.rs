@@ -0,0 +1,1221 @@
+//! LE Extended Advertising
+//!
+//! Extended advertising replaces the single advertiser of the legacy advertising commands with
+//! *advertising sets*. Every advertising set has its own parameters, random address, advertising
+//! data, and scan response data, and any number of sets (up to the number supported by the
+//! controller) can be advertising at the same time. An advertising set is identified by an
+//! [`AdvertisingHandle`] chosen by the host, it is created by the first
+//! [`set_extended_advertising_parameters`] command sent with the handle.
+//!
+//! The advertising data and scan response data of a set using extended advertising PDUs can be
+//! larger than the 31 bytes of legacy advertising. Data longer than the maximum parameter of a
+//! single command is sent to the controller in fragments by
+//! [`set_extended_advertising_data::send`] and [`set_extended_scan_response_data::send`].
+//!
+//! An [`AdvertisingSet`] binds a handle to a host interface, its methods send the commands for
+//! the set and it tracks the *LE Advertising Set Terminated* events for the set.
+//!
+//! # Note
+//! A controller does not allow the legacy advertising commands to be mixed with the extended
+//! advertising commands. Once an extended advertising command is sent, only extended advertising
+//! commands can be used until the controller is reset.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::future::Future;
+use core::time::Duration;
+use crate::gap::advertise::{ADStructPacker, DataTooLargeError, IntoRaw};
+use crate::hci::{
+    events,
+    spin_lock::SpinLock,
+    CommandError,
+    EventStream,
+    HostControllerInterface,
+    HostInterface,
+    ReceivedPacketSource,
+};
+
+pub use crate::hci::events::LEPhy;
+
+/// The maximum number of bytes of advertising data in one command
+const MAX_FRAGMENT_LEN: usize = 251;
+
+/// The handle of an advertising set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AdvertisingHandle {
+    handle: u8,
+}
+
+impl AdvertisingHandle {
+    const MAX: u8 = 0xEF;
+
+    /// Create a new `AdvertisingHandle`
+    ///
+    /// # Error
+    /// `raw` is larger than 0xEF
+    pub fn try_new(raw: u8) -> Result<Self, &'static str> {
+        if raw <= Self::MAX {
+            Ok(AdvertisingHandle { handle: raw })
+        } else {
+            Err("Advertising handle out of range: 0..=0xEF")
+        }
+    }
+
+    /// Get the raw value of the handle
+    pub fn get_raw_handle(&self) -> u8 {
+        self.handle
+    }
+}
+
+impl core::fmt::Display for AdvertisingHandle {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "0x{:X}", self.handle)
+    }
+}
+
+/// Advertising data or scan response data of an advertising set
+///
+/// This is the same as the advertising data of the legacy advertising commands, but it can be
+/// up to 1650 bytes long. The maximum length supported by the controller is returned by
+/// [`read_maximum_advertising_data_length`], data longer than this is rejected by the controller.
+/// Advertising sets using legacy advertising PDUs can only use 31 bytes of data.
+#[derive(Debug, Clone)]
+pub struct AdvertisingData {
+    packer: ADStructPacker<Vec<u8>>,
+}
+
+impl AdvertisingData {
+    /// The largest length of advertising data
+    pub const MAXIMUM_LENGTH: usize = 1650;
+
+    /// Create an empty advertising data that can be up to `MAXIMUM_LENGTH` bytes long
+    pub fn new() -> Self {
+        Self::with_maximum_length(Self::MAXIMUM_LENGTH)
+    }
+
+    /// Create an empty advertising data that can be up to `length` bytes long
+    ///
+    /// Lengths larger than `MAXIMUM_LENGTH` are truncated to `MAXIMUM_LENGTH`.
+    pub fn with_maximum_length(length: usize) -> Self {
+        let buffer = alloc::vec![0; length.min(Self::MAXIMUM_LENGTH)];
+
+        AdvertisingData { packer: ADStructPacker::new(buffer) }
+    }
+
+    /// Add an ADStruct to the advertising data
+    ///
+    /// # Error
+    /// 'data' in its transmission form was too large for remaining free space in the advertising
+    /// data.
+    pub fn try_push<T>(&mut self, data: T) -> Result<(), DataTooLargeError> where T: IntoRaw {
+        self.packer.try_push(data)
+    }
+
+    /// Get the remaining amount of space available for ADStructures
+    pub fn remaining_space(&self) -> usize {
+        self.packer.remaining_space()
+    }
+
+    /// Get the length of the advertising data
+    pub fn len(&self) -> usize {
+        self.packer.len()
+    }
+
+    /// Check if no ADStructures are in the advertising data
+    pub fn is_empty(&self) -> bool {
+        self.packer.len() == 0
+    }
+
+    pub(super) fn as_slice(&self) -> &[u8] {
+        &self.packer.get_buffer()[..self.packer.len()]
+    }
+}
+
+impl Default for AdvertisingData {
+    fn default() -> Self {
+        AdvertisingData::new()
+    }
+}
+
+/// The preference of the host for the controller fragmenting the data of an advertising set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentPreference {
+    /// The controller may fragment the data over multiple PDUs
+    ControllerMayFragment,
+    /// The controller should only fragment the data if it does not fit within one PDU
+    ControllerShouldNotFragment,
+}
+
+impl FragmentPreference {
+    fn into_val(self) -> u8 {
+        match self {
+            FragmentPreference::ControllerMayFragment => 0x00,
+            FragmentPreference::ControllerShouldNotFragment => 0x01,
+        }
+    }
+}
+
+impl Default for FragmentPreference {
+    fn default() -> Self {
+        FragmentPreference::ControllerMayFragment
+    }
+}
+
+/// Set the random address of an advertising set
+///
+/// This is the random address used by the set when the own address type of its parameters is a
+/// random address.
+pub mod set_advertising_set_random_address {
+
+    use crate::hci::*;
+    use super::AdvertisingHandle;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetAdvertisingSetRandomAddress);
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _handle: u8,
+        _random_address: [u8;6],
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>(
+        hci: &'a HostInterface<T>,
+        handle: AdvertisingHandle,
+        random_address: crate::BluetoothDeviceAddress,
+    ) -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _handle: handle.get_raw_handle(),
+            _random_address: random_address,
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Set the parameters of an advertising set
+///
+/// This creates the advertising set if there is no set with the advertising handle. The
+/// parameters of a set cannot be changed while the set is enabled.
+pub mod set_extended_advertising_parameters {
+
+    use crate::hci::*;
+    use crate::hci::le::common::OwnAddressType;
+    use crate::hci::le::transmitter::set_advertising_parameters::{
+        AdvertisingChannel,
+        AdvertisingFilterPolicy,
+        PeerAddressType,
+    };
+    use super::{AdvertisingHandle, LEPhy};
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetExtendedAdvertisingParameters);
+
+    /// The properties of the advertising events of an advertising set
+    ///
+    /// The advertising event properties of a set are a list of these. Advertising is
+    /// non-connectable, non-scannable, and undirected without `Connectable`, `Scannable`, and
+    /// `Directed`. Sets with the `Legacy` property use legacy advertising PDUs, so they can be
+    /// scanned by devices that do not support extended advertising.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum EventProperty {
+        Connectable,
+        Scannable,
+        Directed,
+        HighDutyCycleDirectedConnectable,
+        Legacy,
+        /// Omit the address of the advertiser from all PDUs (anonymous advertising)
+        OmitAdvertiserAddress,
+        /// Include the transmit power in the extended header of the advertising PDU
+        IncludeTxPower,
+    }
+
+    impl EventProperty {
+        fn into_val(self) -> u16 {
+            match self {
+                EventProperty::Connectable => 1 << 0,
+                EventProperty::Scannable => 1 << 1,
+                EventProperty::Directed => 1 << 2,
+                EventProperty::HighDutyCycleDirectedConnectable => 1 << 3,
+                EventProperty::Legacy => 1 << 4,
+                EventProperty::OmitAdvertiserAddress => 1 << 5,
+                EventProperty::IncludeTxPower => 1 << 6,
+            }
+        }
+
+        /// The event properties of legacy connectable and scannable undirected advertising
+        pub fn default_properties() -> &'static [EventProperty] {
+            &[
+                EventProperty::Connectable,
+                EventProperty::Scannable,
+                EventProperty::Legacy,
+            ]
+        }
+    }
+
+    /// The primary advertising interval of an advertising set
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct AdvertisingInterval {
+        interval: u32,
+    }
+
+    impl AdvertisingInterval {
+        const RAW_RANGE: core::ops::RangeInclusive<u32> = 0x20..=0xFF_FFFF;
+
+        const MICRO_SEC_CONV: u64 = 625;
+
+        /// Create an interval from a raw value
+        ///
+        /// # Error
+        /// The value is out of bounds.
+        pub fn try_from_raw(raw: u32) -> Result<Self, &'static str> {
+            if Self::RAW_RANGE.contains(&raw) {
+                Ok(AdvertisingInterval { interval: raw })
+            } else {
+                Err("Raw value out of range: 0x20..=0xFFFFFF")
+            }
+        }
+
+        /// Create an interval from a Duration
+        ///
+        /// # Error
+        /// The value is out of bounds.
+        pub fn try_from_duration(duration: Duration) -> Result<Self, &'static str> {
+            let raw = duration.as_micros() / Self::MICRO_SEC_CONV as u128;
+
+            if raw >= *Self::RAW_RANGE.start() as u128 && raw <= *Self::RAW_RANGE.end() as u128 {
+                Ok(AdvertisingInterval { interval: raw as u32 })
+            } else {
+                Err("Duration out of range: 20ms..=10485.759375s")
+            }
+        }
+
+        /// Get the raw value of the interval
+        pub fn get_raw_val(&self) -> u32 { self.interval }
+
+        /// Get the value of the interval as a `Duration`
+        pub fn get_duration(&self) -> Duration {
+            Duration::from_micros(self.interval as u64 * Self::MICRO_SEC_CONV)
+        }
+    }
+
+    impl Default for AdvertisingInterval {
+        /// The default interval is the same as the default of the legacy advertising interval
+        /// (1.28 seconds)
+        fn default() -> Self {
+            AdvertisingInterval { interval: 0x800 }
+        }
+    }
+
+    /// The parameters of an advertising set
+    ///
+    /// For the event properties and primary advertising channel map, provide a slice containing
+    /// every property or channel desired.
+    ///
+    /// The primary advertising PHY can only be the LE 1M PHY or the LE Coded PHY, the secondary
+    /// advertising PHY is only used by sets that do not use legacy advertising PDUs.
+    #[cfg_attr(test,derive(Debug))]
+    pub struct AdvertisingParameters<'a> {
+        pub event_properties: &'a [EventProperty],
+        pub minimum_advertising_interval: AdvertisingInterval,
+        pub maximum_advertising_interval: AdvertisingInterval,
+        pub primary_advertising_channel_map: &'a [AdvertisingChannel],
+        pub own_address_type: OwnAddressType,
+        pub peer_address_type: PeerAddressType,
+        pub peer_address: crate::BluetoothDeviceAddress,
+        pub advertising_filter_policy: AdvertisingFilterPolicy,
+        /// The maximum transmit power in dBm, `None` means that the host has no preference
+        pub advertising_tx_power: Option<i8>,
+        pub primary_advertising_phy: LEPhy,
+        /// The maximum number of advertising events that can be skipped before sending the
+        /// auxiliary advertising PDU on the secondary advertising channel
+        pub secondary_advertising_max_skip: u8,
+        pub secondary_advertising_phy: LEPhy,
+        /// The value of the advertising SID subfield (0..=0xF) of the advertising data info
+        pub advertising_sid: u8,
+        /// Enable the *LE Scan Request Received* event for the set
+        pub scan_request_notification: bool,
+    }
+
+    impl<'a> Default for AdvertisingParameters<'a> {
+
+        /// Create the parameters for connectable and scannable undirected advertising using
+        /// legacy advertising PDUs on the LE 1M PHY
+        ///
+        /// The peer address is set to zero.
+        fn default() -> Self {
+            AdvertisingParameters {
+                event_properties: EventProperty::default_properties(),
+                minimum_advertising_interval: AdvertisingInterval::default(),
+                maximum_advertising_interval: AdvertisingInterval::default(),
+                primary_advertising_channel_map: AdvertisingChannel::default_channels(),
+                own_address_type: OwnAddressType::default(),
+                peer_address_type: PeerAddressType::default(),
+                peer_address: [0u8;6],
+                advertising_filter_policy: AdvertisingFilterPolicy::default(),
+                advertising_tx_power: None,
+                primary_advertising_phy: LEPhy::_1M,
+                secondary_advertising_max_skip: 0,
+                secondary_advertising_phy: LEPhy::_1M,
+                advertising_sid: 0,
+                scan_request_notification: false,
+            }
+        }
+    }
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct CmdParameter {
+        _handle: u8,
+        _advertising_event_properties: u16,
+        _primary_advertising_interval_min: [u8;3],
+        _primary_advertising_interval_max: [u8;3],
+        _primary_advertising_channel_map: u8,
+        _own_address_type: u8,
+        _peer_address_type: u8,
+        _peer_address: crate::BluetoothDeviceAddress,
+        _advertising_filter_policy: u8,
+        _advertising_tx_power: i8,
+        _primary_advertising_phy: u8,
+        _secondary_advertising_max_skip: u8,
+        _secondary_advertising_phy: u8,
+        _advertising_sid: u8,
+        _scan_request_notification_enable: u8,
+    }
+
+    impl CommandParameter for CmdParameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        selected_tx_power: i8,
+    }
+
+    pub struct Return;
+
+    impl Return {
+        fn try_from(packed: CmdReturn) -> Result<i8, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(packed.selected_tx_power)
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        Return,
+        i8,
+        error::Error
+    );
+
+    impl_command_data_future!(Return, i8, error::Error);
+
+    /// Get the first three bytes of the little endian interval
+    fn interval_bytes(interval: &AdvertisingInterval) -> [u8;3] {
+        let bytes = interval.get_raw_val().to_le_bytes();
+
+        [bytes[0], bytes[1], bytes[2]]
+    }
+
+    /// Send the command
+    ///
+    /// The output of the returned future is the transmit power (in dBm) selected by the
+    /// controller for the set.
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: AdvertisingHandle, params: AdvertisingParameters )
+    -> impl Future<Output=Result<i8, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = CmdParameter {
+            _handle: handle.get_raw_handle(),
+            _advertising_event_properties: params.event_properties.iter()
+                .fold(0u16, |v, p| v | p.into_val())
+                .to_le(),
+            _primary_advertising_interval_min: interval_bytes(&params.minimum_advertising_interval),
+            _primary_advertising_interval_max: interval_bytes(&params.maximum_advertising_interval),
+            _primary_advertising_channel_map: params.primary_advertising_channel_map.iter()
+                .fold(0u8, |v, x| v | x.into_val()),
+            _own_address_type: params.own_address_type.into_val(),
+            _peer_address_type: params.peer_address_type.into_val(),
+            _peer_address: params.peer_address,
+            _advertising_filter_policy: params.advertising_filter_policy.into_val(),
+            _advertising_tx_power: params.advertising_tx_power.unwrap_or(0x7F),
+            _primary_advertising_phy: params.primary_advertising_phy.into_val(),
+            _secondary_advertising_max_skip: params.secondary_advertising_max_skip,
+            _secondary_advertising_phy: params.secondary_advertising_phy.into_val(),
+            _advertising_sid: params.advertising_sid,
+            _scan_request_notification_enable: params.scan_request_notification as u8,
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Setup for the commands that set the advertising data or scan response data of a set
+macro_rules! extended_data_setup {
+    ( $command: ident ) => {
+        use crate::hci::*;
+        use super::{AdvertisingData, AdvertisingHandle, FragmentPreference, MAX_FRAGMENT_LEN};
+
+        impl_status_return!( $command );
+
+        /// The operation of a command with respect to the fragments of the data
+        #[derive(Clone, Copy)]
+        enum Operation {
+            Intermediate,
+            First,
+            Last,
+            Complete,
+        }
+
+        impl Operation {
+            fn into_val(self) -> u8 {
+                match self {
+                    Operation::Intermediate => 0x00,
+                    Operation::First => 0x01,
+                    Operation::Last => 0x02,
+                    Operation::Complete => 0x03,
+                }
+            }
+        }
+
+        /// A fragment of the data, the parameter is of variable length
+        struct Fragment {
+            handle: AdvertisingHandle,
+            operation: Operation,
+            fragment_preference: FragmentPreference,
+            data: Vec<u8>,
+        }
+
+        impl CommandParameter for Fragment {
+            type Parameter = ();
+            const COMMAND: opcodes::HCICommand = $command;
+            fn get_parameter(&self) -> Self::Parameter {}
+
+            fn as_command_packet<'a>(&self) -> alloc::boxed::Box<[u8]> {
+                let parameter_len = 4 + self.data.len();
+
+                let mut packet = Vec::with_capacity(parameter_len + 3);
+
+                packet.extend_from_slice(&$command.as_opcode_pair().as_opcode().to_le_bytes());
+
+                packet.push(parameter_len as u8);
+
+                packet.push(self.handle.get_raw_handle());
+
+                packet.push(self.operation.into_val());
+
+                packet.push(self.fragment_preference.into_val());
+
+                packet.push(self.data.len() as u8);
+
+                packet.extend_from_slice(&self.data);
+
+                packet.into_boxed_slice()
+            }
+        }
+
+        /// Send the command
+        ///
+        /// Data longer than the maximum of a single command (251 bytes) is sent in multiple
+        /// commands, the returned future completes once the controller has accepted every
+        /// fragment. Sending an empty `data` removes the data of the set.
+        pub fn send<'a, T: 'static>(
+            hci: &'a HostInterface<T>,
+            handle: AdvertisingHandle,
+            data: &AdvertisingData,
+            fragment_preference: FragmentPreference,
+        ) -> impl Future<Output=Result<(), impl CommandError>> + 'a
+        where T: HostControllerInterface
+        {
+            let data = data.as_slice();
+
+            let last = data.len().saturating_sub(1) / MAX_FRAGMENT_LEN;
+
+            let fragments: Vec<Fragment> = (0..=last).map(|index| {
+                let start = index * MAX_FRAGMENT_LEN;
+                let end = data.len().min(start + MAX_FRAGMENT_LEN);
+
+                let operation = match index {
+                    _ if last == 0 => Operation::Complete,
+                    0 => Operation::First,
+                    _ if index == last => Operation::Last,
+                    _ => Operation::Intermediate,
+                };
+
+                Fragment {
+                    handle,
+                    operation,
+                    fragment_preference,
+                    data: data[start..end].to_vec(),
+                }
+            })
+            .collect();
+
+            async move {
+                for fragment in fragments {
+                    let result = ReturnedFuture(
+                        hci.send_command(fragment, events::Events::CommandComplete, Duration::from_secs(1))
+                    ).await;
+
+                    if let Err(e) = result {
+                        return Err(e)
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Set the advertising data of an advertising set
+pub mod set_extended_advertising_data {
+
+    const COMMAND: crate::hci::opcodes::HCICommand = crate::hci::opcodes::HCICommand::LEController(crate::hci::opcodes::LEController::SetExtendedAdvertisingData);
+
+    extended_data_setup!(COMMAND);
+}
+
+/// Set the scan response data of an advertising set
+///
+/// Only scannable advertising sets use scan response data.
+pub mod set_extended_scan_response_data {
+
+    const COMMAND: crate::hci::opcodes::HCICommand = crate::hci::opcodes::HCICommand::LEController(crate::hci::opcodes::LEController::SetExtendedScanResponseData);
+
+    extended_data_setup!(COMMAND);
+}
+
+/// Enable or disable advertising sets
+pub mod set_extended_advertising_enable {
+
+    use crate::hci::*;
+    use super::AdvertisingHandle;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetExtendedAdvertisingEnable);
+
+    impl_status_return!(COMMAND);
+
+    /// The enable parameters of an advertising set
+    ///
+    /// By default, an advertising set advertises until it is disabled. The set is also disabled
+    /// by the controller once the duration has passed or the maximum number of extended advertising
+    /// events are sent, the controller then sends the *LE Advertising Set Terminated* event
+    /// (duration does not apply to high duty cycle directed advertising, which always ends with
+    /// the *LE Connection Complete* event).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EnableParameters {
+        handle: AdvertisingHandle,
+        duration: u16,
+        max_extended_advertising_events: u8,
+    }
+
+    impl EnableParameters {
+        const DURATION_RANGE: core::ops::RangeInclusive<u128> = 0x1..=0xFFFF;
+
+        /// Create the enable parameters for advertising until the set is disabled
+        pub fn new(handle: AdvertisingHandle) -> Self {
+            EnableParameters { handle, duration: 0, max_extended_advertising_events: 0 }
+        }
+
+        /// Set the duration to advertise for
+        ///
+        /// The duration is rounded down to a multiple of 10 milliseconds.
+        ///
+        /// # Error
+        /// `duration` is not within the range of 10 milliseconds to 655.35 seconds
+        pub fn with_duration(self, duration: Duration) -> Result<Self, &'static str> {
+            let raw = duration.as_millis() / 10;
+
+            if Self::DURATION_RANGE.contains(&raw) {
+                Ok(EnableParameters { duration: raw as u16, .. self })
+            } else {
+                Err("Duration out of range: 10ms..=655.35s")
+            }
+        }
+
+        /// Set the maximum number of extended advertising events to send
+        ///
+        /// A value of zero means there is no maximum.
+        pub fn with_max_extended_advertising_events(self, max: u8) -> Self {
+            EnableParameters { max_extended_advertising_events: max, .. self }
+        }
+
+        /// Get the advertising handle
+        pub fn get_handle(&self) -> AdvertisingHandle {
+            self.handle
+        }
+    }
+
+    struct Parameter {
+        enable: bool,
+        sets: Vec<EnableParameters>,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = ();
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {}
+
+        fn as_command_packet<'a>(&self) -> alloc::boxed::Box<[u8]> {
+            let parameter_len = 2 + self.sets.len() * 4;
+
+            let mut packet = Vec::with_capacity(parameter_len + 3);
+
+            packet.extend_from_slice(&COMMAND.as_opcode_pair().as_opcode().to_le_bytes());
+
+            packet.push(parameter_len as u8);
+
+            packet.push(self.enable as u8);
+
+            packet.push(self.sets.len() as u8);
+
+            // The parameters of each set are grouped together
+            for set in self.sets.iter() {
+                packet.push(set.handle.get_raw_handle());
+
+                packet.extend_from_slice(&set.duration.to_le_bytes());
+
+                packet.push(set.max_extended_advertising_events);
+            }
+
+            packet.into_boxed_slice()
+        }
+    }
+
+    /// The maximum number of advertising sets within one command
+    const MAX_SETS: usize = 0x3F;
+
+    #[derive(Debug)]
+    pub(super) enum EnableError<E> {
+        Command(E),
+        TooManySets,
+        InvalidDuration(&'static str),
+    }
+
+    impl<E: Display> Display for EnableError<E> {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            match self {
+                EnableError::Command(e) => Display::fmt(e, f),
+                EnableError::TooManySets =>
+                    write!(f, "Too many advertising sets for one command, the maximum is {}", MAX_SETS),
+                EnableError::InvalidDuration(reason) => write!(f, "Invalid advertising duration, {}", reason),
+            }
+        }
+    }
+
+    impl<E: CommandError> CommandError for EnableError<E> {
+        fn kind(&self) -> CommandErrorKind {
+            match self {
+                EnableError::Command(e) => e.kind(),
+                _ => CommandErrorKind::Other,
+            }
+        }
+    }
+
+    /// Send the command
+    ///
+    /// Every set in `sets` is enabled when `enable` is true, or disabled when it is false (the
+    /// duration and maximum number of events are not used to disable a set). Disabling with an
+    /// empty `sets` disables all advertising sets.
+    ///
+    /// # Error
+    /// An error is returned without sending the command if there are more than 63 sets in `sets`
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool, sets: &[EnableParameters] )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        send_sets(hci, enable, sets)
+    }
+
+    pub(super) fn send_sets<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool, sets: &[EnableParameters] )
+    -> impl Future<Output=Result<(), EnableError<impl CommandError>>> + 'a
+    where T: HostControllerInterface
+    {
+        let too_many_sets = sets.len() > MAX_SETS;
+
+        let parameter = Parameter { enable, sets: sets.to_vec() };
+
+        async move {
+            if too_many_sets {
+                return Err(EnableError::TooManySets)
+            }
+
+            ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+                .await
+                .map_err(EnableError::Command)
+        }
+    }
+}
+
+/// Read the maximum length of advertising data supported by the controller
+pub mod read_maximum_advertising_data_length {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadMaximumAdvertisingDataLength);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        maximum_advertising_data_length: u16,
+    }
+
+    pub struct Return;
+
+    impl Return {
+        fn try_from(packed: CmdReturn) -> Result<usize, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(<u16>::from_le(packed.maximum_advertising_data_length) as usize)
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        Return,
+        usize,
+        error::Error
+    );
+
+    impl_command_data_future!(Return, usize, error::Error);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<usize, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Read the number of advertising sets supported by the controller
+///
+/// The number of sets can change as the memory used to store them may be shared with other
+/// features of the controller.
+pub mod read_number_of_supported_advertising_sets {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadNumberOfSupportedAdvertisingSets);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        num_supported_advertising_sets: u8,
+    }
+
+    pub struct Return;
+
+    impl Return {
+        fn try_from(packed: CmdReturn) -> Result<usize, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(packed.num_supported_advertising_sets as usize)
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        Return,
+        usize,
+        error::Error
+    );
+
+    impl_command_data_future!(Return, usize, error::Error);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<usize, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Remove an advertising set
+///
+/// An enabled set cannot be removed.
+pub mod remove_advertising_set {
+
+    use crate::hci::*;
+    use super::AdvertisingHandle;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::RemoveAdvertisingSet);
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _handle: u8,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: AdvertisingHandle )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter { _handle: handle.get_raw_handle() };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Remove all advertising sets
+///
+/// The sets cannot be removed while any of them is enabled.
+pub mod clear_advertising_sets {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ClearAdvertisingSets);
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// The number of events buffered by the event stream of an [`AdvertisingSet`]
+const TERMINATED_EVENTS_CAPACITY: usize = 4;
+
+/// An advertising set
+///
+/// An `AdvertisingSet` is the advertising handle of a set bound to a host interface. The methods
+/// for the commands of a set only fill in the advertising handle, the commands are sent to the
+/// controller in the same way as using the command modules directly. Creating an `AdvertisingSet`
+/// does not create the set on the controller, the set is created by the first call to
+/// [`set_parameters`](AdvertisingSet::set_parameters).
+///
+/// The *LE Advertising Set Terminated* events for the set are received with an
+/// [event stream](crate::hci::EventStream), [`get_terminated`](AdvertisingSet::get_terminated)
+/// returns the last one of them. The LE event mask must have this event enabled for the
+/// controller to send it.
+pub struct AdvertisingSet<'a, I> {
+    hi: &'a HostInterface<I>,
+    handle: AdvertisingHandle,
+    terminated: SpinLock<Option<events::LEAdvertisingSetTerminatedData>>,
+    terminated_events: EventStream,
+}
+
+impl<'a, I> AdvertisingSet<'a, I>
+where I: HostControllerInterface + ReceivedPacketSource + 'static
+{
+    /// Create a new `AdvertisingSet`
+    pub fn new(hi: &'a HostInterface<I>, handle: AdvertisingHandle) -> Self {
+        use events::{Events, EventsData, LEMeta, LEMetaData};
+
+        let terminated_events = hi.subscribe_with_matcher(
+            Events::LEMeta(LEMeta::AdvertisingSetTerminated),
+            TERMINATED_EVENTS_CAPACITY,
+            move |ed: &EventsData| matches!(ed,
+                EventsData::LEMeta(LEMetaData::AdvertisingSetTerminated(data))
+                if data.advertising_handle == handle.get_raw_handle()
+            )
+        );
+
+        AdvertisingSet {
+            hi,
+            handle,
+            terminated: SpinLock::new(None),
+            terminated_events,
+        }
+    }
+
+    /// Get the advertising handle
+    pub fn get_handle(&self) -> AdvertisingHandle {
+        self.handle
+    }
+
+    /// Get the data of the last *LE Advertising Set Terminated* event for the set
+    ///
+    /// `None` is returned if the set has not been terminated by the controller since this
+    /// `AdvertisingSet` was created. The set is terminated by the controller when the duration or
+    /// maximum number of events of the enable parameters is reached, or when a connection is
+    /// created by a connectable set (the connection handle is within the event data).
+    pub fn get_terminated(&self) -> Option<events::LEAdvertisingSetTerminatedData> {
+        use events::{EventsData, LEMetaData};
+
+        let mut terminated = self.terminated.lock();
+
+        while let Some(entry) = self.terminated_events.try_next_event() {
+            match entry {
+                Ok(EventsData::LEMeta(LEMetaData::AdvertisingSetTerminated(data))) => *terminated = Some(data),
+                Ok(_) => (),
+                Err(overflow) => log::warn!("Advertising set {}: {}", self.handle, overflow),
+            }
+        }
+
+        (*terminated).clone()
+    }
+
+    /// Set the parameters of the set
+    ///
+    /// The output of the returned future is the transmit power selected by the controller.
+    pub fn set_parameters(&self, params: set_extended_advertising_parameters::AdvertisingParameters)
+    -> impl Future<Output=Result<i8, impl CommandError>> + 'a
+    {
+        set_extended_advertising_parameters::send(self.hi, self.handle, params)
+    }
+
+    /// Set the random address of the set
+    pub fn set_random_address(&self, random_address: crate::BluetoothDeviceAddress)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        set_advertising_set_random_address::send(self.hi, self.handle, random_address)
+    }
+
+    /// Set the advertising data of the set
+    pub fn set_advertising_data(&self, data: &AdvertisingData, fragment_preference: FragmentPreference)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        set_extended_advertising_data::send(self.hi, self.handle, data, fragment_preference)
+    }
+
+    /// Set the scan response data of the set
+    pub fn set_scan_response_data(&self, data: &AdvertisingData, fragment_preference: FragmentPreference)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        set_extended_scan_response_data::send(self.hi, self.handle, data, fragment_preference)
+    }
+
+    /// Enable the set
+    ///
+    /// A `None` for `duration` means the set advertises until it is disabled and a
+    /// `max_extended_advertising_events` of zero means there is no maximum number of events.
+    ///
+    /// # Error
+    /// An error is returned without sending the command if `duration` is not within the range of
+    /// 10 milliseconds to 655.35 seconds
+    pub fn enable(&self, duration: Option<Duration>, max_extended_advertising_events: u8)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        use set_extended_advertising_enable::{EnableError, EnableParameters};
+
+        let hi = self.hi;
+
+        let parameters = EnableParameters::new(self.handle)
+            .with_max_extended_advertising_events(max_extended_advertising_events);
+
+        let parameters = match duration {
+            Some(duration) => parameters.with_duration(duration),
+            None => Ok(parameters),
+        };
+
+        async move {
+            let parameters = parameters.map_err(EnableError::InvalidDuration)?;
+
+            set_extended_advertising_enable::send_sets(hi, true, &[parameters]).await
+        }
+    }
+
+    /// Disable the set
+    pub fn disable(&self) -> impl Future<Output=Result<(), impl CommandError>> + 'a {
+        let parameters = set_extended_advertising_enable::EnableParameters::new(self.handle);
+
+        set_extended_advertising_enable::send(self.hi, false, &[parameters])
+    }
+
+    /// Remove the set from the controller
+    pub fn remove(&self) -> impl Future<Output=Result<(), impl CommandError>> + 'a {
+        remove_advertising_set::send(self.hi, self.handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::set_extended_advertising_enable::EnableParameters;
+    use super::set_extended_advertising_parameters::{AdvertisingParameters, EventProperty};
+    use crate::hci::error;
+    use crate::hci::testing::fixture::host_interface;
+    use crate::hci::testing::NUM_SUPPORTED_ADVERTISING_SETS;
+    use crate::BluetoothDeviceAddress;
+    use futures::executor::block_on;
+
+    #[test]
+    fn extended_advertising_test() {
+        use crate::gap::advertise::local_name::LocalName;
+
+        const BEACON_ADDRESS: BluetoothDeviceAddress = [0x01, 0x02, 0x03, 0x04, 0x05, 0xC6];
+
+        let (hi, controller) = host_interface();
+
+        assert_eq!(
+            NUM_SUPPORTED_ADVERTISING_SETS,
+            block_on(read_number_of_supported_advertising_sets::send(&hi)).unwrap()
+        );
+        assert_eq!(
+            AdvertisingData::MAXIMUM_LENGTH,
+            block_on(read_maximum_advertising_data_length::send(&hi)).unwrap()
+        );
+
+        let connectable = AdvertisingSet::new(&hi, AdvertisingHandle::try_new(0).unwrap());
+        let beacon = AdvertisingSet::new(&hi, AdvertisingHandle::try_new(1).unwrap());
+
+        // The beacon set does not exist until its parameters are set
+        assert!(block_on(beacon.set_random_address(BEACON_ADDRESS)).is_err());
+
+        block_on(connectable.set_parameters(AdvertisingParameters::default())).unwrap();
+
+        let beacon_parameters = AdvertisingParameters {
+            event_properties: &[EventProperty::IncludeTxPower],
+            advertising_tx_power: Some(-10),
+            secondary_advertising_phy: LEPhy::_2M,
+            .. AdvertisingParameters::default()
+        };
+
+        assert_eq!(-10, block_on(beacon.set_parameters(beacon_parameters)).unwrap());
+
+        block_on(beacon.set_random_address(BEACON_ADDRESS)).unwrap();
+
+        let mut connectable_data = AdvertisingData::new();
+
+        connectable_data.try_push(LocalName::new("virtual", false)).unwrap();
+
+        block_on(connectable.set_advertising_data(&connectable_data, FragmentPreference::default())).unwrap();
+        block_on(connectable.set_scan_response_data(&connectable_data, FragmentPreference::default())).unwrap();
+
+        // The beacon data is sent in three fragments
+        let mut beacon_data = AdvertisingData::new();
+
+        for name in ["a".repeat(200), "b".repeat(200), "c".repeat(200)].iter() {
+            beacon_data.try_push(LocalName::new(name.as_str(), false)).unwrap();
+        }
+
+        assert_eq!(606, beacon_data.len());
+
+        block_on(beacon.set_advertising_data(&beacon_data, FragmentPreference::ControllerShouldNotFragment)).unwrap();
+
+        // Legacy advertising is limited to 31 bytes and the beacon is not scannable
+        assert!(block_on(connectable.set_advertising_data(&beacon_data, FragmentPreference::default())).is_err());
+        assert!(block_on(beacon.set_scan_response_data(&connectable_data, FragmentPreference::default())).is_err());
+
+        let enable_parameters = [
+            EnableParameters::new(connectable.get_handle()),
+            EnableParameters::new(beacon.get_handle())
+                .with_duration(Duration::from_secs(10))
+                .unwrap()
+                .with_max_extended_advertising_events(20),
+        ];
+
+        // Invalid enable parameters are not sent to the controller
+        assert!(block_on(beacon.enable(Some(Duration::from_millis(5)), 0)).is_err());
+        assert!(block_on(set_extended_advertising_enable::send(&hi, true, &[enable_parameters[0]; 64])).is_err());
+        assert!(controller.get_advertising_sets().iter().all(|set| !set.enabled));
+
+        block_on(set_extended_advertising_enable::send(&hi, true, &enable_parameters)).unwrap();
+
+        // The handle, duration, and maximum number of events are grouped for each set
+        assert_eq!(
+            Some(alloc::vec![0x39, 0x20, 10, 1, 2, 0, 0, 0, 0, 1, 0xE8, 0x03, 20]),
+            controller.get_last_command()
+        );
+
+        let sets = controller.get_advertising_sets();
+
+        assert_eq!(2, sets.len());
+        assert!(sets.iter().all(|set| set.enabled));
+        assert_eq!(connectable_data.len(), sets[0].advertising_data.len());
+        assert_eq!(connectable_data.len(), sets[0].scan_response_data.len());
+        assert_eq!(Some(BEACON_ADDRESS), sets[1].random_address);
+        assert_eq!(beacon_data.len(), sets[1].advertising_data.len());
+        assert_eq!(&sets[1].advertising_data[404..406], &[201, 0x09]);
+        assert_eq!(1000, sets[1].duration);
+        assert_eq!(20, sets[1].max_extended_advertising_events);
+
+        // Enabled sets cannot be changed or removed
+        assert!(block_on(connectable.set_parameters(AdvertisingParameters::default())).is_err());
+        assert!(block_on(connectable.remove()).is_err());
+        assert!(block_on(clear_advertising_sets::send(&hi)).is_err());
+
+        assert!(beacon.get_terminated().is_none());
+
+        controller.inject_le_meta_event(
+            events::LEMeta::AdvertisingSetTerminated,
+            &[error::Error::AdvertisingTimeout.into(), 0x01, 0x00, 0x00, 20]
+        ).unwrap();
+
+        let terminated = beacon.get_terminated().unwrap();
+
+        assert_eq!(1, terminated.advertising_handle);
+        assert_eq!(20, terminated.num_completed_extended_advertising_events);
+        assert!(connectable.get_terminated().is_none());
+
+        block_on(beacon.remove()).unwrap();
+
+        assert_eq!(1, controller.get_advertising_sets().len());
+
+        block_on(connectable.disable()).unwrap();
+
+        block_on(clear_advertising_sets::send(&hi)).unwrap();
+
+        assert!(controller.get_advertising_sets().is_empty());
+    }
+}
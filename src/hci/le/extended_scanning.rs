@@ -0,0 +1,791 @@
+//! LE Extended Scanning and Initiating
+//!
+//! These are the scanning and initiating counterparts of the
+//! [extended advertising](crate::hci::le::extended_advertising) commands. Scanning and initiating
+//! are done on the primary advertising PHYs (the LE 1M PHY and the LE Coded PHY) and the
+//! parameters are given separately for each PHY, a PHY without parameters is not used.
+//!
+//! Advertising data of extended advertising can be larger than what fits within a single *LE
+//! Extended Advertising Report* event, the controller sends the data in fragments over multiple
+//! reports. An [`ExtendedScanner`] reassembles these fragments into complete reports.
+//!
+//! # Note
+//! A controller does not allow the legacy scanning and initiating commands to be mixed with the
+//! extended commands. Once an extended command is sent, only extended commands can be used until
+//! the controller is reset.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crate::hci::{
+    events,
+    CommandError,
+    EventStream,
+    HostControllerInterface,
+    HostInterface,
+    ReceivedPacketSource,
+};
+use crate::hci::common::LEAddressType;
+
+/// Get the raw PHY bits and the number of PHYs with parameters from a list of optional PHY
+/// parameters
+///
+/// The list is in the order of the bits of the PHYs.
+fn into_phys<T>(phy_parameters: &[Option<T>]) -> (u8, usize) {
+    phy_parameters.iter()
+        .enumerate()
+        .filter(|(_, parameters)| parameters.is_some())
+        .fold((0, 0), |(bits, count), (bit, _)| (bits | 1 << bit, count + 1))
+}
+
+/// Set the parameters for scanning
+pub mod set_extended_scan_parameters {
+
+    use crate::hci::*;
+    use crate::hci::le::common::OwnAddressType;
+    use crate::hci::le::receiver::set_scan_parameters::{LEScanType, ScanningFilterPolicy};
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetExtendedScanParameters);
+
+    interval!( ScanningInterval, 0x0004, 0xFFFF, SpecDef, 0x0010, 625);
+    interval!( ScanningWindow, 0x0004, 0xFFFF, SpecDef, 0x0010, 625);
+
+    impl_status_return!(COMMAND);
+
+    /// The scanning parameters of a PHY
+    #[derive(Default)]
+    pub struct ScanningPhyParameters {
+        pub scan_type: LEScanType,
+        pub scan_interval: ScanningInterval,
+        pub scan_window: ScanningWindow,
+    }
+
+    /// The scanning parameters
+    ///
+    /// Scanning is done on every PHY with parameters, the default parameters are to passively
+    /// scan on the LE 1M PHY.
+    pub struct ExtendedScanningParameters {
+        pub own_address_type: OwnAddressType,
+        pub scanning_filter_policy: ScanningFilterPolicy,
+        pub le_1m: Option<ScanningPhyParameters>,
+        pub coded: Option<ScanningPhyParameters>,
+    }
+
+    impl Default for ExtendedScanningParameters {
+        fn default() -> Self {
+            ExtendedScanningParameters {
+                own_address_type: OwnAddressType::default(),
+                scanning_filter_policy: ScanningFilterPolicy::default(),
+                le_1m: Some(ScanningPhyParameters::default()),
+                coded: None,
+            }
+        }
+    }
+
+    impl CommandParameter for ExtendedScanningParameters {
+        type Parameter = ();
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {}
+
+        fn as_command_packet<'a>(&self) -> alloc::boxed::Box<[u8]> {
+            // The bit of the LE 2M PHY is reserved as scanning is only on the primary PHYs
+            let phy_parameters = [self.le_1m.as_ref(), None, self.coded.as_ref()];
+
+            let (scanning_phys, num_phys) = super::into_phys(&phy_parameters);
+
+            let parameter_len = 3 + num_phys * 5;
+
+            let mut packet = Vec::with_capacity(parameter_len + 3);
+
+            packet.extend_from_slice(&COMMAND.as_opcode_pair().as_opcode().to_le_bytes());
+
+            packet.push(parameter_len as u8);
+
+            packet.push(self.own_address_type.into_val());
+
+            packet.push(self.scanning_filter_policy.into_val());
+
+            packet.push(scanning_phys);
+
+            // The parameters of each PHY are grouped together
+            for phy in phy_parameters.iter().filter_map(|parameters| *parameters) {
+                packet.push(phy.scan_type.into_val());
+
+                packet.extend_from_slice(&phy.scan_interval.get_raw_val().to_le_bytes());
+
+                packet.extend_from_slice(&phy.scan_window.get_raw_val().to_le_bytes());
+            }
+
+            packet.into_boxed_slice()
+        }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, sp: ExtendedScanningParameters )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(sp, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Enable or disable scanning
+pub mod set_extended_scan_enable {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetExtendedScanEnable);
+
+    impl_status_return!(COMMAND);
+
+    /// Filtering of duplicate advertising reports
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DuplicateFilter {
+        Disabled,
+        Enabled,
+        /// Duplicates are filtered within each scan period, this can only be used with a non-zero
+        /// duration and period
+        ResetEachPeriod,
+    }
+
+    impl DuplicateFilter {
+        fn into_val(self) -> u8 {
+            match self {
+                DuplicateFilter::Disabled => 0x00,
+                DuplicateFilter::Enabled => 0x01,
+                DuplicateFilter::ResetEachPeriod => 0x02,
+            }
+        }
+    }
+
+    impl Default for DuplicateFilter {
+        fn default() -> Self {
+            DuplicateFilter::Disabled
+        }
+    }
+
+    /// The enable parameters of scanning
+    ///
+    /// By default, scanning continues until it is disabled. With a duration, the controller stops
+    /// scanning once the duration has passed and sends the *LE Scan Timeout* event. With both a
+    /// duration and a period, the controller scans for the duration at the start of every period
+    /// until scanning is disabled.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ScanEnableParameters {
+        filter_duplicates: DuplicateFilter,
+        duration: u16,
+        period: u16,
+    }
+
+    impl ScanEnableParameters {
+        const RAW_RANGE: core::ops::RangeInclusive<u128> = 0x1..=0xFFFF;
+
+        /// Create the enable parameters for scanning until scanning is disabled
+        pub fn new(filter_duplicates: DuplicateFilter) -> Self {
+            ScanEnableParameters { filter_duplicates, duration: 0, period: 0 }
+        }
+
+        /// Set the duration of scanning
+        ///
+        /// The duration is rounded down to a multiple of 10 milliseconds.
+        ///
+        /// # Error
+        /// `duration` is not within the range of 10 milliseconds to 655.35 seconds
+        pub fn with_duration(self, duration: Duration) -> Result<Self, &'static str> {
+            let raw = duration.as_millis() / 10;
+
+            if Self::RAW_RANGE.contains(&raw) {
+                Ok(ScanEnableParameters { duration: raw as u16, .. self })
+            } else {
+                Err("Duration out of range: 10ms..=655.35s")
+            }
+        }
+
+        /// Set the period of scanning
+        ///
+        /// The period is rounded down to a multiple of 1.28 seconds.
+        ///
+        /// # Error
+        /// `period` is not within the range of 1.28 seconds to 83,884.8 seconds
+        pub fn with_period(self, period: Duration) -> Result<Self, &'static str> {
+            let raw = period.as_millis() / 1280;
+
+            if Self::RAW_RANGE.contains(&raw) {
+                Ok(ScanEnableParameters { period: raw as u16, .. self })
+            } else {
+                Err("Period out of range: 1.28s..=83884.8s")
+            }
+        }
+    }
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _enable: u8,
+        _filter_duplicates: u8,
+        _duration: u16,
+        _period: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    /// Send the command
+    ///
+    /// The `parameters` are not used when scanning is disabled.
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool, parameters: ScanEnableParameters )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _enable: enable as u8,
+            _filter_duplicates: parameters.filter_duplicates.into_val(),
+            _duration: parameters.duration.to_le(),
+            _period: parameters.period.to_le(),
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Create a connection
+///
+/// The controller initiates the connection on every PHY with parameters, the LE 2M PHY is used
+/// for initiating from an extended advertising PDU received on the LE 1M PHY. When the connection
+/// is created (or the creation is canceled) the controller sends the *LE Enhanced Connection
+/// Complete* event if it is not masked, otherwise it sends the *LE Connection Complete* event.
+pub mod extended_create_connection {
+
+    use crate::hci::*;
+    use crate::hci::common::{ConnectionLatency, LEAddressType, SupervisionTimeout};
+    use crate::hci::le::common::{ConnectionEventLength, OwnAddressType};
+    use crate::hci::le::connection::{
+        create_connection::InitiatorFilterPolicy,
+        ConnectionIntervalBounds,
+    };
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ExtendedCreateConnection);
+
+    interval!( ScanningInterval, 0x0004, 0xFFFF, SpecDef, 0x0010, 625);
+    interval!( ScanningWindow, 0x0004, 0xFFFF, SpecDef, 0x0010, 625);
+
+    impl_command_status_future!();
+
+    /// The initiating parameters of a PHY
+    ///
+    /// The scan interval and window are not used for the LE 2M PHY.
+    pub struct InitiatingPhyParameters {
+        pub scan_interval: ScanningInterval,
+        pub scan_window: ScanningWindow,
+        pub connection_interval: ConnectionIntervalBounds,
+        pub connection_latency: ConnectionLatency,
+        pub supervision_timeout: SupervisionTimeout,
+        pub connection_event_len: ConnectionEventLength,
+    }
+
+    /// The parameters for creating a connection
+    ///
+    /// The peer address type and address are not used when the initiator filter policy is to use
+    /// the white list.
+    pub struct ExtendedConnectionParameters {
+        pub initiator_filter_policy: InitiatorFilterPolicy,
+        pub own_address_type: OwnAddressType,
+        pub peer_address_type: LEAddressType,
+        pub peer_address: crate::BluetoothDeviceAddress,
+        pub le_1m: Option<InitiatingPhyParameters>,
+        pub le_2m: Option<InitiatingPhyParameters>,
+        pub coded: Option<InitiatingPhyParameters>,
+    }
+
+    impl CommandParameter for ExtendedConnectionParameters {
+        type Parameter = ();
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {}
+
+        fn as_command_packet<'a>(&self) -> alloc::boxed::Box<[u8]> {
+            let phy_parameters = [self.le_1m.as_ref(), self.le_2m.as_ref(), self.coded.as_ref()];
+
+            let (initiating_phys, num_phys) = super::into_phys(&phy_parameters);
+
+            let parameter_len = 10 + num_phys * 16;
+
+            let mut packet = Vec::with_capacity(parameter_len + 3);
+
+            packet.extend_from_slice(&COMMAND.as_opcode_pair().as_opcode().to_le_bytes());
+
+            packet.push(parameter_len as u8);
+
+            packet.push(self.initiator_filter_policy.val());
+
+            packet.push(self.own_address_type.into_val());
+
+            packet.push(self.peer_address_type.into_raw());
+
+            packet.extend_from_slice(&self.peer_address);
+
+            packet.push(initiating_phys);
+
+            // The parameters of each PHY are grouped together
+            for phy in phy_parameters.iter().filter_map(|parameters| *parameters) {
+                let fields = [
+                    phy.scan_interval.get_raw_val(),
+                    phy.scan_window.get_raw_val(),
+                    phy.connection_interval.min.get_raw_val(),
+                    phy.connection_interval.max.get_raw_val(),
+                    phy.connection_latency.get_latency(),
+                    phy.supervision_timeout.get_timeout(),
+                    phy.connection_event_len.minimum,
+                    phy.connection_event_len.maximum,
+                ];
+
+                fields.iter().for_each(|field| packet.extend_from_slice(&field.to_le_bytes()));
+            }
+
+            packet.into_boxed_slice()
+        }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, cp: ExtendedConnectionParameters )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(cp, events::Events::CommandStatus, Duration::from_secs(1) ) )
+    }
+}
+
+/// A complete extended advertising report
+///
+/// This contains the advertising data of every fragment of the report. The other fields are
+/// from the last fragment of the report.
+#[derive(Clone)]
+pub struct ExtendedReport {
+    pub event_type: events::LEExtAdvEventType,
+    /// The address type of the advertiser, this is `None` for anonymous advertising
+    pub address_type: Option<LEAddressType>,
+    pub address: crate::BluetoothDeviceAddress,
+    pub primary_phy: events::LEPhy,
+    pub secondary_phy: Option<events::LEPhy>,
+    pub advertising_sid: Option<u8>,
+    pub tx_power: Option<i8>,
+    pub rssi: Option<i8>,
+    pub periodic_advertising_interval: Option<events::LEAdvertiseInterval>,
+    pub direct_address_type: events::LEDirectAddressType,
+    pub direct_address: crate::BluetoothDeviceAddress,
+    /// The reassembled advertising data (or scan response data)
+    pub data: Vec<u8>,
+    /// The controller could not receive all of the data, `data` is only the part of the data
+    /// received before the data was truncated
+    pub truncated: bool,
+}
+
+/// The identification of the advertiser and type of data of a fragment
+///
+/// The fragments of the data of an advertising event are reported together, but the fragments
+/// from different advertisers can be interleaved.
+#[derive(PartialEq)]
+struct FragmentKey {
+    address_type: Option<u8>,
+    address: crate::BluetoothDeviceAddress,
+    advertising_sid: Option<u8>,
+    scan_response: bool,
+}
+
+/// The number of events buffered by the event stream of an [`ExtendedScanner`]
+const REPORT_EVENTS_CAPACITY: usize = 16;
+
+/// The maximum number of incomplete reports kept by an [`ExtendedScanner`]
+///
+/// The controller may never send the rest of a report (such as when scanning is disabled or the
+/// advertiser goes out of range), so the fragments of the least recently continued report are
+/// dropped when another report is started once this limit is reached.
+const PENDING_REPORTS_CAPACITY: usize = 16;
+
+/// A scanner that reassembles extended advertising reports
+///
+/// The *LE Extended Advertising Report* events are received with an
+/// [event stream](crate::hci::EventStream), so only the reports received after the
+/// `ExtendedScanner` is created are returned by it. The fragments of a report are kept until the
+/// last fragment is received, the data of the fragments are then combined into an
+/// [`ExtendedReport`]. When the controller truncates the data, the report is created with the
+/// fragments received before the truncation. Only a limited number of incomplete reports are kept,
+/// the fragments of the incomplete report that was least recently continued are dropped to start
+/// a new report once the limit is reached.
+///
+/// The commands for scanning can be sent through the scanner. The LE event mask must have the
+/// *LE Extended Advertising Report* event enabled for the controller to send the reports.
+pub struct ExtendedScanner<'a, I> {
+    hi: &'a HostInterface<I>,
+    report_events: EventStream,
+    fragments: Vec<(FragmentKey, Vec<u8>)>,
+    reports: VecDeque<ExtendedReport>,
+}
+
+impl<'a, I> ExtendedScanner<'a, I>
+where I: HostControllerInterface + ReceivedPacketSource + 'static
+{
+    /// Create a new `ExtendedScanner`
+    pub fn new(hi: &'a HostInterface<I>) -> Self {
+        let report_events = hi.subscribe(
+            events::Events::LEMeta(events::LEMeta::ExtendedAdvertisingReport),
+            REPORT_EVENTS_CAPACITY,
+        );
+
+        ExtendedScanner {
+            hi,
+            report_events,
+            fragments: Vec::new(),
+            reports: VecDeque::new(),
+        }
+    }
+
+    /// Set the scanning parameters
+    pub fn set_parameters(&self, parameters: set_extended_scan_parameters::ExtendedScanningParameters)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        set_extended_scan_parameters::send(self.hi, parameters)
+    }
+
+    /// Enable scanning
+    pub fn enable(&self, parameters: set_extended_scan_enable::ScanEnableParameters)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        set_extended_scan_enable::send(self.hi, true, parameters)
+    }
+
+    /// Disable scanning
+    ///
+    /// Fragments of reports that were not completed before scanning is disabled are never
+    /// completed by the controller. They are kept until they are dropped to start new reports or
+    /// they are discarded with [`clear_fragments`](ExtendedScanner::clear_fragments).
+    pub fn disable(&self) -> impl Future<Output=Result<(), impl CommandError>> + 'a {
+        let parameters = set_extended_scan_enable::ScanEnableParameters::new(Default::default());
+
+        set_extended_scan_enable::send(self.hi, false, parameters)
+    }
+
+    /// Take the next complete report without waiting
+    ///
+    /// `None` is returned when no report has been completed.
+    pub fn try_next_report(&mut self) -> Option<ExtendedReport> {
+        while let Some(entry) = self.report_events.try_next_event() {
+            self.process_entry(entry);
+        }
+
+        self.reports.pop_front()
+    }
+
+    /// Get a future for the next complete report
+    pub fn next_report(&mut self) -> ExtendedScannerNext<'_, 'a, I> {
+        ExtendedScannerNext { scanner: self }
+    }
+
+    /// Discard the fragments of reports that have not been completed
+    pub fn clear_fragments(&mut self) {
+        self.fragments.clear()
+    }
+
+    fn process_entry(&mut self, entry: Result<events::EventsData, crate::hci::Overflow>) {
+        use events::{EventsData, LEMetaData};
+
+        match entry {
+            Ok(EventsData::LEMeta(LEMetaData::ExtendedAdvertisingReport(reports))) => {
+                for report in reports.into_vec() {
+                    match report {
+                        Ok(report) => self.process_report(report),
+                        Err(e) => log::warn!("Invalid extended advertising report: {}", e),
+                    }
+                }
+            },
+            Ok(_) => (),
+            Err(overflow) => {
+                // A dropped report could be a fragment of any of the incomplete reports
+                log::warn!("Extended scanner: {}", overflow);
+
+                self.fragments.clear();
+            },
+        }
+    }
+
+    fn process_report(&mut self, report: events::LEExtendedAdvertisingReportData) {
+        let key = FragmentKey {
+            address_type: report.address_type.as_ref().map(|address_type| address_type.into_raw()),
+            address: report.address,
+            advertising_sid: report.advertising_sid,
+            scan_response: report.event_type.is_scan_response(),
+        };
+
+        let position = self.fragments.iter().position(|(fragment_key, _)| *fragment_key == key);
+
+        let mut data = match position {
+            Some(index) => self.fragments.remove(index).1,
+            None => Vec::new(),
+        };
+
+        data.extend_from_slice(report.data.get_raw_data());
+
+        let truncated = match report.event_type.data_status() {
+            Ok(events::LEDataStatus::Incomplete) => {
+                if self.fragments.len() >= PENDING_REPORTS_CAPACITY {
+                    log::debug!("Extended scanner dropped the fragments of an incomplete report");
+
+                    self.fragments.remove(0);
+                }
+
+                self.fragments.push((key, data));
+
+                return
+            },
+            Ok(events::LEDataStatus::IncompleteTruncated) => true,
+            Ok(events::LEDataStatus::Complete) => false,
+            Err(e) => {
+                log::warn!("{}", e);
+
+                false
+            },
+        };
+
+        self.reports.push_back(ExtendedReport {
+            event_type: report.event_type,
+            address_type: report.address_type,
+            address: report.address,
+            primary_phy: report.primary_phy,
+            secondary_phy: report.secondary_phy,
+            advertising_sid: report.advertising_sid,
+            tx_power: report.tx_power,
+            rssi: report.rssi,
+            periodic_advertising_interval: report.periodic_advertising_interval,
+            direct_address_type: report.direct_address_type,
+            direct_address: report.direct_address,
+            data,
+            truncated,
+        });
+    }
+}
+
+/// The future returned by [`ExtendedScanner::next_report`]
+pub struct ExtendedScannerNext<'s, 'a, I> {
+    scanner: &'s mut ExtendedScanner<'a, I>,
+}
+
+impl<I> Future for ExtendedScannerNext<'_, '_, I>
+where I: HostControllerInterface + ReceivedPacketSource + 'static
+{
+    type Output = ExtendedReport;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let scanner = &mut *self.get_mut().scanner;
+
+        loop {
+            if let Some(report) = scanner.try_next_report() {
+                return Poll::Ready(report)
+            }
+
+            match Pin::new(&mut scanner.report_events).poll_next(cx) {
+                Poll::Ready(Some(entry)) => scanner.process_entry(entry),
+                _ => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::extended_create_connection::{ExtendedConnectionParameters, InitiatingPhyParameters};
+    use super::set_extended_scan_enable::{DuplicateFilter, ScanEnableParameters};
+    use super::set_extended_scan_parameters::{
+        ExtendedScanningParameters, ScanningInterval, ScanningPhyParameters, ScanningWindow
+    };
+    use crate::hci::testing::VirtualController;
+    use crate::hci::{events, HostInterface};
+    use futures::executor::block_on;
+
+    #[test]
+    fn extended_scanning_test() {
+        use crate::gap::advertise::local_name::LocalName;
+        use crate::hci::common::{ConnectionLatency, LEAddressType, SupervisionTimeout};
+        use crate::hci::le::common::{ConnectionEventLength, OwnAddressType};
+        use crate::hci::le::connection::{
+            create_connection::InitiatorFilterPolicy, ConnectionInterval, ConnectionIntervalBounds
+        };
+        use crate::hci::le::extended_advertising::{
+            set_extended_advertising_parameters::{AdvertisingParameters, EventProperty},
+            AdvertisingData,
+            AdvertisingHandle,
+            AdvertisingSet,
+            FragmentPreference,
+            LEPhy,
+        };
+        use crate::hci::le::receiver::set_scan_parameters::LEScanType;
+        use crate::hci::le::phy_2m_or_coded::read_phy;
+
+        let slave_address = [2, 2, 2, 2, 2, 2];
+
+        let master_controller = VirtualController::new([1, 1, 1, 1, 1, 1]);
+        let slave_controller = VirtualController::new(slave_address);
+
+        master_controller.link(&slave_controller);
+
+        let master = HostInterface::from(master_controller.clone());
+        let slave = HostInterface::from(slave_controller.clone());
+
+        let set = AdvertisingSet::new(&slave, AdvertisingHandle::try_new(0).unwrap());
+
+        let set_parameters = AdvertisingParameters {
+            event_properties: &[EventProperty::Connectable],
+            primary_advertising_phy: LEPhy::Coded,
+            secondary_advertising_phy: LEPhy::Coded,
+            advertising_sid: 5,
+            .. AdvertisingParameters::default()
+        };
+
+        block_on(set.set_parameters(set_parameters)).unwrap();
+
+        // The data is reported in two fragments
+        let name = "d".repeat(240);
+
+        let mut data = AdvertisingData::new();
+
+        data.try_push(LocalName::new(name.as_str(), false)).unwrap();
+
+        block_on(set.set_advertising_data(&data, FragmentPreference::default())).unwrap();
+        block_on(set.enable(None, 0)).unwrap();
+
+        let mut scanner = ExtendedScanner::new(&master);
+
+        let scanning_parameters = ExtendedScanningParameters {
+            le_1m: Some(ScanningPhyParameters::default()),
+            coded: Some(ScanningPhyParameters {
+                scan_type: LEScanType::ActiveScanning,
+                scan_interval: ScanningInterval::try_from_raw(0x40).unwrap(),
+                scan_window: ScanningWindow::try_from_raw(0x20).unwrap(),
+            }),
+            .. ExtendedScanningParameters::default()
+        };
+
+        block_on(scanner.set_parameters(scanning_parameters)).unwrap();
+
+        // The parameters of each scanning PHY are grouped together
+        assert_eq!(
+            Some(alloc::vec![0, 0, 0x5, 0, 0x10, 0, 0x10, 0, 1, 0x40, 0, 0x20, 0]),
+            master_controller.get_extended_scan_parameters()
+        );
+
+        block_on(scanner.enable(ScanEnableParameters::new(DuplicateFilter::Enabled))).unwrap();
+
+        let report = block_on(scanner.next_report());
+
+        assert_eq!(slave_address, report.address);
+        assert_eq!(LEPhy::Coded, report.primary_phy);
+        assert_eq!(Some(LEPhy::Coded), report.secondary_phy);
+        assert_eq!(Some(5), report.advertising_sid);
+        assert!(report.event_type.is_advertising_connectable());
+        assert!(!report.truncated);
+        assert_eq!(data.len(), report.data.len());
+        assert_eq!(&[241, 0x09], &report.data[..2]);
+
+        // Each set is only reported once while scanning is enabled
+        assert!(scanner.try_next_report().is_none());
+
+        block_on(scanner.disable()).unwrap();
+
+        let initiating_parameters = |interval_min, interval_max, timeout| InitiatingPhyParameters {
+            scan_interval: extended_create_connection::ScanningInterval::default(),
+            scan_window: extended_create_connection::ScanningWindow::default(),
+            connection_interval: ConnectionIntervalBounds::try_from(
+                ConnectionInterval::try_from_raw(interval_min).unwrap(),
+                ConnectionInterval::try_from_raw(interval_max).unwrap(),
+            ).unwrap(),
+            connection_latency: ConnectionLatency::try_from(0).unwrap(),
+            supervision_timeout: SupervisionTimeout::try_from_raw(timeout).unwrap(),
+            connection_event_len: ConnectionEventLength::default(),
+        };
+
+        let connection_parameters = ExtendedConnectionParameters {
+            initiator_filter_policy: InitiatorFilterPolicy::DoNotUseWhiteList,
+            own_address_type: OwnAddressType::default(),
+            peer_address_type: LEAddressType::PublicDeviceAddress,
+            peer_address: slave_address,
+            le_1m: Some(initiating_parameters(0x10, 0x20, 0x100)),
+            le_2m: None,
+            coded: Some(initiating_parameters(0x30, 0x40, 0x200)),
+        };
+
+        block_on(extended_create_connection::send(&master, connection_parameters)).unwrap();
+
+        let wait_for_connection = |hi: &HostInterface<VirtualController>| {
+            match block_on(hi.wait_for_event(events::LEMeta::EnhancedConnectionComplete.into(), None)) {
+                Ok(events::EventsData::LEMeta(events::LEMetaData::EnhancedConnectionComplete(data))) => data,
+                _ => panic!("Expected LE enhanced connection complete event"),
+            }
+        };
+
+        let master_data = wait_for_connection(&master);
+        let slave_data = wait_for_connection(&slave);
+
+        assert_eq!(master_data.connection_handle, slave_data.connection_handle);
+        assert!(matches!(master_data.role, events::LERole::Master));
+        assert!(matches!(slave_data.role, events::LERole::Slave));
+        assert_eq!(slave_address, master_data.peer_address);
+        assert!(!master_controller.is_initiating());
+
+        // The virtual controller uses the parameters of the first initiating PHY
+        assert_eq!(0x20, master_data.connection_interval.get_raw_val());
+        assert_eq!(0x100, master_data.supervision_timeout.get_timeout());
+
+        let phys = block_on(read_phy::send(&master, master_data.connection_handle)).unwrap();
+
+        assert_eq!(LEPhy::Coded, phys.tx_phy);
+        assert_eq!(LEPhy::Coded, phys.rx_phy);
+
+        let terminated = set.get_terminated().unwrap();
+
+        assert_eq!(0, terminated.advertising_handle);
+        assert_eq!(master_data.connection_handle, terminated.connection_handle);
+        assert!(slave_controller.get_advertising_sets().iter().all(|set| !set.enabled));
+    }
+
+    #[test]
+    fn pending_reports_test() {
+        let controller = VirtualController::new([1, 1, 1, 1, 1, 1]);
+
+        let hi = HostInterface::from(controller.clone());
+
+        let mut scanner = ExtendedScanner::new(&hi);
+
+        let inject_report = |scanner: &mut ExtendedScanner<_>, address: u8, incomplete: bool, data: u8| {
+            let mut parameter = alloc::vec![1];
+
+            parameter.extend_from_slice(&[if incomplete { 1 << 5 } else { 0 }, 0]);
+            parameter.push(0);
+            parameter.extend_from_slice(&[address; 6]);
+            parameter.extend_from_slice(&[0x1, 0x0, 0xFF, 0x7F, 0x7F, 0, 0]);
+            parameter.extend_from_slice(&[0; 7]);
+            parameter.extend_from_slice(&[1, data]);
+
+            controller.inject_le_meta_event(events::LEMeta::ExtendedAdvertisingReport, &parameter).unwrap();
+
+            scanner.try_next_report()
+        };
+
+        for address in 0..=PENDING_REPORTS_CAPACITY as u8 {
+            assert!(inject_report(&mut scanner, address, true, address).is_none());
+        }
+
+        // The fragment of the first report was dropped to start the last report
+        assert_eq!(PENDING_REPORTS_CAPACITY, scanner.fragments.len());
+
+        let report = inject_report(&mut scanner, 0, false, 0xFF).unwrap();
+
+        assert_eq!(alloc::vec![0xFF], report.data);
+
+        let report = inject_report(&mut scanner, 1, false, 0xFF).unwrap();
+
+        assert_eq!(alloc::vec![1, 0xFF], report.data);
+    }
+}
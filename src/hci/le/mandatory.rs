@@ -34,7 +34,7 @@ macro_rules! add_remove_white_list_setup {
         pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>,
             at: AddressType,
             addr: crate::BluetoothDeviceAddress )
-        -> impl core::future::Future<Output=Result<(), impl Display + Debug>> + 'a
+        -> impl core::future::Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
         {
             let parameter = CommandPrameter {
@@ -76,7 +76,7 @@ pub mod clear_white_list {
 
     impl_status_return!(COMMAND);
 
-    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> ) -> impl Future<Output=Result<(), impl Display + Debug>> + 'a where T: HostControllerInterface
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> ) -> impl Future<Output=Result<(), impl CommandError>> + 'a where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Prameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
     }
@@ -154,9 +154,66 @@ pub mod read_buffer_size {
 
     impl_command_data_future!(BufferSize, error::Error);
 
-    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> ) -> impl Future<Output=Result<BufferSize,impl Display + Debug>> + 'a where T: HostControllerInterface
+    /// The error of reading the buffer size
+    #[derive(Debug)]
+    enum ReadError<L, S> {
+        /// The error of the *LE Read Buffer Size* command
+        LeBufferSize(L),
+        /// The error of the *Read Buffer Size* command
+        SharedBufferSize(S),
+    }
+
+    impl<L: Display, S: Display> Display for ReadError<L, S> {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            match self {
+                ReadError::LeBufferSize(e) => Display::fmt(e, f),
+                ReadError::SharedBufferSize(e) => write!(f, "Failed to read the shared buffer size, {}", e),
+            }
+        }
+    }
+
+    impl<L: CommandError, S: CommandError> CommandError for ReadError<L, S> {
+        fn kind(&self) -> CommandErrorKind {
+            match self {
+                ReadError::LeBufferSize(e) => e.kind(),
+                ReadError::SharedBufferSize(e) => e.kind(),
+            }
+        }
+    }
+
+    /// Read the LE buffer size of the controller
+    ///
+    /// The buffer size is also used by `hci` for the flow control of ACL data, so this command
+    /// should be sent when the host starts. Until it is sent, ACL data is sent to the controller
+    /// without regard for the number of buffers in the controller.
+    ///
+    /// When the controller shares its data buffers between LE and BR/EDR (the returned
+    /// `BufferSize` is `None`), the *Read Buffer Size* command is also sent and the flow control
+    /// of ACL data uses the ACL data buffers returned by it.
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> ) -> impl Future<Output=Result<BufferSize,impl CommandError>> + 'a where T: HostControllerInterface
     {
-        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+        use crate::hci::info_params::read_buffer_size;
+
+        async move {
+            let buffer_size = match ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) ).await {
+                Ok(buffer_size) => buffer_size,
+                Err(e) => return Err(ReadError::LeBufferSize(e)),
+            };
+
+            match buffer_size {
+                BufferSize { packet_len: Some(len), packet_cnt: Some(cnt) } =>
+                    hci.acl_flow.control.lock().enable(len, cnt.into()),
+                _ => match read_buffer_size::send(hci).await {
+                    Ok(shared) => hci.acl_flow.control.lock().enable(
+                        shared.acl_data_packet_length,
+                        shared.total_num_acl_data_packets
+                    ),
+                    Err(e) => return Err(ReadError::SharedBufferSize(e)),
+                },
+            }
+
+            Ok(buffer_size)
+        }
     }
 
 }
@@ -206,7 +263,7 @@ pub mod read_local_supported_features {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-                                 -> impl Future<Output=Result<EnabledLEFeaturesItr, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<EnabledLEFeaturesItr, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -407,7 +464,7 @@ pub mod read_supported_states {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-                                 -> impl Future<Output=Result<alloc::vec::Vec<StatesAndRoles>, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<alloc::vec::Vec<StatesAndRoles>, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -462,7 +519,7 @@ pub mod read_white_list_size {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-                                 -> impl Future<Output=Result<usize, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<usize, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -552,7 +609,7 @@ pub mod set_event_mask {
     /// send(&host_interface, events);
     /// ```
     pub fn send<'a, T: 'static>( hi: &'a HostInterface<T>, enabled_events: &[LEMeta] )
-    -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
     where T: HostControllerInterface
     {
 
@@ -614,7 +671,7 @@ pub mod test_end {
     /// This will return a future with its type 'Output' being the number of packets
     /// received during what ever test was done
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-                                 -> impl Future<Output=Result<usize, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<usize, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -11,6 +11,12 @@ pub mod receiver;
 pub mod connection;
 pub mod encryption;
 pub mod con_pram_req;
+pub mod data_packet_length_extension;
+pub mod phy_2m_or_coded;
+pub mod privacy;
+pub mod extended_advertising;
+pub mod extended_scanning;
+pub mod periodic_advertising;
 
 // LE implementation that is currently TODO
 // pub mod br_edr {
@@ -24,12 +30,6 @@ pub mod con_pram_req;
 //     }
 // }
 //
-// pub mod scannable {
-//     pub mod command {
-//         pub fn set_scan_response_data() { unimplemented!() }
-//     }
-// }
-//
 // pub mod connection_parameters_request_procedure {
 //     pub mod event {
 //         pub fn remote_connection_paramter_request() { unimplemented!() }
@@ -51,108 +51,6 @@ pub mod con_pram_req;
 //     }
 // }
 //
-// pub mod data_packet_length_extension {
-//     pub mod event {
-//         pub fn data_length_change() { unimplemented!() }
-//     }
-//     pub mod command {
-//         pub fn set_data_length() { unimplemented!() }
-//         pub fn read_suggested_default_data_length() { unimplemented!() }
-//         pub fn write_suggested_default_data_length() { unimplemented!() }
-//     }
-// }
-//
-// pub mod privacy {
-//     pub mod event {
-//         pub fn directed_advertising_report() { unimplemented!() }
-//     }
-//     pub mod command {
-//         pub fn set_resolvable_private_address_timeout() { unimplemented!() }
-//         pub fn set_address_resolution_enable() { unimplemented!() }
-//         pub fn add_device_to_resolving_list() { unimplemented!() }
-//         pub fn clear_resolving_list() { unimplemented!() }
-//         pub fn set_privacy_mode() { unimplemented!() }
-//         pub fn read_peer_resolvable_address() { unimplemented!() }
-//         pub fn read_local_resolvable_address() { unimplemented!() }
-//     }
-// }
-//
-// pub mod phy_2m_or_coded {
-//     pub mod event {
-//         pub fn phy_update_complete() { unimplemented!() }
-//     }
-//     pub mod command {
-//         pub fn read_phy() { unimplemented!() }
-//         pub fn set_default_phy() { unimplemented!() }
-//         pub fn set_phy() { unimplemented!() }
-//         pub fn enhanced_transmitter_test() { unimplemented!() }
-//         pub fn enhanced_receiver_test() { unimplemented!() }
-//     }
-// }
-//
-// pub mod extended_advertising {
-//     pub mod event {
-//         pub fn scan_request_received() { unimplemented!() }
-//         pub fn advertising_set_terminated() { unimplemented!() }
-//         pub fn scan_timeout() { unimplemented!() }
-//         pub fn extended_advertising_report() { unimplemented!() }
-//     }
-//     pub mod legacy_event {
-//         /// Superseded by extended_advertising_report
-//         pub fn advertising_report() { unimplemented!() }
-//         /// Superseded by exted_advertising_report
-//         pub fn direted_advertising_report() { unimplemented!() }
-//     }
-//     pub mod command {
-//         pub fn set_advertising_set_random_address() { unimplemented!() }
-//         pub fn set_extended_advertising_parameters() { unimplemented!() }
-//         pub fn set_extended_advertising_data() { unimplemented!() }
-//         pub fn set_extended_scan_response_data() { unimplemented!() }
-//         pub fn set_extended_advertising_enable() { unimplemented!() }
-//         pub fn read_maximum_advertising_data_length() { unimplemented!() }
-//         pub fn read_number_of_supported_advertising_sets() { unimplemented!() }
-//         pub fn remove_advertising_set() { unimplemented!() }
-//         pub fn clear_advertisisng_sets() { unimplemented!() }
-//         pub fn set_extended_scan_parameters() { unimplemented!() }
-//         pub fn set_extended_scan_enable() { unimplemented!() }
-//         pub fn extended_create_connection() { unimplemented!() }
-//     }
-//     pub mod legacy_command {
-//         /// Superseded by set_extended_advertising_parameters
-//         pub fn set_advertising_parameters() { unimplemented!() }
-//         /// No longer used
-//         pub fn read_advertising_channel_tx_power() { unimplemented!() }
-//         /// Superseded by set_extended_advertising_data
-//         pub fn set_advertising_data() { unimplemented!() }
-//         /// Superseded by set_extended_advertising_enable
-//         pub fn set_scan_parameters() { unimplemented!() }
-//         /// Superseded by set_extended_scan_enable
-//         pub fn set_scan_enable() { unimplemented!() }
-//         /// Superseded by extended_create_connection
-//         pub fn extended_create_connection() { unimplemented!() }
-//     }
-// }
-//
-// pub mod periodic_advertising {
-//     pub mod event {
-//         pub fn periodic_advertising_report() { unimplemented!() }
-//         pub fn periodic_advertising_sync_established() { unimplemented!() }
-//         pub fn periodic_advertising_sync_lost() { unimplemented!() }
-//     }
-//     pub mod command {
-//         pub fn set_periodic_advertising_parameters() { unimplemented!() }
-//         pub fn set_periodic_advertising_data() { unimplemented!() }
-//         pub fn set_periodic_advertising_enable() { unimplemented!() }
-//         pub fn periodic_advertising_create_sync() { unimplemented!() }
-//         pub fn periodic_advertising_create_sync_cancel() { unimplemented!() }
-//         pub fn periodic_advertising_terminate_sync() { unimplemented!() }
-//         pub fn add_device_to_periodic_advertising_list() { unimplemented!() }
-//         pub fn remove_device_from_periodic_advertiser_list() { unimplemented!() }
-//         pub fn clear_periodic_advertiser_list() { unimplemented!() }
-//         pub fn read_periodic_advertiser_list_size() { unimplemented!() }
-//     }
-// }
-//
 // pub mod advertising_of_tx_power {
 //     pub mod command {
 //         pub fn read_rf_path_compensation() { unimplemented!() }
@@ -0,0 +1,1040 @@
+//! LE Periodic Advertising
+//!
+//! Periodic advertising is connectionless advertising at a fixed interval, it is sent by an
+//! [extended advertising](crate::hci::le::extended_advertising) set that uses neither legacy
+//! advertising PDUs nor is connectable, scannable, or anonymous. The extended advertising of the
+//! set contains the information a scanner needs to synchronize to the periodic advertising train,
+//! so both the set and the periodic advertising of the set must be enabled for a scanner to
+//! synchronize.
+//!
+//! A [`PeriodicAdvertiser`] sends the periodic advertising commands for an advertising set. On the
+//! scanning side, a [`PeriodicSync`] is the synchronization to a periodic advertising train, it
+//! returns the periodic advertising data as reports until the synchronization is lost.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use crate::hci::{
+    common::ConnectionHandle,
+    error,
+    events,
+    CommandError,
+    CommandErrorKind,
+    EventStream,
+    HostControllerInterface,
+    HostInterface,
+    ReceivedPacketSource,
+};
+
+pub use super::extended_advertising::{AdvertisingData, AdvertisingHandle};
+
+/// The maximum number of bytes of periodic advertising data in one command
+const MAX_FRAGMENT_LEN: usize = 252;
+
+/// Set the parameters of the periodic advertising of an advertising set
+///
+/// The advertising set must already exist and it cannot be set while periodic advertising is
+/// enabled for the set.
+pub mod set_periodic_advertising_parameters {
+
+    use crate::hci::*;
+    use super::AdvertisingHandle;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetPeriodicAdvertisingParameters);
+
+    interval!( PeriodicAdvertisingInterval, 0x0006, 0xFFFF, ApiDef, 0x0050, 1250);
+
+    impl_status_return!(COMMAND);
+
+    /// The periodic advertising parameters
+    ///
+    /// The controller chooses the interval of the periodic advertising from the range of the
+    /// minimum to the maximum interval.
+    #[derive(Default)]
+    #[cfg_attr(test,derive(Debug))]
+    pub struct PeriodicAdvertisingParameters {
+        pub minimum_interval: PeriodicAdvertisingInterval,
+        pub maximum_interval: PeriodicAdvertisingInterval,
+        /// Include the transmit power within the periodic advertising PDUs
+        pub include_tx_power: bool,
+    }
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _handle: u8,
+        _minimum_interval: u16,
+        _maximum_interval: u16,
+        _properties: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>(
+        hci: &'a HostInterface<T>,
+        handle: AdvertisingHandle,
+        parameters: PeriodicAdvertisingParameters
+    ) -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _handle: handle.get_raw_handle(),
+            _minimum_interval: parameters.minimum_interval.get_raw_val().to_le(),
+            _maximum_interval: parameters.maximum_interval.get_raw_val().to_le(),
+            _properties: (if parameters.include_tx_power { 1u16 << 6 } else { 0 }).to_le(),
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Set the periodic advertising data of an advertising set
+///
+/// The parameters of the periodic advertising must be set before the data is set.
+pub mod set_periodic_advertising_data {
+
+    use crate::hci::*;
+    use super::{AdvertisingData, AdvertisingHandle, MAX_FRAGMENT_LEN};
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetPeriodicAdvertisingData);
+
+    impl_status_return!(COMMAND);
+
+    /// The operation of a command with respect to the fragments of the data
+    #[derive(Clone, Copy)]
+    enum Operation {
+        Intermediate,
+        First,
+        Last,
+        Complete,
+    }
+
+    impl Operation {
+        fn into_val(self) -> u8 {
+            match self {
+                Operation::Intermediate => 0x00,
+                Operation::First => 0x01,
+                Operation::Last => 0x02,
+                Operation::Complete => 0x03,
+            }
+        }
+    }
+
+    /// A fragment of the data, the parameter is of variable length
+    struct Fragment {
+        handle: AdvertisingHandle,
+        operation: Operation,
+        data: Vec<u8>,
+    }
+
+    impl CommandParameter for Fragment {
+        type Parameter = ();
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {}
+
+        fn as_command_packet<'a>(&self) -> alloc::boxed::Box<[u8]> {
+            let parameter_len = 3 + self.data.len();
+
+            let mut packet = Vec::with_capacity(parameter_len + 3);
+
+            packet.extend_from_slice(&COMMAND.as_opcode_pair().as_opcode().to_le_bytes());
+
+            packet.push(parameter_len as u8);
+
+            packet.push(self.handle.get_raw_handle());
+
+            packet.push(self.operation.into_val());
+
+            packet.push(self.data.len() as u8);
+
+            packet.extend_from_slice(&self.data);
+
+            packet.into_boxed_slice()
+        }
+    }
+
+    /// Send the command
+    ///
+    /// Data longer than the maximum of a single command (252 bytes) is sent in multiple commands,
+    /// the returned future completes once the controller has accepted every fragment. While
+    /// periodic advertising is enabled, the data can only be replaced with data that fits within
+    /// a single command.
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: AdvertisingHandle, data: &AdvertisingData )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let data = data.as_slice();
+
+        let last = data.len().saturating_sub(1) / MAX_FRAGMENT_LEN;
+
+        let fragments: Vec<Fragment> = (0..=last).map(|index| {
+            let start = index * MAX_FRAGMENT_LEN;
+            let end = data.len().min(start + MAX_FRAGMENT_LEN);
+
+            let operation = match index {
+                _ if last == 0 => Operation::Complete,
+                0 => Operation::First,
+                _ if index == last => Operation::Last,
+                _ => Operation::Intermediate,
+            };
+
+            Fragment {
+                handle,
+                operation,
+                data: data[start..end].to_vec(),
+            }
+        })
+        .collect();
+
+        async move {
+            for fragment in fragments {
+                let result = ReturnedFuture(
+                    hci.send_command(fragment, events::Events::CommandComplete, Duration::from_secs(1))
+                ).await;
+
+                if let Err(e) = result {
+                    return Err(e)
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Enable or disable the periodic advertising of an advertising set
+///
+/// Periodic advertising can be enabled before the advertising set is enabled, but it is not sent
+/// until the set is enabled.
+pub mod set_periodic_advertising_enable {
+
+    use crate::hci::*;
+    use super::AdvertisingHandle;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetPeriodicAdvertisingEnable);
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _enable: u8,
+        _handle: u8,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool, handle: AdvertisingHandle )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _enable: enable as u8,
+            _handle: handle.get_raw_handle(),
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Synchronize to a periodic advertising train
+///
+/// Only one synchronization can be pending at a time. When the controller has synchronized with
+/// the periodic advertising train (or the command is canceled with
+/// [`periodic_advertising_create_sync_cancel`](super::periodic_advertising_create_sync_cancel)),
+/// it sends the *LE Periodic Advertising Sync Established* event.
+pub mod periodic_advertising_create_sync {
+
+    use crate::hci::*;
+    use crate::hci::le::transmitter::set_advertising_parameters::PeerAddressType;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::PeriodicAdvertisingCreateSync);
+
+    interval!( SyncTimeout, 0x000A, 0x4000, ApiDef, 0x00C8, 10000);
+
+    impl_command_status_future!();
+
+    /// The periodic advertising to synchronize with
+    #[cfg_attr(test,derive(Debug))]
+    pub enum AdvertiserFilter {
+        /// Synchronize with the periodic advertising of the advertising set with the advertising
+        /// SID sent by the advertiser
+        Advertiser {
+            advertising_sid: u8,
+            address_type: PeerAddressType,
+            address: crate::BluetoothDeviceAddress,
+        },
+        /// Synchronize with the periodic advertising of any advertiser within the periodic
+        /// advertiser list
+        PeriodicAdvertiserList,
+    }
+
+    /// The parameters for synchronizing
+    ///
+    /// By default, no periodic advertising packets are skipped.
+    #[cfg_attr(test,derive(Debug))]
+    pub struct CreateSyncParameters {
+        filter: AdvertiserFilter,
+        skip: u16,
+        sync_timeout: SyncTimeout,
+    }
+
+    impl CreateSyncParameters {
+        const MAX_SKIP: u16 = 0x01F3;
+
+        /// Create the parameters for synchronizing with the advertising matched by `filter`
+        ///
+        /// The synchronization is lost when no periodic advertising packet is received within
+        /// the `sync_timeout`.
+        pub fn new(filter: AdvertiserFilter, sync_timeout: SyncTimeout) -> Self {
+            CreateSyncParameters { filter, skip: 0, sync_timeout }
+        }
+
+        /// Set the maximum number of periodic advertising events that can be skipped after a
+        /// successful receive
+        ///
+        /// # Error
+        /// `skip` is larger than 0x1F3
+        pub fn with_skip(self, skip: u16) -> Result<Self, &'static str> {
+            if skip <= Self::MAX_SKIP {
+                Ok(CreateSyncParameters { skip, .. self })
+            } else {
+                Err("Skip out of range: 0..=0x1F3")
+            }
+        }
+
+        /// Get the filter of the periodic advertising to synchronize with
+        pub fn get_filter(&self) -> &AdvertiserFilter {
+            &self.filter
+        }
+    }
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _options: u8,
+        _advertising_sid: u8,
+        _advertiser_address_type: u8,
+        _advertiser_address: crate::BluetoothDeviceAddress,
+        _skip: u16,
+        _sync_timeout: u16,
+        _unused: u8,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, parameters: CreateSyncParameters )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let (options, advertising_sid, address_type, address) = match parameters.filter {
+            AdvertiserFilter::Advertiser { advertising_sid, address_type, address } =>
+                (0, advertising_sid, address_type.into_val(), address),
+            AdvertiserFilter::PeriodicAdvertiserList => (1, 0, 0, [0;6]),
+        };
+
+        let parameter = Parameter {
+            _options: options,
+            _advertising_sid: advertising_sid,
+            _advertiser_address_type: address_type,
+            _advertiser_address: address,
+            _skip: parameters.skip.to_le(),
+            _sync_timeout: parameters.sync_timeout.get_raw_val().to_le(),
+            _unused: 0,
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandStatus, Duration::from_secs(1) ) )
+    }
+}
+
+/// Cancel the pending synchronization
+///
+/// The controller sends the *LE Periodic Advertising Sync Established* event with the status
+/// *Operation Cancelled by Host* after the command completes.
+pub mod periodic_advertising_create_sync_cancel {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::PeriodicAdvertisingCreateSyncCancel);
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Stop the synchronization with a periodic advertising train
+pub mod periodic_advertising_terminate_sync {
+
+    use crate::hci::*;
+    use crate::hci::common::ConnectionHandle;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::PeriodicAdvertisingTerminateSync);
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _sync_handle: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, sync_handle: ConnectionHandle )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter { _sync_handle: sync_handle.get_raw_handle().to_le() };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Setup for the commands that add or remove an entry of the periodic advertiser list
+macro_rules! periodic_advertiser_list_entry_setup {
+    ( $command: ident ) => {
+        use crate::hci::*;
+        use crate::hci::le::transmitter::set_advertising_parameters::PeerAddressType;
+
+        impl_status_return!( $command );
+
+        #[repr(packed)]
+        #[derive(Clone, Copy)]
+        struct Parameter {
+            _address_type: u8,
+            _address: crate::BluetoothDeviceAddress,
+            _advertising_sid: u8,
+        }
+
+        impl CommandParameter for Parameter {
+            type Parameter = Self;
+            const COMMAND: opcodes::HCICommand = $command;
+            fn get_parameter(&self) -> Self::Parameter { *self }
+        }
+
+        /// Send the command
+        ///
+        /// An entry of the list is the address of an advertiser and the advertising SID of the
+        /// advertising set of the advertiser.
+        pub fn send<'a, T: 'static>(
+            hci: &'a HostInterface<T>,
+            address_type: PeerAddressType,
+            address: crate::BluetoothDeviceAddress,
+            advertising_sid: u8,
+        ) -> impl Future<Output=Result<(), impl CommandError>> + 'a
+        where T: HostControllerInterface
+        {
+            let parameter = Parameter {
+                _address_type: address_type.into_val(),
+                _address: address,
+                _advertising_sid: advertising_sid,
+            };
+
+            ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+        }
+    };
+}
+
+/// Add an advertiser to the periodic advertiser list
+///
+/// The list cannot be changed while synchronizing with the periodic advertiser list.
+pub mod add_device_to_periodic_advertiser_list {
+
+    const COMMAND: crate::hci::opcodes::HCICommand = crate::hci::opcodes::HCICommand::LEController(crate::hci::opcodes::LEController::AddDeviceToPeriodicAdvertiserList);
+
+    periodic_advertiser_list_entry_setup!(COMMAND);
+}
+
+/// Remove an advertiser from the periodic advertiser list
+///
+/// The list cannot be changed while synchronizing with the periodic advertiser list.
+pub mod remove_device_from_periodic_advertiser_list {
+
+    const COMMAND: crate::hci::opcodes::HCICommand = crate::hci::opcodes::HCICommand::LEController(crate::hci::opcodes::LEController::RemoveDeviceFromPeriodicAdvertiserList);
+
+    periodic_advertiser_list_entry_setup!(COMMAND);
+}
+
+/// Remove every advertiser from the periodic advertiser list
+///
+/// The list cannot be changed while synchronizing with the periodic advertiser list.
+pub mod clear_periodic_advertiser_list {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ClearPeriodicAdvertiserList);
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Read the number of entries that can be stored in the periodic advertiser list
+pub mod read_periodic_advertiser_list_size {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadPeriodicAdvertiserListSize);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        periodic_advertiser_list_size: u8,
+    }
+
+    pub struct Return;
+
+    impl Return {
+        fn try_from(packed: CmdReturn) -> Result<usize, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(packed.periodic_advertiser_list_size as usize)
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        Return,
+        usize,
+        error::Error
+    );
+
+    impl_command_data_future!(Return, usize, error::Error);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<usize, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// The periodic advertising of an advertising set
+///
+/// A `PeriodicAdvertiser` is the advertising handle of a set bound to a host interface, the
+/// methods send the periodic advertising commands for the set. The advertising set must be
+/// created (by setting its parameters) before the periodic advertising parameters are set.
+pub struct PeriodicAdvertiser<'a, I> {
+    hi: &'a HostInterface<I>,
+    handle: AdvertisingHandle,
+}
+
+impl<'a, I> PeriodicAdvertiser<'a, I>
+where I: HostControllerInterface + 'static
+{
+    /// Create a new `PeriodicAdvertiser`
+    pub fn new(hi: &'a HostInterface<I>, handle: AdvertisingHandle) -> Self {
+        PeriodicAdvertiser { hi, handle }
+    }
+
+    /// Get the advertising handle
+    pub fn get_handle(&self) -> AdvertisingHandle {
+        self.handle
+    }
+
+    /// Set the periodic advertising parameters of the set
+    pub fn set_parameters(&self, parameters: set_periodic_advertising_parameters::PeriodicAdvertisingParameters)
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    {
+        set_periodic_advertising_parameters::send(self.hi, self.handle, parameters)
+    }
+
+    /// Set the periodic advertising data of the set
+    pub fn set_data(&self, data: &AdvertisingData) -> impl Future<Output=Result<(), impl CommandError>> + 'a {
+        set_periodic_advertising_data::send(self.hi, self.handle, data)
+    }
+
+    /// Enable the periodic advertising of the set
+    pub fn enable(&self) -> impl Future<Output=Result<(), impl CommandError>> + 'a {
+        set_periodic_advertising_enable::send(self.hi, true, self.handle)
+    }
+
+    /// Disable the periodic advertising of the set
+    pub fn disable(&self) -> impl Future<Output=Result<(), impl CommandError>> + 'a {
+        set_periodic_advertising_enable::send(self.hi, false, self.handle)
+    }
+}
+
+/// A complete periodic advertising report
+///
+/// This contains the periodic advertising data of every fragment of the report. The transmit
+/// power and RSSI are from the last fragment of the report.
+#[derive(Debug, Clone)]
+pub struct PeriodicReport {
+    pub tx_power: Option<i8>,
+    pub rssi: Option<i8>,
+    /// The reassembled periodic advertising data
+    pub data: Vec<u8>,
+    /// The controller could not receive all of the data, `data` is only the part of the data
+    /// received before the data was truncated
+    pub truncated: bool,
+}
+
+#[derive(Debug)]
+enum EstablishError<C> {
+    Command(C),
+    Status(error::Error),
+}
+
+impl<C: Display> Display for EstablishError<C> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            EstablishError::Command(e) => Display::fmt(e, f),
+            EstablishError::Status(e) => Display::fmt(e, f),
+        }
+    }
+}
+
+impl<C: CommandError> CommandError for EstablishError<C> {
+    fn kind(&self) -> CommandErrorKind {
+        match self {
+            EstablishError::Command(e) => e.kind(),
+            EstablishError::Status(e) => CommandErrorKind::Status(*e),
+        }
+    }
+}
+
+/// The value of the sync handle of a [`PeriodicSync`] before the sync is established
+const NO_SYNC_HANDLE: usize = usize::MAX;
+
+/// The number of events buffered by the report event stream of a [`PeriodicSync`]
+const REPORT_EVENTS_CAPACITY: usize = 16;
+
+/// A synchronization with a periodic advertising train
+///
+/// A `PeriodicSync` is the stream of the periodic advertising reports of the periodic advertising
+/// train. The fragments of a report are kept until the last fragment is received, the data of the
+/// fragments are then combined into a [`PeriodicReport`]. The stream ends when the *LE Periodic
+/// Advertising Sync Lost* event is received for the sync, this has the same interface as a
+/// `Stream` of the futures crate with [`poll_next_report`](PeriodicSync::poll_next_report).
+///
+/// The LE event mask must have the *LE Periodic Advertising Sync Established*, *LE Periodic
+/// Advertising Report*, and *LE Periodic Advertising Sync Lost* events enabled for the controller
+/// to send them.
+pub struct PeriodicSync<'a, I> {
+    hi: &'a HostInterface<I>,
+    established: events::LEPeriodicAdvertisingSyncEstablishedData,
+    report_events: EventStream,
+    lost_events: EventStream,
+    fragment: Option<Vec<u8>>,
+    /// Fragments are dropped until the end of the report when a fragment of the report is
+    /// dropped by the event stream
+    dropping: bool,
+    reports: VecDeque<PeriodicReport>,
+    lost: bool,
+}
+
+impl<'a, I> PeriodicSync<'a, I>
+where I: HostControllerInterface + ReceivedPacketSource + 'static
+{
+    /// Synchronize with a periodic advertising train
+    ///
+    /// The returned future sends the *LE Periodic Advertising Create Sync* command and completes
+    /// when the *LE Periodic Advertising Sync Established* event is received. There is no timeout
+    /// for synchronizing, the pending synchronization can be canceled with
+    /// [`periodic_advertising_create_sync_cancel`] and the output of the future is then an error.
+    pub fn establish(
+        hi: &'a HostInterface<I>,
+        parameters: periodic_advertising_create_sync::CreateSyncParameters
+    ) -> impl Future<Output=Result<Self, impl CommandError>> + 'a
+    {
+        use events::{Events, EventsData, LEMeta, LEMetaData};
+
+        let sync_handle = Arc::new(AtomicUsize::new(NO_SYNC_HANDLE));
+
+        // Only one synchronization can be pending, so every established event is for this
+        // synchronization. The handle is stored when the established event is matched as the
+        // reports for the sync can be received before the established event is processed.
+        let established_handle = sync_handle.clone();
+
+        let mut established_events = hi.subscribe_with_matcher(
+            Events::LEMeta(LEMeta::PeriodicAdvertisingSyncEstablished),
+            1,
+            move |ed: &EventsData| match ed {
+                EventsData::LEMeta(LEMetaData::PeriodicAdvertisingSyncEstablished(data)) => {
+                    if let error::Error::NoError = data.status {
+                        established_handle.store(data.sync_handle.get_raw_handle() as usize, Ordering::Relaxed);
+                    }
+
+                    true
+                },
+                _ => false,
+            }
+        );
+
+        let report_handle = sync_handle.clone();
+
+        let report_events = hi.subscribe_with_matcher(
+            Events::LEMeta(LEMeta::PeriodicAdvertisingReport),
+            REPORT_EVENTS_CAPACITY,
+            move |ed: &EventsData| matches!(ed,
+                EventsData::LEMeta(LEMetaData::PeriodicAdvertisingReport(data))
+                if data.sync_handle.get_raw_handle() as usize == report_handle.load(Ordering::Relaxed)
+            )
+        );
+
+        let lost_events = hi.subscribe_with_matcher(
+            Events::LEMeta(LEMeta::PeriodicAdvertisingSyncLost),
+            1,
+            move |ed: &EventsData| matches!(ed,
+                EventsData::LEMeta(LEMetaData::PeriodicAdvertisingSyncLost(data))
+                if data.sync_handle.get_raw_handle() as usize == sync_handle.load(Ordering::Relaxed)
+            )
+        );
+
+        async move {
+            if let Err(e) = periodic_advertising_create_sync::send(hi, parameters).await {
+                return Err(EstablishError::Command(e))
+            }
+
+            let established = loop {
+                match established_events.next_event().await {
+                    Ok(EventsData::LEMeta(LEMetaData::PeriodicAdvertisingSyncEstablished(data))) => break data,
+                    Ok(_) => (),
+                    Err(overflow) => log::warn!("Periodic sync: {}", overflow),
+                }
+            };
+
+            if let error::Error::NoError = established.status {
+                Ok(PeriodicSync {
+                    hi,
+                    established,
+                    report_events,
+                    lost_events,
+                    fragment: None,
+                    dropping: false,
+                    reports: VecDeque::new(),
+                    lost: false,
+                })
+            } else {
+                Err(EstablishError::Status(established.status))
+            }
+        }
+    }
+
+    /// Get the sync handle
+    pub fn get_sync_handle(&self) -> ConnectionHandle {
+        self.established.sync_handle
+    }
+
+    /// Get the data of the *LE Periodic Advertising Sync Established* event of the sync
+    pub fn get_established(&self) -> &events::LEPeriodicAdvertisingSyncEstablishedData {
+        &self.established
+    }
+
+    /// Poll for the next complete report
+    ///
+    /// `Ready(None)` is returned once the sync is lost and all reports received before the sync
+    /// was lost are returned.
+    pub fn poll_next_report(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<PeriodicReport>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(report) = this.reports.pop_front() {
+                return Poll::Ready(Some(report))
+            }
+
+            if this.lost {
+                return Poll::Ready(None)
+            }
+
+            if let Poll::Ready(Some(entry)) = Pin::new(&mut this.report_events).poll_next(cx) {
+                this.process_entry(entry);
+
+                continue
+            }
+
+            // Every report is received before the sync lost event, so the sync is only lost once
+            // the report events are processed.
+            match Pin::new(&mut this.lost_events).poll_next(cx) {
+                Poll::Ready(Some(_)) => {
+                    this.fragment = None;
+                    this.lost = true;
+                },
+                _ => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Get a future for the next complete report
+    ///
+    /// The output is `None` once the sync is lost.
+    pub fn next_report(&mut self) -> PeriodicSyncNext<'_, 'a, I> {
+        PeriodicSyncNext { sync: self }
+    }
+
+    /// Stop synchronizing with the periodic advertising train
+    pub fn terminate(self) -> impl Future<Output=Result<(), impl CommandError>> + 'a {
+        periodic_advertising_terminate_sync::send(self.hi, self.established.sync_handle)
+    }
+
+    fn process_entry(&mut self, entry: Result<events::EventsData, crate::hci::Overflow>) {
+        use events::{EventsData, LEMetaData};
+
+        match entry {
+            Ok(EventsData::LEMeta(LEMetaData::PeriodicAdvertisingReport(report))) => self.process_report(report),
+            Ok(_) => (),
+            Err(overflow) => {
+                log::warn!("Periodic sync {}: {}", self.established.sync_handle, overflow);
+
+                self.fragment = None;
+                self.dropping = true;
+            },
+        }
+    }
+
+    fn process_report(&mut self, report: events::LEPeriodicAdvertisingReportData) {
+        if self.dropping {
+            self.dropping = matches!(report.data_status, events::LEDataStatus::Incomplete);
+
+            return
+        }
+
+        let mut data = self.fragment.take().unwrap_or_default();
+
+        data.extend_from_slice(&report.data);
+
+        let truncated = match report.data_status {
+            events::LEDataStatus::Incomplete => {
+                self.fragment = Some(data);
+
+                return
+            },
+            events::LEDataStatus::IncompleteTruncated => true,
+            events::LEDataStatus::Complete => false,
+        };
+
+        self.reports.push_back(PeriodicReport {
+            tx_power: report.tx_power,
+            rssi: report.rssi,
+            data,
+            truncated,
+        });
+    }
+}
+
+/// The future returned by [`PeriodicSync::next_report`]
+pub struct PeriodicSyncNext<'s, 'a, I> {
+    sync: &'s mut PeriodicSync<'a, I>,
+}
+
+impl<I> Future for PeriodicSyncNext<'_, '_, I>
+where I: HostControllerInterface + ReceivedPacketSource + 'static
+{
+    type Output = Option<PeriodicReport>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut *self.get_mut().sync).poll_next_report(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::periodic_advertising_create_sync::{AdvertiserFilter, CreateSyncParameters, SyncTimeout};
+    use super::set_periodic_advertising_parameters::PeriodicAdvertisingParameters;
+    use crate::hci::testing::{VirtualController, PERIODIC_ADVERTISER_LIST_SIZE, TRANSMIT_POWER_LEVEL};
+    use crate::hci::{error, events, HostInterface};
+    use futures::executor::block_on;
+
+    #[test]
+    fn periodic_advertising_test() {
+        use crate::gap::advertise::local_name::LocalName;
+        use crate::hci::le::extended_advertising::{
+            set_extended_advertising_parameters::AdvertisingParameters, AdvertisingSet, LEPhy,
+        };
+        use crate::hci::le::transmitter::set_advertising_parameters::PeerAddressType;
+
+        let advertiser_address = [2, 2, 2, 2, 2, 2];
+
+        let scanner_controller = VirtualController::new([1, 1, 1, 1, 1, 1]);
+        let advertiser_controller = VirtualController::new(advertiser_address);
+
+        scanner_controller.link(&advertiser_controller);
+
+        let scanner = HostInterface::from(scanner_controller.clone());
+        let advertiser = HostInterface::from(advertiser_controller.clone());
+
+        let handle = AdvertisingHandle::try_new(0).unwrap();
+
+        let set = AdvertisingSet::new(&advertiser, handle);
+        let periodic = PeriodicAdvertiser::new(&advertiser, handle);
+
+        let periodic_parameters = || PeriodicAdvertisingParameters {
+            include_tx_power: true,
+            .. PeriodicAdvertisingParameters::default()
+        };
+
+        // Periodic advertising cannot be used by a connectable and scannable set
+        block_on(set.set_parameters(AdvertisingParameters::default())).unwrap();
+
+        assert!(block_on(periodic.set_parameters(periodic_parameters())).is_err());
+
+        let set_parameters = AdvertisingParameters {
+            event_properties: &[],
+            secondary_advertising_phy: LEPhy::_2M,
+            advertising_sid: 3,
+            .. AdvertisingParameters::default()
+        };
+
+        block_on(set.set_parameters(set_parameters)).unwrap();
+
+        // The data is sent in two commands and reported in two fragments
+        let mut data = AdvertisingData::new();
+
+        data.try_push(LocalName::new("s".repeat(250).as_str(), false)).unwrap();
+        data.try_push(LocalName::new("t".repeat(48).as_str(), false)).unwrap();
+
+        assert_eq!(302, data.len());
+
+        assert!(block_on(periodic.set_data(&data)).is_err());
+
+        block_on(periodic.set_parameters(periodic_parameters())).unwrap();
+        block_on(periodic.set_data(&data)).unwrap();
+        block_on(periodic.enable()).unwrap();
+        block_on(set.enable(None, 0)).unwrap();
+
+        assert_eq!(data.len(), advertiser_controller.get_advertising_sets()[0].periodic_data.len());
+
+        assert_eq!(
+            PERIODIC_ADVERTISER_LIST_SIZE,
+            block_on(read_periodic_advertiser_list_size::send(&scanner)).unwrap()
+        );
+
+        block_on(add_device_to_periodic_advertiser_list::send(
+            &scanner,
+            PeerAddressType::PublicAddress,
+            advertiser_address,
+            3,
+        )).unwrap();
+
+        let sync_parameters = CreateSyncParameters::new(
+            AdvertiserFilter::PeriodicAdvertiserList,
+            SyncTimeout::default()
+        );
+
+        let mut sync = block_on(PeriodicSync::establish(&scanner, sync_parameters)).unwrap();
+
+        assert_eq!(3, sync.get_established().advertising_sid);
+        assert_eq!(advertiser_address, sync.get_established().advertiser_address);
+        assert_eq!(LEPhy::_2M, sync.get_established().advertiser_phy);
+        assert_eq!(alloc::vec![sync.get_sync_handle().get_raw_handle()], scanner_controller.get_sync_handles());
+        assert!(!scanner_controller.is_synchronizing());
+
+        let report = block_on(sync.next_report()).unwrap();
+
+        assert_eq!(Some(TRANSMIT_POWER_LEVEL), report.tx_power);
+        assert!(!report.truncated);
+        assert_eq!(data.len(), report.data.len());
+        assert_eq!(&[49, 0x09], &report.data[252..254]);
+
+        // The periodic advertising data is reported every time it is set
+        let mut sensor_data = AdvertisingData::new();
+
+        sensor_data.try_push(LocalName::new("sensor", false)).unwrap();
+
+        block_on(periodic.set_data(&sensor_data)).unwrap();
+
+        let report = block_on(sync.next_report()).unwrap();
+
+        assert_eq!(sensor_data.len(), report.data.len());
+
+        // The sync is lost once the periodic advertising stops
+        block_on(periodic.disable()).unwrap();
+
+        assert!(block_on(sync.next_report()).is_none());
+        assert!(scanner_controller.get_sync_handles().is_empty());
+        assert!(block_on(sync.terminate()).is_err());
+
+        // Synchronizing with an advertiser that is not advertising is canceled
+        let sync_parameters = CreateSyncParameters::new(
+            AdvertiserFilter::Advertiser {
+                advertising_sid: 3,
+                address_type: PeerAddressType::PublicAddress,
+                address: advertiser_address,
+            },
+            SyncTimeout::default()
+        ).with_skip(4).unwrap();
+
+        let mut established = scanner.subscribe(events::LEMeta::PeriodicAdvertisingSyncEstablished.into(), 1);
+
+        block_on(periodic_advertising_create_sync::send(&scanner, sync_parameters)).unwrap();
+
+        assert!(scanner_controller.is_synchronizing());
+
+        block_on(periodic_advertising_create_sync_cancel::send(&scanner)).unwrap();
+
+        match block_on(established.next_event()) {
+            Ok(events::EventsData::LEMeta(events::LEMetaData::PeriodicAdvertisingSyncEstablished(data))) =>
+                assert_eq!(error::Error::OperationCancelledByHost, data.status),
+            _ => panic!("Expected LE periodic advertising sync established event"),
+        }
+
+        block_on(clear_periodic_advertiser_list::send(&scanner)).unwrap();
+    }
+}
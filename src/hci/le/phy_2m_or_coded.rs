@@ -0,0 +1,343 @@
+//! LE 2M and LE Coded PHY
+//!
+//! A connection starts out using the LE 1M PHY for both transmitting and receiving. The LE 2M
+//! PHY doubles the symbol rate for a higher throughput and the LE Coded PHY uses forward error
+//! correction (with either 2 or 8 symbols per bit, S2 or S8) for a longer range.
+//!
+//! The PHY preferences for new connections are set with [`set_default_phy`], the PHYs of a
+//! connection are changed with [`set_phy`], and the PHYs currently used by a connection are read
+//! with [`read_phy`]. A change of the PHYs is reported with the *LE PHY Update Complete* event,
+//! which must be enabled with the [LE event mask](crate::hci::le::mandatory::set_event_mask).
+
+pub use crate::hci::events::LEPhy;
+
+/// Get the raw PHY preference bits of a list of PHYs
+///
+/// The return is the pair of the 'all PHYs' bit and the PHY bits, a `None` for `phys` means
+/// there is no preference.
+fn into_phy_bits(phys: Option<&[LEPhy]>) -> (bool, u8) {
+    match phys {
+        None => (true, 0),
+        Some(phys) => (false, phys.iter().fold(0, |bits, phy| bits | match phy {
+            LEPhy::_1M => 1 << 0,
+            LEPhy::_2M => 1 << 1,
+            LEPhy::Coded => 1 << 2,
+        })),
+    }
+}
+
+/// Get the raw All_PHYS, TX_PHYS, and RX_PHYS parameters
+fn into_phy_parameters(tx_phys: Option<&[LEPhy]>, rx_phys: Option<&[LEPhy]>) -> (u8, u8, u8) {
+    let (tx_all, tx_bits) = into_phy_bits(tx_phys);
+    let (rx_all, rx_bits) = into_phy_bits(rx_phys);
+
+    ((tx_all as u8) | (rx_all as u8) << 1, tx_bits, rx_bits)
+}
+
+/// Read the PHYs used by a connection
+pub mod read_phy {
+
+    use crate::hci::*;
+    use crate::hci::common::ConnectionHandle;
+    use super::LEPhy;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadPHY);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        connection_handle: u16,
+        tx_phy: u8,
+        rx_phy: u8,
+    }
+
+    /// The PHYs used by a connection
+    #[derive(Debug, Clone, Copy)]
+    pub struct PhyInfo {
+        pub handle: ConnectionHandle,
+        /// The PHY used for transmitting
+        pub tx_phy: LEPhy,
+        /// The PHY used for receiving
+        pub rx_phy: LEPhy,
+    }
+
+    impl PhyInfo {
+        fn try_from(packed: CmdReturn) -> Result<Self, alloc::string::String> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(PhyInfo {
+                    handle: ConnectionHandle::try_from(<u16>::from_le(packed.connection_handle))?,
+                    tx_phy: LEPhy::try_from(packed.tx_phy)?,
+                    rx_phy: LEPhy::try_from(packed.rx_phy)?,
+                })
+            }
+            else {
+                Err(alloc::format!("{}", status))
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        PhyInfo,
+        alloc::string::String
+    );
+
+    impl_command_data_future!(PhyInfo, alloc::string::String);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _handle: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Parameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: ConnectionHandle )
+    -> impl Future<Output=Result<PhyInfo, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter { _handle: handle.get_raw_handle().to_le() };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Set the preferred PHYs for all new connections
+pub mod set_default_phy {
+
+    use crate::hci::*;
+    use super::LEPhy;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetDefaultPHY);
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _all_phys: u8,
+        _tx_phys: u8,
+        _rx_phys: u8,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Parameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    /// Send the command
+    ///
+    /// The inputs `tx_phys` and `rx_phys` are the PHYs the host prefers the controller to use for
+    /// transmitting and receiving. A `None` means that the host has no preference.
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, tx_phys: Option<&[LEPhy]>, rx_phys: Option<&[LEPhy]> )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let (all_phys, tx_phys, rx_phys) = super::into_phy_parameters(tx_phys, rx_phys);
+
+        let parameter = Parameter {
+            _all_phys: all_phys,
+            _tx_phys: tx_phys,
+            _rx_phys: rx_phys,
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Set the PHYs of a connection
+///
+/// The controller may not be able to change to the preferred PHYs (e.g. the peer does not
+/// support them), the PHYs used after the change are within the *LE PHY Update Complete* event.
+pub mod set_phy {
+
+    use crate::hci::*;
+    use crate::hci::common::ConnectionHandle;
+    use super::LEPhy;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetPHY);
+
+    /// The coding preferred for transmitting on the LE Coded PHY
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CodedPhyOption {
+        NoPreference,
+        /// Two symbols per bit (S2)
+        S2,
+        /// Eight symbols per bit (S8)
+        S8,
+    }
+
+    impl CodedPhyOption {
+        fn into_val(self) -> u16 {
+            match self {
+                CodedPhyOption::NoPreference => 0,
+                CodedPhyOption::S2 => 1,
+                CodedPhyOption::S8 => 2,
+            }
+        }
+    }
+
+    impl Default for CodedPhyOption {
+        fn default() -> Self {
+            CodedPhyOption::NoPreference
+        }
+    }
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _handle: u16,
+        _all_phys: u8,
+        _tx_phys: u8,
+        _rx_phys: u8,
+        _phy_options: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Parameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    impl_command_status_future!();
+
+    /// The number of *LE PHY Update Complete* events buffered while waiting for the update
+    const PHY_UPDATE_EVENTS_CAPACITY: usize = 1;
+
+    /// Send the command
+    ///
+    /// The inputs `tx_phys` and `rx_phys` are the PHYs the host prefers the controller to use for
+    /// transmitting and receiving, a `None` means that the host has no preference. The
+    /// `coded_option` is only used when the LE Coded PHY is used for transmitting.
+    ///
+    /// The returned future completes when the *LE PHY Update Complete* event for the connection
+    /// is received. The subscription to the event is made when this is called, so an event for the
+    /// connection received before this is called is not taken as the completion of the update. The
+    /// `timeout` is used for the Command Status event, and for the PHY Update Complete event when
+    /// the host interface has a [timer](crate::hci::HostInterface::set_timer).
+    pub fn send<'a, T: 'static>(
+        hci: &'a HostInterface<T>,
+        handle: ConnectionHandle,
+        tx_phys: Option<&[LEPhy]>,
+        rx_phys: Option<&[LEPhy]>,
+        coded_option: CodedPhyOption,
+        timeout: Duration,
+    ) -> impl Future<Output=Result<events::LEPHYUpdateCompleteData, impl CommandError>> + 'a
+    where T: HostControllerInterface + ReceivedPacketSource
+    {
+        use alloc::boxed::Box;
+        use events::{Events, EventsData, LEMeta, LEMetaData};
+
+        let (all_phys, tx_phys, rx_phys) = super::into_phy_parameters(tx_phys, rx_phys);
+
+        let parameter = Parameter {
+            _handle: handle.get_raw_handle().to_le(),
+            _all_phys: all_phys,
+            _tx_phys: tx_phys,
+            _rx_phys: rx_phys,
+            _phy_options: coded_option.into_val().to_le(),
+        };
+
+        // The subscription must be made before the command is sent so that the event cannot be
+        // received before there is a stream to receive it.
+        let mut update_events = hci.subscribe_with_matcher(
+            Events::LEMeta(LEMeta::PHYUpdateComplete),
+            PHY_UPDATE_EVENTS_CAPACITY,
+            move |ed: &EventsData| matches!(ed,
+                EventsData::LEMeta(LEMetaData::PHYUpdateComplete(data)) if data.connection_handle == handle
+            )
+        );
+
+        let status = ReturnedFuture( hci.send_command(parameter, events::Events::CommandStatus, timeout ) );
+
+        async move {
+            status.await.map_err(StatusThenEventError::Status)?;
+
+            let update = Box::pin(async {
+                loop {
+                    match update_events.next_event().await {
+                        Ok(EventsData::LEMeta(LEMetaData::PHYUpdateComplete(data))) => break data,
+                        Ok(_) => (),
+                        Err(overflow) => log::warn!("PHY update of connection {}: {}", handle, overflow),
+                    }
+                }
+            });
+
+            match hci.get_timer() {
+                Some(timer) => crate::timer::Timed::new(&*timer, timeout, update).await
+                    .map_err(StatusThenEventError::Receive),
+                None => Ok(update.await),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::set_phy::CodedPhyOption;
+    use crate::hci::common::ConnectionHandle;
+    use crate::hci::events;
+    use crate::hci::testing::fixture::{host_interface, CONNECTION_COMPLETE};
+    use core::time::Duration;
+    use futures::executor::block_on;
+
+    #[test]
+    fn phy_test() {
+
+        let (hi, controller) = host_interface();
+
+        block_on(set_default_phy::send(&hi, Some(&[LEPhy::_1M, LEPhy::_2M]), None)).unwrap();
+
+        assert_eq!([0x02, 0x03, 0x00], controller.get_default_phys());
+
+        assert!(block_on(set_default_phy::send(&hi, Some(&[]), None)).is_err());
+
+        controller.inject_le_meta_event(events::LEMeta::ConnectionComplete, &CONNECTION_COMPLETE).unwrap();
+
+        let handle = match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+            Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) => data.connection_handle,
+            _ => panic!("Expected LE connection complete event"),
+        };
+
+        let phys = block_on(read_phy::send(&hi, handle)).unwrap();
+
+        assert_eq!(LEPhy::_1M, phys.tx_phy);
+        assert_eq!(LEPhy::_1M, phys.rx_phy);
+
+        // A PHY update of the connection before the command is sent is not the completion of
+        // the command
+        controller.inject_le_meta_event(events::LEMeta::PHYUpdateComplete, &[0, 0x40, 0x00, 1, 1]).unwrap();
+
+        let update = block_on(set_phy::send(
+            &hi,
+            handle,
+            Some(&[LEPhy::_2M]),
+            Some(&[LEPhy::Coded]),
+            CodedPhyOption::S8,
+            Duration::from_secs(1)
+        )).unwrap();
+
+        assert_eq!(handle, update.connection_handle);
+        assert_eq!(LEPhy::_2M, update.tx_phy);
+        assert_eq!(LEPhy::Coded, update.rx_phy);
+
+        let phys = block_on(read_phy::send(&hi, handle)).unwrap();
+
+        assert_eq!(LEPhy::_2M, phys.tx_phy);
+        assert_eq!(LEPhy::Coded, phys.rx_phy);
+
+        let unknown = ConnectionHandle::try_from(0x41).unwrap();
+
+        assert!(block_on(set_phy::send(&hi, unknown, None, None, CodedPhyOption::default(), Duration::from_secs(1))).is_err());
+    }
+}
@@ -0,0 +1,684 @@
+//! LE Privacy
+//!
+//! These are the commands for address resolution by the controller. The controller keeps a
+//! *resolving list* of the identity addresses and Identity Resolving Keys (IRKs) of peer devices
+//! together with the local IRK used for each peer. With address resolution enabled, the controller
+//! resolves the resolvable private addresses (RPAs) of the peer devices in the resolving list to
+//! their identity addresses, so the white list is used with the identity addresses of the peer
+//! devices.
+//!
+//! The resolving list cannot be changed while address resolution is enabled and advertising,
+//! scanning, or creating a connection is enabled. The function [`load_resolving_list`] sets up the
+//! resolving list and the white list from the keys of a
+//! [`SecurityManager`](crate::sm::SecurityManager).
+
+use crate::hci::*;
+
+/// The type of a peer identity address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerIdentityAddressType {
+    Public,
+    RandomStatic,
+}
+
+impl PeerIdentityAddressType {
+    fn into_val(self) -> u8 {
+        match self {
+            PeerIdentityAddressType::Public => 0x00,
+            PeerIdentityAddressType::RandomStatic => 0x01,
+        }
+    }
+
+    fn into_white_list_type(self) -> crate::hci::le::common::AddressType {
+        use crate::hci::le::common::AddressType;
+
+        match self {
+            PeerIdentityAddressType::Public => AddressType::PublicDeviceAddress,
+            PeerIdentityAddressType::RandomStatic => AddressType::RandomDeviceAddress,
+        }
+    }
+}
+
+/// An entry of the resolving list
+///
+/// The IRKs are in native endian. A local IRK of zero means that the controller uses the identity
+/// address of this device (instead of a RPA) with the peer device, and a peer IRK of zero means
+/// that the peer device uses its identity address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvingListEntry {
+    pub peer_identity_address_type: PeerIdentityAddressType,
+    pub peer_identity_address: crate::BluetoothDeviceAddress,
+    pub peer_irk: u128,
+    pub local_irk: u128,
+}
+
+/// The timeout for changing to a new resolvable private address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpaTimeout {
+    seconds: u16,
+}
+
+impl RpaTimeout {
+    const SECONDS_RANGE: core::ops::RangeInclusive<u16> = 0x1..=0xA1B8;
+
+    /// Create a new `RpaTimeout`
+    ///
+    /// The timeout is rounded down to whole seconds.
+    ///
+    /// # Error
+    /// `timeout` is not within the range of 1 second to 11.5 hours (41400 seconds)
+    pub fn try_new(timeout: Duration) -> Result<Self, &'static str> {
+        let seconds = timeout.as_secs();
+
+        if seconds <= <u16>::MAX as u64 && Self::SECONDS_RANGE.contains(&(seconds as u16)) {
+            Ok(RpaTimeout { seconds: seconds as u16 })
+        } else {
+            Err("RPA timeout out of range: 1..=41400 seconds")
+        }
+    }
+
+    /// Get the timeout
+    pub fn get_timeout(&self) -> Duration {
+        Duration::from_secs(self.seconds.into())
+    }
+}
+
+impl Default for RpaTimeout {
+    /// The default timeout of 15 minutes
+    fn default() -> Self {
+        RpaTimeout { seconds: 900 }
+    }
+}
+
+/// The parameter for commands that only take a peer identity address
+#[repr(packed)]
+#[derive(Clone, Copy)]
+struct PeerIdentityParameter {
+    _peer_identity_address_type: u8,
+    _peer_identity_address: [u8;6],
+}
+
+impl PeerIdentityParameter {
+    fn new(address_type: PeerIdentityAddressType, address: crate::BluetoothDeviceAddress) -> Self {
+        PeerIdentityParameter {
+            _peer_identity_address_type: address_type.into_val(),
+            _peer_identity_address: address,
+        }
+    }
+}
+
+/// Implement the return of the read peer/local resolvable address commands
+macro_rules! impl_resolvable_address_return {
+    ( $command: ident ) => {
+        #[repr(packed)]
+        pub(crate) struct CmdReturn {
+            status: u8,
+            address: [u8;6],
+        }
+
+        pub struct Return;
+
+        impl Return {
+            fn try_from(packed: CmdReturn) -> Result<crate::BluetoothDeviceAddress, error::Error> {
+                let status = error::Error::from(packed.status);
+
+                if let error::Error::NoError = status {
+                    Ok(packed.address)
+                }
+                else {
+                    Err(status)
+                }
+            }
+        }
+
+        impl_get_data_for_command!(
+            $command,
+            CmdReturn,
+            Return,
+            crate::BluetoothDeviceAddress,
+            error::Error
+        );
+
+        impl_command_data_future!(Return, crate::BluetoothDeviceAddress, error::Error);
+    }
+}
+
+pub mod add_device_to_resolving_list {
+
+    use crate::hci::*;
+    use super::ResolvingListEntry;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::AddDeviceToResolvingList);
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _peer_identity_address_type: u8,
+        _peer_identity_address: [u8;6],
+        _peer_irk: [u8;16],
+        _local_irk: [u8;16],
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Parameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, entry: ResolvingListEntry )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _peer_identity_address_type: entry.peer_identity_address_type.into_val(),
+            _peer_identity_address: entry.peer_identity_address,
+            _peer_irk: entry.peer_irk.to_le_bytes(),
+            _local_irk: entry.local_irk.to_le_bytes(),
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+pub mod remove_device_from_resolving_list {
+
+    use crate::hci::*;
+    use super::{PeerIdentityAddressType, PeerIdentityParameter};
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::RemoveDeviceFromResolvingList);
+
+    impl_status_return!(COMMAND);
+
+    struct Parameter(PeerIdentityParameter);
+
+    impl CommandParameter for Parameter {
+        type Parameter = PeerIdentityParameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { self.0 }
+    }
+
+    pub fn send<'a, T: 'static>(
+        hci: &'a HostInterface<T>,
+        address_type: PeerIdentityAddressType,
+        address: crate::BluetoothDeviceAddress,
+    ) -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter(PeerIdentityParameter::new(address_type, address));
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+pub mod clear_resolving_list {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ClearResolvingList);
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+pub mod read_resolving_list_size {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadResolvingListSize);
+
+    #[repr(packed)]
+    pub(crate) struct CmdReturn {
+        status: u8,
+        size: u8,
+    }
+
+    pub struct Return;
+
+    impl Return {
+        fn try_from( packed: CmdReturn) -> Result<usize, error::Error> {
+            let status = error::Error::from(packed.status);
+
+            if let error::Error::NoError = status {
+                Ok(packed.size as usize)
+            }
+            else {
+                Err(status)
+            }
+        }
+    }
+
+    impl_get_data_for_command!(
+        COMMAND,
+        CmdReturn,
+        Return,
+        usize,
+        error::Error
+    );
+
+    impl_command_data_future!(Return, usize, error::Error);
+
+    #[derive(Clone, Copy)]
+    struct Parameter;
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
+    -> impl Future<Output=Result<usize, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Read the current RPA used by a peer device in the resolving list
+pub mod read_peer_resolvable_address {
+
+    use crate::hci::*;
+    use super::{PeerIdentityAddressType, PeerIdentityParameter};
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadPeerResolvableAddress);
+
+    impl_resolvable_address_return!(COMMAND);
+
+    struct Parameter(PeerIdentityParameter);
+
+    impl CommandParameter for Parameter {
+        type Parameter = PeerIdentityParameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { self.0 }
+    }
+
+    pub fn send<'a, T: 'static>(
+        hci: &'a HostInterface<T>,
+        address_type: PeerIdentityAddressType,
+        address: crate::BluetoothDeviceAddress,
+    ) -> impl Future<Output=Result<crate::BluetoothDeviceAddress, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter(PeerIdentityParameter::new(address_type, address));
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Read the current RPA used by this device with a peer device in the resolving list
+pub mod read_local_resolvable_address {
+
+    use crate::hci::*;
+    use super::{PeerIdentityAddressType, PeerIdentityParameter};
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::ReadLocalResolvableAddress);
+
+    impl_resolvable_address_return!(COMMAND);
+
+    struct Parameter(PeerIdentityParameter);
+
+    impl CommandParameter for Parameter {
+        type Parameter = PeerIdentityParameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { self.0 }
+    }
+
+    pub fn send<'a, T: 'static>(
+        hci: &'a HostInterface<T>,
+        address_type: PeerIdentityAddressType,
+        address: crate::BluetoothDeviceAddress,
+    ) -> impl Future<Output=Result<crate::BluetoothDeviceAddress, impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter(PeerIdentityParameter::new(address_type, address));
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+pub mod set_address_resolution_enable {
+
+    use crate::hci::*;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetAddressResolutionEnable);
+
+    impl_status_return!(COMMAND);
+
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _enable: u8,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter { _enable: if enable { 1 } else { 0 } };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+pub mod set_resolvable_private_address_timeout {
+
+    use crate::hci::*;
+    use super::RpaTimeout;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetResolvablePrivateAddressTimeout);
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _rpa_timeout: u16,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, timeout: RpaTimeout )
+    -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter { _rpa_timeout: timeout.seconds.to_le() };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Set the privacy mode used for a peer device in the resolving list
+pub mod set_privacy_mode {
+
+    use crate::hci::*;
+    use super::PeerIdentityAddressType;
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetPrivacyMode);
+
+    /// The privacy mode of a peer device
+    ///
+    /// With network privacy the peer device is only accepted when it uses a RPA, with device
+    /// privacy the peer device is also accepted when it uses its identity address.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PrivacyMode {
+        NetworkPrivacy,
+        DevicePrivacy,
+    }
+
+    impl PrivacyMode {
+        fn into_val(self) -> u8 {
+            match self {
+                PrivacyMode::NetworkPrivacy => 0x00,
+                PrivacyMode::DevicePrivacy => 0x01,
+            }
+        }
+    }
+
+    impl Default for PrivacyMode {
+        fn default() -> Self {
+            PrivacyMode::NetworkPrivacy
+        }
+    }
+
+    impl_status_return!(COMMAND);
+
+    #[repr(packed)]
+    #[derive(Clone, Copy)]
+    struct Parameter {
+        _peer_identity_address_type: u8,
+        _peer_identity_address: [u8;6],
+        _privacy_mode: u8,
+    }
+
+    impl CommandParameter for Parameter {
+        type Parameter = Self;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter { *self }
+    }
+
+    pub fn send<'a, T: 'static>(
+        hci: &'a HostInterface<T>,
+        address_type: PeerIdentityAddressType,
+        address: crate::BluetoothDeviceAddress,
+        mode: PrivacyMode,
+    ) -> impl Future<Output=Result<(), impl CommandError>> + 'a
+    where T: HostControllerInterface
+    {
+        let parameter = Parameter {
+            _peer_identity_address_type: address_type.into_val(),
+            _peer_identity_address: address,
+            _privacy_mode: mode.into_val(),
+        };
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+}
+
+/// Load the resolving list and the white list with the bonded devices of a security manager
+///
+/// The resolving list is cleared and then loaded with the entries of the peer devices in the key
+/// database of `sm` that have both an IRK and an identity address (see
+/// [`get_resolving_list_entries`](crate::sm::SecurityManager::get_resolving_list_entries)). The
+/// white list is not cleared, the identity addresses of these peer devices are added to the
+/// entries already within it. Address resolution is enabled once the lists are loaded, so a bonded
+/// device using a RPA passes white list filtering.
+///
+/// No more entries are loaded than the size of the resolving list or the size of the white list
+/// (as read from the controller), any entries past these sizes are not loaded. Address resolution
+/// is enabled again even when loading the lists fails.
+///
+/// This must not be called while advertising, scanning, or creating a connection is enabled. The
+/// output is the number of entries added to the resolving list.
+pub fn load_resolving_list<'a, T: 'static>(hci: &'a HostInterface<T>, sm: &crate::sm::SecurityManager)
+-> impl Future<Output=Result<usize, ProcedureError>> + 'a
+where T: HostControllerInterface
+{
+    use crate::hci::le::mandatory::{add_device_to_white_list, read_white_list_size};
+
+    let entries: Vec<ResolvingListEntry> = sm.get_resolving_list_entries().collect();
+
+    async move {
+        set_address_resolution_enable::send(hci, false).await
+            .map_err(|e| ProcedureError::new("LE Set Address Resolution Enable", e))?;
+
+        let loaded = async {
+            let resolving_list_size = read_resolving_list_size::send(hci).await
+                .map_err(|e| ProcedureError::new("LE Read Resolving List Size", e))?;
+
+            let white_list_size = read_white_list_size::send(hci).await
+                .map_err(|e| ProcedureError::new("LE Read White List Size", e))?;
+
+            let count = core::cmp::min(entries.len(), core::cmp::min(resolving_list_size, white_list_size));
+
+            if count < entries.len() {
+                log::warn!("Only {} of the {} bonded devices are loaded into the resolving list",
+                    count, entries.len());
+            }
+
+            clear_resolving_list::send(hci).await
+                .map_err(|e| ProcedureError::new("LE Clear Resolving List", e))?;
+
+            for entry in entries[..count].iter() {
+                add_device_to_resolving_list::send(hci, *entry).await
+                    .map_err(|e| ProcedureError::new("LE Add Device To Resolving List", e))?;
+
+                add_device_to_white_list::send(
+                    hci,
+                    entry.peer_identity_address_type.into_white_list_type(),
+                    entry.peer_identity_address
+                ).await
+                    .map_err(|e| ProcedureError::new("LE Add Device To White List", e))?;
+            }
+
+            Ok::<_, ProcedureError>(count)
+        }.await;
+
+        let enabled = set_address_resolution_enable::send(hci, true).await
+            .map_err(|e| ProcedureError::new("LE Set Address Resolution Enable", e));
+
+        let count = loaded?;
+
+        enabled.map(|_| count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::set_privacy_mode::PrivacyMode;
+    use crate::hci::le::mandatory::{add_device_to_white_list, clear_white_list};
+    use crate::hci::testing::fixture::host_interface;
+    use crate::hci::testing::{WhiteListEntry, RESOLVING_LIST_SIZE};
+    use futures::executor::block_on;
+
+    #[test]
+    fn privacy_test() {
+        use crate::sm::{KeyDBEntry, SecurityManager};
+
+        let (hi, controller) = host_interface();
+
+        let peer_address = [1, 2, 3, 4, 5, 6];
+        let peer_irk = 0x0123_4567_89AB_CDEF_0123_4567_89AB_CDEF;
+
+        assert_eq!(RESOLVING_LIST_SIZE, block_on(read_resolving_list_size::send(&hi)).unwrap());
+
+        assert!(RpaTimeout::try_new(Duration::from_secs(0)).is_err());
+        assert!(RpaTimeout::try_new(Duration::from_secs(0xA1B9)).is_err());
+
+        let timeout = RpaTimeout::try_new(Duration::from_secs(60)).unwrap();
+
+        block_on(set_resolvable_private_address_timeout::send(&hi, timeout)).unwrap();
+
+        assert_eq!(60, controller.get_rpa_timeout());
+
+        let entry = ResolvingListEntry {
+            peer_identity_address_type: PeerIdentityAddressType::Public,
+            peer_identity_address: peer_address,
+            peer_irk,
+            local_irk: 0,
+        };
+
+        block_on(add_device_to_resolving_list::send(&hi, entry)).unwrap();
+
+        assert!(block_on(add_device_to_resolving_list::send(&hi, entry)).is_err());
+
+        let rpa = block_on(read_peer_resolvable_address::send(&hi, PeerIdentityAddressType::Public, peer_address))
+            .unwrap();
+
+        assert_eq!(crate::sm::toolbox::ah(peer_irk, [rpa[3], rpa[4], rpa[5]]), [rpa[0], rpa[1], rpa[2]]);
+
+        // There is no local RPA without a local IRK
+        assert!(block_on(read_local_resolvable_address::send(&hi, PeerIdentityAddressType::Public, peer_address))
+            .is_err());
+
+        block_on(set_privacy_mode::send(&hi, PeerIdentityAddressType::Public, peer_address, PrivacyMode::DevicePrivacy))
+            .unwrap();
+
+        assert_eq!(1, controller.get_resolving_list()[0].privacy_mode);
+
+        assert!(block_on(remove_device_from_resolving_list::send(&hi, PeerIdentityAddressType::RandomStatic, peer_address))
+            .is_err());
+
+        block_on(remove_device_from_resolving_list::send(&hi, PeerIdentityAddressType::Public, peer_address)).unwrap();
+
+        assert!(controller.get_resolving_list().is_empty());
+
+        block_on(add_device_to_resolving_list::send(&hi, entry)).unwrap();
+
+        block_on(set_address_resolution_enable::send(&hi, true)).unwrap();
+
+        assert!(controller.is_address_resolution_enabled());
+
+        block_on(clear_resolving_list::send(&hi)).unwrap();
+
+        assert!(controller.get_resolving_list().is_empty());
+
+        // A `KeyDBEntry` is only created by bonding, so the entries are deserialized from the
+        // fields `ltk`, `csrk`, `irk`, `peer_csrk`, `peer_irk`, and `peer_addr`.
+        let key_db_entry = |local_irk: Option<u128>, peer_irk: Option<u128>, peer_addr: Option<(u32, [u8;6])>| {
+            let raw = serializer::serialize(
+                &(None::<u128>, None::<(u128, u32)>, local_irk, None::<(u128, u32)>, peer_irk, peer_addr)
+            ).unwrap();
+
+            serializer::deserialize::<KeyDBEntry>(&raw).unwrap()
+        };
+
+        let static_address = [7, 8, 9, 10, 11, 0xC0];
+
+        let sm = SecurityManager::new(vec![
+            key_db_entry(Some(2), Some(peer_irk), Some((0, peer_address))),
+            key_db_entry(None, Some(3), Some((1, static_address))),
+            key_db_entry(Some(4), Some(5), None),
+            key_db_entry(Some(6), None, Some((0, [1;6]))),
+        ]);
+
+        assert_eq!(2, block_on(load_resolving_list(&hi, &sm)).unwrap());
+
+        let mut resolving_list = controller.get_resolving_list();
+
+        resolving_list.sort_by_key(|entry| entry.address_type);
+
+        assert_eq!(peer_address, resolving_list[0].address);
+        assert_eq!(peer_irk.to_le_bytes(), resolving_list[0].peer_irk);
+        assert_eq!(2u128.to_le_bytes(), resolving_list[0].local_irk);
+        assert_eq!(static_address, resolving_list[1].address);
+        assert_eq!(3u128.to_le_bytes(), resolving_list[1].peer_irk);
+        assert_eq!(0u128.to_le_bytes(), resolving_list[1].local_irk);
+
+        let white_list = controller.get_white_list();
+
+        assert!(white_list.contains(&WhiteListEntry { address_type: 0, address: peer_address }));
+        assert!(white_list.contains(&WhiteListEntry { address_type: 1, address: static_address }));
+        assert_eq!(2, white_list.len());
+
+        assert!(controller.is_address_resolution_enabled());
+
+        // Address resolution is enabled again when loading the lists fails
+        block_on(clear_white_list::send(&hi)).unwrap();
+
+        block_on(add_device_to_white_list::send(&hi, PeerIdentityAddressType::Public.into_white_list_type(), [0xA; 6])).unwrap();
+
+        controller.set_white_list_size(1);
+
+        assert!(block_on(load_resolving_list(&hi, &sm)).is_err());
+
+        assert!(controller.is_address_resolution_enabled());
+
+        // Only the entries that fit within the white list are loaded
+        block_on(clear_white_list::send(&hi)).unwrap();
+
+        assert_eq!(1, block_on(load_resolving_list(&hi, &sm)).unwrap());
+
+        assert_eq!(1, controller.get_resolving_list().len());
+        assert_eq!(1, controller.get_white_list().len());
+
+        assert!(controller.is_address_resolution_enabled());
+    }
+}
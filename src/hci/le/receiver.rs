@@ -17,7 +17,7 @@ pub mod receiver_test {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, frequency: Frequency )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(frequency, events::Events::CommandComplete , Duration::from_secs(1) ) )
@@ -58,7 +58,7 @@ pub mod set_scan_enable {
     /// The command has the ability to enable/disable scanning and filter duplicate
     /// advertisement.
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool, filter_duplicates: bool)
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         let cmd_param = Parameter {
@@ -91,7 +91,7 @@ pub mod set_scan_parameters {
     }
 
     impl LEScanType {
-        fn into_val(&self) -> u8 {
+        pub(crate) fn into_val(&self) -> u8 {
             match *self {
                 LEScanType::PassiveScanning => 0x00,
                 LEScanType::ActiveScanning  => 0x01,
@@ -121,7 +121,7 @@ pub mod set_scan_parameters {
     }
 
     impl ScanningFilterPolicy {
-        fn into_val(&self) -> u8 {
+        pub(crate) fn into_val(&self) -> u8 {
             match *self {
                 ScanningFilterPolicy::AcceptAll => 0x00,
                 ScanningFilterPolicy::WhiteListed => 0x01,
@@ -184,7 +184,7 @@ pub mod set_scan_parameters {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, sp: ScanningParameters )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(sp, events::Events::CommandComplete, Duration::from_secs(1) ) )
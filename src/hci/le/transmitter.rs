@@ -53,7 +53,7 @@ pub mod read_advertising_channel_tx_power {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T> )
-                                 -> impl Future<Output=Result<TxPower, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<TxPower, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -117,7 +117,7 @@ pub mod transmitter_test{
         channel: Frequency,
         payload: TestPayload,
         payload_length: u8 )
-        -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+        -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
 
@@ -135,7 +135,7 @@ pub mod transmitter_test{
 pub mod set_advertising_data {
 
     use crate::hci::*;
-    use crate::gap::advertise::{IntoRaw,DataTooLargeError};
+    use crate::gap::advertise::{ADStructPacker,IntoRaw,DataTooLargeError};
 
     const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetAdvertisingData);
 
@@ -158,8 +158,7 @@ pub mod set_advertising_data {
     /// early termination is desired).
     #[derive(Debug,Clone,Copy)]
     pub struct AdvertisingData {
-        length: usize,
-        payload: Payload,
+        packer: ADStructPacker<Payload>,
     }
 
     impl AdvertisingData {
@@ -188,8 +187,7 @@ pub mod set_advertising_data {
         /// ```
         pub fn early_terminate() -> Self {
             AdvertisingData{
-                length: 0,
-                payload: Payload::default(),
+                packer: ADStructPacker::new(Payload::default()),
             }
         }
 
@@ -204,23 +202,7 @@ pub mod set_advertising_data {
                            -> Result<(), DataTooLargeError>
             where T: IntoRaw
         {
-            let raw_data = data.into_raw();
-
-            if raw_data.len() + self.length <= self.payload.len() {
-                let old_len = self.length;
-
-                self.length += raw_data.len();
-
-                self.payload[old_len..self.length].copy_from_slice(&raw_data);
-
-                Ok(())
-            }
-            else {
-                Err(DataTooLargeError {
-                    overflow: raw_data.len() + self.length - self.payload.len(),
-                    remaining: self.payload.len() - self.length,
-                })
-            }
+            self.packer.try_push(data)
         }
 
         /// Get the remaining amount of space available for ADStructures
@@ -228,7 +210,7 @@ pub mod set_advertising_data {
         /// Use this to get the remaining space that can be sent in an advertising
         /// packet.
         pub fn remaining_space(&self) -> usize {
-            self.payload.len() - self.length as usize
+            self.packer.remaining_space()
         }
     }
 
@@ -237,8 +219,8 @@ pub mod set_advertising_data {
         const COMMAND: opcodes::HCICommand = COMMAND;
         fn get_parameter(&self) -> Self::Parameter {
             CmdParameter {
-                _length: self.length as u8,
-                _data: self.payload
+                _length: self.packer.len() as u8,
+                _data: *self.packer.get_buffer()
             }
         }
     }
@@ -246,7 +228,7 @@ pub mod set_advertising_data {
     impl_status_return!(COMMAND);
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, adv_data: AdvertisingData )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(adv_data, events::Events::CommandComplete, Duration::from_secs(1) ) )
@@ -275,7 +257,7 @@ pub mod set_advertising_enable {
         }
     }
 
-    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool ) -> impl Future<Output=Result<(), impl Display + Debug>> + 'a where T: HostControllerInterface
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, enable: bool ) -> impl Future<Output=Result<(), impl CommandError>> + 'a where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter{ enable }, events::Events::CommandComplete, Duration::from_secs(1) ) )
     }
@@ -335,7 +317,7 @@ pub mod set_advertising_parameters {
     }
 
     impl PeerAddressType {
-        fn into_val(&self) -> u8 {
+        pub(crate) fn into_val(&self) -> u8 {
             match *self {
                 PeerAddressType::PublicAddress => 0x00,
                 PeerAddressType::RandomAddress => 0x01,
@@ -358,7 +340,7 @@ pub mod set_advertising_parameters {
     }
 
     impl AdvertisingChannel {
-        fn into_val(&self) -> u8 {
+        pub(crate) fn into_val(&self) -> u8 {
             match *self {
                 AdvertisingChannel::Channel37 => 0x01,
                 AdvertisingChannel::Channel38 => 0x02,
@@ -384,7 +366,7 @@ pub mod set_advertising_parameters {
     }
 
     impl AdvertisingFilterPolicy {
-        fn into_val(&self) -> u8 {
+        pub(crate) fn into_val(&self) -> u8 {
             match *self {
                 AdvertisingFilterPolicy::AllDevices => 0x00,
                 AdvertisingFilterPolicy::AllConnectionRequestsWhitlistedDeviceScanRequests => 0x01,
@@ -479,7 +461,7 @@ pub mod set_advertising_parameters {
     impl_status_return!(COMMAND);
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, params: AdvertisingParameters )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
 
@@ -530,10 +512,129 @@ pub mod set_random_address {
     }
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, rand_addr: crate::BluetoothDeviceAddress )
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         ReturnedFuture( hci.send_command(Parameter{ rand_address: rand_addr }, events::Events::CommandComplete, Duration::from_secs(1) ) )
     }
 
 }
+
+/// LE Set Scan Response Data Command
+///
+/// The scan response data is sent by a scannable advertiser in response to a scan request from
+/// an active scanner. It has the same format as advertising data, so it is commonly used for the
+/// data that does not fit within the advertising data (such as a long local name).
+pub mod set_scan_response_data {
+
+    use crate::hci::*;
+    use crate::gap::advertise::{ADStructPacker,IntoRaw,DataTooLargeError};
+
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::LEController(opcodes::LEController::SetScanResponseData);
+
+    type Payload = [u8;31];
+
+    #[repr(packed)]
+    #[doc(hidden)]
+    pub struct CmdParameter {
+        _length: u8,
+        _data: [u8;31],
+    }
+
+    /// Scan response data
+    ///
+    /// The scan response data is made up of AD Structs in the same way as
+    /// [`AdvertisingData`](super::set_advertising_data::AdvertisingData). The maximum amount of
+    /// AD structures that can be sent in a legacy scan response is 31 bytes.
+    #[derive(Debug,Clone,Copy)]
+    pub struct ScanResponseData {
+        packer: ADStructPacker<Payload>,
+    }
+
+    impl ScanResponseData {
+
+        /// Create an empty scan response data
+        pub fn new() -> Self {
+            ScanResponseData {
+                packer: ADStructPacker::new(Payload::default()),
+            }
+        }
+
+        /// Add an ADStruct to the scan response data
+        ///
+        /// # Error
+        /// 'data' in its transmission form was too large for remaining free space in
+        /// the scan response data.
+        pub fn try_push<T>(&mut self, data: T ) -> Result<(), DataTooLargeError>
+            where T: IntoRaw
+        {
+            self.packer.try_push(data)
+        }
+
+        /// Get the remaining amount of space available for ADStructures
+        pub fn remaining_space(&self) -> usize {
+            self.packer.remaining_space()
+        }
+    }
+
+    impl Default for ScanResponseData {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl CommandParameter for ScanResponseData {
+        type Parameter = CmdParameter;
+        const COMMAND: opcodes::HCICommand = COMMAND;
+        fn get_parameter(&self) -> Self::Parameter {
+            CmdParameter {
+                _length: self.packer.len() as u8,
+                _data: *self.packer.get_buffer()
+            }
+        }
+    }
+
+    impl_status_return!(COMMAND);
+
+    pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, scan_response_data: ScanResponseData )
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
+        where T: HostControllerInterface
+    {
+        ReturnedFuture( hci.send_command(scan_response_data, events::Events::CommandComplete, Duration::from_secs(1) ) )
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::hci::testing::fixture::host_interface;
+    use futures::executor::block_on;
+
+    #[test]
+    fn scan_response_data_test() {
+        use crate::gap::advertise::local_name::LocalName;
+        use crate::hci::le::transmitter::set_scan_response_data;
+
+        let (hi, controller) = host_interface();
+
+        let name = "a virtual controller name";
+
+        let mut scan_response = set_scan_response_data::ScanResponseData::new();
+
+        scan_response.try_push(LocalName::new(name, false)).unwrap();
+
+        assert_eq!(31 - 2 - name.len(), scan_response.remaining_space());
+
+        // The name does not fit twice
+        assert!(scan_response.try_push(LocalName::new(name, false)).is_err());
+
+        block_on(set_scan_response_data::send(&hi, scan_response)).unwrap();
+
+        let mut expected = vec![name.len() as u8 + 1, 0x09];
+
+        expected.extend_from_slice(name.as_bytes());
+
+        assert_eq!(expected, controller.get_scan_response_data());
+    }
+}
@@ -22,7 +22,7 @@ pub mod read_remote_version_information {
     impl_command_status_future!();
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: ConnectionHandle)
-                                 -> impl Future<Output=Result<(), impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<(), impl CommandError>> + 'a
         where T: HostControllerInterface
     {
 
@@ -2,11 +2,16 @@
 //!
 //! The HCI is the primary way of interacting with the controller for this library.
 
+mod event_stream;
+mod flow_control;
 mod opcodes;
+pub(crate) mod spin_lock;
 pub mod common;
 pub mod error;
 #[macro_use] pub mod events;
 
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::Debug;
@@ -15,10 +20,17 @@ use core::future::Future;
 use core::pin::Pin;
 use core::time::Duration;
 use core::task::{ Poll, Waker };
+use spin_lock::SpinLock;
+use crate::timer::{Timeout, Timer};
+
+pub use event_stream::{EventStream, EventStreamNext, Overflow};
 
 /// Used to get the information required for sending a command from the host to the controller
 ///
-/// The type Parameter should be a packed structure of the command's parameters
+/// The type Parameter should be a packed structure of the command's parameters. Commands with a
+/// parameter of variable length cannot be a structure, these commands override `as_command_packet`
+/// instead. Implementations of [`HostControllerInterface`] should always use `as_command_packet`
+/// to get the command packet.
 pub trait CommandParameter {
     /// Data for the parameter as specified by the Bluetooth Specification.
     type Parameter;
@@ -277,6 +289,157 @@ impl HciAclData {
     }
 }
 
+/// The packet status flag of a HCI synchronous data packet
+///
+/// This is only used for packets sent from the controller to the host when erroneous data
+/// reporting is enabled, otherwise it is always `CorrectlyReceived`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPacketStatus {
+    CorrectlyReceived,
+    PossiblyInvalid,
+    NoDataReceived,
+    PartiallyLost,
+}
+
+impl SyncPacketStatus {
+
+    /// Get the value shifted into the correct place of the Packet Status Flag in the HCI
+    /// synchronous data packet. The returned value is in host byte order.
+    fn get_shifted_val(&self) -> u16 {
+        ( match self {
+            SyncPacketStatus::CorrectlyReceived => 0x0,
+            SyncPacketStatus::PossiblyInvalid => 0x1,
+            SyncPacketStatus::NoDataReceived => 0x2,
+            SyncPacketStatus::PartiallyLost => 0x3,
+        } ) << 12
+    }
+
+    /// Get the `SyncPacketStatus` from the first 16 bits of a HCI synchronous data packet. The
+    /// input `val` does not need to be masked to only include the Packet Status Flag, however it
+    /// does need to be in host byte order.
+    fn from_shifted_val(val: u16) -> Self {
+        match (val >> 12) & 3 {
+            0x0 => SyncPacketStatus::CorrectlyReceived,
+            0x1 => SyncPacketStatus::PossiblyInvalid,
+            0x2 => SyncPacketStatus::NoDataReceived,
+            0x3 => SyncPacketStatus::PartiallyLost,
+            _ => panic!("This cannot happen"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HciSyncPacketConvertError {
+    PacketTooSmall,
+    InvalidConnectionHandle( &'static str ),
+}
+
+impl Display for HciSyncPacketConvertError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            HciSyncPacketConvertError::PacketTooSmall =>
+                write!(f, "Packet is too small to be a valid HCI synchronous data"),
+            HciSyncPacketConvertError::InvalidConnectionHandle(reason) =>
+                write!(f, "Invalid connection handle, {}", reason),
+        }
+    }
+}
+
+/// HCI synchronous (SCO or eSCO) data
+#[derive(Debug)]
+pub struct HciSyncData {
+    connection_handle: common::ConnectionHandle,
+    packet_status_flag: SyncPacketStatus,
+    payload: Vec<u8>,
+}
+
+impl HciSyncData {
+
+    /// The maximum size of the payload
+    ///
+    /// The length field of a HCI synchronous data packet is only one byte.
+    pub const MAXIMUM_PAYLOAD_SIZE: usize = <u8>::MAX as usize;
+
+    pub fn new(
+        connection_handle: common::ConnectionHandle,
+        packet_status_flag: SyncPacketStatus,
+        payload: Vec<u8>
+    ) -> Self
+    {
+        HciSyncData { connection_handle, packet_status_flag, payload }
+    }
+
+    pub fn get_handle(&self) -> &common::ConnectionHandle {
+        &self.connection_handle
+    }
+
+    pub fn get_payload(&self) -> &[u8] { &self.payload }
+
+    pub fn get_packet_status_flag(&self) -> SyncPacketStatus { self.packet_status_flag }
+
+    /// Convert the HciSyncData into a packet
+    ///
+    /// This will convert HciSyncData into a packet that can be sent between the host and
+    /// controller.
+    ///
+    /// # Panics
+    /// The length of the payload is greater than
+    /// [`MAXIMUM_PAYLOAD_SIZE`](HciSyncData::MAXIMUM_PAYLOAD_SIZE)
+    pub fn get_packet(&self) -> alloc::vec::Vec<u8> {
+
+        assert!( self.payload.len() <= Self::MAXIMUM_PAYLOAD_SIZE,
+            "Payload of synchronous data is larger than {} bytes", Self::MAXIMUM_PAYLOAD_SIZE );
+
+        let mut v = alloc::vec::Vec::with_capacity( self.payload.len() + 3 );
+
+        let first_2_bytes = self.connection_handle.get_raw_handle()
+            | self.packet_status_flag.get_shifted_val();
+
+        v.extend_from_slice( &first_2_bytes.to_le_bytes() );
+
+        v.push( self.payload.len() as u8 );
+
+        v.extend_from_slice( &self.payload );
+
+        v
+    }
+
+    /// Attempt to create a `HciSyncData`
+    ///
+    /// A `HciSyncData` is created if the packet is in the correct HCI synchronous data packet
+    /// format. If not, then an error is returned.
+    pub fn from_packet(packet: &[u8]) -> Result<Self, HciSyncPacketConvertError> {
+        const HEADER_SIZE: usize = 3;
+
+        if packet.len() >= HEADER_SIZE {
+            let first_2_bytes = <u16>::from_le_bytes( [ packet[0], packet[1] ] );
+
+            let connection_handle = match common::ConnectionHandle::try_from( first_2_bytes & 0xFFF) {
+                Ok(handle) => handle,
+                Err(e) => return Err( HciSyncPacketConvertError::InvalidConnectionHandle(e) ),
+            };
+
+            let packet_status_flag = SyncPacketStatus::from_shifted_val( first_2_bytes );
+
+            let data_length = packet[2] as usize;
+
+            match packet.get(HEADER_SIZE..(HEADER_SIZE + data_length)) {
+                Some(payload) => Ok(
+                    HciSyncData {
+                        connection_handle,
+                        packet_status_flag,
+                        payload: payload.to_vec(),
+                    }
+                ),
+                None => Err( HciSyncPacketConvertError::PacketTooSmall ),
+            }
+
+        } else {
+            Err( HciSyncPacketConvertError::PacketTooSmall )
+        }
+    }
+}
+
 
 /// Trait for interfacing with the controller
 ///
@@ -407,9 +570,90 @@ pub trait HciAclDataInterface {
     ) -> Option<Result<alloc::vec::Vec<HciAclData>, Self::ReceiveAclDataError>>;
 }
 
+/// HCI synchronous data interface
+///
+/// This is the trait that must be implemented by the platform specific HCI structure to support
+/// synchronous (SCO and eSCO) data. The methods mirror the methods of [`HciAclDataInterface`],
+/// except that they operate on [`HciSyncData`].
+pub trait HciSyncDataInterface {
+    type SendSyncDataError: Debug + Display;
+    type ReceiveSyncDataError: Debug + Display;
+
+    /// Send synchronous data
+    ///
+    /// This will send synchronous data to the controller for sending to the connected bluetooth
+    /// device.
+    ///
+    /// The return value is the number of bytes of synchronous data payload + 1 ( due to added
+    /// packet indicator ) sent.
+    fn send_sync(
+        &self,
+        data: HciSyncData,
+    ) -> Result<usize, Self::SendSyncDataError>;
+
+    /// Register a handle for receiving synchronous data packets
+    ///
+    /// Synchronous data is sent by the controller at a regular interval for the entire time the
+    /// synchronous connection exists. Lower level implementations should utilize this function to
+    /// enable buffers for each connection handle.
+    fn start_sync_receiver(&self, handle: common::ConnectionHandle);
+
+    /// Unregister a handle for receiving synchronous data packets
+    ///
+    /// Once this is called any buffers can be dropped that are associated with the given handle.
+    fn stop_sync_receiver(&self, handle: &common::ConnectionHandle);
+
+    /// Receive synchronous data
+    ///
+    /// Receive data from the controller for the given connection handle. If no data is available
+    /// to be received then None will be returned and the provided waker will be used when the next
+    /// synchronous data is received.
+    fn receive_sync(
+        &self,
+        handle: &common::ConnectionHandle,
+        waker: &Waker,
+    ) -> Option<Result<alloc::vec::Vec<HciSyncData>, Self::ReceiveSyncDataError>>;
+}
+
+/// The type of a HCI packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HciPacketType {
+    Command,
+    AclData,
+    SyncData,
+    Event,
+}
+
+/// A listener for the packets received from the controller
+pub trait ReceivedPacketListener: Send + Sync {
+    /// Called with every packet received from the controller
+    ///
+    /// The `packet` is the entire HCI packet without any transport specific information (such as
+    /// the HCI packet indicator). This is called before the packet is processed by the interface,
+    /// so it is called even for packets that are never given to the host.
+    fn on_packet(&self, packet_type: HciPacketType, packet: &[u8]);
+}
+
+/// An interface that can report every packet received from the controller
+///
+/// The trait methods [`receive_event`](HostControllerInterface::receive_event) and
+/// [`receive`](HciAclDataInterface::receive) only return packets that are converted into their
+/// useable form and waited on by the host. This trait is for things that need the packets
+/// exactly as they were received, such as capturing the HCI traffic.
+pub trait ReceivedPacketSource {
+    /// Set the listener for received packets
+    ///
+    /// Any previously set listener is replaced, a listener can be removed by setting it to None.
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>);
+}
+
 enum SendCommandError<I> where I: HostControllerInterface {
     Send(<I as HostControllerInterface>::SendCommandError),
     Recv(<I as HostControllerInterface>::ReceiveEventError),
+    /// The timer of the `HostInterface` expired before the response was received
+    TimedOut,
+    /// The command is not within the commands supported by the controller
+    NotSupported(opcodes::HCICommand),
 }
 
 impl<I> Debug for SendCommandError<I> where I: HostControllerInterface {
@@ -417,6 +661,8 @@ impl<I> Debug for SendCommandError<I> where I: HostControllerInterface {
         match self {
             SendCommandError::Send(err) => Debug::fmt(err, f),
             SendCommandError::Recv(err) => Debug::fmt(err, f),
+            SendCommandError::TimedOut => f.write_str("TimedOut"),
+            SendCommandError::NotSupported(command) => write!(f, "NotSupported({:?})", command),
         }
     }
 }
@@ -426,14 +672,26 @@ impl<I> Display for SendCommandError<I> where I: HostControllerInterface {
         match self {
             SendCommandError::Send(err) => Display::fmt(err, f),
             SendCommandError::Recv(err) => Display::fmt(err, f),
+            SendCommandError::TimedOut => f.write_str("Timed out waiting for the response to the command"),
+            SendCommandError::NotSupported(command) =>
+                write!(f, "The command {:?} is not supported by the controller", command),
         }
     }
 }
 
-struct CommandFutureReturn<'a, I, CD, P>
+impl<I> CommandError for SendCommandError<I> where I: HostControllerInterface {
+    fn kind(&self) -> CommandErrorKind {
+        match self {
+            SendCommandError::TimedOut => CommandErrorKind::TimedOut,
+            SendCommandError::NotSupported(command) => CommandErrorKind::NotSupported(*command),
+            _ => CommandErrorKind::Other,
+        }
+    }
+}
+
+struct CommandFutureReturn<'a, I, CD>
 where I: HostControllerInterface,
       CD: CommandParameter,
-      P: EventMatcher + Send + Sync + 'static,
 {
     interface: &'a I,
     /// Parameter data sent with the command packet
@@ -442,36 +700,281 @@ where I: HostControllerInterface,
     /// will be sent to the controller if the `command_data` isn't set to Some.
     command_data: Option<CD>,
     event: events::Events,
-    matcher: Pin<Arc<P>>,
+    /// The other event the response can be received as
+    alternate_event: Option<events::Events>,
+    matcher: Pin<Arc<flow_control::CommandMatcher>>,
     timeout: Option<Duration>,
+    command_flow: &'a SpinLock<flow_control::CommandFlowControl>,
+    /// The ticket of the command while it is queued for a command credit
+    ticket: Option<usize>,
+    /// Set to true while the command is sent and the response has not been received
+    awaiting_response: bool,
+    timer: Option<Arc<dyn Timer>>,
+    /// The timeout for the response, this is created once the command is sent
+    response_timeout: Option<Timeout>,
+    /// Set to false when the command is known to not be supported by the controller
+    supported: bool,
 }
 
-impl<'a, I, CD, P> CommandFutureReturn<'a, I, CD, P>
+impl<'a, I, CD> CommandFutureReturn<'a, I, CD>
 where I: HostControllerInterface,
       CD: CommandParameter + Unpin,
-      P: EventMatcher + Send + Sync + 'static,
 {
 
     /// This is just called within an implemenation of future created by the macro
     /// `[impl_returned_future]`(../index.html#impl_returned_future)
     fn fut_poll(&mut self, cx: &mut core::task::Context) -> Poll<Result<events::EventsData, SendCommandError<I>>> {
 
+        if self.command_data.is_some() && !self.supported {
+            log::debug!("Command {:?} is not supported by the controller", CD::COMMAND);
+
+            self.command_data.take();
+
+            return Poll::Ready(Err(SendCommandError::NotSupported(CD::COMMAND)))
+        }
+
+        if self.command_data.is_some() {
+            self.receive_abandoned(cx);
+
+            if !self.acquire(cx) {
+                log::trace!("Command {:?} is waiting for a command credit", CD::COMMAND);
+
+                return Poll::Pending
+            }
+        }
+
         if let Some(ref data) = self.command_data {
             match self.interface.send_command(data, cx.waker().clone() ) {
-                Err(e) => return Poll::Ready(Err(SendCommandError::Send(e))),
+                Err(e) => {
+                    self.release(None);
+
+                    self.dequeue();
+
+                    return Poll::Ready(Err(SendCommandError::Send(e)))
+                },
                 // False means the command wasn't sent
-                Ok(false) => return Poll::Pending,
-                Ok(true) => { self.command_data.take(); },
+                Ok(false) => {
+                    self.command_flow.lock().unsent(self.matcher.opcode);
+
+                    return Poll::Pending
+                },
+                Ok(true) => {
+                    self.command_data.take();
+
+                    if let Some(ticket) = self.ticket.take() {
+                        self.command_flow.lock().sent(ticket);
+                    }
+
+                    self.awaiting_response = true;
+                },
             }
         }
 
-        match self.interface.receive_event(self.event, cx.waker(), self.matcher.clone(), self.timeout) {
-            None => Poll::Pending,
-            Some(result) => Poll::Ready(result.map_err(|e| SendCommandError::Recv(e)))
+        match self.receive_response(cx) {
+            None => self.poll_response_timeout(cx),
+            Some(result) => {
+                self.awaiting_response = false;
+
+                self.release(Self::response_credits(&result));
+
+                Poll::Ready(result.map_err(|e| SendCommandError::Recv(e)))
+            }
+        }
+    }
+
+    /// Receive the response as either the event or the alternate event of the command
+    ///
+    /// When the response is received as one of the two events, the matcher no longer matches any
+    /// event and the expectation of the other event is kept by the flow control until the
+    /// interface times it out.
+    fn receive_response(&mut self, cx: &mut core::task::Context)
+    -> Option<Result<events::EventsData, I::ReceiveEventError>>
+    {
+        let events = core::iter::once(self.event).chain(self.alternate_event);
+
+        for event in events {
+            let result = self.interface.receive_event(event, cx.waker(), self.matcher.clone(), self.timeout);
+
+            if result.is_some() {
+                if let Some(other) = flow_control::Abandoned::other_event(
+                    event,
+                    self.event,
+                    self.alternate_event,
+                    &self.matcher,
+                    self.timeout,
+                    &mut self.response_timeout,
+                ) {
+                    self.command_flow.lock().abandon(other);
+                }
+
+                return result
+            }
+        }
+
+        None
+    }
+
+    /// Acquire a credit to send the command
+    ///
+    /// When there are no credits, the *Command Complete* event with the opcode 0x0000 is checked
+    /// for as it may give more credits.
+    fn acquire(&mut self, cx: &mut core::task::Context) -> bool {
+        let opcode = self.matcher.opcode;
+
+        if self.command_flow.lock().acquire(&mut self.ticket, opcode, cx.waker()) {
+            return true
+        }
+
+        let nop_matcher = self.command_flow.lock().get_nop_matcher();
+
+        match self.interface.receive_event(events::Events::CommandComplete, cx.waker(), nop_matcher, None) {
+            Some(Ok(events::EventsData::CommandComplete(data))) => {
+                log::trace!("Controller set the number of command credits to {}", data.number_of_hci_command_packets);
+
+                let wakers = self.command_flow.lock().update_credits(data.number_of_hci_command_packets);
+
+                wakers.into_iter().for_each(|waker| waker.wake());
+
+                self.command_flow.lock().acquire(&mut self.ticket, opcode, cx.waker())
+            },
+            Some(Ok(_)) => false,
+            Some(Err(e)) => {
+                log::error!("Failed to receive the number of command credits: {:?}", e);
+
+                false
+            },
+            None => false,
+        }
+    }
+
+    /// Receive the responses to the commands that were dropped while waiting for them
+    ///
+    /// The opcode of a dropped command is released once its response is received or the response
+    /// times out.
+    fn receive_abandoned(&self, cx: &mut core::task::Context) {
+        let abandoned = self.command_flow.lock().take_abandoned();
+
+        for mut command in abandoned {
+            let mut received = None;
+
+            for event in core::iter::once(command.event).chain(command.alternate_event) {
+                let result = self.interface.receive_event(event, cx.waker(), command.matcher.clone(), command.timeout);
+
+                if let Some(result) = result {
+                    received = Some((event, result));
+                    break
+                }
+            }
+
+            let credits = match received {
+                Some((event, result)) => {
+                    if let Some(other) = flow_control::Abandoned::other_event(
+                        event,
+                        command.event,
+                        command.alternate_event,
+                        &command.matcher,
+                        command.timeout,
+                        &mut command.response_timeout,
+                    ) {
+                        self.command_flow.lock().abandon(other);
+                    }
+
+                    Some(Self::response_credits(&result))
+                },
+                None => match command.response_timeout.as_mut().map(|timeout| timeout.as_mut().poll(cx)) {
+                    Some(Poll::Ready(())) => Some(None),
+                    _ => None,
+                },
+            };
+
+            match credits {
+                Some(credits) if command.reserved => {
+                    let wakers = self.command_flow.lock().release(command.matcher.opcode, credits);
+
+                    wakers.into_iter().for_each(|waker| waker.wake());
+                },
+                Some(_) => (),
+                None => self.command_flow.lock().abandon(command),
+            }
+        }
+    }
+
+    /// Get the Num_HCI_Command_Packets field of the response to a command
+    fn response_credits(result: &Result<events::EventsData, I::ReceiveEventError>) -> Option<u8> {
+        match result {
+            Ok(events::EventsData::CommandComplete(ref data)) => Some(data.number_of_hci_command_packets),
+            Ok(events::EventsData::CommandStatus(ref data)) => Some(data.number_of_hci_command_packets),
+            _ => None,
+        }
+    }
+
+    /// Poll the timeout for the response
+    ///
+    /// This is always pending if the host interface does not have a timer or there is no timeout
+    /// for the command.
+    fn poll_response_timeout(&mut self, cx: &mut core::task::Context) -> Poll<Result<events::EventsData, SendCommandError<I>>> {
+        if let (Some(timer), Some(duration)) = (self.timer.as_ref(), self.timeout) {
+            let timeout = self.response_timeout.get_or_insert_with(|| timer.timeout(duration));
+
+            if timeout.as_mut().poll(cx).is_ready() {
+                log::debug!("Command {:?} timed out", CD::COMMAND);
+
+                self.awaiting_response = false;
+
+                self.release(None);
+
+                return Poll::Ready(Err(SendCommandError::TimedOut))
+            }
+        }
+
+        Poll::Pending
+    }
+
+    fn release(&self, credits: Option<u8>) {
+        let wakers = self.command_flow.lock().release(self.matcher.opcode, credits);
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+
+    fn dequeue(&mut self) {
+        if let Some(ticket) = self.ticket.take() {
+            let wakers = self.command_flow.lock().dequeue(ticket);
+
+            wakers.into_iter().for_each(|waker| waker.wake());
         }
     }
 }
 
+impl<'a, I, CD> Drop for CommandFutureReturn<'a, I, CD>
+where I: HostControllerInterface,
+      CD: CommandParameter,
+{
+    /// Give up the place in the queue of a command that will not be sent
+    ///
+    /// A command that was sent keeps its opcode reserved until its response is received by one of
+    /// the other commands.
+    fn drop(&mut self) {
+        let wakers = if let Some(ticket) = self.ticket {
+            self.command_flow.lock().dequeue(ticket)
+        } else if self.awaiting_response {
+            self.command_flow.lock().abandon(flow_control::Abandoned {
+                event: self.event,
+                alternate_event: self.alternate_event,
+                matcher: self.matcher.clone(),
+                timeout: self.timeout,
+                response_timeout: self.response_timeout.take(),
+                reserved: true,
+            });
+
+            Vec::new()
+        } else {
+            Vec::new()
+        };
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+}
+
 struct EventReturnFuture<'a, I, P>
 where I: HostControllerInterface,
       P: EventMatcher + Sync + Send + 'static
@@ -496,14 +999,202 @@ P: EventMatcher + Send + Sync + 'static
     }
 }
 
+/// A command that is only known by its opcode
+struct RawCommand {
+    opcode: u16,
+    parameter: Vec<u8>,
+}
+
+impl CommandParameter for RawCommand {
+    type Parameter = ();
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::Raw;
+    fn get_parameter(&self) -> Self::Parameter {}
+
+    fn as_command_packet(&self) -> alloc::boxed::Box<[u8]> {
+        let mut packet = Vec::with_capacity(self.parameter.len() + 3);
+
+        packet.extend_from_slice(&self.opcode.to_le_bytes());
+
+        packet.push(self.parameter.len() as u8);
+
+        packet.extend_from_slice(&self.parameter);
+
+        packet.into_boxed_slice()
+    }
+}
+
+/// The response to a command sent with
+/// [`send_raw_command`](HostInterface::send_raw_command)
+#[derive(Debug, Clone)]
+pub enum RawCommandResponse {
+    /// The return parameter of a *Command Complete* event
+    Complete(Vec<u8>),
+    /// The status of a *Command Status* event
+    Status(error::Error),
+}
+
+struct RawCommandFuture<'a, I>( CommandFutureReturn<'a, I, RawCommand> )
+where I: HostControllerInterface;
+
+impl<'a, I> Future for RawCommandFuture<'a, I>
+where I: HostControllerInterface,
+{
+    type Output = Result<RawCommandResponse, OutputErr<SendCommandError<I>, &'static str>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context) -> Poll<Self::Output> {
+        self.get_mut().0.fut_poll(cx).map(|result| match result {
+            Ok(events::EventsData::CommandComplete(data)) =>
+                Ok(RawCommandResponse::Complete(data.raw_data.to_vec())),
+            Ok(events::EventsData::CommandStatus(data)) =>
+                Ok(RawCommandResponse::Status(data.status)),
+            Ok(event) =>
+                Err(OutputErr::ReceivedIncorrectEvent(event.get_enum_name())),
+            Err(reason) =>
+                Err(OutputErr::TargetSpecificErr(reason)),
+        })
+    }
+}
+
+/// Future for sending commands that the controller does not respond to
+///
+/// The commands are sent in order without waiting for a command credit, so this must only be
+/// used for the commands that the host is allowed to send regardless of the Num_HCI_Command_Packets
+/// (such as *Host Number Of Completed Packets*).
+struct CommandSendFuture<'a, I, CD> {
+    interface: &'a I,
+    commands: VecDeque<CD>,
+}
+
+impl<'a, I, CD> Future for CommandSendFuture<'a, I, CD>
+where I: HostControllerInterface,
+      CD: CommandParameter + Unpin,
+{
+    type Output = Result<(), SendCommandError<I>>;
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        while let Some(command) = this.commands.front() {
+            match this.interface.send_command(command, cx.waker().clone()) {
+                Err(e) => return Poll::Ready(Err(SendCommandError::Send(e))),
+                Ok(false) => return Poll::Pending,
+                Ok(true) => { this.commands.pop_front(); },
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The error of a [`StatusThenEventFuture`]
+#[derive(Debug)]
+enum StatusThenEventError<S, R> {
+    /// The error of the command, this includes an error status within the *Command Status* event
+    Status(S),
+    /// The error of receiving the event that completes the command
+    Receive(R),
+}
+
+impl<S: Display, R: Display> Display for StatusThenEventError<S, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            StatusThenEventError::Status(e) => Display::fmt(e, f),
+            StatusThenEventError::Receive(e) => Display::fmt(e, f),
+        }
+    }
+}
+
+impl<S: CommandError, R: Display + Debug> CommandError for StatusThenEventError<S, R> {
+    fn kind(&self) -> CommandErrorKind {
+        match self {
+            StatusThenEventError::Status(e) => e.kind(),
+            StatusThenEventError::Receive(_) => CommandErrorKind::Other,
+        }
+    }
+}
+
+/// Future for a command that is responded to with a *Command Status* event and is completed by
+/// a later event (usually a LE Meta event)
+///
+/// The `status` future is polled to completion before the `event` future is polled. The data of
+/// the completing event is taken out of it with `get_data`, the matcher of the `event` future must
+/// only match events that `get_data` returns `Some` for.
+struct StatusThenEventFuture<S, E, D> {
+    status: Option<S>,
+    event: E,
+    get_data: fn(events::EventsData) -> Option<D>,
+}
+
+impl<S, E, D> StatusThenEventFuture<S, E, D> {
+    fn new(status: S, event: E, get_data: fn(events::EventsData) -> Option<D>) -> Self {
+        StatusThenEventFuture { status: Some(status), event, get_data }
+    }
+}
+
+impl<S, E, D, SE, EE> Future for StatusThenEventFuture<S, E, D>
+where S: Future<Output=Result<(), SE>> + Unpin,
+      E: Future<Output=Result<events::EventsData, EE>> + Unpin,
+{
+    type Output = Result<D, StatusThenEventError<SE, EE>>;
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        if let Some(status) = this.status.as_mut() {
+            match Pin::new(status).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(StatusThenEventError::Status(e))),
+                Poll::Ready(Ok(())) => this.status = None,
+            }
+        }
+
+        match Pin::new(&mut this.event).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => Poll::Ready(Err(StatusThenEventError::Receive(e))),
+            Poll::Ready(Ok(event_data)) => match (this.get_data)(event_data) {
+                Some(data) => Poll::Ready(Ok(data)),
+                None => unreachable!("Event matcher only matches the event that completes the command"),
+            },
+        }
+    }
+}
+
 /// The host interface
 ///
 /// This is used by the host to interact with the interface between itself and the Bluetooth
 /// Controller.
+///
+/// # Command Flow Control
+/// Commands are sent within the limit of the Num_HCI_Command_Packets returned by the controller
+/// with the response to every command. Commands that cannot be sent yet are queued by the host
+/// interface, so any number of commands can be awaited at the same time. A command is also queued
+/// while another command with the same opcode is waiting for its response, as the response is
+/// routed to the command by its opcode. Clones of a `HostInterface` share the same command queue.
+///
+/// ACL data sent through a connection channel is flow controlled by the number of data buffers of
+/// the controller, see [`new_le_acl_connection_channel`](HostInterface::new_le_acl_connection_channel).
+///
+/// # Event Streams
+/// The futures returned by `wait_for_event` only receive a single event, any event received
+/// before the future is created (or after it is complete) may never be seen by the host. For
+/// events that are sent by the controller at any time, such as disconnections or advertising
+/// reports, a stream of every received event can be created with
+/// [`subscribe`](HostInterface::subscribe).
+///
+/// # Timeouts
+/// The timeout of a command is passed to the interface, so whether or not a command times out is
+/// up to the implementation of `HostControllerInterface`. For an interface without timeouts, a
+/// [`Timer`] can be set with [`set_timer`](HostInterface::set_timer). The host interface then
+/// times out commands on its own.
 #[derive(Clone)]
 pub struct HostInterface<I>
 {
-    interface: I
+    interface: I,
+    command_flow: Arc<SpinLock<flow_control::CommandFlowControl>>,
+    acl_flow: Arc<flow_control::AclFlow>,
+    subscriptions: Arc<event_stream::Subscriptions>,
+    timer: Arc<SpinLock<Option<Arc<dyn Timer>>>>,
+    supported_commands: Arc<SpinLock<Option<Vec<info_params::read_local_supported_commands::SupportedCommands>>>>,
 }
 
 impl<I> AsRef<I> for HostInterface<I> {
@@ -520,19 +1211,55 @@ impl<I> AsMut<I> for HostInterface<I> {
 
 impl<I> HostInterface<I> {
     pub fn into_inner(self) -> I { self.interface }
+
+    /// Set the commands supported by the controller
+    ///
+    /// Once the supported commands are set, a command that is not one of them fails immediately
+    /// with the error kind [`NotSupported`](CommandErrorKind::NotSupported) instead of being sent
+    /// to the controller. Setting this to `None` turns off this check (this is the default). The
+    /// supported commands are shared with every clone of this host interface.
+    ///
+    /// The supported commands are set by [`controller_info::init`], they only need to be set
+    /// directly when the controller is initialized without it.
+    pub fn set_supported_commands(
+        &self,
+        commands: Option<&[info_params::read_local_supported_commands::SupportedCommands]>
+    ) {
+        *self.supported_commands.lock() = commands.map(|c| c.to_vec());
+    }
+
+    /// Check if a command is supported by the controller
+    ///
+    /// A command is assumed to be supported if the supported commands are not set or the command
+    /// has no bit within the supported commands.
+    fn is_command_supported(&self, command: opcodes::HCICommand) -> bool {
+        use info_params::read_local_supported_commands::SupportedCommands;
+
+        match (SupportedCommands::try_from_command(command), &*self.supported_commands.lock()) {
+            (Some(sc), Some(supported)) => supported.contains(&sc),
+            _ => true,
+        }
+    }
 }
 
 impl<I> From<I> for HostInterface<I>
 {
     fn from(interface: I) -> Self {
-        HostInterface { interface }
+        HostInterface {
+            interface,
+            command_flow: Arc::new(SpinLock::new(flow_control::CommandFlowControl::new())),
+            acl_flow: Arc::new(flow_control::AclFlow::new()),
+            subscriptions: Arc::new(event_stream::Subscriptions::new()),
+            timer: Arc::new(SpinLock::new(None)),
+            supported_commands: Arc::new(SpinLock::new(None)),
+        }
     }
 }
 
 impl<T> ::core::default::Default for HostInterface<T> where T: Default {
 
     fn default() -> Self {
-        HostInterface { interface: T::default() }
+        HostInterface::from(T::default())
     }
 }
 
@@ -546,6 +1273,24 @@ where I: HostControllerInterface
         &self.interface
     }
 
+    /// Set the timer for timeouts
+    ///
+    /// Once the timer is set, a command fails when its response is not received within the
+    /// timeout of the command. The timer is also used by other parts of bo-tie that need a
+    /// timeout for the events received by this host interface, such as the futures for encrypting
+    /// a connection created by the Security Manager.
+    ///
+    /// The timer is shared with every clone of this host interface. Setting it only affects the
+    /// commands sent after it is set.
+    pub fn set_timer<T>(&self, timer: T) where T: Timer + 'static {
+        *self.timer.lock() = Some(Arc::new(timer));
+    }
+
+    /// Get the timer for timeouts
+    pub fn get_timer(&self) -> Option<Arc<dyn Timer>> {
+        self.timer.lock().clone()
+    }
+
     /// Send a command to the controller
     ///
     /// The command data will be used in the command packet to determine what HCI command is sent
@@ -556,52 +1301,94 @@ where I: HostControllerInterface
     /// A future is returned for waiting on the event generated from the controller in *direct*
     /// response to the sent command.
     fn send_command<'a, CD, D>( &'a self, cmd_data: CD, event: events::Events, timeout: D )
-    -> CommandFutureReturn<'a, I, CD, impl EventMatcher + Send + Sync + 'static>
+    -> CommandFutureReturn<'a, I, CD>
     where CD: CommandParameter + Unpin + 'static,
           D: Into<Option<Duration>>,
     {
-        let cmd_matcher = | ed: &events::EventsData | {
-
-            fn match_opcode<CD: CommandParameter>(opcode: Option<u16>) -> bool {
-                match opcode {
-                    Some(opcode) => {
-                        use core::convert::TryFrom;
-
-                        let expected_op_code =
-                            opcodes::HCICommand::try_from(<CD as CommandParameter>::COMMAND)
-                            .unwrap();
-
-                        let recv_oc_code = opcodes::HCICommand::try_from(
-                            opcodes::OpCodePair::from_opcode(opcode)
-                        );
-
-                        match recv_oc_code {
-                            Ok(code) => {
-                                expected_op_code == code
-                            },
-                            Err(reason) => {
-                                log::error!("{}", reason);
-                                false
-                            }
-                        }
-                    }
-                    None => false,
-                }
-            }
+        let opcode = CD::COMMAND.as_opcode_pair().as_opcode();
 
-            match ed {
-                events::EventsData::CommandComplete(data) => match_opcode::<CD>(data.command_opcode),
-                events::EventsData::CommandStatus(data)   => match_opcode::<CD>(data.command_opcode),
-                _ => false
-            }
-        };
+        self.send_command_with_opcode(cmd_data, opcode, event, timeout)
+    }
 
+    /// Send a command to the controller with the given opcode
+    ///
+    /// This is the same as `send_command` except the response to the command is matched with
+    /// `opcode` instead of the opcode of `CD::COMMAND`. It is used for commands whose opcode is not
+    /// known until they are created.
+    fn send_command_with_opcode<'a, CD, D>(
+        &'a self,
+        cmd_data: CD,
+        opcode: u16,
+        event: events::Events,
+        timeout: D
+    ) -> CommandFutureReturn<'a, I, CD>
+    where CD: CommandParameter + Unpin + 'static,
+          D: Into<Option<Duration>>,
+    {
         CommandFutureReturn {
             interface: &self.interface,
             command_data: Some(cmd_data),
             event,
-            matcher: Arc::pin(cmd_matcher),
+            alternate_event: None,
+            matcher: Arc::pin(flow_control::CommandMatcher::new(opcode)),
             timeout: timeout.into(),
+            command_flow: &self.command_flow,
+            ticket: None,
+            awaiting_response: false,
+            timer: self.get_timer(),
+            response_timeout: None,
+            supported: self.is_command_supported(CD::COMMAND),
+        }
+    }
+
+    /// Send a command by its opcode
+    ///
+    /// This is for sending a command that does not have a module within this library. The
+    /// `opcode` is the opcode of the command and `parameter` is the parameter of the command packet
+    /// in the format defined by the specification for the command. The command is flow controlled
+    /// the same way as all the other commands, and the `timeout` is the timeout for the response.
+    ///
+    /// The controller responds to the command with either a *Command Complete* or a *Command
+    /// Status* event, and the output of the returned future is the return parameter of a *Command
+    /// Complete* event or the status of a *Command Status* event, whichever is received first.
+    ///
+    /// # Error
+    /// An error is returned without sending the command if `parameter` is longer than 255 bytes
+    pub fn send_raw_command<'a>(
+        &'a self,
+        opcode: u16,
+        parameter: &[u8],
+        timeout: Duration,
+    ) -> impl Future<Output=Result<RawCommandResponse, impl CommandError>> + 'a
+    {
+        let too_long = parameter.len() > <u8>::MAX as usize;
+
+        let raw_command = RawCommand { opcode, parameter: parameter.to_vec() };
+
+        async move {
+            if too_long {
+                return Err(OutputErr::CommandDataConversionError("The parameter of a command is longer than 255 bytes"))
+            }
+
+            let mut future = self.send_command_with_opcode(raw_command, opcode, events::Events::CommandComplete, timeout);
+
+            future.alternate_event = Some(events::Events::CommandStatus);
+
+            RawCommandFuture( future ).await
+        }
+    }
+
+    /// Send commands that the controller does not respond to
+    ///
+    /// The commands are not flow controlled and no event is waited on for them. The returned
+    /// future is ready once every command was given to the interface.
+    fn send_command_without_response<'a, CD, C>(&'a self, commands: C) -> CommandSendFuture<'a, I, CD>
+    where CD: CommandParameter + Unpin,
+          C: IntoIterator<Item=CD>,
+    {
+        CommandSendFuture {
+            interface: &self.interface,
+            commands: commands.into_iter().collect(),
         }
     }
 
@@ -667,70 +1454,297 @@ where I: HostControllerInterface
 }
 
 
-struct LeAclHciChannel<'a, I> where I: HciAclDataInterface {
+impl<I> HostInterface<I>
+where I: ReceivedPacketSource
+{
+    /// Subscribe to an event
+    ///
+    /// The returned stream receives every `event` sent by the controller until the stream is
+    /// dropped. At most `capacity` received events are buffered by the stream, see
+    /// [`EventStream`] for what happens when the buffer is full.
+    ///
+    /// Events are received by subscriptions through the listener for received packets of the
+    /// interface. The host interface becomes the owner of this listener the first time it
+    /// subscribes to an event, replacing any listener that was set directly on the interface. A
+    /// listener for received packets is instead set on the host interface (it implements
+    /// [`ReceivedPacketSource`]), it is then passed every received packet.
+    pub fn subscribe(&self, event: events::Events, capacity: usize) -> EventStream {
+        fn match_all(_: &events::EventsData) -> bool { true }
+
+        self.subscribe_with_matcher(event, capacity, match_all)
+    }
+
+    /// Subscribe to the events that match `matcher`
+    ///
+    /// This is the same as [`subscribe`](HostInterface::subscribe) except the stream only receives
+    /// the events for which `matcher` returns true.
+    pub fn subscribe_with_matcher<P>(&self, event: events::Events, capacity: usize, matcher: P) -> EventStream
+    where P: EventMatcher + 'static
+    {
+        self.subscriptions.install(&self.interface);
+
+        EventStream::new(self.subscriptions.clone(), event, matcher, capacity, false)
+    }
+
+    /// Subscribe to the latest event that matches `matcher`
+    ///
+    /// This is for events that replace the state set by the events before them. The returned
+    /// stream buffers a single event, when another event is received before it is taken from the
+    /// stream it replaces the buffered event.
+    pub(crate) fn subscribe_latest<P>(&self, event: events::Events, matcher: P) -> EventStream
+    where P: EventMatcher + 'static
+    {
+        self.subscriptions.install(&self.interface);
+
+        EventStream::new(self.subscriptions.clone(), event, matcher, 1, true)
+    }
+}
+
+/// The listener is kept by the host interface
+///
+/// The subscriptions of the host interface are set as the listener of the interface, and they
+/// pass every received packet on to the listener set here.
+impl<I> ReceivedPacketSource for HostInterface<I>
+where I: ReceivedPacketSource
+{
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>) {
+        self.subscriptions.set_listener(listener);
+
+        self.subscriptions.install(&self.interface);
+    }
+}
+
+/// The number of *LE Data Length Change* events buffered by a connection channel
+const DATA_LENGTH_EVENTS_CAPACITY: usize = 4;
+
+/// Matcher for the *LE Data Length Change* events of a connection
+struct DataLengthChangeMatcher(common::ConnectionHandle);
+
+impl EventMatcher for DataLengthChangeMatcher {
+    fn match_event(&self, event_data: &events::EventsData) -> bool {
+        match event_data {
+            events::EventsData::LEMeta(events::LEMetaData::DataLengthChange(data)) =>
+                data.connection_handle == self.0,
+            _ => false,
+        }
+    }
+}
+
+/// The source of the *LE Data Length Change* events of a connection channel
+enum DataLengthEvents {
+    /// The events are taken from the interface
+    Interface(Pin<Arc<DataLengthChangeMatcher>>),
+    /// The events are received through a subscription
+    Subscription(EventStream),
+}
+
+struct LeAclHciChannel<'a, I> where I: HostControllerInterface + HciAclDataInterface {
     handle: common::ConnectionHandle,
-    hi: &'a HostInterface<I>
+    hi: &'a HostInterface<I>,
+    /// The maximum number of payload octets of a Link Layer data PDU sent over the connection
+    max_tx_octets: core::sync::atomic::AtomicUsize,
+    data_length_events: DataLengthEvents,
+    /// The error of the last failed attempt to send the queued data
+    send_error: SpinLock<Option<I::SendAclDataError>>,
 }
 
-impl<'a, I> LeAclHciChannel<'a, I> where I: HciAclDataInterface {
+impl<'a, I> LeAclHciChannel<'a, I>
+where I: HostControllerInterface + HciAclDataInterface
+{
 
-    fn new(hi: &'a HostInterface<I>, handle: common::ConnectionHandle) -> Self {
+    fn new(hi: &'a HostInterface<I>, handle: common::ConnectionHandle, data_length_events: DataLengthEvents) -> Self {
 
         hi.interface.start_receiver(handle);
 
-        LeAclHciChannel { handle, hi }
+        // The handle is only reused by the controller once the connection it was assigned to is
+        // disconnected, so the buffers still counted for that connection were freed.
+        hi.acl_flow.control.lock().disconnected(handle);
+
+        LeAclHciChannel {
+            handle,
+            hi,
+            max_tx_octets: HciAclData::MINIMUM_LE_U_FRAGMENT_START_SIZE.into(),
+            data_length_events,
+            send_error: SpinLock::new(None),
+        }
+    }
+
+    /// Get the maximum number of payload octets of a Link Layer data PDU
+    ///
+    /// The value is updated from the *LE Data Length Change* events received for the connection
+    /// since the channel was created.
+    fn get_max_tx_octets(&self) -> usize {
+        use core::sync::atomic::Ordering;
+
+        let update = |event_data| {
+            if let events::EventsData::LEMeta(events::LEMetaData::DataLengthChange(data)) = event_data {
+                log::debug!("Maximum transmit octets of connection {} changed to {}",
+                    self.handle, data.max_tx_octets.octets);
+
+                self.max_tx_octets.store(data.max_tx_octets.octets.into(), Ordering::Relaxed)
+            }
+        };
+
+        match self.data_length_events {
+            DataLengthEvents::Interface(ref matcher) => {
+                let waker = Waker::from(self.hi.acl_flow.clone());
+
+                while let Some(result) = self.hi.interface.receive_event(
+                    events::Events::LEMeta(events::LEMeta::DataLengthChange),
+                    &waker,
+                    matcher.clone(),
+                    None)
+                {
+                    match result {
+                        Ok(event_data) => update(event_data),
+                        Err(e) => {
+                            log::error!("Failed to receive LE Data Length Change event: {}", e);
+                            break
+                        }
+                    }
+                }
+            },
+            DataLengthEvents::Subscription(ref stream) => while let Some(entry) = stream.try_next_event() {
+                match entry {
+                    Ok(event_data) => update(event_data),
+                    Err(overflow) => log::error!("LE Data Length Change events of connection {}: {}", self.handle, overflow),
+                }
+            },
+        }
+
+        self.max_tx_octets.load(Ordering::Relaxed)
+    }
+
+    /// Send the queued ACL data to the controller
+    ///
+    /// Before sending, the data buffers freed by the controller are returned from any received
+    /// *Number Of Completed Packets* and *Disconnection Complete* events. If the interface fails to send a packet, the packet is
+    /// dropped and the error is kept for [`poll_flush`](crate::l2cap::ConnectionChannel::poll_flush).
+    fn process_queue(&self) {
+        use core::sync::atomic::Ordering;
+
+        let acl_flow = &self.hi.acl_flow;
+
+        acl_flow.receive_completed(&self.hi.interface);
+
+        // Only one channel sends the queued data at a time so the packets are given to the
+        // interface in the order they were queued. Packets queued by another channel while this
+        // channel is sending are also sent by this channel.
+        while !acl_flow.sending.swap(true, Ordering::Acquire) {
+            let result = self.send_queued();
+
+            acl_flow.sending.store(false, Ordering::Release);
+
+            if let Err(e) = result {
+                log::error!("Failed to send ACL data: {}", e);
+
+                *self.send_error.lock() = Some(e);
+
+                break
+            }
+
+            if !acl_flow.control.lock().can_send() {
+                break
+            }
+        }
+    }
+
+    /// Send queued packets until the queue is empty or the controller has no free buffers
+    ///
+    /// The flow control lock is not held while a packet is sent by the interface.
+    fn send_queued(&self) -> Result<(), I::SendAclDataError> {
+        loop {
+            let packet = match self.hi.acl_flow.control.lock().next() {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+
+            let handle = *packet.get_handle();
+
+            if let Err(e) = self.hi.interface.send(packet) {
+                self.hi.acl_flow.control.lock().unsent(handle);
+
+                return Err(e)
+            }
+        }
     }
 }
 
 impl<'a,I> crate::l2cap::ConnectionChannel for LeAclHciChannel<'a, I>
-where I: HciAclDataInterface
+where I: HostControllerInterface + HciAclDataInterface
 {
-    fn send<Pdu>(&self, data: Pdu ) where Pdu: Into<crate::l2cap::L2capPdu> {
+    type SendError = I::SendAclDataError;
+
+    fn send<Pdu>(&self, data: Pdu ) -> crate::l2cap::ConChanFutureTx<'_, Self> where Pdu: Into<crate::l2cap::L2capPdu> {
 
         let l2cap_pdu = data.into();
 
-        if let Some(mtu) = l2cap_pdu.get_mtu() {
+        let packet_len = self.hi.acl_flow.control.lock().get_packet_len();
+
+        let fragment_size = match l2cap_pdu.get_mtu() {
+            Some(mtu) => core::cmp::min(mtu, self.get_max_tx_octets()),
+            None => usize::MAX,
+        };
+
+        // The controller cannot take ACL data packets larger than its data buffers
+        let fragment_size = packet_len.map_or(fragment_size, |len| core::cmp::min(fragment_size, len));
+
+        let payload = l2cap_pdu.into_data();
+
+        if payload.len() > fragment_size {
             log::trace!("fragmenting l2cap data for transmission");
+        }
 
-            let payload = l2cap_pdu.into_data();
+        let mut control = self.hi.acl_flow.control.lock();
 
-            let fragment_size = core::cmp::min(mtu, HciAclData::MINIMUM_LE_U_FRAGMENT_START_SIZE);
+        if payload.is_empty() {
+            // An empty payload is still sent as a single start fragment
+            control.push(HciAclData::new(self.handle, AclPacketBoundary::FirstNonFlushable, AclBroadcastFlag::NoBroadcast, Vec::new()));
+        }
 
-            payload.chunks(fragment_size).enumerate().for_each(|(i, chunk)| {
-                let hci_acl_data = if i == 0 {
-                    log::trace!("Start packet");
-                    HciAclData::new(
-                        self.handle,
-                        AclPacketBoundary::FirstNonFlushable,
-                        AclBroadcastFlag::NoBroadcast,
-                        chunk.to_vec()
-                    )
-                } else {
-                    HciAclData::new(
-                        self.handle,
-                        AclPacketBoundary::ContinuingFragment,
-                        AclBroadcastFlag::NoBroadcast,
-                        chunk.to_vec()
-                    )
-                };
+        payload.chunks(fragment_size).enumerate().for_each(|(i, chunk)| {
+            let packet_boundary_flag = if i == 0 {
+                AclPacketBoundary::FirstNonFlushable
+            } else {
+                AclPacketBoundary::ContinuingFragment
+            };
 
-                self.hi.interface.send(hci_acl_data).expect("Failed to send hci acl data");
-            })
-        } else {
-            let hci_acl_data = HciAclData::new(
-                self.handle,
-                AclPacketBoundary::FirstNonFlushable,
-                AclBroadcastFlag::NoBroadcast,
-                l2cap_pdu.into_data()
-            );
+            control.push(HciAclData::new(self.handle, packet_boundary_flag, AclBroadcastFlag::NoBroadcast, chunk.to_vec()));
+        });
+
+        drop(control);
 
-            self.hi.interface.send(hci_acl_data).expect("Failed to send hci acl data");
+        self.process_queue();
+
+        crate::l2cap::ConChanFutureTx::new(self)
+    }
+
+    fn poll_flush(&self, waker: &core::task::Waker) -> Poll<Result<(), Self::SendError>> {
+        self.hi.acl_flow.add_waker(waker);
+
+        self.process_queue();
+
+        if let Some(e) = self.send_error.lock().take() {
+            Poll::Ready(Err(e))
+        } else if self.hi.acl_flow.control.lock().is_queued(self.handle) {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
         }
     }
 
     fn receive(&self, waker: &core::task::Waker) -> Option<alloc::vec::Vec<crate::l2cap::AclDataFragment>> {
         use crate::l2cap::AclDataFragment;
 
+        // Queued data is also sent whenever the channel is polled for received data
+        if let Poll::Ready(result) = self.poll_flush(waker) {
+            self.hi.acl_flow.remove_waker(waker);
+
+            if let Err(e) = result {
+                log::error!("Failed to send queued data: {}", e);
+            }
+        }
+
         self.hi.interface
         .receive(&self.handle, waker)
         .and_then( |received| match received {
@@ -748,21 +1762,101 @@ where I: HciAclDataInterface
 
 
 
-impl<'a,I> core::ops::Drop for LeAclHciChannel<'a,I> where I: HciAclDataInterface {
+impl<'a,I> core::ops::Drop for LeAclHciChannel<'a,I> where I: HostControllerInterface + HciAclDataInterface {
     fn drop(&mut self) {
+        // The connection may still be up, so the buffers of the packets already sent are only
+        // returned once they are completed or the connection is disconnected
+        self.hi.acl_flow.control.lock().drop_queued(self.handle);
+
         self.hi.interface.stop_receiver(&self.handle)
     }
 }
 
-impl<I> HostInterface<I> where I: HciAclDataInterface {
+impl<I> HostInterface<I> where I: HostControllerInterface + HciAclDataInterface {
 
     /// Make an ACL data connection channel
     ///
     /// Make a connection channel for the provided connection handle.
+    ///
+    /// # Flow Control
+    /// Once the buffer size of the controller is read with
+    /// [`read_buffer_size`](le::mandatory::read_buffer_size), ACL data is only sent to the
+    /// controller while it has a free data buffer. Data that cannot be sent yet is queued and
+    /// sent as the *Number Of Completed Packets* events are received. The future returned by
+    /// `send` of the channel stays pending until the data is given to the controller, and it
+    /// outputs the error of the interface if the data could not be sent. Any data still queued
+    /// when the channel is dropped is discarded, so the channel should only be dropped once the
+    /// connection is disconnected.
+    ///
+    /// The buffers of the packets that were not completed when the connection is disconnected are
+    /// freed by the controller. Channels made by this method take the *Number Of Completed
+    /// Packets* events from the interface and do not see the *Disconnection Complete* events, so
+    /// those buffers are only returned once a channel is made for the next connection that is
+    /// assigned the same connection handle. Use
+    /// [`new_subscribed_le_acl_connection_channel`](HostInterface::new_subscribed_le_acl_connection_channel)
+    /// if the interface is a [`ReceivedPacketSource`].
+    ///
+    /// # Data Length
+    /// L2CAP PDUs are fragmented to the maximum transmit payload of the Link Layer data PDUs of the
+    /// connection. This starts at 27 bytes and is changed by the *LE Data Length Change* events
+    /// for the connection (see [`data_packet_length_extension`](le::data_packet_length_extension)).
+    /// These events are taken from the interface by the channel when it sends data.
     pub fn new_le_acl_connection_channel<'a>(&'a self, connection_event_data: &events::LEConnectionCompleteData)
         -> impl crate::l2cap::ConnectionChannel + 'a
     {
-        LeAclHciChannel::new(self, connection_event_data.connection_handle.clone())
+        let handle = connection_event_data.connection_handle;
+
+        LeAclHciChannel::new(self, handle, DataLengthEvents::Interface(Arc::pin(DataLengthChangeMatcher(handle))))
+    }
+}
+
+impl<I> HostInterface<I> where I: HostControllerInterface + HciAclDataInterface + ReceivedPacketSource {
+
+    /// Make an ACL data connection channel that receives its events through subscriptions
+    ///
+    /// This is the same as
+    /// [`new_le_acl_connection_channel`](HostInterface::new_le_acl_connection_channel) except
+    /// the events used by the channel are received through [subscriptions](HostInterface::subscribe)
+    /// instead of being taken from the interface, so they can still be waited for or subscribed to
+    /// by the host.
+    ///
+    /// The *Number Of Completed Packets* and *Disconnection Complete* events are subscribed to when
+    /// the first of these channels is made. From then on every connection channel of the host
+    /// interface returns the buffers of a connection as soon as it is disconnected.
+    ///
+    /// The *LE Data Length Change* events for the connection are subscribed to when the channel is
+    /// made, so the channel should be made as soon as the connection is established.
+    pub fn new_subscribed_le_acl_connection_channel<'a>(
+        &'a self,
+        connection_event_data: &events::LEConnectionCompleteData
+    ) -> impl crate::l2cap::ConnectionChannel + 'a
+    {
+        let handle = connection_event_data.connection_handle;
+
+        let mut flow_events = self.acl_flow.events.lock();
+
+        if flow_events.is_none() {
+            *flow_events = Some(flow_control::FlowEvents {
+                completed_packets: self.subscribe(
+                    events::Events::NumberOfCompletedPackets,
+                    flow_control::COMPLETED_PACKETS_CAPACITY
+                ),
+                disconnections: self.subscribe(
+                    events::Events::DisconnectionComplete,
+                    flow_control::DISCONNECTIONS_CAPACITY
+                ),
+            });
+        }
+
+        drop(flow_events);
+
+        let data_length_events = self.subscribe_with_matcher(
+            events::Events::LEMeta(events::LEMeta::DataLengthChange),
+            DATA_LENGTH_EVENTS_CAPACITY,
+            DataLengthChangeMatcher(handle)
+        );
+
+        LeAclHciChannel::new(self, handle, DataLengthEvents::Subscription(data_length_events))
     }
 }
 
@@ -837,6 +1931,79 @@ where TargErr: Display + Debug,
 }
 
 
+impl<I, CmdErr> CommandError for OutputErr<SendCommandError<I>, CmdErr>
+where I: HostControllerInterface,
+      CmdErr: Display + Debug,
+{
+    fn kind(&self) -> CommandErrorKind {
+        match self {
+            OutputErr::TargetSpecificErr(err) => err.kind(),
+            OutputErr::CommandStatusErr(status) => CommandErrorKind::Status(*status),
+            _ => CommandErrorKind::Other,
+        }
+    }
+}
+
+/// The kind of a [`CommandError`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandErrorKind {
+    /// The command is not within the commands supported by the controller (see
+    /// [`set_supported_commands`](HostInterface::set_supported_commands)), it was not sent to the
+    /// controller.
+    NotSupported(opcodes::HCICommand),
+    /// The timer of the `HostInterface` expired before the response to the command was received
+    TimedOut,
+    /// The controller returned this error as the status of the command, either within the
+    /// *Command Status* event or within the event that completes the command.
+    Status(error::Error),
+    /// Any other error, such as an error of the interface or of the data returned by the
+    /// controller
+    Other,
+}
+
+/// The error of a command
+///
+/// This is the error of the futures returned by the `send` functions of the command modules. It
+/// can be displayed, and the [`kind`](CommandError::kind) of the error can be matched on.
+pub trait CommandError: Display + Debug {
+    /// Get the kind of the error
+    fn kind(&self) -> CommandErrorKind;
+}
+
+/// The error of a procedure made up of multiple commands
+///
+/// Functions that send a sequence of commands, such as [`controller_info::init`], stop at the
+/// first command that fails. This is the error of that command along with the name of it.
+#[derive(Debug)]
+pub struct ProcedureError {
+    command: &'static str,
+    kind: CommandErrorKind,
+    reason: String,
+}
+
+impl ProcedureError {
+    pub(crate) fn new<E: CommandError>(command: &'static str, err: E) -> Self {
+        ProcedureError { command, kind: err.kind(), reason: err.to_string() }
+    }
+
+    /// Get the name of the command that failed
+    pub fn get_command(&self) -> &'static str {
+        self.command
+    }
+}
+
+impl Display for ProcedureError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "The {} command failed, {}", self.command, self.reason)
+    }
+}
+
+impl CommandError for ProcedureError {
+    fn kind(&self) -> CommandErrorKind {
+        self.kind
+    }
+}
+
 macro_rules! event_pattern_creator {
     ( $event_path:path, $( $data:pat ),+ ) => { $event_path ( $($data),+ ) };
     ( $event_path:path ) => { $event_path };
@@ -846,15 +2013,13 @@ macro_rules! impl_returned_future {
     // these inputs match the inputs from crate::hci::events::impl_get_data_for_command
     ($return_type: ty, $event:path, $data:pat, $error:ty, $to_do: block) => {
 
-        struct ReturnedFuture<'a, I, CD, P>( CommandFutureReturn<'a, I, CD, P> )
+        struct ReturnedFuture<'a, I, CD>( CommandFutureReturn<'a, I, CD> )
         where I: HostControllerInterface,
-              CD: CommandParameter + Unpin,
-              P: EventMatcher + Send + Sync + 'static;
+              CD: CommandParameter + Unpin;
 
-        impl<'a, I, CD, P> core::future::Future for ReturnedFuture<'a, I, CD, P>
+        impl<'a, I, CD> core::future::Future for ReturnedFuture<'a, I, CD>
         where I: HostControllerInterface,
               CD: CommandParameter + Unpin,
-              P: EventMatcher + Send + Sync + 'static,
         {
             type Output = core::result::Result< $return_type, crate::hci::OutputErr<SendCommandError<I>,$error>>;
 
@@ -935,3 +2100,84 @@ pub mod cb;
 pub mod info_params;
 pub mod status_prams;
 pub mod testing;
+pub mod vendor;
+pub mod controller_info;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::testing::fixture::{host_interface, ADDRESS};
+    use futures::executor::block_on;
+
+    #[test]
+    fn raw_command_test() {
+        let (hi, _) = host_interface();
+
+        // Read BD_ADDR
+        match block_on(hi.send_raw_command(0x1009, &[], Duration::from_secs(1))).unwrap() {
+            RawCommandResponse::Complete(return_parameter) => {
+                assert_eq!(error::Error::NoError, error::Error::from(return_parameter[0]));
+                assert_eq!(&ADDRESS, &return_parameter[1..]);
+            },
+            response => panic!("Unexpected response {:?}", response),
+        }
+
+        // Disconnect of a connection that does not exist
+        match block_on(hi.send_raw_command(0x0406, &[0x40, 0x00, 0x13], Duration::from_secs(1))).unwrap() {
+            RawCommandResponse::Status(status) => assert_eq!(error::Error::UnknownConnectionIdentifier, status),
+            response => panic!("Unexpected response {:?}", response),
+        }
+
+        // The expectation of the event a response was not received as does not take the response
+        // of the next command with the same opcode
+        for _ in 0..2 {
+            let response = block_on(hi.send_raw_command(0x1009, &[], Duration::from_secs(1))).unwrap();
+
+            assert!(matches!(response, RawCommandResponse::Complete(_)));
+
+            let response = block_on(hi.send_raw_command(0x0406, &[0x40, 0x00, 0x13], Duration::from_secs(1))).unwrap();
+
+            assert!(matches!(response, RawCommandResponse::Status(_)));
+        }
+
+        // A parameter that is too long is not sent to the controller
+        assert!(block_on(hi.send_raw_command(0x1009, &[0; 256], Duration::from_secs(1))).is_err());
+    }
+
+    #[test]
+    fn sync_data_packet_test() {
+        let handle = common::ConnectionHandle::try_from(0x123).unwrap();
+
+        let statuses = [
+            (SyncPacketStatus::CorrectlyReceived, 0x0),
+            (SyncPacketStatus::PossiblyInvalid, 0x1),
+            (SyncPacketStatus::NoDataReceived, 0x2),
+            (SyncPacketStatus::PartiallyLost, 0x3),
+        ];
+
+        for (status, flag) in statuses.iter() {
+            let packet = HciSyncData::new(handle, *status, vec![1, 2, 3]).get_packet();
+
+            // The packet status flag is bits 12 and 13 of the first two bytes
+            assert_eq!(0x123 | flag << 12, <u16>::from_le_bytes([packet[0], packet[1]]));
+            assert_eq!(&[3, 1, 2, 3], &packet[2..]);
+
+            let sync_data = HciSyncData::from_packet(&packet).unwrap();
+
+            assert_eq!(handle, *sync_data.get_handle());
+            assert_eq!(*status, sync_data.get_packet_status_flag());
+            assert_eq!(&[1, 2, 3], sync_data.get_payload());
+        }
+
+        // The reserved bits after the packet status flag are ignored
+        let sync_data = HciSyncData::from_packet(&[0x23, 0xD1, 0]).unwrap();
+
+        assert_eq!(handle, *sync_data.get_handle());
+        assert_eq!(SyncPacketStatus::PossiblyInvalid, sync_data.get_packet_status_flag());
+        assert!(sync_data.get_payload().is_empty());
+
+        assert!(matches!(HciSyncData::from_packet(&[0x23, 0x01]), Err(HciSyncPacketConvertError::PacketTooSmall)));
+        assert!(matches!(HciSyncData::from_packet(&[0x23, 0x01, 2, 1]), Err(HciSyncPacketConvertError::PacketTooSmall)));
+    }
+}
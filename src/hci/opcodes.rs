@@ -7,6 +7,17 @@ pub enum HCICommand {
     InformationParameters(InformationParameters),
     StatusParameters(StatusParameters),
     LEController(LEController),
+    /// A vendor specific command
+    ///
+    /// The associated value is the OCF of the command, only the lower 10 bits are used.
+    VendorSpecific(u16),
+    /// A command sent by its opcode
+    ///
+    /// This is the command of
+    /// [`send_raw_command`](crate::hci::HostInterface::send_raw_command). The opcode of the
+    /// command is only within its command packet, the opcode pair of `Raw` is the opcode of the
+    /// *No Operation* command (zero).
+    Raw,
 }
 
 impl HCICommand {
@@ -17,10 +28,15 @@ impl HCICommand {
             HCICommand::InformationParameters(ref ocf) => ocf.as_opcode_pair(),
             HCICommand::StatusParameters(ref ocf) => ocf.as_opcode_pair(),
             HCICommand::LEController(ref ocf) => ocf.as_opcode_pair(),
+            HCICommand::VendorSpecific(ocf) => OpCodePair { ogf: VENDOR_SPECIFIC_OGF, ocf: ocf & 0x3FF },
+            HCICommand::Raw => OpCodePair { ogf: 0, ocf: 0 },
         }
     }
 }
 
+/// The OpCode Group Field of the vendor specific commands
+const VENDOR_SPECIFIC_OGF: u16 = 0x3F;
+
 /// An type for the pair of OGF (OpCode Group Field) and OCF (OpCode Command Field)
 pub struct OpCodePair {
     pub(crate) ogf: u16,
@@ -63,6 +79,7 @@ impl TryFrom<OpCodePair> for HCICommand {
             0x4 => Ok(HCICommand::InformationParameters( InformationParameters::try_from(opc_pair.ocf)? )),
             0x5 => Ok(HCICommand::StatusParameters( StatusParameters::try_from(opc_pair.ocf)? )),
             0x8 => Ok(HCICommand::LEController( LEController::try_from(opc_pair.ocf)? )),
+            VENDOR_SPECIFIC_OGF => Ok(HCICommand::VendorSpecific( opc_pair.ocf )),
             _ => Err(alloc::format!("Unknown OpCode Group Field value: 0x{:x}", opc_pair.ogf)),
         }
     }
@@ -106,6 +123,9 @@ pub enum ControllerAndBaseband {
     SetEventMask,
     Reset,
     ReadTransmitPowerLevel,
+    SetControllerToHostFlowControl,
+    HostBufferSize,
+    HostNumberOfCompletedPackets,
 }
 
 impl ControllerAndBaseband {
@@ -121,6 +141,9 @@ impl ControllerAndBaseband {
                 SetEventMask => 0x1,
                 Reset => 0x3,
                 ReadTransmitPowerLevel => 0x2d,
+                SetControllerToHostFlowControl => 0x31,
+                HostBufferSize => 0x33,
+                HostNumberOfCompletedPackets => 0x35,
             }
         }
     }
@@ -130,6 +153,9 @@ impl ControllerAndBaseband {
             0x1  => Ok(ControllerAndBaseband::SetEventMask),
             0x3  => Ok(ControllerAndBaseband::Reset),
             0x2d => Ok(ControllerAndBaseband::ReadTransmitPowerLevel),
+            0x31 => Ok(ControllerAndBaseband::SetControllerToHostFlowControl),
+            0x33 => Ok(ControllerAndBaseband::HostBufferSize),
+            0x35 => Ok(ControllerAndBaseband::HostNumberOfCompletedPackets),
             _ => Err(alloc::format!(ocf_error!(), "Controller and Baseband", ocf)),
         }
     }
@@ -140,6 +166,7 @@ pub enum InformationParameters {
     ReadLocalSupportedVersionInformation,
     ReadLocalSupportedCommands,
     ReadLocalSupportedFeatures,
+    ReadBufferSize,
     #[allow(non_camel_case_types)] ReadBD_ADDR,
 }
 
@@ -156,6 +183,7 @@ impl InformationParameters {
                 ReadLocalSupportedVersionInformation => 0x1,
                 ReadLocalSupportedCommands => 0x2,
                 ReadLocalSupportedFeatures => 0x3,
+                ReadBufferSize => 0x5,
                 ReadBD_ADDR => 0x9,
             }
         }
@@ -166,6 +194,7 @@ impl InformationParameters {
             0x1 => Ok(InformationParameters::ReadLocalSupportedVersionInformation),
             0x2 => Ok(InformationParameters::ReadLocalSupportedCommands),
             0x3 => Ok(InformationParameters::ReadLocalSupportedFeatures),
+            0x5 => Ok(InformationParameters::ReadBufferSize),
             0x9 => Ok(InformationParameters::ReadBD_ADDR),
             _ => Err(alloc::format!(ocf_error!(), "Information Parameters", ocf)),
         }
@@ -234,6 +263,44 @@ pub enum LEController {
     TestEnd,
     ReadConnectionParameterRequestReply,
     ReadConnectionParameterRequestNegativeReply,
+    SetDataLength,
+    ReadSuggestedDefaultDataLength,
+    WriteSuggestedDefaultDataLength,
+    AddDeviceToResolvingList,
+    RemoveDeviceFromResolvingList,
+    ClearResolvingList,
+    ReadResolvingListSize,
+    ReadPeerResolvableAddress,
+    ReadLocalResolvableAddress,
+    SetAddressResolutionEnable,
+    SetResolvablePrivateAddressTimeout,
+    ReadMaximumDataLength,
+    ReadPHY,
+    SetDefaultPHY,
+    SetPHY,
+    SetAdvertisingSetRandomAddress,
+    SetExtendedAdvertisingParameters,
+    SetExtendedAdvertisingData,
+    SetExtendedScanResponseData,
+    SetExtendedAdvertisingEnable,
+    ReadMaximumAdvertisingDataLength,
+    ReadNumberOfSupportedAdvertisingSets,
+    RemoveAdvertisingSet,
+    ClearAdvertisingSets,
+    SetPeriodicAdvertisingParameters,
+    SetPeriodicAdvertisingData,
+    SetPeriodicAdvertisingEnable,
+    SetExtendedScanParameters,
+    SetExtendedScanEnable,
+    ExtendedCreateConnection,
+    PeriodicAdvertisingCreateSync,
+    PeriodicAdvertisingCreateSyncCancel,
+    PeriodicAdvertisingTerminateSync,
+    AddDeviceToPeriodicAdvertiserList,
+    RemoveDeviceFromPeriodicAdvertiserList,
+    ClearPeriodicAdvertiserList,
+    ReadPeriodicAdvertiserListSize,
+    SetPrivacyMode,
 }
 
 impl LEController {
@@ -278,6 +345,44 @@ impl LEController {
                 TestEnd => 0x1f,
                 ReadConnectionParameterRequestReply => 0x20,
                 ReadConnectionParameterRequestNegativeReply => 0x21,
+                SetDataLength => 0x22,
+                ReadSuggestedDefaultDataLength => 0x23,
+                WriteSuggestedDefaultDataLength => 0x24,
+                AddDeviceToResolvingList => 0x27,
+                RemoveDeviceFromResolvingList => 0x28,
+                ClearResolvingList => 0x29,
+                ReadResolvingListSize => 0x2a,
+                ReadPeerResolvableAddress => 0x2b,
+                ReadLocalResolvableAddress => 0x2c,
+                SetAddressResolutionEnable => 0x2d,
+                SetResolvablePrivateAddressTimeout => 0x2e,
+                ReadMaximumDataLength => 0x2f,
+                ReadPHY => 0x30,
+                SetDefaultPHY => 0x31,
+                SetPHY => 0x32,
+                SetAdvertisingSetRandomAddress => 0x35,
+                SetExtendedAdvertisingParameters => 0x36,
+                SetExtendedAdvertisingData => 0x37,
+                SetExtendedScanResponseData => 0x38,
+                SetExtendedAdvertisingEnable => 0x39,
+                ReadMaximumAdvertisingDataLength => 0x3a,
+                ReadNumberOfSupportedAdvertisingSets => 0x3b,
+                RemoveAdvertisingSet => 0x3c,
+                ClearAdvertisingSets => 0x3d,
+                SetPeriodicAdvertisingParameters => 0x3e,
+                SetPeriodicAdvertisingData => 0x3f,
+                SetPeriodicAdvertisingEnable => 0x40,
+                SetExtendedScanParameters => 0x41,
+                SetExtendedScanEnable => 0x42,
+                ExtendedCreateConnection => 0x43,
+                PeriodicAdvertisingCreateSync => 0x44,
+                PeriodicAdvertisingCreateSyncCancel => 0x45,
+                PeriodicAdvertisingTerminateSync => 0x46,
+                AddDeviceToPeriodicAdvertiserList => 0x47,
+                RemoveDeviceFromPeriodicAdvertiserList => 0x48,
+                ClearPeriodicAdvertiserList => 0x49,
+                ReadPeriodicAdvertiserListSize => 0x4a,
+                SetPrivacyMode => 0x4e,
             }
         }
     }
@@ -314,6 +419,46 @@ impl LEController {
             0x1d => Ok(LEController::ReceiverTest),
             0x1e => Ok(LEController::TransmitterTest),
             0x1f => Ok(LEController::TestEnd),
+            0x20 => Ok(LEController::ReadConnectionParameterRequestReply),
+            0x21 => Ok(LEController::ReadConnectionParameterRequestNegativeReply),
+            0x22 => Ok(LEController::SetDataLength),
+            0x23 => Ok(LEController::ReadSuggestedDefaultDataLength),
+            0x24 => Ok(LEController::WriteSuggestedDefaultDataLength),
+            0x27 => Ok(LEController::AddDeviceToResolvingList),
+            0x28 => Ok(LEController::RemoveDeviceFromResolvingList),
+            0x29 => Ok(LEController::ClearResolvingList),
+            0x2a => Ok(LEController::ReadResolvingListSize),
+            0x2b => Ok(LEController::ReadPeerResolvableAddress),
+            0x2c => Ok(LEController::ReadLocalResolvableAddress),
+            0x2d => Ok(LEController::SetAddressResolutionEnable),
+            0x2e => Ok(LEController::SetResolvablePrivateAddressTimeout),
+            0x2f => Ok(LEController::ReadMaximumDataLength),
+            0x30 => Ok(LEController::ReadPHY),
+            0x31 => Ok(LEController::SetDefaultPHY),
+            0x32 => Ok(LEController::SetPHY),
+            0x35 => Ok(LEController::SetAdvertisingSetRandomAddress),
+            0x36 => Ok(LEController::SetExtendedAdvertisingParameters),
+            0x37 => Ok(LEController::SetExtendedAdvertisingData),
+            0x38 => Ok(LEController::SetExtendedScanResponseData),
+            0x39 => Ok(LEController::SetExtendedAdvertisingEnable),
+            0x3a => Ok(LEController::ReadMaximumAdvertisingDataLength),
+            0x3b => Ok(LEController::ReadNumberOfSupportedAdvertisingSets),
+            0x3c => Ok(LEController::RemoveAdvertisingSet),
+            0x3d => Ok(LEController::ClearAdvertisingSets),
+            0x3e => Ok(LEController::SetPeriodicAdvertisingParameters),
+            0x3f => Ok(LEController::SetPeriodicAdvertisingData),
+            0x40 => Ok(LEController::SetPeriodicAdvertisingEnable),
+            0x41 => Ok(LEController::SetExtendedScanParameters),
+            0x42 => Ok(LEController::SetExtendedScanEnable),
+            0x43 => Ok(LEController::ExtendedCreateConnection),
+            0x44 => Ok(LEController::PeriodicAdvertisingCreateSync),
+            0x45 => Ok(LEController::PeriodicAdvertisingCreateSyncCancel),
+            0x46 => Ok(LEController::PeriodicAdvertisingTerminateSync),
+            0x47 => Ok(LEController::AddDeviceToPeriodicAdvertiserList),
+            0x48 => Ok(LEController::RemoveDeviceFromPeriodicAdvertiserList),
+            0x49 => Ok(LEController::ClearPeriodicAdvertiserList),
+            0x4a => Ok(LEController::ReadPeriodicAdvertiserListSize),
+            0x4e => Ok(LEController::SetPrivacyMode),
             _ => Err(alloc::format!(ocf_error!(), "LE Controller", ocf)),
         }
     }
@@ -331,4 +476,13 @@ mod tests {
 
         assert_eq!( oc, HCICommand::try_from( OpCodePair{ ogf, ocf } ).unwrap() );
     }
+
+    #[test]
+    fn vendor_specific_op_code_test() {
+        let oc = HCICommand::VendorSpecific(0x1);
+
+        assert_eq!( 0xFC01, oc.as_opcode_pair().as_opcode() );
+
+        assert_eq!( oc, HCICommand::try_from( OpCodePair::from_opcode(0xFC01) ).unwrap() );
+    }
 }
@@ -54,7 +54,7 @@ pub mod read_rssi {
     impl_command_data_future!(RSSIInfo, error::Error);
 
     pub fn send<'a, T: 'static>( hci: &'a HostInterface<T>, handle: ConnectionHandle )
-                                 -> impl Future<Output=Result<RSSIInfo, impl Display + Debug>> + 'a
+                                 -> impl Future<Output=Result<RSSIInfo, impl CommandError>> + 'a
         where T: HostControllerInterface
     {
         let parameter = Parameter {
@@ -1 +1,3379 @@
-//! Testing Commands
\ No newline at end of file
+//! A virtual controller for testing
+//!
+//! [`VirtualController`] is a Bluetooth controller that only exists in memory. It implements both
+//! [`HostControllerInterface`] and [`HciAclDataInterface`] so that it can be used as the interface
+//! of a [`HostInterface`](super::HostInterface) in place of a real controller. This makes it
+//! possible to test the command modules of `hci` without any Bluetooth hardware.
+//!
+//! Every command sent to the virtual controller is answered as it is sent with either a *Command
+//! Complete* or a *Command Status* event (except for a valid *Host Number Of Completed Packets*
+//! command, which is not answered by a controller). The state set by the commands for the white list,
+//! advertising, and scanning is kept by the virtual controller and can be read back by the test.
+//! Anything that would normally come from a peer device, such as a LE Meta event or ACL data, must
+//! be injected by the test unless the virtual controller is linked to another virtual controller.
+//!
+//! # Linking
+//! Two virtual controllers can be [linked](VirtualController::link) to act as the peer device of
+//! each other. When one linked controller is initiating a connection and the other is advertising
+//! in a way that the initiator accepts, a connection is created and both controllers send a *LE
+//! Connection Complete* event to their host. ACL data sent over a connection created by the link
+//! is received by the host of the peer controller, and the disconnect, connection update, and
+//! encryption procedures are carried out with the peer. This makes it possible to run both the
+//! master and the slave of a connection within the same process.
+//!
+//! The advertising sets of a linked controller are reported to its peer when the peer is scanning
+//! with the extended scanning commands, every enabled set is reported once each time scanning is
+//! enabled. A connection is also created with an advertising set when the initiating PHYs of the
+//! *LE Extended Create Connection* command include the primary advertising PHY of the set, the
+//! connection then uses this PHY.
+//!
+//! A linked controller synchronizes with the periodic advertising of its peer when the periodic
+//! advertising is enabled for an enabled advertising set matching its pending *LE Periodic
+//! Advertising Create Sync* command. The periodic advertising data is reported once when the sync is
+//! established and again every time the data is set by the peer. The sync is lost when the
+//! periodic advertising of the set is no longer sent.
+//!
+//! # ACL Data Buffers
+//! ACL data sent over a connection created by the link is completed as soon as it is received by
+//! the peer, so a *Number Of Completed Packets* event is sent for every packet. ACL data sent over
+//! any other connection is kept by the virtual controller until it is taken by the test, and the
+//! test must inject the *Number Of Completed Packets* event to free the data buffer.
+//!
+//! The LE ACL data buffers are separate from the BR/EDR ACL data buffers unless the virtual
+//! controller is set to [share](VirtualController::set_shared_data_buffers) them. Shared buffers
+//! are reported by the *Read Buffer Size* command instead of the *LE Read Buffer Size* command.
+//!
+//! # Timeouts
+//! The virtual controller does not implement timeouts, the timeout passed to `receive_event` is
+//! ignored. Since the response to a command is generated when the command is sent, a command never
+//! has the chance to time out.
+//!
+//! # Event Masks
+//! The event masks are stored by the virtual controller, but they are not used to filter events.
+//! All events, generated or injected, are sent to the host.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU16, Ordering};
+use core::task::Waker;
+use core::time::Duration;
+use crate::BluetoothDeviceAddress;
+use super::{
+    common::ConnectionHandle,
+    error,
+    events,
+    opcodes,
+    CommandParameter,
+    EventMatcher,
+    HciAclData,
+    HciAclDataInterface,
+    HciPacketType,
+    HostControllerInterface,
+    ReceivedPacketListener,
+    ReceivedPacketSource,
+    spin_lock::SpinLock,
+};
+
+/// The default number of entries in the white list of a virtual controller
+pub const DEFAULT_WHITE_LIST_SIZE: usize = 8;
+
+/// The number of entries in the resolving list of a virtual controller
+pub const RESOLVING_LIST_SIZE: usize = 8;
+
+/// Default RPA timeout in seconds (v5.0 | Vol 2, Part E, 7.8.45)
+const DEFAULT_RPA_TIMEOUT: u16 = 900;
+
+/// The number of advertising sets supported by a virtual controller
+pub const NUM_SUPPORTED_ADVERTISING_SETS: usize = 4;
+
+/// The maximum length of the advertising data (or scan response data) of an advertising set
+const MAX_ADVERTISING_DATA_LENGTH: u16 = 1650;
+
+/// The maximum length of the data of an advertising set that uses legacy advertising PDUs
+const LEGACY_ADVERTISING_DATA_LENGTH: usize = 31;
+
+/// The maximum length of the data within one LE Extended Advertising Report event
+const MAX_REPORT_DATA_LENGTH: usize = 229;
+
+/// The maximum length of the data within one LE Periodic Advertising Report event
+const MAX_PERIODIC_REPORT_DATA_LENGTH: usize = 247;
+
+/// The number of entries of the periodic advertiser list
+pub const PERIODIC_ADVERTISER_LIST_SIZE: usize = 4;
+
+/// The largest raw sync handle
+const MAX_SYNC_HANDLE: u16 = 0xEFF;
+
+/// The number of HCI commands the host is allowed to send (the Num_HCI_Command_Packets field)
+const NUM_HCI_COMMAND_PACKETS: u8 = 1;
+
+/// Size of the LE ACL data buffer of the controller (the LE_ACL_Data_Packet_Length field)
+pub const LE_ACL_DATA_PACKET_LENGTH: u16 = 27;
+
+/// Number of LE ACL data buffers of the controller (the Total_Num_LE_ACL_Data_Packets field)
+pub const TOTAL_NUM_LE_ACL_DATA_PACKETS: u8 = 4;
+
+/// Size of the ACL data buffer of the controller (the ACL_Data_Packet_Length field)
+pub const ACL_DATA_PACKET_LENGTH: u16 = 64;
+
+/// Number of ACL data buffers of the controller (the Total_Num_ACL_Data_Packets field)
+pub const TOTAL_NUM_ACL_DATA_PACKETS: u16 = 3;
+
+/// Version 5.0 of the Bluetooth Specification
+pub const HCI_VERSION: u8 = 0x09;
+
+/// The manufacturer name 0xFFFF is reserved for testing
+pub const MANUFACTURER_NAME: u16 = 0xFFFF;
+
+/// The transmit power level (in dBm) reported for every connection and for advertising
+pub const TRANSMIT_POWER_LEVEL: i8 = 0;
+
+/// The RSSI (in dBm) reported for every connection
+pub const RSSI: i8 = -50;
+
+/// LMP features (only the 'LE Supported (Controller)' and 'BR/EDR Not Supported' bits are set)
+const LMP_FEATURES: [u8;8] = [0, 0, 0, 0, 0x60, 0, 0, 0];
+
+/// LE features (only 'LE Encryption', 'Connection Parameters Request Procedure', 'LE Data Packet
+/// Length Extension', 'LL Privacy', 'LE 2M PHY', 'LE Coded PHY', 'LE Extended Advertising' and 'LE
+/// Periodic Advertising' are set)
+const LE_FEATURES: [u8;8] = [0x63, 0x39, 0, 0, 0, 0, 0, 0];
+
+/// The minimum (and initial) data length of a connection as the octets and time pair
+const MIN_DATA_LENGTH: (u16, u16) = (0x1B, 0x148);
+
+/// The maximum data length supported by the controller as the octets and time pair
+const MAX_DATA_LENGTH: (u16, u16) = (0xFB, 0x848);
+
+/// LE supported states (every state and role combination in the v5.0 specification)
+const LE_SUPPORTED_STATES: [u8;8] = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x03, 0, 0];
+
+/// Default value of the event mask (v5.0 | Vol 2, Part E, 7.3.1)
+const DEFAULT_EVENT_MASK: [u8;8] = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x1F, 0, 0];
+
+/// Default value of the LE event mask (v5.0 | Vol 2, Part E, 7.8.1)
+const DEFAULT_LE_EVENT_MASK: [u8;8] = [0x1F, 0, 0, 0, 0, 0, 0, 0];
+
+/// Default advertising parameters (v5.0 | Vol 2, Part E, 7.8.5)
+const DEFAULT_ADVERTISING_PARAMETERS: [u8;15] = [
+    0x00, 0x08, 0x00, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x07, 0
+];
+
+/// Default scan parameters (v5.0 | Vol 2, Part E, 7.8.10)
+const DEFAULT_SCAN_PARAMETERS: [u8;7] = [0, 0x10, 0x00, 0x10, 0x00, 0, 0];
+
+/// Errors of the virtual controller
+#[derive(Debug)]
+pub enum Error {
+    /// The command packet could not be decoded by the virtual controller
+    InvalidCommand(String),
+    /// The event packet could not be converted into event data
+    InvalidEvent(String),
+    /// There is no receiver started for the connection handle
+    NoReceiver(ConnectionHandle),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidCommand(reason) => write!(f, "Invalid command, {}", reason),
+            Error::InvalidEvent(reason) => write!(f, "Invalid event, {}", reason),
+            Error::NoReceiver(handle) => write!(f, "No receiver started for connection handle {}", handle),
+        }
+    }
+}
+
+/// An entry in the white list of the virtual controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WhiteListEntry {
+    /// The address type as the raw value of [`AddressType`](crate::hci::le::common::AddressType)
+    pub address_type: u8,
+    pub address: BluetoothDeviceAddress,
+}
+
+/// An entry in the resolving list of the virtual controller
+///
+/// The RPAs of an entry are generated by the virtual controller when the entry is added, there is
+/// no RPA for an IRK of zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvingListEntry {
+    /// The raw peer identity address type
+    pub address_type: u8,
+    pub address: BluetoothDeviceAddress,
+    /// The peer IRK as it is in the command parameter
+    pub peer_irk: [u8;16],
+    /// The local IRK as it is in the command parameter
+    pub local_irk: [u8;16],
+    /// The raw privacy mode
+    pub privacy_mode: u8,
+    pub peer_rpa: Option<BluetoothDeviceAddress>,
+    pub local_rpa: Option<BluetoothDeviceAddress>,
+}
+
+/// An advertising set of the virtual controller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedAdvertisingSet {
+    /// The raw advertising handle
+    pub handle: u8,
+    /// The parameter of the *LE Set Extended Advertising Parameters* command without the
+    /// advertising handle
+    pub parameters: [u8;24],
+    pub random_address: Option<BluetoothDeviceAddress>,
+    pub advertising_data: Vec<u8>,
+    pub scan_response_data: Vec<u8>,
+    pub enabled: bool,
+    /// The raw duration of the last *LE Set Extended Advertising Enable* command for the set
+    pub duration: u16,
+    /// The maximum number of extended advertising events of the last *LE Set Extended Advertising
+    /// Enable* command for the set
+    pub max_extended_advertising_events: u8,
+    /// The parameter of the *LE Set Periodic Advertising Parameters* command without the
+    /// advertising handle
+    pub periodic_parameters: Option<[u8;6]>,
+    pub periodic_data: Vec<u8>,
+    pub periodic_enabled: bool,
+}
+
+impl ExtendedAdvertisingSet {
+    /// Get the raw advertising event properties
+    fn event_properties(&self) -> u16 {
+        u16_at(&self.parameters, 0)
+    }
+
+    /// Check if the set uses legacy advertising PDUs
+    fn is_legacy(&self) -> bool {
+        self.event_properties() & (1 << 4) != 0
+    }
+
+    /// Check if the set is scannable
+    fn is_scannable(&self) -> bool {
+        self.event_properties() & (1 << 1) != 0
+    }
+
+    /// Check if the periodic advertising of the set is sent
+    ///
+    /// Periodic advertising is only sent while the set is also enabled.
+    fn is_periodic_advertising(&self) -> bool {
+        self.enabled && self.periodic_enabled
+    }
+}
+
+/// Buffered ACL data for a connection handle
+#[derive(Default)]
+struct AclReceiver {
+    buffer: Vec<HciAclData>,
+    waker: Option<Waker>,
+}
+
+/// The link between two virtual controllers
+#[derive(Clone)]
+struct Link {
+    peer: Weak<SpinLock<State>>,
+    /// The next connection handle, this is shared between both linked controllers so that a
+    /// connection has the same handle on both ends
+    next_handle: Arc<AtomicU16>,
+}
+
+/// Something done by the peer of a connection created over the link
+enum PeerAction {
+    /// The peer controller sends an event packet to its host
+    Event(Vec<u8>),
+    /// The peer controller requests the long term key from its host
+    EncryptionRequest {
+        handle: u16,
+        random_number: [u8;8],
+        encrypted_diversifier: [u8;2],
+        long_term_key: [u8;16],
+    },
+}
+
+/// The event packets and peer actions generated by a command
+#[derive(Default)]
+struct Generated {
+    events: Vec<Vec<u8>>,
+    peer: Vec<PeerAction>,
+}
+
+/// The internal state of the virtual controller
+struct State {
+    address: BluetoothDeviceAddress,
+    random_address: Option<BluetoothDeviceAddress>,
+    event_mask: [u8;8],
+    le_event_mask: [u8;8],
+    white_list: Vec<WhiteListEntry>,
+    white_list_size: usize,
+    /// The LE ACL data buffers are shared with BR/EDR
+    shared_data_buffers: bool,
+    advertising_enabled: bool,
+    advertising_parameters: [u8;15],
+    advertising_data: Vec<u8>,
+    scan_response_data: Vec<u8>,
+    scanning_enabled: bool,
+    filter_duplicates: bool,
+    scan_parameters: [u8;7],
+    /// The raw parameters of the LE Create Connection command while initiating
+    ///
+    /// When initiating with the LE Extended Create Connection command, these are the parameters
+    /// for the first initiating PHY in the format of the LE Create Connection command followed by
+    /// the raw initiating PHYs.
+    initiating: Option<Vec<u8>>,
+    /// The raw connection handles of the current connections
+    connections: Vec<u16>,
+    events: VecDeque<events::EventsData>,
+    event_wakers: Vec<Waker>,
+    sent_acl_data: Vec<HciAclData>,
+    /// The raw packet of the last command sent by the host
+    last_command: Option<Vec<u8>>,
+    acl_receivers: BTreeMap<ConnectionHandle, AclReceiver>,
+    random_seed: u64,
+    link: Option<Link>,
+    /// The raw connection handles of the connections created over the link
+    linked: Vec<u16>,
+    /// The long term keys sent by the peer for the encryption requests waiting on the host
+    pending_encryption: BTreeMap<u16, [u8;16]>,
+    packet_listener: Option<Arc<dyn ReceivedPacketListener>>,
+    controller_to_host_flow_control: u8,
+    /// The raw parameter of the *Host Buffer Size* command
+    host_buffer_size: Option<[u8;7]>,
+    /// The raw connection handles and counts reported by *Host Number Of Completed Packets*
+    host_completed_packets: Vec<(u16, u16)>,
+    /// The suggested default data length as the octets and time pair
+    suggested_data_length: (u16, u16),
+    /// The raw parameter of the *LE Set Default PHY* command
+    default_phys: [u8;3],
+    /// The raw transmit and receive PHYs of the connections (a connection without an entry uses
+    /// the LE 1M PHY for both)
+    phys: BTreeMap<u16, (u8, u8)>,
+    resolving_list: Vec<ResolvingListEntry>,
+    address_resolution_enabled: bool,
+    /// The RPA timeout in seconds
+    rpa_timeout: u16,
+    advertising_sets: Vec<ExtendedAdvertisingSet>,
+    /// The raw parameter of the *LE Set Extended Scan Parameters* command
+    extended_scan_parameters: Option<Vec<u8>>,
+    /// The raw handles of the advertising sets of the peer reported since scanning was enabled
+    reported_sets: Vec<u8>,
+    /// The raw parameter of the *LE Periodic Advertising Create Sync* command while synchronizing
+    creating_sync: Option<[u8;14]>,
+    /// The entries of the periodic advertiser list as the raw address type, address, and
+    /// advertising SID
+    periodic_advertiser_list: Vec<[u8;8]>,
+    /// The raw sync handles of the established syncs
+    syncs: Vec<u16>,
+    /// The raw handles of the advertising sets of the peer for the syncs established over the
+    /// link
+    linked_syncs: BTreeMap<u16, u8>,
+    /// The raw handles of the advertising sets with periodic advertising data set since the
+    /// periodic advertising was last reported to the peer
+    updated_periodic_sets: Vec<u8>,
+}
+
+/// The status and return parameters of a command
+enum Response {
+    /// Return parameters for a Command Complete event
+    Complete(Vec<u8>),
+    /// Status for a Command Status event
+    Status(error::Error),
+    /// The controller does not respond to the command
+    Nothing,
+}
+
+/// Get the little endian u16 at `index` of `bytes`
+fn u16_at(bytes: &[u8], index: usize) -> u16 {
+    <u16>::from_le_bytes([bytes[index], bytes[index + 1]])
+}
+
+/// Get the raw connection handle at the start of the command parameter
+fn handle_at_start(parameter: &[u8]) -> u16 {
+    u16_at(parameter, 0) & 0xFFF
+}
+
+/// Create an event packet from the event code and the event parameter
+fn event_packet(event_code: u8, parameter: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(parameter.len() + 2);
+
+    packet.push(event_code);
+    packet.push(parameter.len() as u8);
+    packet.extend_from_slice(parameter);
+
+    packet
+}
+
+/// Create a LE Meta event packet from the sub event and the rest of the event parameter
+fn le_meta_event_packet(sub_event: events::LEMeta, parameter: &[u8]) -> Vec<u8> {
+    let mut le_parameter = Vec::with_capacity(parameter.len() + 1);
+
+    le_parameter.push(sub_event.get_val());
+    le_parameter.extend_from_slice(parameter);
+
+    event_packet(events::Events::LEMeta(sub_event).get_val(), &le_parameter)
+}
+
+/// Convert the parameter of a LE Connection Complete event into the parameter of a LE Enhanced
+/// Connection Complete event without resolvable private addresses
+fn into_enhanced_connection_complete(parameter: &[u8]) -> Vec<u8> {
+    let mut enhanced = parameter[..11].to_vec();
+
+    enhanced.extend_from_slice(&[0;12]);
+    enhanced.extend_from_slice(&parameter[11..]);
+
+    enhanced
+}
+
+/// Get the event packets of the LE Periodic Advertising Reports for the periodic advertising data
+///
+/// The data is reported in fragments of up to `MAX_PERIODIC_REPORT_DATA_LENGTH` bytes.
+fn periodic_advertising_reports(sync_handle: u16, tx_power: u8, data: &[u8]) -> Vec<Vec<u8>> {
+    let fragments = if data.is_empty() {
+        alloc::vec![data]
+    } else {
+        data.chunks(MAX_PERIODIC_REPORT_DATA_LENGTH).collect::<Vec<_>>()
+    };
+
+    fragments.iter().enumerate().map(|(index, fragment)| {
+        // The data status of every fragment except the last is 'incomplete, more data to come'
+        let data_status = if index + 1 == fragments.len() { 0 } else { 1 };
+
+        let mut event_parameter = sync_handle.to_le_bytes().to_vec();
+
+        event_parameter.extend_from_slice(&[tx_power, RSSI as u8, 0xFF, data_status, fragment.len() as u8]);
+        event_parameter.extend_from_slice(fragment);
+
+        le_meta_event_packet(events::LEMeta::PeriodicAdvertisingReport, &event_parameter)
+    })
+    .collect()
+}
+
+/// The positions of the commands supported by the virtual controller within the supported
+/// commands bitmask (v5.0 | Vol 2, Part E, 6.27)
+///
+/// Each pair is the octet and bit of a command. *Read Local Supported Commands* is not in the
+/// bitmask as it is always supported.
+const SUPPORTED_COMMAND_POSITIONS: &[(usize, usize)] = &[
+    (0,5),  // Disconnect
+    (2,7),  // Read Remote Version Information
+    (5,6),  // Set Event Mask
+    (5,7),  // Reset
+    (10,2), // Read Transmit Power Level
+    (10,5), // Set Controller To Host Flow Control
+    (10,6), // Host Buffer Size
+    (10,7), // Host Number Of Completed Packets
+    (14,3), // Read Local Version Information
+    (14,5), // Read Local Supported Features
+    (14,7), // Read Buffer Size
+    (15,1), // Read BD_ADDR
+    (15,5), // Read RSSI
+    (25,0), // LE Set Event Mask
+    (25,1), // LE Read Buffer Size
+    (25,2), // LE Read Local Supported Features
+    (25,4), // LE Set Random Address
+    (25,5), // LE Set Advertising Parameters
+    (25,6), // LE Read Advertising Channel TX Power
+    (25,7), // LE Set Advertising Data
+    (26,0), // LE Set Scan Response Data
+    (26,1), // LE Set Advertising Enable
+    (26,2), // LE Set Scan Parameters
+    (26,3), // LE Set Scan Enable
+    (26,4), // LE Create Connection
+    (26,5), // LE Create Connection Cancel
+    (26,6), // LE Read White List Size
+    (26,7), // LE Clear White List
+    (27,0), // LE Add Device To White List
+    (27,1), // LE Remove Device From White List
+    (27,2), // LE Connection Update
+    (27,3), // LE Set Host Channel Classification
+    (27,4), // LE Read Channel Map
+    (27,5), // LE Read Remote Features
+    (27,6), // LE Encrypt
+    (27,7), // LE Rand
+    (28,0), // LE Start Encryption
+    (28,1), // LE Long Term Key Request Reply
+    (28,2), // LE Long Term Key Request Negative Reply
+    (28,3), // LE Read Supported States
+    (28,4), // LE Receiver Test
+    (28,5), // LE Transmitter Test
+    (28,6), // LE Test End
+    (33,4), // LE Remote Connection Parameter Request Reply
+    (33,5), // LE Remote Connection Parameter Request Negative Reply
+    (33,6), // LE Set Data Length
+    (33,7), // LE Read Suggested Default Data Length
+    (34,0), // LE Write Suggested Default Data Length
+    (34,3), // LE Add Device To Resolving List
+    (34,4), // LE Remove Device From Resolving List
+    (34,5), // LE Clear Resolving List
+    (34,6), // LE Read Resolving List Size
+    (34,7), // LE Read Peer Resolvable Address
+    (35,0), // LE Read Local Resolvable Address
+    (35,1), // LE Set Address Resolution Enable
+    (35,2), // LE Set Resolvable Private Address Timeout
+    (35,3), // LE Read Maximum Data Length
+    (35,4), // LE Read PHY
+    (35,5), // LE Set Default PHY
+    (35,6), // LE Set PHY
+    (36,1), // LE Set Advertising Set Random Address
+    (36,2), // LE Set Extended Advertising Parameters
+    (36,3), // LE Set Extended Advertising Data
+    (36,4), // LE Set Extended Scan Response Data
+    (36,5), // LE Set Extended Advertising Enable
+    (36,6), // LE Read Maximum Advertising Data Length
+    (36,7), // LE Read Number of Supported Advertising Sets
+    (37,0), // LE Remove Advertising Set
+    (37,1), // LE Clear Advertising Sets
+    (37,2), // LE Set Periodic Advertising Parameters
+    (37,3), // LE Set Periodic Advertising Data
+    (37,4), // LE Set Periodic Advertising Enable
+    (37,5), // LE Set Extended Scan Parameters
+    (37,6), // LE Set Extended Scan Enable
+    (37,7), // LE Extended Create Connection
+    (38,0), // LE Periodic Advertising Create Sync
+    (38,1), // LE Periodic Advertising Create Sync Cancel
+    (38,2), // LE Periodic Advertising Terminate Sync
+    (38,3), // LE Add Device To Periodic Advertiser List
+    (38,4), // LE Remove Device From Periodic Advertiser List
+    (38,5), // LE Clear Periodic Advertiser List
+    (38,6), // LE Read Periodic Advertiser List Size
+    (39,2), // LE Set Privacy Mode
+];
+
+/// Get the supported commands bitmask of the virtual controller
+fn supported_commands() -> [u8;64] {
+    let mut mask = [0u8;64];
+
+    SUPPORTED_COMMAND_POSITIONS.iter().for_each(|(octet, bit)| mask[*octet] |= 1 << bit);
+
+    mask
+}
+
+impl State {
+    fn new(address: BluetoothDeviceAddress) -> Self {
+        State {
+            address,
+            random_address: None,
+            event_mask: DEFAULT_EVENT_MASK,
+            le_event_mask: DEFAULT_LE_EVENT_MASK,
+            white_list: Vec::new(),
+            white_list_size: DEFAULT_WHITE_LIST_SIZE,
+            shared_data_buffers: false,
+            advertising_enabled: false,
+            advertising_parameters: DEFAULT_ADVERTISING_PARAMETERS,
+            advertising_data: Vec::new(),
+            scan_response_data: Vec::new(),
+            scanning_enabled: false,
+            filter_duplicates: false,
+            scan_parameters: DEFAULT_SCAN_PARAMETERS,
+            initiating: None,
+            connections: Vec::new(),
+            events: VecDeque::new(),
+            event_wakers: Vec::new(),
+            sent_acl_data: Vec::new(),
+            last_command: None,
+            acl_receivers: BTreeMap::new(),
+            random_seed: 0x2545_F491_4F6C_DD1D,
+            link: None,
+            linked: Vec::new(),
+            pending_encryption: BTreeMap::new(),
+            packet_listener: None,
+            controller_to_host_flow_control: 0,
+            host_buffer_size: None,
+            host_completed_packets: Vec::new(),
+            suggested_data_length: MIN_DATA_LENGTH,
+            default_phys: [0x03, 0, 0],
+            phys: BTreeMap::new(),
+            resolving_list: Vec::new(),
+            address_resolution_enabled: false,
+            rpa_timeout: DEFAULT_RPA_TIMEOUT,
+            advertising_sets: Vec::new(),
+            extended_scan_parameters: None,
+            reported_sets: Vec::new(),
+            creating_sync: None,
+            periodic_advertiser_list: Vec::new(),
+            syncs: Vec::new(),
+            linked_syncs: BTreeMap::new(),
+            updated_periodic_sets: Vec::new(),
+        }
+    }
+
+    /// Reset the controller
+    ///
+    /// Only the state that is set by HCI commands is reset, the white list size, the link, and the
+    /// ACL receivers and packet listener set up by the host are kept.
+    fn reset(&mut self) {
+        let mut reset = State::new(self.address);
+
+        reset.white_list_size = self.white_list_size;
+
+        reset.shared_data_buffers = self.shared_data_buffers;
+
+        reset.link = self.link.take();
+
+        reset.packet_listener = self.packet_listener.take();
+
+        reset.acl_receivers = core::mem::take(&mut self.acl_receivers);
+
+        reset.event_wakers = core::mem::take(&mut self.event_wakers);
+
+        *self = reset;
+    }
+
+    /// Check if the white list is in use by advertising, scanning, or initiating
+    fn is_white_list_in_use(&self) -> bool {
+        (self.advertising_enabled && self.advertising_parameters[14] != 0) ||
+        self.advertising_sets.iter().any(|set| set.enabled && set.parameters[17] != 0) ||
+        (self.scanning_enabled && self.scanning_filter_policy() & 1 != 0) ||
+        self.initiating.as_ref().map(|parameter| parameter[4] != 0).unwrap_or_default()
+    }
+
+    /// Check if the resolving list cannot be changed
+    ///
+    /// The resolving list cannot be changed while address resolution is enabled and advertising,
+    /// scanning, or initiating is enabled.
+    fn is_resolving_list_in_use(&self) -> bool {
+        self.address_resolution_enabled &&
+        (self.is_advertising_enabled() || self.scanning_enabled || self.initiating.is_some())
+    }
+
+    /// Get the raw scanning filter policy of the extended scan parameters, or of the legacy scan
+    /// parameters if the extended scan parameters were not set
+    fn scanning_filter_policy(&self) -> u8 {
+        self.extended_scan_parameters.as_ref()
+            .map(|parameter| parameter[1])
+            .unwrap_or(self.scan_parameters[6])
+    }
+
+    /// Check if legacy advertising or any advertising set is enabled
+    fn is_advertising_enabled(&self) -> bool {
+        self.advertising_enabled || self.advertising_sets.iter().any(|set| set.enabled)
+    }
+
+    /// Get the raw address type and address used by an advertising set
+    fn advertising_set_address(&self, set: &ExtendedAdvertisingSet) -> Option<(u8, BluetoothDeviceAddress)> {
+        match set.parameters[9] {
+            0x00 | 0x02 => Some((0, self.address)),
+            0x01 | 0x03 => set.random_address.map(|address| (1, address)),
+            _ => None,
+        }
+    }
+
+    /// Get the event packets of the LE Extended Advertising Reports for an advertising set
+    ///
+    /// The data of the set is reported in fragments of up to `MAX_REPORT_DATA_LENGTH` bytes. The
+    /// scan response data is also reported when `scan_response` is true and the set is scannable.
+    fn extended_advertising_reports(&self, set: &ExtendedAdvertisingSet, scan_response: bool) -> Vec<Vec<u8>> {
+        let properties = set.event_properties();
+
+        let (address_type, address) = if properties & (1 << 5) != 0 {
+            // Anonymous advertising
+            (0xFF, BluetoothDeviceAddress::default())
+        } else {
+            match self.advertising_set_address(set) {
+                Some(address) => address,
+                None => return Vec::new(),
+            }
+        };
+
+        // The event type is the connectable, scannable, directed, and legacy properties (high
+        // duty cycle directed advertising is directed advertising)
+        let event_type = properties & 0x17 | (properties & 0x8) >> 1;
+
+        let mut data_sets = alloc::vec![(event_type, &set.advertising_data)];
+
+        if scan_response && set.is_scannable() {
+            data_sets.push((event_type | 1 << 3, &set.scan_response_data));
+        }
+
+        let mut packets = Vec::new();
+
+        for (event_type, data) in data_sets {
+            let fragments = if data.is_empty() {
+                alloc::vec![&data[..]]
+            } else {
+                data.chunks(MAX_REPORT_DATA_LENGTH).collect::<Vec<_>>()
+            };
+
+            for (index, fragment) in fragments.iter().enumerate() {
+                // The data status of every fragment except the last is 'incomplete, more data to come'
+                let data_status: u16 = if index + 1 == fragments.len() { 0 } else { 1 };
+
+                let mut event_parameter = alloc::vec![1];
+
+                event_parameter.extend_from_slice(&(event_type | data_status << 5).to_le_bytes());
+                event_parameter.push(address_type);
+                event_parameter.extend_from_slice(&address);
+
+                if set.is_legacy() {
+                    event_parameter.extend_from_slice(&[0x1, 0x0, 0xFF]);
+                } else {
+                    event_parameter.extend_from_slice(&[set.parameters[19], set.parameters[21], set.parameters[22]]);
+                }
+
+                event_parameter.push(if properties & (1 << 6) != 0 { TRANSMIT_POWER_LEVEL as u8 } else { 0x7F });
+                event_parameter.push(RSSI as u8);
+                event_parameter.extend_from_slice(&0u16.to_le_bytes());
+
+                if properties & (1 << 2) != 0 {
+                    event_parameter.extend_from_slice(&set.parameters[10..17]);
+                } else {
+                    event_parameter.extend_from_slice(&[0;7]);
+                }
+
+                event_parameter.push(fragment.len() as u8);
+                event_parameter.extend_from_slice(fragment);
+
+                packets.push(le_meta_event_packet(events::LEMeta::ExtendedAdvertisingReport, &event_parameter));
+            }
+        }
+
+        packets
+    }
+
+    /// Get the reports of the enabled advertising sets for a scanner
+    ///
+    /// The inputs are the raw parameter of the *LE Set Extended Scan Parameters* command of the
+    /// scanner and the handles of the sets already reported to the scanner. The return is the
+    /// handles of the reported sets and the event packets of the reports.
+    fn link_advertising_reports(&self, scan_parameter: &[u8], reported: &[u8]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let scanning_phys = scan_parameter[2];
+
+        let mut handles = Vec::new();
+        let mut packets = Vec::new();
+
+        for set in self.advertising_sets.iter().filter(|set| set.enabled && !reported.contains(&set.handle)) {
+            let coded = !set.is_legacy() && set.parameters[19] == 0x3;
+
+            // The parameters of the LE 1M PHY are listed before the parameters of the LE Coded PHY
+            let scan_type = match (coded, scanning_phys & 0x1 != 0, scanning_phys & 0x4 != 0) {
+                (false, true, _) => scan_parameter[3],
+                (true, le_1m, true) => scan_parameter[3 + le_1m as usize * 5],
+                _ => continue,
+            };
+
+            handles.push(set.handle);
+
+            packets.extend(self.extended_advertising_reports(set, scan_type == 0x1));
+        }
+
+        (handles, packets)
+    }
+
+    /// Get the index of the advertising set with the raw advertising handle
+    fn advertising_set_position(&self, raw_handle: u8) -> Option<usize> {
+        self.advertising_sets.iter().position(|set| set.handle == raw_handle)
+    }
+
+    /// Set the advertising data or scan response data of an advertising set (v5.0 | Vol 2, Part E,
+    /// 7.8.54 and 7.8.55)
+    ///
+    /// The fragments of the data are appended to the data of the set as they are received.
+    fn set_extended_data(&mut self, scan_response: bool, parameter: &[u8]) -> error::Error {
+        use error::Error::*;
+
+        let (handle, operation, data) = (parameter[0], parameter[1], &parameter[4..]);
+
+        let index = match self.advertising_set_position(handle) {
+            _ if parameter.len() != 4 + parameter[3] as usize => return InvalidHCICommandParameters,
+            Some(index) => index,
+            None => return UnknownAdvertisingIdentifier,
+        };
+
+        let set = &mut self.advertising_sets[index];
+
+        let is_complete = operation == 0x3;
+
+        if operation > 0x3 ||
+            (set.is_legacy() && (!is_complete || data.len() > LEGACY_ADVERTISING_DATA_LENGTH)) ||
+            (scan_response && !set.is_scannable() && !data.is_empty())
+        {
+            return InvalidHCICommandParameters;
+        }
+
+        if set.enabled && !is_complete {
+            return CommandDisallowed;
+        }
+
+        let stored = if scan_response { &mut set.scan_response_data } else { &mut set.advertising_data };
+
+        // The first fragment and complete data replace the data of the set
+        let kept = if operation == 0x1 || is_complete { 0 } else { stored.len() };
+
+        if kept + data.len() > MAX_ADVERTISING_DATA_LENGTH as usize {
+            return MemoryCapacityExceeded;
+        }
+
+        stored.truncate(kept);
+
+        stored.extend_from_slice(data);
+
+        NoError
+    }
+
+    /// Set the periodic advertising data of an advertising set
+    ///
+    /// The input is the parameter of the *LE Set Periodic Advertising Data* command, the return is
+    /// the status of the command.
+    fn set_periodic_data(&mut self, parameter: &[u8]) -> error::Error {
+        use error::Error::*;
+
+        let (handle, operation, data) = (parameter[0], parameter[1], &parameter[3..]);
+
+        let index = match self.advertising_set_position(handle) {
+            _ if parameter.len() != 3 + parameter[2] as usize || operation > 0x3 =>
+                return InvalidHCICommandParameters,
+            Some(index) => index,
+            None => return UnknownAdvertisingIdentifier,
+        };
+
+        let set = &mut self.advertising_sets[index];
+
+        let is_complete = operation == 0x3;
+
+        if set.periodic_parameters.is_none() || (set.periodic_enabled && !is_complete) {
+            return CommandDisallowed;
+        }
+
+        // The first fragment and complete data replace the data of the set
+        let kept = if operation == 0x1 || is_complete { 0 } else { set.periodic_data.len() };
+
+        if kept + data.len() > MAX_ADVERTISING_DATA_LENGTH as usize {
+            return MemoryCapacityExceeded;
+        }
+
+        set.periodic_data.truncate(kept);
+
+        set.periodic_data.extend_from_slice(data);
+
+        // The data is complete with the last fragment
+        if (operation == 0x2 || is_complete) && !self.updated_periodic_sets.contains(&handle) {
+            self.updated_periodic_sets.push(handle);
+        }
+
+        NoError
+    }
+
+    /// Check if the periodic advertiser list is used by the pending *LE Periodic Advertising
+    /// Create Sync* command
+    fn is_periodic_advertiser_list_in_use(&self) -> bool {
+        self.creating_sync.map(|parameter| parameter[0] & 1 != 0).unwrap_or_default()
+    }
+
+    /// Get the advertising set with periodic advertising matching the parameter of a *LE Periodic
+    /// Advertising Create Sync* command
+    ///
+    /// The periodic advertiser list is the list of the controller synchronizing.
+    fn find_periodic_advertising(&self, create_parameter: &[u8;14], periodic_advertiser_list: &[[u8;8]])
+    -> Option<&ExtendedAdvertisingSet>
+    {
+        self.advertising_sets.iter()
+            .filter(|set| set.is_periodic_advertising() && set.event_properties() & (1 << 5) == 0)
+            .find(|set| match self.advertising_set_address(set) {
+                Some((address_type, address)) => {
+                    let mut entry = [0u8;8];
+
+                    entry[0] = address_type;
+                    entry[1..7].copy_from_slice(&address);
+                    entry[7] = set.parameters[22];
+
+                    if create_parameter[0] & 1 != 0 {
+                        periodic_advertiser_list.contains(&entry)
+                    } else {
+                        create_parameter[1] == entry[7] && create_parameter[2..9] == entry[..7]
+                    }
+                },
+                None => false,
+            })
+    }
+
+    /// Get the index of the resolving list entry with the peer identity address at the start of the
+    /// command parameter
+    fn resolving_list_position(&self, parameter: &[u8]) -> Option<usize> {
+        self.resolving_list.iter()
+            .position(|entry| entry.address_type == parameter[0] && entry.address == parameter[1..7])
+    }
+
+    fn is_connected(&self, raw_handle: u16) -> bool {
+        self.connections.contains(&raw_handle)
+    }
+
+    /// Check if the connection was created over the link
+    fn is_linked(&self, raw_handle: u16) -> bool {
+        self.is_connected(raw_handle) && self.linked.contains(&raw_handle)
+    }
+
+    /// Get the address type and address used for the own address type of a command parameter
+    ///
+    /// The resolvable private address types fall back to the public or random address as there is
+    /// no resolving list in the virtual controller.
+    fn own_address(&self, own_address_type: u8) -> Option<(u8, BluetoothDeviceAddress)> {
+        match own_address_type {
+            0x00 | 0x02 => Some((0, self.address)),
+            0x01 | 0x03 => self.random_address.map(|address| (1, address)),
+            _ => None,
+        }
+    }
+
+    /// Check if a connection is accepted by this controller as the advertiser
+    ///
+    /// The inputs are the raw parameter of the *LE Create Connection* command (in the format of the
+    /// `initiating` field), the address of the initiator, and the white list of the initiator. The
+    /// return is the address of the advertiser, the raw handle of the advertising set, and the raw
+    /// PHY of the connection if the connection is accepted. There is no advertising set handle
+    /// when the connection is accepted by legacy advertising.
+    fn accept_connection(
+        &self,
+        create_parameter: &[u8],
+        initiator: (u8, BluetoothDeviceAddress),
+        initiator_white_list: &[WhiteListEntry],
+    ) -> Option<((u8, BluetoothDeviceAddress), Option<u8>, u8)>
+    {
+        let initiating_phys = create_parameter.get(25).copied().unwrap_or(0x1);
+
+        let is_initiator = |address_type: u8, address: &[u8]| address_type == initiator.0 && *address == initiator.1;
+
+        let advertiser_accepts = |filter_policy: u8| match filter_policy {
+            0x02 | 0x03 => self.white_list.contains(
+                &WhiteListEntry { address_type: initiator.0, address: initiator.1 }
+            ),
+            _ => true,
+        };
+
+        let initiator_accepts = |advertiser: &(u8, BluetoothDeviceAddress)| if create_parameter[4] == 0 {
+            create_parameter[5] == advertiser.0 && create_parameter[6..12] == advertiser.1
+        } else {
+            initiator_white_list.contains(&WhiteListEntry { address_type: advertiser.0, address: advertiser.1 })
+        };
+
+        if self.advertising_enabled && initiating_phys & 0x1 != 0 {
+            let parameters = &self.advertising_parameters;
+
+            let is_connectable = match parameters[4] {
+                0x00 => true,
+                // Directed advertising is only connectable by the peer address
+                0x01 | 0x04 => is_initiator(parameters[6], &parameters[7..13]),
+                _ => false,
+            };
+
+            if let Some(advertiser) = self.own_address(parameters[5]) {
+                if is_connectable && advertiser_accepts(parameters[14]) && initiator_accepts(&advertiser) {
+                    return Some((advertiser, None, 0x1));
+                }
+            }
+        }
+
+        self.advertising_sets.iter().filter(|set| set.enabled).find_map(|set| {
+            let properties = set.event_properties();
+
+            let phy = if set.is_legacy() { 0x1 } else { set.parameters[19] };
+
+            let phy_bit = if phy == 0x3 { 0x4 } else { 0x1 };
+
+            let is_connectable = properties & 0x1 != 0 &&
+                (properties & 0x4 == 0 || is_initiator(set.parameters[10], &set.parameters[11..17]));
+
+            let advertiser = self.advertising_set_address(set)?;
+
+            if is_connectable && initiating_phys & phy_bit != 0 && advertiser_accepts(set.parameters[17]) &&
+                initiator_accepts(&advertiser)
+            {
+                Some((advertiser, Some(set.handle), phy))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Generate the next pseudo random number (xorshift64*)
+    fn next_random(&mut self) -> u64 {
+        self.random_seed ^= self.random_seed >> 12;
+        self.random_seed ^= self.random_seed << 25;
+        self.random_seed ^= self.random_seed >> 27;
+
+        self.random_seed.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Generate a resolvable private address from an IRK (v5.0 | Vol 6, Part B, 1.3.2.2)
+    ///
+    /// `None` is returned if the IRK is zero.
+    fn new_rpa(&mut self, irk: [u8;16]) -> Option<BluetoothDeviceAddress> {
+        let irk = <u128>::from_le_bytes(irk);
+
+        if irk == 0 { return None }
+
+        let random = self.next_random().to_le_bytes();
+
+        let prand = [random[0], random[1], random[2] & 0x3F | 0x40];
+
+        let mut address = BluetoothDeviceAddress::default();
+
+        address[..3].copy_from_slice(&crate::sm::toolbox::ah(irk, prand));
+        address[3..].copy_from_slice(&prand);
+
+        Some(address)
+    }
+
+    /// Process a command packet
+    ///
+    /// The return contains the list of event packets generated by the command, the first of which
+    /// is always the Command Complete or Command Status event, and the actions for the peer of a
+    /// connection created over the link.
+    fn process_command(&mut self, packet: &[u8]) -> Result<Generated, Error> {
+        if packet.len() < 3 {
+            return Err(Error::InvalidCommand("command packet is too small".into()));
+        }
+
+        self.last_command = Some(packet.to_vec());
+
+        let opcode = u16_at(packet, 0);
+
+        let parameter = &packet[3..];
+
+        if parameter.len() != packet[2] as usize {
+            return Err(Error::InvalidCommand("parameter length does not match the packet length".into()));
+        }
+
+        let command = opcodes::HCICommand::try_from(opcodes::OpCodePair::from_opcode(opcode))
+            .map_err(Error::InvalidCommand)?;
+
+        let mut generated = Generated::default();
+
+        let response = if parameter.len() < Self::parameter_len(command) {
+            Self::invalid_parameters(command)
+        } else {
+            self.process_parameter(command, parameter, &mut generated)
+        };
+
+        let response_packet = match response {
+            Response::Nothing => return Ok(generated),
+            Response::Complete(return_parameter) => {
+                let mut event_parameter = Vec::with_capacity(return_parameter.len() + 3);
+
+                event_parameter.push(NUM_HCI_COMMAND_PACKETS);
+                event_parameter.extend_from_slice(&opcode.to_le_bytes());
+                event_parameter.extend_from_slice(&return_parameter);
+
+                event_packet(events::Events::CommandComplete.get_val(), &event_parameter)
+            },
+            Response::Status(status) => {
+                let mut event_parameter = Vec::with_capacity(4);
+
+                event_parameter.push(status.into());
+                event_parameter.push(NUM_HCI_COMMAND_PACKETS);
+                event_parameter.extend_from_slice(&opcode.to_le_bytes());
+
+                event_packet(events::Events::CommandStatus.get_val(), &event_parameter)
+            },
+        };
+
+        generated.events.insert(0, response_packet);
+
+        Ok(generated)
+    }
+
+    /// The minimum length of the command parameter of a command
+    fn parameter_len(command: opcodes::HCICommand) -> usize {
+        use opcodes::HCICommand::*;
+        use opcodes::LEController as LE;
+
+        match command {
+            LinkControl(opcodes::LinkControl::Disconnect) => 3,
+            LinkControl(opcodes::LinkControl::ReadRemoteVersionInformation) => 2,
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::SetEventMask) => 8,
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::ReadTransmitPowerLevel) => 3,
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::SetControllerToHostFlowControl) => 1,
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::HostBufferSize) => 7,
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::HostNumberOfCompletedPackets) => 1,
+            StatusParameters(opcodes::StatusParameters::ReadRSSI) => 2,
+            LEController(LE::SetEventMask) => 8,
+            LEController(LE::SetRandomAddress) => 6,
+            LEController(LE::SetAdvertisingParameters) => 15,
+            LEController(LE::SetAdvertisingData) |
+            LEController(LE::SetScanResponseData) => 32,
+            LEController(LE::SetAdvertisingEnable) => 1,
+            LEController(LE::SetScanParameters) => 7,
+            LEController(LE::SetScanEnable) => 2,
+            LEController(LE::CreateConnection) => 25,
+            LEController(LE::AddDeviceToWhiteList) |
+            LEController(LE::RemoveDeviceFromWhiteList) => 7,
+            LEController(LE::ConnectionUpdate) => 14,
+            LEController(LE::SetHostChannelClassification) => 5,
+            LEController(LE::ReadChannelMap) |
+            LEController(LE::ReadRemoteFeatures) => 2,
+            LEController(LE::Encrypt) => 32,
+            LEController(LE::StartEncryption) => 28,
+            LEController(LE::LongTermKeyRequestReply) => 18,
+            LEController(LE::LongTermKeyRequestNegativeReply) => 2,
+            LEController(LE::ReceiverTest) => 1,
+            LEController(LE::TransmitterTest) => 3,
+            LEController(LE::ReadConnectionParameterRequestReply) => 14,
+            LEController(LE::ReadConnectionParameterRequestNegativeReply) => 3,
+            LEController(LE::SetDataLength) => 6,
+            LEController(LE::WriteSuggestedDefaultDataLength) => 4,
+            LEController(LE::AddDeviceToResolvingList) => 39,
+            LEController(LE::RemoveDeviceFromResolvingList) => 7,
+            LEController(LE::ReadPeerResolvableAddress) => 7,
+            LEController(LE::ReadLocalResolvableAddress) => 7,
+            LEController(LE::SetAddressResolutionEnable) => 1,
+            LEController(LE::SetResolvablePrivateAddressTimeout) => 2,
+            LEController(LE::ReadPHY) => 2,
+            LEController(LE::SetDefaultPHY) => 3,
+            LEController(LE::SetPHY) => 7,
+            LEController(LE::SetAdvertisingSetRandomAddress) => 7,
+            LEController(LE::SetExtendedAdvertisingParameters) => 25,
+            LEController(LE::SetExtendedAdvertisingData) |
+            LEController(LE::SetExtendedScanResponseData) => 4,
+            LEController(LE::SetExtendedAdvertisingEnable) => 2,
+            LEController(LE::RemoveAdvertisingSet) => 1,
+            LEController(LE::SetExtendedScanParameters) => 3,
+            LEController(LE::SetExtendedScanEnable) => 6,
+            LEController(LE::ExtendedCreateConnection) => 10,
+            LEController(LE::SetPeriodicAdvertisingParameters) => 7,
+            LEController(LE::SetPeriodicAdvertisingData) => 3,
+            LEController(LE::SetPeriodicAdvertisingEnable) => 2,
+            LEController(LE::PeriodicAdvertisingCreateSync) => 14,
+            LEController(LE::PeriodicAdvertisingTerminateSync) => 2,
+            LEController(LE::AddDeviceToPeriodicAdvertiserList) |
+            LEController(LE::RemoveDeviceFromPeriodicAdvertiserList) => 8,
+            LEController(LE::SetPrivacyMode) => 8,
+            _ => 0,
+        }
+    }
+
+    /// Check if the command is answered with a Command Status event
+    fn is_status_command(command: opcodes::HCICommand) -> bool {
+        use opcodes::HCICommand::*;
+        use opcodes::LEController as LE;
+
+        matches!(command,
+            LinkControl(_) |
+            LEController(LE::CreateConnection) |
+            LEController(LE::ConnectionUpdate) |
+            LEController(LE::ReadRemoteFeatures) |
+            LEController(LE::StartEncryption) |
+            LEController(LE::SetPHY) |
+            LEController(LE::ExtendedCreateConnection) |
+            LEController(LE::PeriodicAdvertisingCreateSync)
+        )
+    }
+
+    /// Check if the octets and time are valid parameters for a data length (v5.0 | Vol 2, Part E,
+    /// 7.8.33)
+    fn is_valid_data_length(octets: u16, time: u16) -> bool {
+        (0x1B..=0xFB).contains(&octets) && (0x148..=0x4290).contains(&time)
+    }
+
+    /// Check if the All_PHYS, TX_PHYS, and RX_PHYS are valid parameters for the PHY commands (v5.0
+    /// | Vol 2, Part E, 7.8.48)
+    ///
+    /// A direction without a preference must still list at least one PHY.
+    fn is_valid_phys(all_phys: u8, tx_phys: u8, rx_phys: u8) -> bool {
+        all_phys & !0x3 == 0 &&
+        tx_phys & !0x7 == 0 &&
+        rx_phys & !0x7 == 0 &&
+        (all_phys & 0x1 != 0 || tx_phys != 0) &&
+        (all_phys & 0x2 != 0 || rx_phys != 0)
+    }
+
+    /// Get the raw PHY chosen from the preferred PHYs
+    ///
+    /// The virtual controller prefers the LE 2M PHY, then the LE Coded PHY, and then the LE 1M
+    /// PHY. The `current` PHY is kept when there is no preference.
+    fn choose_phy(no_preference: bool, phys: u8, current: u8) -> u8 {
+        if no_preference {
+            current
+        } else if phys & 0x2 != 0 {
+            0x2
+        } else if phys & 0x4 != 0 {
+            0x3
+        } else {
+            0x1
+        }
+    }
+
+    /// Get the raw transmit and receive PHYs of a connection
+    fn get_phys(&self, raw_handle: u16) -> (u8, u8) {
+        self.phys.get(&raw_handle).copied().unwrap_or((0x1, 0x1))
+    }
+
+    fn invalid_parameters(command: opcodes::HCICommand) -> Response {
+        if Self::is_status_command(command) {
+            Response::Status(error::Error::InvalidHCICommandParameters)
+        } else {
+            Response::Complete(alloc::vec![error::Error::InvalidHCICommandParameters.into()])
+        }
+    }
+
+    /// A Command Complete response that only contains a status
+    fn status_only(status: error::Error) -> Response {
+        Response::Complete(alloc::vec![status.into()])
+    }
+
+    /// A Command Complete response that contains a status and a connection handle
+    fn status_and_handle(&self, raw_handle: u16) -> Response {
+        let status = if self.is_connected(raw_handle) {
+            error::Error::NoError
+        } else {
+            error::Error::UnknownConnectionIdentifier
+        };
+
+        let mut ret = alloc::vec![status.into()];
+
+        ret.extend_from_slice(&raw_handle.to_le_bytes());
+
+        Response::Complete(ret)
+    }
+
+    /// The event parameter of an Encryption Change event
+    fn encryption_change_parameter(status: error::Error, raw_handle: u16, enabled: bool) -> Vec<u8> {
+        let mut event_parameter = alloc::vec![status.into()];
+
+        event_parameter.extend_from_slice(&raw_handle.to_le_bytes());
+        event_parameter.push(enabled as u8);
+
+        event_parameter
+    }
+
+    fn process_parameter(
+        &mut self,
+        command: opcodes::HCICommand,
+        parameter: &[u8],
+        generated: &mut Generated
+    ) -> Response
+    {
+        use opcodes::HCICommand::*;
+        use error::Error::{NoError, UnknownConnectionIdentifier};
+
+        match command {
+            LinkControl(opcodes::LinkControl::Disconnect) => {
+                let handle = handle_at_start(parameter);
+
+                if self.is_connected(handle) {
+                    let mut event_parameter = alloc::vec![NoError.into()];
+
+                    event_parameter.extend_from_slice(&handle.to_le_bytes());
+                    event_parameter.push(error::Error::ConnectionTerminatedByLocalHost.into());
+
+                    generated.events.push(event_packet(events::Events::DisconnectionComplete.get_val(), &event_parameter));
+
+                    if self.is_linked(handle) {
+                        // The peer receives the reason given by the host
+                        event_parameter[3] = parameter[2];
+
+                        generated.peer.push(PeerAction::Event(
+                            event_packet(events::Events::DisconnectionComplete.get_val(), &event_parameter)
+                        ));
+                    }
+
+                    Response::Status(NoError)
+                } else {
+                    Response::Status(UnknownConnectionIdentifier)
+                }
+            },
+            LinkControl(opcodes::LinkControl::ReadRemoteVersionInformation) => {
+                let handle = handle_at_start(parameter);
+
+                if self.is_connected(handle) {
+                    let mut event_parameter = alloc::vec![NoError.into()];
+
+                    event_parameter.extend_from_slice(&handle.to_le_bytes());
+                    event_parameter.push(HCI_VERSION);
+                    event_parameter.extend_from_slice(&MANUFACTURER_NAME.to_le_bytes());
+                    event_parameter.extend_from_slice(&0u16.to_le_bytes());
+
+                    generated.events.push(event_packet(
+                        events::Events::ReadRemoteVersionInformationComplete.get_val(),
+                        &event_parameter
+                    ));
+
+                    Response::Status(NoError)
+                } else {
+                    Response::Status(UnknownConnectionIdentifier)
+                }
+            },
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::SetEventMask) => {
+                self.event_mask.copy_from_slice(&parameter[..8]);
+
+                Self::status_only(NoError)
+            },
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::Reset) => {
+                self.reset();
+
+                Self::status_only(NoError)
+            },
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::ReadTransmitPowerLevel) => {
+                match self.status_and_handle(handle_at_start(parameter)) {
+                    Response::Complete(mut ret) => {
+                        ret.push(TRANSMIT_POWER_LEVEL as u8);
+
+                        Response::Complete(ret)
+                    },
+                    status => status,
+                }
+            },
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::SetControllerToHostFlowControl) => {
+                if parameter[0] > 0x3 {
+                    Self::status_only(error::Error::InvalidHCICommandParameters)
+                } else {
+                    self.controller_to_host_flow_control = parameter[0];
+
+                    Self::status_only(NoError)
+                }
+            },
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::HostBufferSize) => {
+                let mut buffer_size = [0u8;7];
+
+                buffer_size.copy_from_slice(&parameter[..7]);
+
+                self.host_buffer_size = Some(buffer_size);
+
+                Self::status_only(NoError)
+            },
+            ControllerAndBaseband(opcodes::ControllerAndBaseband::HostNumberOfCompletedPackets) => {
+                let num_handles = parameter[0] as usize;
+
+                if parameter.len() != 1 + num_handles * 4 {
+                    Self::status_only(error::Error::InvalidHCICommandParameters)
+                } else {
+                    parameter[1..].chunks_exact(4).for_each(|chunk| {
+                        self.host_completed_packets.push((handle_at_start(chunk), u16_at(chunk, 2)))
+                    });
+
+                    Response::Nothing
+                }
+            },
+            InformationParameters(opcodes::InformationParameters::ReadLocalSupportedVersionInformation) => {
+                let mut ret = alloc::vec![NoError.into(), HCI_VERSION];
+
+                ret.extend_from_slice(&0u16.to_le_bytes());
+                ret.push(HCI_VERSION);
+                ret.extend_from_slice(&MANUFACTURER_NAME.to_le_bytes());
+                ret.extend_from_slice(&0u16.to_le_bytes());
+
+                Response::Complete(ret)
+            },
+            InformationParameters(opcodes::InformationParameters::ReadLocalSupportedCommands) => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&supported_commands());
+
+                Response::Complete(ret)
+            },
+            InformationParameters(opcodes::InformationParameters::ReadLocalSupportedFeatures) => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&LMP_FEATURES);
+
+                Response::Complete(ret)
+            },
+            InformationParameters(opcodes::InformationParameters::ReadBufferSize) => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&ACL_DATA_PACKET_LENGTH.to_le_bytes());
+                ret.push(0);
+                ret.extend_from_slice(&TOTAL_NUM_ACL_DATA_PACKETS.to_le_bytes());
+                ret.extend_from_slice(&0u16.to_le_bytes());
+
+                Response::Complete(ret)
+            },
+            InformationParameters(opcodes::InformationParameters::ReadBD_ADDR) => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&self.address);
+
+                Response::Complete(ret)
+            },
+            StatusParameters(opcodes::StatusParameters::ReadRSSI) => {
+                match self.status_and_handle(handle_at_start(parameter)) {
+                    Response::Complete(mut ret) => {
+                        ret.push(RSSI as u8);
+
+                        Response::Complete(ret)
+                    },
+                    status => status,
+                }
+            },
+            LEController(le_command) => self.process_le_parameter(le_command, parameter, generated),
+            VendorSpecific(_) | Raw => Self::status_only(error::Error::UnknownHCICommand),
+        }
+    }
+
+    fn process_le_parameter(
+        &mut self,
+        command: opcodes::LEController,
+        parameter: &[u8],
+        generated: &mut Generated
+    ) -> Response
+    {
+        use opcodes::LEController::*;
+        use error::Error::{
+            NoError,
+            CommandDisallowed,
+            InvalidHCICommandParameters,
+            MemoryCapacityExceeded,
+            OperationCancelledByHost,
+            UnknownAdvertisingIdentifier,
+            UnknownConnectionIdentifier,
+        };
+
+        match command {
+            SetEventMask => {
+                self.le_event_mask.copy_from_slice(&parameter[..8]);
+
+                Self::status_only(NoError)
+            },
+            ReadBufferSize => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                if self.shared_data_buffers {
+                    ret.extend_from_slice(&[0, 0, 0]);
+                } else {
+                    ret.extend_from_slice(&LE_ACL_DATA_PACKET_LENGTH.to_le_bytes());
+                    ret.push(TOTAL_NUM_LE_ACL_DATA_PACKETS);
+                }
+
+                Response::Complete(ret)
+            },
+            ReadLocalSupportedFeatures => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&LE_FEATURES);
+
+                Response::Complete(ret)
+            },
+            SetRandomAddress => {
+                if self.advertising_enabled || self.scanning_enabled {
+                    Self::status_only(CommandDisallowed)
+                } else {
+                    let mut address = BluetoothDeviceAddress::default();
+
+                    address.copy_from_slice(&parameter[..6]);
+
+                    self.random_address = Some(address);
+
+                    Self::status_only(NoError)
+                }
+            },
+            SetAdvertisingParameters => {
+                let interval_min = u16_at(parameter, 0);
+                let interval_max = u16_at(parameter, 2);
+                let is_high_duty_cycle_directed = parameter[4] == 0x01;
+                let channel_map = parameter[13];
+
+                if self.advertising_enabled {
+                    Self::status_only(CommandDisallowed)
+                } else if channel_map & 0x7 == 0 || (!is_high_duty_cycle_directed &&
+                    (interval_min > interval_max || interval_min < 0x20 || interval_max > 0x4000))
+                {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else {
+                    self.advertising_parameters.copy_from_slice(&parameter[..15]);
+
+                    Self::status_only(NoError)
+                }
+            },
+            ReadAdvertisingChannelTxPower => {
+                Response::Complete(alloc::vec![NoError.into(), TRANSMIT_POWER_LEVEL as u8])
+            },
+            SetAdvertisingData | SetScanResponseData => {
+                let len = parameter[0] as usize;
+
+                if len > 31 {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else {
+                    let data = parameter[1..(1 + len)].to_vec();
+
+                    if let SetAdvertisingData = command {
+                        self.advertising_data = data;
+                    } else {
+                        self.scan_response_data = data;
+                    }
+
+                    Self::status_only(NoError)
+                }
+            },
+            SetAdvertisingEnable => {
+                match parameter[0] {
+                    0 => { self.advertising_enabled = false; Self::status_only(NoError) },
+                    1 => { self.advertising_enabled = true; Self::status_only(NoError) },
+                    _ => Self::status_only(InvalidHCICommandParameters),
+                }
+            },
+            SetScanParameters => {
+                if self.scanning_enabled {
+                    Self::status_only(CommandDisallowed)
+                } else {
+                    self.scan_parameters.copy_from_slice(&parameter[..7]);
+
+                    Self::status_only(NoError)
+                }
+            },
+            SetScanEnable => {
+                if parameter[0] > 1 || parameter[1] > 1 {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else {
+                    self.scanning_enabled = parameter[0] == 1;
+                    self.filter_duplicates = parameter[1] == 1;
+
+                    Self::status_only(NoError)
+                }
+            },
+            CreateConnection => {
+                if self.initiating.is_some() {
+                    Response::Status(CommandDisallowed)
+                } else {
+                    self.initiating = Some(parameter[..25].to_vec());
+
+                    Response::Status(NoError)
+                }
+            },
+            SetExtendedScanParameters => {
+                let num_phys = (parameter[2] & 0x1 != 0) as usize + (parameter[2] & 0x4 != 0) as usize;
+
+                if self.scanning_enabled {
+                    Self::status_only(CommandDisallowed)
+                } else if parameter[0] > 0x3 || parameter[1] > 0x3 || parameter[2] & !0x5 != 0 || num_phys == 0 ||
+                    parameter.len() != 3 + num_phys * 5
+                {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else {
+                    self.extended_scan_parameters = Some(parameter.to_vec());
+
+                    Self::status_only(NoError)
+                }
+            },
+            SetExtendedScanEnable => {
+                // Filtering duplicates for each scan period requires a duration and period
+                if parameter[0] > 1 || parameter[1] > 2 ||
+                    (parameter[0] == 1 && parameter[1] == 2 && (u16_at(parameter, 2) == 0 || u16_at(parameter, 4) == 0))
+                {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else {
+                    if parameter[0] == 1 && !self.scanning_enabled {
+                        self.reported_sets.clear();
+                    }
+
+                    self.scanning_enabled = parameter[0] == 1;
+                    self.filter_duplicates = parameter[1] != 0;
+
+                    Self::status_only(NoError)
+                }
+            },
+            ExtendedCreateConnection => {
+                let initiating_phys = parameter[9];
+
+                let num_phys = initiating_phys.count_ones() as usize;
+
+                // Initiating must be done on at least one of the primary advertising PHYs
+                if self.initiating.is_some() {
+                    Response::Status(CommandDisallowed)
+                } else if initiating_phys & !0x7 != 0 || initiating_phys & 0x5 == 0 ||
+                    parameter.len() != 10 + num_phys * 16
+                {
+                    Response::Status(InvalidHCICommandParameters)
+                } else {
+                    // The value of a per PHY parameter for the first initiating PHY
+                    let first = |index: usize| &parameter[(10 + index * 2)..(12 + index * 2)];
+
+                    let mut initiating = Vec::with_capacity(26);
+
+                    initiating.extend_from_slice(first(0));
+                    initiating.extend_from_slice(first(1));
+                    initiating.push(parameter[0]);
+                    initiating.extend_from_slice(&parameter[2..9]);
+                    initiating.push(parameter[1]);
+
+                    (2..8).for_each(|index| initiating.extend_from_slice(first(index)));
+
+                    initiating.push(initiating_phys);
+
+                    self.initiating = Some(initiating);
+
+                    Response::Status(NoError)
+                }
+            },
+            CreateConnectionCancel => {
+                match self.initiating.take() {
+                    Some(initiating) => {
+                        // The connection complete event is still sent when the creation of a
+                        // connection is canceled, the values are taken from the parameters of the
+                        // LE Create Connection command.
+                        let mut event_parameter = alloc::vec![UnknownConnectionIdentifier.into()];
+
+                        event_parameter.extend_from_slice(&0u16.to_le_bytes());
+                        event_parameter.push(0);
+                        event_parameter.push(initiating[5]);
+                        event_parameter.extend_from_slice(&initiating[6..12]);
+                        event_parameter.extend_from_slice(&initiating[13..15]);
+                        event_parameter.extend_from_slice(&initiating[17..21]);
+                        event_parameter.push(0);
+
+                        // Initiating by the LE Extended Create Connection command is canceled with
+                        // the LE Enhanced Connection Complete event
+                        generated.events.push(if initiating.len() > 25 {
+                            le_meta_event_packet(
+                                events::LEMeta::EnhancedConnectionComplete,
+                                &into_enhanced_connection_complete(&event_parameter)
+                            )
+                        } else {
+                            le_meta_event_packet(events::LEMeta::ConnectionComplete, &event_parameter)
+                        });
+
+                        Self::status_only(NoError)
+                    }
+                    None => Self::status_only(CommandDisallowed),
+                }
+            },
+            ReadWhiteListSize => {
+                Response::Complete(alloc::vec![NoError.into(), self.white_list_size as u8])
+            },
+            ClearWhiteList => {
+                if self.is_white_list_in_use() {
+                    Self::status_only(CommandDisallowed)
+                } else {
+                    self.white_list.clear();
+
+                    Self::status_only(NoError)
+                }
+            },
+            AddDeviceToWhiteList | RemoveDeviceFromWhiteList => {
+                let mut entry = WhiteListEntry {
+                    address_type: parameter[0],
+                    address: BluetoothDeviceAddress::default(),
+                };
+
+                entry.address.copy_from_slice(&parameter[1..7]);
+
+                if self.is_white_list_in_use() {
+                    Self::status_only(CommandDisallowed)
+                } else if let RemoveDeviceFromWhiteList = command {
+                    self.white_list.retain(|listed| listed != &entry);
+
+                    Self::status_only(NoError)
+                } else if self.white_list.contains(&entry) {
+                    Self::status_only(NoError)
+                } else if self.white_list.len() >= self.white_list_size {
+                    Self::status_only(MemoryCapacityExceeded)
+                } else {
+                    self.white_list.push(entry);
+
+                    Self::status_only(NoError)
+                }
+            },
+            ConnectionUpdate => {
+                let handle = handle_at_start(parameter);
+
+                if self.is_connected(handle) {
+                    // The connection interval used is the maximum interval requested by the host
+                    let mut event_parameter = alloc::vec![NoError.into()];
+
+                    event_parameter.extend_from_slice(&handle.to_le_bytes());
+                    event_parameter.extend_from_slice(&parameter[4..10]);
+
+                    let packet = le_meta_event_packet(events::LEMeta::ConnectionUpdateComplete, &event_parameter);
+
+                    if self.is_linked(handle) {
+                        generated.peer.push(PeerAction::Event(packet.clone()));
+                    }
+
+                    generated.events.push(packet);
+
+                    Response::Status(NoError)
+                } else {
+                    Response::Status(UnknownConnectionIdentifier)
+                }
+            },
+            SetHostChannelClassification => Self::status_only(NoError),
+            ReadChannelMap => {
+                match self.status_and_handle(handle_at_start(parameter)) {
+                    Response::Complete(mut ret) => {
+                        ret.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]);
+
+                        Response::Complete(ret)
+                    },
+                    status => status,
+                }
+            },
+            ReadRemoteFeatures => {
+                let handle = handle_at_start(parameter);
+
+                if self.is_connected(handle) {
+                    let mut event_parameter = alloc::vec![NoError.into()];
+
+                    event_parameter.extend_from_slice(&handle.to_le_bytes());
+                    event_parameter.extend_from_slice(&LE_FEATURES);
+
+                    generated.events.push(le_meta_event_packet(events::LEMeta::ReadRemoteFeaturesComplete, &event_parameter));
+
+                    Response::Status(NoError)
+                } else {
+                    Response::Status(UnknownConnectionIdentifier)
+                }
+            },
+            Encrypt => {
+                let mut key = [0u8;16];
+                let mut plain_text = [0u8;16];
+
+                key.copy_from_slice(&parameter[..16]);
+                plain_text.copy_from_slice(&parameter[16..32]);
+
+                let cypher_text = crate::sm::toolbox::e(
+                    <u128>::from_be_bytes(key),
+                    <u128>::from_be_bytes(plain_text)
+                );
+
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&cypher_text.to_be_bytes());
+
+                Response::Complete(ret)
+            },
+            Rand => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&self.next_random().to_le_bytes());
+
+                Response::Complete(ret)
+            },
+            StartEncryption if self.is_linked(handle_at_start(parameter)) => {
+                let mut random_number = [0u8;8];
+                let mut encrypted_diversifier = [0u8;2];
+                let mut long_term_key = [0u8;16];
+
+                random_number.copy_from_slice(&parameter[2..10]);
+                encrypted_diversifier.copy_from_slice(&parameter[10..12]);
+                long_term_key.copy_from_slice(&parameter[12..28]);
+
+                generated.peer.push(PeerAction::EncryptionRequest {
+                    handle: handle_at_start(parameter),
+                    random_number,
+                    encrypted_diversifier,
+                    long_term_key,
+                });
+
+                Response::Status(NoError)
+            },
+            LongTermKeyRequestReply if self.pending_encryption.contains_key(&handle_at_start(parameter)) => {
+                let handle = handle_at_start(parameter);
+
+                let expected_key = self.pending_encryption.remove(&handle).unwrap();
+
+                // A key mismatch would cause the MIC check to fail on a real link, here the
+                // encryption change is reported as failed on both ends.
+                let event_parameter = if expected_key[..] == parameter[2..18] {
+                    Self::encryption_change_parameter(NoError, handle, true)
+                } else {
+                    Self::encryption_change_parameter(error::Error::PINorKeyMissing, handle, false)
+                };
+
+                let packet = event_packet(events::Events::EncryptionChange.get_val(), &event_parameter);
+
+                generated.peer.push(PeerAction::Event(packet.clone()));
+
+                generated.events.push(packet);
+
+                self.status_and_handle(handle)
+            },
+            LongTermKeyRequestNegativeReply if self.pending_encryption.contains_key(&handle_at_start(parameter)) => {
+                let handle = handle_at_start(parameter);
+
+                self.pending_encryption.remove(&handle);
+
+                let event_parameter = Self::encryption_change_parameter(error::Error::PINorKeyMissing, handle, false);
+
+                generated.peer.push(PeerAction::Event(
+                    event_packet(events::Events::EncryptionChange.get_val(), &event_parameter)
+                ));
+
+                self.status_and_handle(handle)
+            },
+            StartEncryption | LongTermKeyRequestReply => {
+                let handle = handle_at_start(parameter);
+
+                let response = if let StartEncryption = command {
+                    Response::Status(if self.is_connected(handle) { NoError } else { UnknownConnectionIdentifier })
+                } else {
+                    self.status_and_handle(handle)
+                };
+
+                if self.is_connected(handle) {
+                    let event_parameter = Self::encryption_change_parameter(NoError, handle, true);
+
+                    generated.events.push(event_packet(events::Events::EncryptionChange.get_val(), &event_parameter));
+                }
+
+                response
+            },
+            LongTermKeyRequestNegativeReply |
+            ReadConnectionParameterRequestReply |
+            ReadConnectionParameterRequestNegativeReply => {
+                self.status_and_handle(handle_at_start(parameter))
+            },
+            ReadSupportedStates => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&LE_SUPPORTED_STATES);
+
+                Response::Complete(ret)
+            },
+            ReceiverTest | TransmitterTest => Self::status_only(NoError),
+            TestEnd => Response::Complete(alloc::vec![NoError.into(), 0, 0]),
+            SetDataLength => {
+                let handle = handle_at_start(parameter);
+                let tx_octets = u16_at(parameter, 2);
+                let tx_time = u16_at(parameter, 4);
+
+                if !Self::is_valid_data_length(tx_octets, tx_time) {
+                    let mut ret = alloc::vec![InvalidHCICommandParameters.into()];
+
+                    ret.extend_from_slice(&handle.to_le_bytes());
+
+                    Response::Complete(ret)
+                } else {
+                    if self.is_connected(handle) {
+                        // The suggested length is used up to the maximum supported by the
+                        // controller, the receive length is always the supported maximum.
+                        let mut event_parameter = handle.to_le_bytes().to_vec();
+
+                        event_parameter.extend_from_slice(&tx_octets.min(MAX_DATA_LENGTH.0).to_le_bytes());
+                        event_parameter.extend_from_slice(&tx_time.min(MAX_DATA_LENGTH.1).to_le_bytes());
+                        event_parameter.extend_from_slice(&MAX_DATA_LENGTH.0.to_le_bytes());
+                        event_parameter.extend_from_slice(&MAX_DATA_LENGTH.1.to_le_bytes());
+
+                        generated.events.push(le_meta_event_packet(events::LEMeta::DataLengthChange, &event_parameter));
+
+                        if self.is_linked(handle) {
+                            // The transmit and receive lengths are swapped for the peer
+                            let (tx, rx) = event_parameter[2..].split_at(4);
+
+                            let peer_parameter = [&handle.to_le_bytes()[..], rx, tx].concat();
+
+                            generated.peer.push(PeerAction::Event(
+                                le_meta_event_packet(events::LEMeta::DataLengthChange, &peer_parameter)
+                            ));
+                        }
+                    }
+
+                    self.status_and_handle(handle)
+                }
+            },
+            ReadSuggestedDefaultDataLength => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&self.suggested_data_length.0.to_le_bytes());
+                ret.extend_from_slice(&self.suggested_data_length.1.to_le_bytes());
+
+                Response::Complete(ret)
+            },
+            WriteSuggestedDefaultDataLength => {
+                let octets = u16_at(parameter, 0);
+                let time = u16_at(parameter, 2);
+
+                if Self::is_valid_data_length(octets, time) {
+                    self.suggested_data_length = (octets, time);
+
+                    Self::status_only(NoError)
+                } else {
+                    Self::status_only(InvalidHCICommandParameters)
+                }
+            },
+            ReadMaximumDataLength => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                for _ in 0..2 {
+                    ret.extend_from_slice(&MAX_DATA_LENGTH.0.to_le_bytes());
+                    ret.extend_from_slice(&MAX_DATA_LENGTH.1.to_le_bytes());
+                }
+
+                Response::Complete(ret)
+            },
+            AddDeviceToResolvingList => {
+                if self.is_resolving_list_in_use() {
+                    Self::status_only(CommandDisallowed)
+                } else if self.resolving_list_position(parameter).is_some() {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else if self.resolving_list.len() >= RESOLVING_LIST_SIZE {
+                    Self::status_only(MemoryCapacityExceeded)
+                } else {
+                    let mut entry = ResolvingListEntry {
+                        address_type: parameter[0],
+                        address: BluetoothDeviceAddress::default(),
+                        peer_irk: [0;16],
+                        local_irk: [0;16],
+                        privacy_mode: 0,
+                        peer_rpa: None,
+                        local_rpa: None,
+                    };
+
+                    entry.address.copy_from_slice(&parameter[1..7]);
+                    entry.peer_irk.copy_from_slice(&parameter[7..23]);
+                    entry.local_irk.copy_from_slice(&parameter[23..39]);
+
+                    entry.peer_rpa = self.new_rpa(entry.peer_irk);
+                    entry.local_rpa = self.new_rpa(entry.local_irk);
+
+                    self.resolving_list.push(entry);
+
+                    Self::status_only(NoError)
+                }
+            },
+            RemoveDeviceFromResolvingList => {
+                match self.resolving_list_position(parameter) {
+                    _ if self.is_resolving_list_in_use() => Self::status_only(CommandDisallowed),
+                    Some(index) => {
+                        self.resolving_list.remove(index);
+
+                        Self::status_only(NoError)
+                    },
+                    None => Self::status_only(UnknownConnectionIdentifier),
+                }
+            },
+            ClearResolvingList => {
+                if self.is_resolving_list_in_use() {
+                    Self::status_only(CommandDisallowed)
+                } else {
+                    self.resolving_list.clear();
+
+                    Self::status_only(NoError)
+                }
+            },
+            ReadResolvingListSize => {
+                Response::Complete(alloc::vec![NoError.into(), RESOLVING_LIST_SIZE as u8])
+            },
+            ReadPeerResolvableAddress | ReadLocalResolvableAddress => {
+                let rpa = self.resolving_list_position(parameter)
+                    .and_then(|index| if let ReadPeerResolvableAddress = command {
+                        self.resolving_list[index].peer_rpa
+                    } else {
+                        self.resolving_list[index].local_rpa
+                    });
+
+                match rpa {
+                    Some(rpa) => {
+                        let mut ret = alloc::vec![NoError.into()];
+
+                        ret.extend_from_slice(&rpa);
+
+                        Response::Complete(ret)
+                    },
+                    None => Response::Complete(alloc::vec![UnknownConnectionIdentifier.into(), 0, 0, 0, 0, 0, 0]),
+                }
+            },
+            SetAddressResolutionEnable => {
+                if self.is_advertising_enabled() || self.scanning_enabled || self.initiating.is_some() {
+                    Self::status_only(CommandDisallowed)
+                } else if parameter[0] > 1 {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else {
+                    self.address_resolution_enabled = parameter[0] == 1;
+
+                    Self::status_only(NoError)
+                }
+            },
+            SetResolvablePrivateAddressTimeout => {
+                let timeout = u16_at(parameter, 0);
+
+                if (0x1..=0xA1B8).contains(&timeout) {
+                    self.rpa_timeout = timeout;
+
+                    Self::status_only(NoError)
+                } else {
+                    Self::status_only(InvalidHCICommandParameters)
+                }
+            },
+            SetPrivacyMode => {
+                match self.resolving_list_position(parameter) {
+                    _ if self.is_resolving_list_in_use() => Self::status_only(CommandDisallowed),
+                    _ if parameter[7] > 1 => Self::status_only(InvalidHCICommandParameters),
+                    Some(index) => {
+                        self.resolving_list[index].privacy_mode = parameter[7];
+
+                        Self::status_only(NoError)
+                    },
+                    None => Self::status_only(UnknownConnectionIdentifier),
+                }
+            },
+            ReadPHY => {
+                let handle = handle_at_start(parameter);
+
+                match self.status_and_handle(handle) {
+                    Response::Complete(mut ret) => {
+                        let (tx_phy, rx_phy) = self.get_phys(handle);
+
+                        ret.extend_from_slice(&[tx_phy, rx_phy]);
+
+                        Response::Complete(ret)
+                    },
+                    status => status,
+                }
+            },
+            SetDefaultPHY => {
+                if Self::is_valid_phys(parameter[0], parameter[1], parameter[2]) {
+                    self.default_phys.copy_from_slice(&parameter[..3]);
+
+                    Self::status_only(NoError)
+                } else {
+                    Self::status_only(InvalidHCICommandParameters)
+                }
+            },
+            SetPHY => {
+                let handle = handle_at_start(parameter);
+
+                if !Self::is_valid_phys(parameter[2], parameter[3], parameter[4]) || u16_at(parameter, 5) > 2 {
+                    Response::Status(InvalidHCICommandParameters)
+                } else if self.is_connected(handle) {
+                    let (tx_phy, rx_phy) = self.get_phys(handle);
+
+                    let event_parameter = [
+                        NoError.into(),
+                        handle.to_le_bytes()[0],
+                        handle.to_le_bytes()[1],
+                        Self::choose_phy(parameter[2] & 0x1 != 0, parameter[3], tx_phy),
+                        Self::choose_phy(parameter[2] & 0x2 != 0, parameter[4], rx_phy),
+                    ];
+
+                    generated.events.push(le_meta_event_packet(events::LEMeta::PHYUpdateComplete, &event_parameter));
+
+                    if self.is_linked(handle) {
+                        // The transmit and receive PHYs are swapped for the peer
+                        let peer_parameter = [
+                            event_parameter[0],
+                            event_parameter[1],
+                            event_parameter[2],
+                            event_parameter[4],
+                            event_parameter[3],
+                        ];
+
+                        generated.peer.push(PeerAction::Event(
+                            le_meta_event_packet(events::LEMeta::PHYUpdateComplete, &peer_parameter)
+                        ));
+                    }
+
+                    Response::Status(NoError)
+                } else {
+                    Response::Status(UnknownConnectionIdentifier)
+                }
+            },
+            SetAdvertisingSetRandomAddress => {
+                match self.advertising_set_position(parameter[0]) {
+                    Some(index) if self.advertising_sets[index].enabled => Self::status_only(CommandDisallowed),
+                    Some(index) => {
+                        let mut address = BluetoothDeviceAddress::default();
+
+                        address.copy_from_slice(&parameter[1..7]);
+
+                        self.advertising_sets[index].random_address = Some(address);
+
+                        Self::status_only(NoError)
+                    },
+                    None => Self::status_only(UnknownAdvertisingIdentifier),
+                }
+            },
+            SetExtendedAdvertisingParameters => {
+                let handle = parameter[0];
+                let interval_min = u32::from_le_bytes([parameter[3], parameter[4], parameter[5], 0]);
+                let interval_max = u32::from_le_bytes([parameter[6], parameter[7], parameter[8], 0]);
+                let requested_tx_power = parameter[19] as i8;
+
+                let index = self.advertising_set_position(handle);
+
+                let status = if handle > 0xEF || interval_min < 0x20 || interval_min > interval_max ||
+                    parameter[9] & 0x7 == 0
+                {
+                    InvalidHCICommandParameters
+                } else if index.map(|index| self.advertising_sets[index].enabled).unwrap_or_default() {
+                    CommandDisallowed
+                } else if index.is_none() && self.advertising_sets.len() >= NUM_SUPPORTED_ADVERTISING_SETS {
+                    MemoryCapacityExceeded
+                } else {
+                    let mut parameters = [0u8;24];
+
+                    parameters.copy_from_slice(&parameter[1..25]);
+
+                    match index {
+                        Some(index) => self.advertising_sets[index].parameters = parameters,
+                        None => self.advertising_sets.push(ExtendedAdvertisingSet {
+                            handle,
+                            parameters,
+                            random_address: None,
+                            advertising_data: Vec::new(),
+                            scan_response_data: Vec::new(),
+                            enabled: false,
+                            duration: 0,
+                            max_extended_advertising_events: 0,
+                            periodic_parameters: None,
+                            periodic_data: Vec::new(),
+                            periodic_enabled: false,
+                        }),
+                    }
+
+                    NoError
+                };
+
+                // The selected transmit power is the requested power limited by the power level of
+                // the virtual controller (0x7F is no preference)
+                let selected_tx_power = if requested_tx_power == 0x7F {
+                    TRANSMIT_POWER_LEVEL
+                } else {
+                    requested_tx_power.min(TRANSMIT_POWER_LEVEL)
+                };
+
+                Response::Complete(alloc::vec![status.into(), selected_tx_power as u8])
+            },
+            SetExtendedAdvertisingData => Self::status_only(self.set_extended_data(false, parameter)),
+            SetExtendedScanResponseData => Self::status_only(self.set_extended_data(true, parameter)),
+            SetExtendedAdvertisingEnable => {
+                let (enable, num_sets) = (parameter[0], parameter[1] as usize);
+
+                // The handle, duration, and maximum number of events are grouped for each set
+                let entries = parameter.get(2..).unwrap_or_default().chunks(4);
+
+                let indexes: Option<Vec<usize>> = entries.clone()
+                    .map(|entry| self.advertising_set_position(entry[0]))
+                    .collect();
+
+                if enable > 1 || parameter.len() != 2 + num_sets * 4 || (enable == 1 && num_sets == 0) {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else if enable == 0 && num_sets == 0 {
+                    // Disabling with no sets disables all advertising sets
+                    self.advertising_sets.iter_mut().for_each(|set| set.enabled = false);
+
+                    Self::status_only(NoError)
+                } else if let Some(indexes) = indexes {
+                    for (entry, index) in entries.zip(indexes) {
+                        let set = &mut self.advertising_sets[index];
+
+                        set.enabled = enable == 1;
+
+                        if set.enabled {
+                            set.duration = u16_at(entry, 1);
+                            set.max_extended_advertising_events = entry[3];
+                        }
+                    }
+
+                    Self::status_only(NoError)
+                } else {
+                    Self::status_only(UnknownAdvertisingIdentifier)
+                }
+            },
+            ReadMaximumAdvertisingDataLength => {
+                let mut ret = alloc::vec![NoError.into()];
+
+                ret.extend_from_slice(&MAX_ADVERTISING_DATA_LENGTH.to_le_bytes());
+
+                Response::Complete(ret)
+            },
+            ReadNumberOfSupportedAdvertisingSets => {
+                Response::Complete(alloc::vec![NoError.into(), NUM_SUPPORTED_ADVERTISING_SETS as u8])
+            },
+            RemoveAdvertisingSet => {
+                match self.advertising_set_position(parameter[0]) {
+                    Some(index) if self.advertising_sets[index].enabled || self.advertising_sets[index].periodic_enabled =>
+                        Self::status_only(CommandDisallowed),
+                    Some(index) => {
+                        self.advertising_sets.remove(index);
+
+                        Self::status_only(NoError)
+                    },
+                    None => Self::status_only(UnknownAdvertisingIdentifier),
+                }
+            },
+            ClearAdvertisingSets => {
+                if self.advertising_sets.iter().any(|set| set.enabled || set.periodic_enabled) {
+                    Self::status_only(CommandDisallowed)
+                } else {
+                    self.advertising_sets.clear();
+
+                    Self::status_only(NoError)
+                }
+            },
+            SetPeriodicAdvertisingParameters => {
+                let (interval_min, interval_max) = (u16_at(parameter, 1), u16_at(parameter, 3));
+
+                match self.advertising_set_position(parameter[0]) {
+                    Some(index) => {
+                        let set = &mut self.advertising_sets[index];
+
+                        // Periodic advertising is not used by legacy, connectable, scannable, or
+                        // anonymous advertising
+                        if set.periodic_enabled {
+                            Self::status_only(CommandDisallowed)
+                        } else if interval_min < 0x6 || interval_min > interval_max || set.event_properties() & 0x33 != 0 {
+                            Self::status_only(InvalidHCICommandParameters)
+                        } else {
+                            let mut periodic_parameters = [0u8;6];
+
+                            periodic_parameters.copy_from_slice(&parameter[1..7]);
+
+                            set.periodic_parameters = Some(periodic_parameters);
+
+                            Self::status_only(NoError)
+                        }
+                    },
+                    None => Self::status_only(UnknownAdvertisingIdentifier),
+                }
+            },
+            SetPeriodicAdvertisingData => Self::status_only(self.set_periodic_data(parameter)),
+            SetPeriodicAdvertisingEnable => {
+                match self.advertising_set_position(parameter[1]) {
+                    _ if parameter[0] > 1 => Self::status_only(InvalidHCICommandParameters),
+                    Some(index) => {
+                        let set = &mut self.advertising_sets[index];
+
+                        if parameter[0] == 1 && set.periodic_parameters.is_none() {
+                            Self::status_only(CommandDisallowed)
+                        } else {
+                            set.periodic_enabled = parameter[0] == 1;
+
+                            Self::status_only(NoError)
+                        }
+                    },
+                    None => Self::status_only(UnknownAdvertisingIdentifier),
+                }
+            },
+            PeriodicAdvertisingCreateSync => {
+                let (skip, sync_timeout) = (u16_at(parameter, 9), u16_at(parameter, 11));
+
+                if self.creating_sync.is_some() {
+                    Response::Status(CommandDisallowed)
+                } else if parameter[0] > 1 || parameter[1] > 0xF || parameter[2] > 1 || skip > 0x1F3 ||
+                    !(0xA..=0x4000).contains(&sync_timeout)
+                {
+                    Response::Status(InvalidHCICommandParameters)
+                } else {
+                    let mut create_parameter = [0u8;14];
+
+                    create_parameter.copy_from_slice(&parameter[..14]);
+
+                    self.creating_sync = Some(create_parameter);
+
+                    Response::Status(NoError)
+                }
+            },
+            PeriodicAdvertisingCreateSyncCancel => {
+                match self.creating_sync.take() {
+                    Some(create_parameter) => {
+                        // The sync established event is still sent when synchronizing is canceled.
+                        // The values other than the status are not used by the host, but they must
+                        // be valid to be sent as an event.
+                        let mut event_parameter = alloc::vec![OperationCancelledByHost.into()];
+
+                        event_parameter.extend_from_slice(&0u16.to_le_bytes());
+                        event_parameter.extend_from_slice(&create_parameter[1..9]);
+                        event_parameter.push(0x1);
+                        event_parameter.extend_from_slice(&0x6u16.to_le_bytes());
+                        event_parameter.push(0);
+
+                        generated.events.push(
+                            le_meta_event_packet(events::LEMeta::PeriodicAdvertisingSyncEstablished, &event_parameter)
+                        );
+
+                        Self::status_only(NoError)
+                    },
+                    None => Self::status_only(CommandDisallowed),
+                }
+            },
+            PeriodicAdvertisingTerminateSync => {
+                let sync_handle = u16_at(parameter, 0);
+
+                if self.creating_sync.is_some() {
+                    Self::status_only(CommandDisallowed)
+                } else if self.syncs.contains(&sync_handle) {
+                    self.syncs.retain(|handle| *handle != sync_handle);
+
+                    self.linked_syncs.remove(&sync_handle);
+
+                    Self::status_only(NoError)
+                } else {
+                    Self::status_only(UnknownAdvertisingIdentifier)
+                }
+            },
+            AddDeviceToPeriodicAdvertiserList => {
+                let mut entry = [0u8;8];
+
+                entry.copy_from_slice(&parameter[..8]);
+
+                if self.is_periodic_advertiser_list_in_use() {
+                    Self::status_only(CommandDisallowed)
+                } else if entry[0] > 1 || entry[7] > 0xF || self.periodic_advertiser_list.contains(&entry) {
+                    Self::status_only(InvalidHCICommandParameters)
+                } else if self.periodic_advertiser_list.len() >= PERIODIC_ADVERTISER_LIST_SIZE {
+                    Self::status_only(MemoryCapacityExceeded)
+                } else {
+                    self.periodic_advertiser_list.push(entry);
+
+                    Self::status_only(NoError)
+                }
+            },
+            RemoveDeviceFromPeriodicAdvertiserList => {
+                let position = self.periodic_advertiser_list.iter().position(|entry| entry[..] == parameter[..8]);
+
+                match position {
+                    _ if self.is_periodic_advertiser_list_in_use() => Self::status_only(CommandDisallowed),
+                    Some(index) => {
+                        self.periodic_advertiser_list.remove(index);
+
+                        Self::status_only(NoError)
+                    },
+                    None => Self::status_only(UnknownAdvertisingIdentifier),
+                }
+            },
+            ClearPeriodicAdvertiserList => {
+                if self.is_periodic_advertiser_list_in_use() {
+                    Self::status_only(CommandDisallowed)
+                } else {
+                    self.periodic_advertiser_list.clear();
+
+                    Self::status_only(NoError)
+                }
+            },
+            ReadPeriodicAdvertiserListSize => {
+                Response::Complete(alloc::vec![NoError.into(), PERIODIC_ADVERTISER_LIST_SIZE as u8])
+            },
+        }
+    }
+
+    /// Add an event packet to the events to be received by the host
+    ///
+    /// The state of the controller is updated for events that affect it, e.g. a connection complete
+    /// event adds a connection and a disconnection complete event removes one.
+    fn push_event(&mut self, packet: &[u8]) -> Result<(), Error> {
+        let event_data = events::EventsData::from_packet(packet).map_err(Error::InvalidEvent)?;
+
+        match &event_data {
+            events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data)) =>
+                self.complete_connection(data.status, data.connection_handle, &data.role),
+            events::EventsData::LEMeta(events::LEMetaData::EnhancedConnectionComplete(data)) =>
+                self.complete_connection(data.status, data.connection_handle, &data.role),
+            events::EventsData::LEMeta(events::LEMetaData::PeriodicAdvertisingSyncEstablished(data)) => {
+                self.creating_sync = None;
+
+                let raw_handle = data.sync_handle.get_raw_handle();
+
+                if let error::Error::NoError = data.status {
+                    if !self.syncs.contains(&raw_handle) {
+                        self.syncs.push(raw_handle);
+                    }
+                }
+            },
+            events::EventsData::LEMeta(events::LEMetaData::PeriodicAdvertisingSyncLost(data)) => {
+                let raw_handle = data.sync_handle.get_raw_handle();
+
+                self.syncs.retain(|handle| *handle != raw_handle);
+
+                self.linked_syncs.remove(&raw_handle);
+            },
+            events::EventsData::DisconnectionComplete(data) => {
+                let raw_handle = data.connection_handle.get_raw_handle();
+
+                self.connections.retain(|handle| *handle != raw_handle);
+                self.linked.retain(|handle| *handle != raw_handle);
+                self.pending_encryption.remove(&raw_handle);
+                self.phys.remove(&raw_handle);
+            },
+            events::EventsData::LEMeta(events::LEMetaData::PHYUpdateComplete(data)) => {
+                if let error::Error::NoError = data.status {
+                    let raw_handle = data.connection_handle.get_raw_handle();
+
+                    let into_raw = |phy: &events::LEPhy| match phy {
+                        events::LEPhy::_1M => 0x1,
+                        events::LEPhy::_2M => 0x2,
+                        events::LEPhy::Coded => 0x3,
+                    };
+
+                    self.phys.insert(raw_handle, (into_raw(&data.tx_phy), into_raw(&data.rx_phy)));
+                }
+            },
+            events::EventsData::LEMeta(events::LEMetaData::AdvertisingSetTerminated(data)) => {
+                self.advertising_sets.iter_mut()
+                    .filter(|set| set.handle == data.advertising_handle)
+                    .for_each(|set| set.enabled = false);
+            },
+            _ => (),
+        }
+
+        self.events.push_back(event_data);
+
+        Ok(())
+    }
+
+    /// Update the state for a LE Connection Complete or LE Enhanced Connection Complete event
+    fn complete_connection(&mut self, status: error::Error, handle: ConnectionHandle, role: &events::LERole) {
+        self.initiating = None;
+
+        if let error::Error::NoError = status {
+            let raw_handle = handle.get_raw_handle();
+
+            if let events::LERole::Slave = role {
+                self.advertising_enabled = false;
+            }
+
+            if !self.is_connected(raw_handle) {
+                self.connections.push(raw_handle);
+            }
+        }
+    }
+
+    fn take_event_wakers(&mut self) -> Vec<Waker> {
+        core::mem::take(&mut self.event_wakers)
+    }
+}
+
+/// A virtual Bluetooth controller
+///
+/// The virtual controller is a stand in for a real Bluetooth controller. Clones of a
+/// `VirtualController` are handles to the same controller, so a clone can be kept by a test to
+/// query the state of the controller or inject events and data after the original is moved into
+/// a [`HostInterface`](super::HostInterface).
+#[derive(Clone)]
+pub struct VirtualController {
+    state: Arc<SpinLock<State>>,
+}
+
+impl VirtualController {
+
+    /// Create a new virtual controller with the provided public address
+    pub fn new(address: BluetoothDeviceAddress) -> Self {
+        VirtualController { state: Arc::new(SpinLock::new(State::new(address))) }
+    }
+
+    /// Link this controller with another virtual controller
+    ///
+    /// After linking, each controller is the peer device of the other. A connection is created
+    /// as soon as one controller is initiating a connection to the other while the other is
+    /// advertising. Any previous link of either controller is replaced.
+    ///
+    /// # Panic
+    /// A virtual controller cannot be linked with itself (or a clone of itself).
+    pub fn link(&self, peer: &VirtualController) {
+        assert!(!Arc::ptr_eq(&self.state, &peer.state), "a virtual controller cannot be linked to itself");
+
+        let next_handle = Arc::new(AtomicU16::new(1));
+
+        self.state.lock().link = Some(Link {
+            peer: Arc::downgrade(&peer.state),
+            next_handle: next_handle.clone(),
+        });
+
+        peer.state.lock().link = Some(Link {
+            peer: Arc::downgrade(&self.state),
+            next_handle,
+        });
+
+        self.establish_link_connections();
+    }
+
+    /// Set the number of entries that can be in the white list
+    ///
+    /// The white list size is not changed by the HCI reset command.
+    pub fn set_white_list_size(&self, size: usize) {
+        self.state.lock().white_list_size = size;
+    }
+
+    /// Set if the LE ACL data buffers are shared with BR/EDR
+    ///
+    /// When the buffers are shared, the *LE Read Buffer Size* command returns zero for the buffer
+    /// size. This is not changed by the HCI reset command.
+    pub fn set_shared_data_buffers(&self, shared: bool) {
+        self.state.lock().shared_data_buffers = shared;
+    }
+
+    /// Get the public address of the controller
+    pub fn get_address(&self) -> BluetoothDeviceAddress {
+        self.state.lock().address
+    }
+
+    /// Get the random address set by the host
+    pub fn get_random_address(&self) -> Option<BluetoothDeviceAddress> {
+        self.state.lock().random_address
+    }
+
+    /// Get the event mask set by the host
+    pub fn get_event_mask(&self) -> [u8;8] {
+        self.state.lock().event_mask
+    }
+
+    /// Get the LE event mask set by the host
+    pub fn get_le_event_mask(&self) -> [u8;8] {
+        self.state.lock().le_event_mask
+    }
+
+    /// Get the entries of the white list
+    pub fn get_white_list(&self) -> Vec<WhiteListEntry> {
+        self.state.lock().white_list.clone()
+    }
+
+    /// Get the entries of the resolving list
+    pub fn get_resolving_list(&self) -> Vec<ResolvingListEntry> {
+        self.state.lock().resolving_list.clone()
+    }
+
+    /// Check if address resolution is enabled
+    pub fn is_address_resolution_enabled(&self) -> bool {
+        self.state.lock().address_resolution_enabled
+    }
+
+    /// Get the RPA timeout in seconds
+    pub fn get_rpa_timeout(&self) -> u16 {
+        self.state.lock().rpa_timeout
+    }
+
+    /// Check if advertising is enabled
+    pub fn is_advertising(&self) -> bool {
+        self.state.lock().advertising_enabled
+    }
+
+    /// Check if the controller is synchronizing with periodic advertising
+    pub fn is_synchronizing(&self) -> bool {
+        self.state.lock().creating_sync.is_some()
+    }
+
+    /// Get the raw sync handles of the established syncs
+    pub fn get_sync_handles(&self) -> Vec<u16> {
+        self.state.lock().syncs.clone()
+    }
+
+    /// Get the parameters set by the *LE Set Extended Scan Parameters* command
+    ///
+    /// The parameters are returned in the format of the parameter of the command.
+    pub fn get_extended_scan_parameters(&self) -> Option<Vec<u8>> {
+        self.state.lock().extended_scan_parameters.clone()
+    }
+
+    /// Get the advertising sets
+    pub fn get_advertising_sets(&self) -> Vec<ExtendedAdvertisingSet> {
+        self.state.lock().advertising_sets.clone()
+    }
+
+    /// Get the advertising parameters
+    ///
+    /// The parameters are returned in the format of the parameter of the *LE Set Advertising
+    /// Parameters* command.
+    pub fn get_advertising_parameters(&self) -> [u8;15] {
+        self.state.lock().advertising_parameters
+    }
+
+    /// Get the significant part of the advertising data
+    pub fn get_advertising_data(&self) -> Vec<u8> {
+        self.state.lock().advertising_data.clone()
+    }
+
+    /// Get the significant part of the scan response data
+    pub fn get_scan_response_data(&self) -> Vec<u8> {
+        self.state.lock().scan_response_data.clone()
+    }
+
+    /// Check if scanning is enabled
+    pub fn is_scanning(&self) -> bool {
+        self.state.lock().scanning_enabled
+    }
+
+    /// Check if duplicate advertising reports are filtered while scanning
+    pub fn is_filtering_duplicates(&self) -> bool {
+        self.state.lock().filter_duplicates
+    }
+
+    /// Get the scan parameters
+    ///
+    /// The parameters are returned in the format of the parameter of the *LE Set Scan Parameters*
+    /// command.
+    pub fn get_scan_parameters(&self) -> [u8;7] {
+        self.state.lock().scan_parameters
+    }
+
+    /// Check if the controller is creating a connection
+    pub fn is_initiating(&self) -> bool {
+        self.state.lock().initiating.is_some()
+    }
+
+    /// Get the connection handles of the current connections
+    pub fn get_connections(&self) -> Vec<ConnectionHandle> {
+        self.state.lock().connections.iter()
+            .filter_map(|raw| ConnectionHandle::try_from(*raw).ok())
+            .collect()
+    }
+
+    /// Inject an event
+    ///
+    /// The `parameter` is the event parameter (the part of the HCI event packet after the
+    /// parameter total length field). For a LE Meta event the parameter must not contain the sub
+    /// event code.
+    ///
+    /// An error is returned if the event parameter cannot be converted into event data.
+    pub fn inject_event(&self, event: events::Events, parameter: &[u8]) -> Result<(), Error> {
+        let packet = match event {
+            events::Events::LEMeta(sub_event) => le_meta_event_packet(sub_event, parameter),
+            _ => event_packet(event.get_val(), parameter),
+        };
+
+        self.push_events(core::iter::once(packet))
+    }
+
+    /// Inject a LE Meta event
+    ///
+    /// This is the same as [`inject_event`](VirtualController::inject_event) with a LE Meta event.
+    pub fn inject_le_meta_event(&self, sub_event: events::LEMeta, parameter: &[u8]) -> Result<(), Error> {
+        self.inject_event(events::Events::LEMeta(sub_event), parameter)
+    }
+
+    /// Inject ACL data from the peer device
+    ///
+    /// The data is dropped if no receiver is started for the connection handle.
+    pub fn inject_acl_data(&self, data: HciAclData) {
+        self.notify_listener(HciPacketType::AclData, &data.get_packet());
+
+        let waker = {
+            let mut state = self.state.lock();
+
+            match state.acl_receivers.get_mut(data.get_handle()) {
+                Some(receiver) => {
+                    receiver.buffer.push(data);
+                    receiver.waker.take()
+                },
+                None => {
+                    log::info!("Dropped ACL data for connection handle {}, no receiver started",
+                        data.get_handle());
+                    None
+                },
+            }
+        };
+
+        if let Some(waker) = waker { waker.wake() }
+    }
+
+    /// Take the ACL data sent by the host
+    pub fn take_sent_acl_data(&self) -> Vec<HciAclData> {
+        core::mem::take(&mut self.state.lock().sent_acl_data)
+    }
+
+    /// Get the raw packet of the last command sent by the host
+    pub fn get_last_command(&self) -> Option<Vec<u8>> {
+        self.state.lock().last_command.clone()
+    }
+
+    /// Get the raw value of the Flow_Control_Enable parameter of *Set Controller To Host Flow
+    /// Control*
+    pub fn get_controller_to_host_flow_control(&self) -> u8 {
+        self.state.lock().controller_to_host_flow_control
+    }
+
+    /// Get the host buffer size
+    ///
+    /// The buffer size is returned in the format of the parameter of the *Host Buffer Size*
+    /// command, None is returned if the command was not sent since the last reset.
+    pub fn get_host_buffer_size(&self) -> Option<[u8;7]> {
+        self.state.lock().host_buffer_size
+    }
+
+    /// Get the default PHY preferences
+    ///
+    /// The preferences are returned in the format of the parameter of the *LE Set Default PHY*
+    /// command (the All_PHYS, TX_PHYS, and RX_PHYS octets).
+    pub fn get_default_phys(&self) -> [u8;3] {
+        self.state.lock().default_phys
+    }
+
+    /// Take the packets reported as completed by the host
+    ///
+    /// Every entry is a connection handle and the number of completed packets as given by a
+    /// *Host Number Of Completed Packets* command.
+    pub fn take_host_completed_packets(&self) -> Vec<(ConnectionHandle, u16)> {
+        core::mem::take(&mut self.state.lock().host_completed_packets)
+            .into_iter()
+            .map(|(raw_handle, count)| (ConnectionHandle::try_from(raw_handle).unwrap(), count))
+            .collect()
+    }
+
+    /// Get the controller linked with this controller
+    fn get_peer(&self) -> Option<VirtualController> {
+        self.state.lock().link.as_ref()
+            .and_then(|link| link.peer.upgrade())
+            .map(|state| VirtualController { state })
+    }
+
+    /// Perform the actions of the peer generated by a command
+    fn apply_peer_actions(&self, actions: Vec<PeerAction>) -> Result<(), Error> {
+        let event_packets = actions.into_iter().map(|action| match action {
+            PeerAction::Event(packet) => packet,
+            PeerAction::EncryptionRequest { handle, random_number, encrypted_diversifier, long_term_key } => {
+                let mut event_parameter = handle.to_le_bytes().to_vec();
+
+                event_parameter.extend_from_slice(&random_number);
+                event_parameter.extend_from_slice(&encrypted_diversifier);
+
+                self.state.lock().pending_encryption.insert(handle, long_term_key);
+
+                le_meta_event_packet(events::LEMeta::LongTermKeyRequest, &event_parameter)
+            },
+        })
+        .collect::<Vec<_>>();
+
+        self.push_events(event_packets)
+    }
+
+    /// Create a connection over the link in whichever direction is possible
+    fn establish_link_connections(&self) {
+        if let Some(peer) = self.get_peer() {
+            Self::connect(self, &peer);
+            Self::connect(&peer, self);
+        }
+    }
+
+    /// Create a connection if `initiator` is initiating a connection to `advertiser`
+    ///
+    /// The two controllers are never locked at the same time.
+    fn connect(initiator: &VirtualController, advertiser: &VirtualController) {
+        let (create_parameter, initiator_address, white_list, next_handle) = {
+            let state = initiator.state.lock();
+
+            match (&state.initiating, &state.link) {
+                (Some(parameter), Some(link)) => match state.own_address(parameter[12]) {
+                    Some(address) => (parameter.clone(), address, state.white_list.clone(), link.next_handle.clone()),
+                    None => return,
+                },
+                _ => return,
+            }
+        };
+
+        let (advertiser_address, advertising_set, phy) = {
+            let mut state = advertiser.state.lock();
+
+            match state.accept_connection(&create_parameter, initiator_address, &white_list) {
+                Some((address, advertising_set, phy)) => {
+                    match advertising_set {
+                        Some(raw_handle) => state.advertising_sets.iter_mut()
+                            .filter(|set| set.handle == raw_handle)
+                            .for_each(|set| set.enabled = false),
+                        None => state.advertising_enabled = false,
+                    }
+
+                    (address, advertising_set, phy)
+                },
+                None => return,
+            }
+        };
+
+        let handle = next_handle.fetch_add(1, Ordering::Relaxed) & 0xFFF;
+
+        // The connection interval used is the maximum interval requested by the initiator. The LE
+        // Enhanced Connection Complete event is used by the extended commands.
+        let event_parameter = |role: u8, peer: (u8, BluetoothDeviceAddress), enhanced: bool| {
+            let mut event_parameter = alloc::vec![error::Error::NoError.into()];
+
+            event_parameter.extend_from_slice(&handle.to_le_bytes());
+            event_parameter.push(role);
+            event_parameter.push(peer.0);
+            event_parameter.extend_from_slice(&peer.1);
+            event_parameter.extend_from_slice(&create_parameter[15..21]);
+            event_parameter.push(0);
+
+            if enhanced {
+                le_meta_event_packet(
+                    events::LEMeta::EnhancedConnectionComplete,
+                    &into_enhanced_connection_complete(&event_parameter)
+                )
+            } else {
+                le_meta_event_packet(events::LEMeta::ConnectionComplete, &event_parameter)
+            }
+        };
+
+        let initiator_events = alloc::vec![event_parameter(0, advertiser_address, create_parameter.len() > 25)];
+
+        let mut advertiser_events = alloc::vec![event_parameter(1, initiator_address, advertising_set.is_some())];
+
+        if let Some(raw_handle) = advertising_set {
+            let mut event_parameter = alloc::vec![error::Error::NoError.into(), raw_handle];
+
+            event_parameter.extend_from_slice(&handle.to_le_bytes());
+            event_parameter.push(0);
+
+            advertiser_events.push(le_meta_event_packet(events::LEMeta::AdvertisingSetTerminated, &event_parameter));
+        }
+
+        initiator.push_link_connection(handle, phy, initiator_events);
+        advertiser.push_link_connection(handle, phy, advertiser_events);
+    }
+
+    /// Report the advertising sets of each linked controller to the other controller
+    fn report_link_advertising(&self) {
+        if let Some(peer) = self.get_peer() {
+            Self::report_advertising(self, &peer);
+            Self::report_advertising(&peer, self);
+            Self::report_periodic_advertising(self, &peer);
+            Self::report_periodic_advertising(&peer, self);
+        }
+    }
+
+    /// Synchronize `scanner` with the periodic advertising of `advertiser` and send the periodic
+    /// advertising reports and sync lost events for the syncs of `scanner`
+    ///
+    /// The two controllers are never locked at the same time.
+    fn report_periodic_advertising(scanner: &VirtualController, advertiser: &VirtualController) {
+        let (creating_sync, periodic_advertiser_list, linked_syncs) = {
+            let state = scanner.state.lock();
+
+            (state.creating_sync, state.periodic_advertiser_list.clone(), state.linked_syncs.clone())
+        };
+
+        // The sync established event parameter after the sync handle, the raw handle of the set,
+        // and the transmit power and data of the periodic advertising
+        let mut established = None;
+
+        // The raw sync handles and the transmit power and data of the reports for the syncs
+        let mut updates = Vec::new();
+
+        let mut lost = Vec::new();
+
+        {
+            let mut state = advertiser.state.lock();
+
+            let updated_sets = core::mem::take(&mut state.updated_periodic_sets);
+
+            let tx_power = |set: &ExtendedAdvertisingSet| match set.periodic_parameters {
+                Some(parameters) if parameters[4] & (1 << 6) != 0 => TRANSMIT_POWER_LEVEL as u8,
+                _ => 0x7F,
+            };
+
+            let train = creating_sync.as_ref()
+                .and_then(|create_parameter| state.find_periodic_advertising(create_parameter, &periodic_advertiser_list));
+
+            if let Some(set) = train {
+                if let Some((address_type, address)) = state.advertising_set_address(set) {
+                    let mut event_parameter = alloc::vec![set.parameters[22], address_type];
+
+                    event_parameter.extend_from_slice(&address);
+                    event_parameter.push(set.parameters[21]);
+                    event_parameter.extend_from_slice(&set.periodic_parameters.unwrap_or_default()[2..4]);
+                    event_parameter.push(0);
+
+                    established = Some((event_parameter, set.handle, tx_power(set), set.periodic_data.clone()));
+                }
+            }
+
+            for (sync_handle, set_handle) in linked_syncs {
+                match state.advertising_sets.iter().find(|set| set.handle == set_handle) {
+                    Some(set) if set.is_periodic_advertising() => if updated_sets.contains(&set_handle) {
+                        updates.push((sync_handle, tx_power(set), set.periodic_data.clone()))
+                    },
+                    _ => lost.push(sync_handle),
+                }
+            }
+        }
+
+        let mut packets = Vec::new();
+
+        if let Some((parameter, set_handle, tx_power, data)) = established {
+            let mut state = scanner.state.lock();
+
+            let sync_handle = (0..=MAX_SYNC_HANDLE).find(|handle| !state.syncs.contains(handle));
+
+            if let Some(sync_handle) = sync_handle {
+                state.linked_syncs.insert(sync_handle, set_handle);
+
+                let mut event_parameter = alloc::vec![error::Error::NoError.into()];
+
+                event_parameter.extend_from_slice(&sync_handle.to_le_bytes());
+                event_parameter.extend_from_slice(&parameter);
+
+                packets.push(le_meta_event_packet(events::LEMeta::PeriodicAdvertisingSyncEstablished, &event_parameter));
+
+                packets.extend(periodic_advertising_reports(sync_handle, tx_power, &data));
+            }
+        }
+
+        for (sync_handle, tx_power, data) in updates {
+            packets.extend(periodic_advertising_reports(sync_handle, tx_power, &data));
+        }
+
+        for sync_handle in lost {
+            packets.push(le_meta_event_packet(events::LEMeta::PeriodicAdvertisingSyncLost, &sync_handle.to_le_bytes()));
+        }
+
+        if !packets.is_empty() {
+            // The packets are built by the virtual controller so they are always valid
+            scanner.push_events(packets).ok();
+        }
+    }
+
+    /// Send the reports of the advertising sets of `advertiser` to `scanner`
+    ///
+    /// Only the sets not yet reported since `scanner` enabled scanning are reported. The two
+    /// controllers are never locked at the same time.
+    fn report_advertising(scanner: &VirtualController, advertiser: &VirtualController) {
+        let (scan_parameter, reported) = {
+            let state = scanner.state.lock();
+
+            match &state.extended_scan_parameters {
+                Some(parameter) if state.scanning_enabled => (parameter.clone(), state.reported_sets.clone()),
+                _ => return,
+            }
+        };
+
+        let (handles, packets) = advertiser.state.lock().link_advertising_reports(&scan_parameter, &reported);
+
+        if !handles.is_empty() {
+            scanner.state.lock().reported_sets.extend(handles);
+
+            // The packets are built by the virtual controller so they are always valid
+            scanner.push_events(packets).ok();
+        }
+    }
+
+    /// Add a connection created over the link
+    ///
+    /// A receiver is started for the connection so that ACL data sent by the peer is buffered
+    /// until the host creates its channel for the connection.
+    fn push_link_connection(&self, raw_handle: u16, phy: u8, event_packets: Vec<Vec<u8>>) {
+        event_packets.iter().for_each(|packet| self.notify_listener(HciPacketType::Event, packet));
+
+        let wakers = {
+            let mut state = self.state.lock();
+
+            state.linked.push(raw_handle);
+
+            if let Ok(handle) = ConnectionHandle::try_from(raw_handle) {
+                state.acl_receivers.entry(handle).or_default();
+            }
+
+            // A connection uses the PHY that the connection was created on
+            if phy != 0x1 {
+                state.phys.insert(raw_handle, (phy, phy));
+            }
+
+            // The packets are built by the virtual controller so they are always valid
+            event_packets.iter().for_each(|packet| { state.push_event(packet).ok(); });
+
+            state.take_event_wakers()
+        };
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+
+    /// Give a packet sent to the host to the received packet listener
+    fn notify_listener(&self, packet_type: HciPacketType, packet: &[u8]) {
+        let listener = self.state.lock().packet_listener.clone();
+
+        if let Some(listener) = listener { listener.on_packet(packet_type, packet) }
+    }
+
+    fn push_events<E>(&self, event_packets: E) -> Result<(), Error>
+    where E: IntoIterator<Item=Vec<u8>>
+    {
+        let event_packets = event_packets.into_iter().collect::<Vec<_>>();
+
+        event_packets.iter().for_each(|packet| self.notify_listener(HciPacketType::Event, packet));
+
+        let (result, wakers) = {
+            let mut state = self.state.lock();
+
+            let result = event_packets.iter().try_for_each(|packet| state.push_event(packet));
+
+            (result, state.take_event_wakers())
+        };
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+
+        result
+    }
+}
+
+impl HostControllerInterface for VirtualController {
+    type SendCommandError = Error;
+    type ReceiveEventError = Error;
+
+    fn send_command<D,W>(&self, cmd_data: &D, _: W) -> Result<bool, Self::SendCommandError>
+    where D: CommandParameter,
+          W: Into<Option<Waker>>,
+    {
+        let generated = self.state.lock().process_command(&cmd_data.as_command_packet())?;
+
+        self.push_events(generated.events)?;
+
+        if let Some(peer) = self.get_peer() {
+            peer.apply_peer_actions(generated.peer)?;
+        }
+
+        self.establish_link_connections();
+
+        self.report_link_advertising();
+
+        Ok(true)
+    }
+
+    fn receive_event<P>(
+        &self,
+        event: events::Events,
+        waker: &Waker,
+        matcher: Pin<Arc<P>>,
+        _: Option<Duration>
+    ) -> Option<Result<events::EventsData, Self::ReceiveEventError>>
+    where P: EventMatcher + Send + Sync + 'static
+    {
+        let mut state = self.state.lock();
+
+        let position = state.events.iter()
+            .position(|data| data.get_enum_name() == event && matcher.match_event(data));
+
+        match position {
+            Some(index) => state.events.remove(index).map(Ok),
+            None => {
+                if !state.event_wakers.iter().any(|stored| stored.will_wake(waker)) {
+                    state.event_wakers.push(waker.clone());
+                }
+
+                None
+            }
+        }
+    }
+}
+
+impl HciAclDataInterface for VirtualController {
+    type SendAclDataError = Error;
+    type ReceiveAclDataError = Error;
+
+    /// Send ACL data to the controller
+    ///
+    /// Data sent over a connection created by the link is received by the peer controller, any
+    /// other data is kept for [`take_sent_acl_data`](VirtualController::take_sent_acl_data).
+    ///
+    /// A *Number Of Completed Packets* event is sent for data that is received by the peer
+    /// controller. The event must be injected by the test for any other data.
+    fn send(&self, data: HciAclData) -> Result<usize, Self::SendAclDataError> {
+        let len = data.get_payload().len() + 1;
+
+        let handle = *data.get_handle();
+
+        let is_linked = self.state.lock().is_linked(handle.get_raw_handle());
+
+        match self.get_peer() {
+            Some(peer) if is_linked => {
+                peer.inject_acl_data(data);
+
+                let mut parameter = Vec::with_capacity(5);
+
+                parameter.push(1);
+                parameter.extend_from_slice(&handle.get_raw_handle().to_le_bytes());
+                parameter.extend_from_slice(&1u16.to_le_bytes());
+
+                self.push_events(Some(event_packet(events::Events::NumberOfCompletedPackets.get_val(), &parameter)))?;
+            },
+            _ => self.state.lock().sent_acl_data.push(data),
+        }
+
+        Ok(len)
+    }
+
+    fn start_receiver(&self, handle: ConnectionHandle) {
+        self.state.lock().acl_receivers.entry(handle).or_default();
+    }
+
+    fn stop_receiver(&self, handle: &ConnectionHandle) {
+        self.state.lock().acl_receivers.remove(handle);
+    }
+
+    fn receive(
+        &self,
+        handle: &ConnectionHandle,
+        waker: &Waker
+    ) -> Option<Result<Vec<HciAclData>, Self::ReceiveAclDataError>>
+    {
+        match self.state.lock().acl_receivers.get_mut(handle) {
+            None => Some(Err(Error::NoReceiver(*handle))),
+            Some(receiver) if receiver.buffer.is_empty() => {
+                receiver.waker = Some(waker.clone());
+                None
+            },
+            Some(receiver) => Some(Ok(core::mem::take(&mut receiver.buffer))),
+        }
+    }
+}
+
+impl ReceivedPacketSource for VirtualController {
+    fn set_received_packet_listener(&self, listener: Option<Arc<dyn ReceivedPacketListener>>) {
+        self.state.lock().packet_listener = listener;
+    }
+}
+
+/// The setup shared by the tests of the `hci` modules
+#[cfg(test)]
+pub(crate) mod fixture {
+
+    use super::*;
+    use crate::hci::HostInterface;
+    use futures::executor::block_on;
+
+    /// The address of the virtual controller created by `host_interface`
+    pub(crate) const ADDRESS: BluetoothDeviceAddress = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+
+    pub(crate) fn host_interface() -> (HostInterface<VirtualController>, VirtualController) {
+        let controller = VirtualController::new(ADDRESS);
+
+        (HostInterface::from(controller.clone()), controller)
+    }
+
+    /// LE Connection Complete event parameter for a connection as the slave with handle 0x40
+    pub(crate) const CONNECTION_COMPLETE: [u8;18] = [
+        0x00, 0x40, 0x00, 0x01, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x18, 0x00, 0x00,
+        0x00, 0x48, 0x00, 0x00
+    ];
+
+    /// Connect the host of `master_controller` to the host of `slave_controller`
+    ///
+    /// The controllers must be linked, the return is the LE Connection Complete event data for the
+    /// master and the slave.
+    pub(crate) fn connect_linked(
+        master: &HostInterface<VirtualController>,
+        slave: &HostInterface<VirtualController>,
+        slave_address: BluetoothDeviceAddress,
+    ) -> (events::LEConnectionCompleteData, events::LEConnectionCompleteData)
+    {
+        use crate::hci::common::{ConnectionLatency, LEAddressType, SupervisionTimeout};
+        use crate::hci::le::common::{ConnectionEventLength, OwnAddressType};
+        use crate::hci::le::connection::{self, create_connection};
+        use crate::hci::le::transmitter::{set_advertising_enable, set_advertising_parameters};
+
+        block_on(set_advertising_parameters::send(slave, Default::default())).unwrap();
+        block_on(set_advertising_enable::send(slave, true)).unwrap();
+
+        let parameters = create_connection::ConnectionParameters::new_without_whitelist(
+            create_connection::ScanningInterval::default(),
+            create_connection::ScanningWindow::default(),
+            LEAddressType::PublicDeviceAddress,
+            slave_address,
+            OwnAddressType::default(),
+            connection::ConnectionIntervalBounds::try_from(
+                connection::ConnectionInterval::try_from_raw(0x10).unwrap(),
+                connection::ConnectionInterval::try_from_raw(0x20).unwrap(),
+            ).unwrap(),
+            ConnectionLatency::try_from(0).unwrap(),
+            SupervisionTimeout::try_from_raw(0x100).unwrap(),
+            ConnectionEventLength::default(),
+        );
+
+        block_on(create_connection::send(master, parameters)).unwrap();
+
+        let wait_for_connection = |hi: &HostInterface<VirtualController>| {
+            match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+                Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) => data,
+                _ => panic!("Expected LE connection complete event"),
+            }
+        };
+
+        (wait_for_connection(master), wait_for_connection(slave))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::fixture::{connect_linked, host_interface, ADDRESS, CONNECTION_COMPLETE};
+    use crate::hci::HostInterface;
+    use crate::hci::le::common::AddressType;
+    use futures::executor::block_on;
+
+    #[test]
+    fn read_bd_addr_test() {
+        use crate::hci::le::mandatory::{read_bd_addr, reset};
+
+        let (hi, _) = host_interface();
+
+        block_on(reset::send(&hi)).unwrap();
+
+        assert_eq!(ADDRESS, block_on(read_bd_addr::send(&hi)).unwrap());
+    }
+
+    #[test]
+    fn white_list_test() {
+        use crate::hci::le::mandatory::{
+            add_device_to_white_list,
+            clear_white_list,
+            read_white_list_size,
+            remove_device_from_white_list
+        };
+
+        let (hi, controller) = host_interface();
+
+        let address_1 = [1, 2, 3, 4, 5, 6];
+        let address_2 = [7, 8, 9, 10, 11, 12];
+
+        controller.set_white_list_size(2);
+
+        assert_eq!(2, block_on(read_white_list_size::send(&hi)).unwrap());
+
+        block_on(add_device_to_white_list::send(&hi, AddressType::PublicDeviceAddress, address_1)).unwrap();
+        block_on(add_device_to_white_list::send(&hi, AddressType::RandomDeviceAddress, address_2)).unwrap();
+
+        assert!(block_on(add_device_to_white_list::send(&hi, AddressType::PublicDeviceAddress, ADDRESS)).is_err());
+
+        block_on(remove_device_from_white_list::send(&hi, AddressType::PublicDeviceAddress, address_1)).unwrap();
+
+        assert_eq!(
+            vec![WhiteListEntry { address_type: 1, address: address_2 }],
+            controller.get_white_list()
+        );
+
+        block_on(clear_white_list::send(&hi)).unwrap();
+
+        assert!(controller.get_white_list().is_empty());
+    }
+
+    #[test]
+    fn advertising_test() {
+        use crate::gap::advertise::local_name::LocalName;
+        use crate::hci::le::transmitter::{
+            set_advertising_data,
+            set_advertising_enable,
+            set_advertising_parameters,
+        };
+
+        let (hi, controller) = host_interface();
+
+        let mut adv_data = set_advertising_data::AdvertisingData::new();
+
+        adv_data.try_push(LocalName::new("virtual", false)).unwrap();
+
+        block_on(set_advertising_data::send(&hi, adv_data)).unwrap();
+
+        block_on(set_advertising_parameters::send(&hi, Default::default())).unwrap();
+
+        block_on(set_advertising_enable::send(&hi, true)).unwrap();
+
+        assert!(controller.is_advertising());
+
+        assert_eq!(b"\x08\x09virtual", &controller.get_advertising_data()[..]);
+
+        // Advertising parameters cannot be changed while advertising
+        assert!(block_on(set_advertising_parameters::send(&hi, Default::default())).is_err());
+
+        block_on(set_advertising_enable::send(&hi, false)).unwrap();
+
+        assert!(!controller.is_advertising());
+    }
+
+    #[test]
+    fn scanning_test() {
+        use crate::hci::le::receiver::{set_scan_enable, set_scan_parameters};
+
+        let (hi, controller) = host_interface();
+
+        let mut parameters = set_scan_parameters::ScanningParameters::default();
+
+        parameters.scan_type = set_scan_parameters::LEScanType::ActiveScanning;
+
+        block_on(set_scan_parameters::send(&hi, parameters)).unwrap();
+
+        block_on(set_scan_enable::send(&hi, true, true)).unwrap();
+
+        assert!(controller.is_scanning());
+        assert!(controller.is_filtering_duplicates());
+        assert_eq!(1, controller.get_scan_parameters()[0]);
+
+        // Scan parameters cannot be changed while scanning
+        assert!(block_on(set_scan_parameters::send(&hi, Default::default())).is_err());
+    }
+
+    #[test]
+    fn injected_connection_test() {
+        use crate::hci::le::connection::disconnect;
+
+        let (hi, controller) = host_interface();
+
+        controller.inject_le_meta_event(events::LEMeta::ConnectionComplete, &CONNECTION_COMPLETE).unwrap();
+
+        let handle = match block_on(hi.wait_for_event(events::LEMeta::ConnectionComplete.into(), None)) {
+            Ok(events::EventsData::LEMeta(events::LEMetaData::ConnectionComplete(data))) =>
+                data.connection_handle,
+            _ => panic!("Expected LE connection complete event"),
+        };
+
+        assert_eq!(vec![handle], controller.get_connections());
+
+        let parameters = disconnect::DisconnectParameters {
+            connection_handle: handle,
+            disconnect_reason: disconnect::DisconnectReason::RemoteUserTerminatedConnection,
+        };
+
+        block_on(disconnect::send(&hi, parameters)).unwrap();
+
+        match block_on(hi.wait_for_event(events::Events::DisconnectionComplete, None)) {
+            Ok(events::EventsData::DisconnectionComplete(data)) =>
+                assert_eq!(handle, data.connection_handle),
+            _ => panic!("Expected disconnection complete event"),
+        }
+
+        assert!(controller.get_connections().is_empty());
+    }
+
+    #[test]
+    fn acl_data_test() {
+        use crate::hci::{AclBroadcastFlag, AclPacketBoundary};
+
+        let controller = VirtualController::new(ADDRESS);
+
+        let handle = ConnectionHandle::try_from(0x40).unwrap();
+
+        let waker = futures::task::noop_waker();
+
+        let data = HciAclData::new(
+            handle,
+            AclPacketBoundary::FirstNonFlushable,
+            AclBroadcastFlag::NoBroadcast,
+            vec![1, 2, 3]
+        );
+
+        assert!(controller.receive(&handle, &waker).unwrap().is_err());
+
+        controller.start_receiver(handle);
+
+        assert!(controller.receive(&handle, &waker).is_none());
+
+        controller.inject_acl_data(data);
+
+        let received = controller.receive(&handle, &waker).unwrap().unwrap();
+
+        assert_eq!(&[1, 2, 3], received[0].get_payload());
+
+        controller.stop_receiver(&handle);
+    }
+
+    #[test]
+    fn linked_connection_test() {
+        use crate::l2cap::{AclData, ChannelIdentifier, ConnectionChannel, LeUserChannelIdentifier};
+
+        let master_address = [1, 1, 1, 1, 1, 1];
+        let slave_address = [2, 2, 2, 2, 2, 2];
+
+        let master_controller = VirtualController::new(master_address);
+        let slave_controller = VirtualController::new(slave_address);
+
+        master_controller.link(&slave_controller);
+
+        let master = HostInterface::from(master_controller.clone());
+        let slave = HostInterface::from(slave_controller.clone());
+
+        let (master_data, slave_data) = connect_linked(&master, &slave, slave_address);
+
+        assert_eq!(master_data.connection_handle, slave_data.connection_handle);
+        assert!(matches!(master_data.role, events::LERole::Master));
+        assert!(matches!(slave_data.role, events::LERole::Slave));
+        assert_eq!(slave_address, master_data.peer_address);
+        assert_eq!(master_address, slave_data.peer_address);
+        assert!(!slave_controller.is_advertising());
+        assert!(!master_controller.is_initiating());
+
+        let master_channel = master.new_le_acl_connection_channel(&master_data);
+        let slave_channel = slave.new_le_acl_connection_channel(&slave_data);
+
+        master_channel.send(AclData::new(
+            vec![1, 2, 3, 4],
+            ChannelIdentifier::LE(LeUserChannelIdentifier::AttributeProtocol)
+        ));
+
+        let received = block_on(slave_channel.future_receiver()).unwrap();
+
+        assert_eq!(&[1, 2, 3, 4], received[0].get_payload());
+        assert!(master_controller.take_sent_acl_data().is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+//! Vendor Specific Commands and Events
+//!
+//! Vendor specific commands are the commands with the OpCode Group Field `0x3F`. The parameter and
+//! the return parameter of these commands are defined by the manufacturer of the controller, so
+//! this library only knows them as bytes. A vendor specific command is created by implementing
+//! [`VendorCommand`] and it is sent to the controller with [`send`].
+//!
+//! Vendor specific events (the event code `0xFF`) are received as the event data
+//! [`VendorSpecific`](crate::hci::events::EventsData::VendorSpecific).
+
+use crate::hci::*;
+use core::marker::PhantomData;
+
+/// A vendor specific command
+///
+/// The parameter of the command is the bytes returned by `get_parameter`.
+pub trait VendorCommand {
+    /// The OpCode Command Field of the command
+    ///
+    /// Only the lower 10 bits of the value are used.
+    const OCF: u16;
+
+    /// Get the parameter of the command
+    ///
+    /// The parameter must not be longer than 255 bytes.
+    fn get_parameter(&self) -> Vec<u8>;
+}
+
+struct Parameter<C> {
+    parameter: Vec<u8>,
+    _command: PhantomData<fn() -> C>,
+}
+
+impl<C> CommandParameter for Parameter<C> where C: VendorCommand {
+    type Parameter = ();
+    const COMMAND: opcodes::HCICommand = opcodes::HCICommand::VendorSpecific(C::OCF & 0x3FF);
+    fn get_parameter(&self) -> Self::Parameter {}
+
+    fn as_command_packet<'a>(&self) -> alloc::boxed::Box<[u8]> {
+        let mut packet = Vec::with_capacity(self.parameter.len() + 3);
+
+        packet.extend_from_slice(&Self::COMMAND.as_opcode_pair().as_opcode().to_le_bytes());
+
+        packet.push(self.parameter.len() as u8);
+
+        packet.extend_from_slice(&self.parameter);
+
+        packet.into_boxed_slice()
+    }
+}
+
+impl_returned_future!(
+    Vec<u8>,
+    crate::hci::events::EventsData::CommandComplete,
+    data,
+    &'static str,
+    {
+        use crate::hci::OutputErr::ResponseHasNoAssociatedCommand;
+
+        if data.command_opcode.is_some() {
+            core::task::Poll::Ready(Ok(data.raw_data.to_vec()))
+        } else {
+            core::task::Poll::Ready(Err(ResponseHasNoAssociatedCommand))
+        }
+    }
+);
+
+/// Send a vendor specific command
+///
+/// The output of the returned future is the return parameter of the *Command Complete* event
+/// sent by the controller in response to the command. Any status of the command is part of the
+/// return parameter (it is usually the first byte), it is not checked by this function.
+///
+/// # Error
+/// An error is returned without sending the command if the parameter of the command is longer
+/// than 255 bytes
+pub fn send<'a, T: 'static, C>( hci: &'a HostInterface<T>, command: C )
+-> impl Future<Output=Result<Vec<u8>, impl CommandError>> + 'a
+where T: HostControllerInterface,
+      C: VendorCommand + 'static,
+{
+    let parameter = command.get_parameter();
+
+    let too_long = parameter.len() > <u8>::MAX as usize;
+
+    let parameter = Parameter::<C> { parameter, _command: PhantomData };
+
+    async move {
+        if too_long {
+            return Err(OutputErr::CommandDataConversionError("The parameter of a vendor command is longer than 255 bytes"))
+        }
+
+        ReturnedFuture( hci.send_command(parameter, events::Events::CommandComplete, Duration::from_secs(1) ) ).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::hci::testing::fixture::{host_interface, ADDRESS};
+    use crate::hci::{error, events};
+    use futures::executor::block_on;
+
+    #[test]
+    fn vendor_specific_test() {
+        use crate::hci::vendor;
+
+        struct WritePublicAddress;
+
+        struct TooLong;
+
+        impl vendor::VendorCommand for WritePublicAddress {
+            const OCF: u16 = 0x1;
+
+            fn get_parameter(&self) -> Vec<u8> { ADDRESS.to_vec() }
+        }
+
+        impl vendor::VendorCommand for TooLong {
+            const OCF: u16 = 0x2;
+
+            fn get_parameter(&self) -> Vec<u8> { vec![0; 256] }
+        }
+
+        let (hi, controller) = host_interface();
+
+        // The virtual controller does not know any vendor specific commands
+        assert_eq!(
+            vec![u8::from(error::Error::UnknownHCICommand)],
+            block_on(vendor::send(&hi, WritePublicAddress)).unwrap()
+        );
+
+        // A parameter that is too long is not sent to the controller
+        assert!(block_on(vendor::send(&hi, TooLong)).is_err());
+
+        controller.inject_event(events::Events::VendorSpecific, &[0x01, 0x02, 0x03]).unwrap();
+
+        match block_on(hi.wait_for_event(events::Events::VendorSpecific, None)) {
+            Ok(events::EventsData::VendorSpecific(data)) => assert_eq!(vec![0x01, 0x02, 0x03], data.data),
+            _ => panic!("Expected vendor specific event"),
+        }
+    }
+}
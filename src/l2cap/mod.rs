@@ -291,7 +291,27 @@ impl<Mtu> From<(AclData, Mtu)> for L2capPdu where Mtu: Into<Option<usize>>
 /// A connection channel is used for sending and receiving Asynchronous Connection-oriented (ACL)
 /// data packets between the Host and Bluetooth Controller.
 pub trait ConnectionChannel {
-    fn send<Pdu>(&self, data: Pdu) where Pdu: Into<L2capPdu>;
+    /// The error of giving data to the controller
+    type SendError: core::fmt::Debug + core::fmt::Display;
+
+    /// Send data to the connected device
+    ///
+    /// The data is sent as soon as the controller can take it, any data that cannot be sent yet
+    /// is queued by the connection channel. The returned future is pending until the data (and all
+    /// data sent before it) is given to the controller, but it does not need to be polled for the
+    /// data to be sent.
+    fn send<Pdu>(&self, data: Pdu) -> ConChanFutureTx<'_, Self> where Pdu: Into<L2capPdu>;
+
+    /// Poll for all sent data to be given to the controller
+    ///
+    /// This returns `Poll::Pending` while there is data queued by the connection channel. The
+    /// waker is woken when the queued data may be able to be sent. An error is returned when
+    /// queued data could not be given to the controller.
+    ///
+    /// The default implementation is for a connection channel that never queues data.
+    fn poll_flush(&self, _waker: &core::task::Waker) -> core::task::Poll<Result<(), Self::SendError>> {
+        core::task::Poll::Ready(Ok(()))
+    }
 
     fn receive(&self, waker: &core::task::Waker) -> Option<Vec<AclDataFragment>>;
 
@@ -305,6 +325,31 @@ pub trait ConnectionChannel {
     }
 }
 
+/// A future for waiting on the data sent to the connected device
+///
+/// This is returned by [`send`](ConnectionChannel::send) of a `ConnectionChannel`, it is ready
+/// once the data is given to the controller. Dropping this future does not stop the data from
+/// being sent.
+pub struct ConChanFutureTx<'a, C> where C: ?Sized {
+    cc: &'a C,
+}
+
+impl<'a, C> ConChanFutureTx<'a, C> where C: ?Sized {
+    pub fn new(cc: &'a C) -> Self {
+        ConChanFutureTx { cc }
+    }
+}
+
+impl<'a, C> core::future::Future for ConChanFutureTx<'a, C>
+where C: ConnectionChannel + ?Sized
+{
+    type Output = Result<(), C::SendError>;
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context) -> core::task::Poll<Self::Output> {
+        self.cc.poll_flush(cx.waker())
+    }
+}
+
 /// A future for asynchronously waiting for received packets from the connected device
 ///
 /// This struct is created via the function [`future_receiver`](ConnectionChannel::future_receiver)
@@ -6,6 +6,7 @@ use core::marker::Unpin;
 use core::pin::Pin;
 use core::task::{Context,Poll};
 use core::time::Duration;
+use crate::timer::Timeout;
 use crate::hci::{
     cb::set_event_mask::EventMask,
     common::ConnectionHandle,
@@ -51,6 +52,8 @@ struct MasterLazyEncryptFuture<'a, HCI, F1, SMFn, F2, SEFn, WFEFn, F3> {
     start_encryption_fn: SEFn,
     wait_for_event_with_matcher_fn: WFEFn,
     encrypt_timeout: Option<Duration>,
+    /// The timeout created with the timer of the host interface
+    timer_timeout: Option<Timeout>,
 }
 
 impl<'a, HCI, F1, SMFn, F2, SEFn, WFEFn, F3>
@@ -77,7 +80,8 @@ MasterLazyEncryptFuture<'a, HCI, F1, SMFn, F2, SEFn, WFEFn, F3>
             set_mask_fn,
             start_encryption_fn,
             wait_for_event_with_matcher_fn,
-            encrypt_timeout: encrypt_timeout.into()
+            encrypt_timeout: encrypt_timeout.into(),
+            timer_timeout: None,
         }
     }
 }
@@ -138,6 +142,8 @@ for MasterLazyEncryptFuture<'a, HCI, F1, SMFn, F2, SEFn, WFEFn, F3,>
                                 MasterEncryptEventMatcher(this.connection_handle),
                             );
 
+                            this.timer_timeout = new_timer_timeout(this.hci, this.encrypt_timeout);
+
                             this.current = MasterLazyEncryptCurrent::StartEncryption(
                                 start_encrypt_fut,
                                 encrypt_change_fut,
@@ -171,7 +177,7 @@ for MasterLazyEncryptFuture<'a, HCI, F1, SMFn, F2, SEFn, WFEFn, F3,>
 
                     match Pin::new(change_fut).poll(cx).map_err(err_map) {
                         Poll::Pending => match Pin::new(refresh_fut).poll(cx).map_err(err_map) {
-                            Poll::Pending => break Poll::Pending,
+                            Poll::Pending => break poll_timer_timeout(&mut this.timer_timeout, cx),
                             Poll::Ready(Err(e)) => break Poll::Ready(Err(e)),
                             Poll::Ready(Ok(_)) => break Poll::Ready(Ok(())),
                         },
@@ -224,6 +230,26 @@ fn err_map<E: 'static>(e: E) -> super::Error where E: Debug {
     super::Error::EncryptionFailed(Box::new(e))
 }
 
+/// Create a timeout with the timer of the host interface
+///
+/// The event futures are given the timeout as well, but it is up to the interface to implement
+/// it. This timeout is for when the host interface has a timer.
+fn new_timer_timeout<HCI>(hci: &HostInterface<HCI>, timeout: Option<Duration>) -> Option<Timeout>
+where HCI: HostControllerInterface
+{
+    match (hci.get_timer(), timeout) {
+        (Some(timer), Some(duration)) => Some(timer.timeout(duration)),
+        _ => None,
+    }
+}
+
+fn poll_timer_timeout(timeout: &mut Option<Timeout>, cx: &mut Context) -> Poll<Result<(), Error>> {
+    match timeout.as_mut().map(|timeout| timeout.as_mut().poll(cx)) {
+        Some(Poll::Ready(_)) => Poll::Ready(Err(Error::Timeout)),
+        _ => Poll::Pending,
+    }
+}
+
 pub fn new_await_encrypt_slave_future<'a, HCI, D, LTK>(
     ltk: LTK,
     event_mask: SlaveEventMask,
@@ -246,6 +272,7 @@ pub fn new_await_encrypt_slave_future<'a, HCI, D, LTK>(
         wait_for_event_with_matcher_fn: crate::hci::HostInterface::wait_for_event_with_matcher,
         connection_handle,
         ltk_event_timeout: timeout.into(),
+        timer_timeout: None,
         current: SlaveLazyEncryptCurrent::None,
         ltk: ltk.into(),
     }
@@ -263,6 +290,8 @@ struct SlaveLazyEncryptFuture<'a, HCI, FNeg, FPos, FutEm, FutLEm, FutLTKE, FutNe
     set_le_event_fn: FLE,
     wait_for_event_with_matcher_fn: WFEFn,
     ltk_event_timeout: Option<Duration>,
+    /// The timeout created with the timer of the host interface
+    timer_timeout: Option<Timeout>,
     connection_handle: ConnectionHandle,
     current: SlaveLazyEncryptCurrent<FutEm, FutLEm, FutLTKE, FutNeg, FutPos>,
     ltk: Option<u128>,
@@ -324,13 +353,15 @@ for SlaveLazyEncryptFuture<'a, HCI, FNeg, FPos, FutEm, FutLEm, FutLTKE, FutNeg,
                                 SlaveLTKEventMatcher(this.connection_handle),
                             );
 
+                            this.timer_timeout = new_timer_timeout(this.hci, this.ltk_event_timeout);
+
                             this.current = SlaveLazyEncryptCurrent::AwaitLTKReq(ltk_event_fut);
                         }
                     }
                 }
                 SlaveLazyEncryptCurrent::AwaitLTKReq(future) => {
                     match Pin::new(future).poll(cx).map_err(err_map) {
-                        Poll::Pending => break Poll::Pending,
+                        Poll::Pending => break poll_timer_timeout(&mut this.timer_timeout, cx),
                         Poll::Ready(Err(e)) => break Poll::Ready(Err(e)),
                         Poll::Ready(Ok(_)) => {
                             match this.ltk {
@@ -55,6 +55,11 @@ const L2CAP_SECURE_CONNECTIONS_MTU: usize = 65;
 const ENCRYPTION_KEY_MIN_SIZE: usize = 7;
 const ENCRYPTION_KEY_MAX_SIZE: usize = 16;
 
+/// The timeout of the Security Manager Timer (v5.0 | Vol 3, Part H, 3.4)
+///
+/// Pairing fails if the next Security Manager command is not received within this time.
+pub const PAIRING_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(30);
+
 const SECURITY_MANAGER_L2CAP_CHANNEL_ID: crate::l2cap::ChannelIdentifier =
     crate::l2cap::ChannelIdentifier::LE(crate::l2cap::LeUserChannelIdentifier::SecurityManagerProtocol);
 
@@ -67,6 +72,8 @@ pub enum Error {
     UnsupportedFeature,
     PairingFailed(pairing::PairingFailedReason),
     EncryptionFailed(alloc::boxed::Box<dyn core::fmt::Debug>),
+    /// A procedure of the Security Manager timed out
+    Timeout,
 }
 
 #[derive(Debug,PartialEq,Eq,Clone,Copy)]
@@ -438,6 +445,36 @@ impl SecurityManager {
         self.key_db.iter().filter_map(|entry| entry.peer_irk )
     }
 
+    /// Get the resolving list entries of the peer devices in the Key Database
+    ///
+    /// An entry is returned for every peer device with both an IRK and an identity address. The
+    /// local IRK of an entry is the IRK of this device for the peer device, or the static IRK if
+    /// the peer device does not have one. If neither exist the local IRK is zero (this device uses
+    /// its identity address with the peer).
+    ///
+    /// These are the entries loaded into the controller by
+    /// [`load_resolving_list`](crate::hci::le::privacy::load_resolving_list).
+    pub fn get_resolving_list_entries(&self)
+    -> impl core::iter::Iterator<Item = crate::hci::le::privacy::ResolvingListEntry> + '_
+    {
+        use crate::hci::le::privacy::{PeerIdentityAddressType, ResolvingListEntry};
+
+        self.key_db.iter().filter_map(move |entry| {
+            let (peer_identity_address_type, peer_identity_address) = match entry.peer_addr {
+                Some(BluAddr::Public(address)) => (PeerIdentityAddressType::Public, address),
+                Some(BluAddr::StaticRandom(address)) => (PeerIdentityAddressType::RandomStatic, address),
+                None => return None,
+            };
+
+            entry.peer_irk.map(|peer_irk| ResolvingListEntry {
+                peer_identity_address_type,
+                peer_identity_address,
+                peer_irk,
+                local_irk: entry.irk.or(self.static_irk).unwrap_or_default(),
+            })
+        })
+    }
+
     /// Assign a static Identity Resolving Key (IRK)
     ///
     /// Assign's the value as the static IRK for this device and a returns it. A IRK is generated if
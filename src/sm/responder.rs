@@ -1,5 +1,8 @@
 
 use super::*;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crate::timer::{Timeout, Timer};
 
 type LazyEncrypt<'a, C> = super::LazyEncrypt<'a, super::Slave, C>;
 
@@ -89,6 +92,9 @@ where C: ConnectionChannel
             initiator_address_is_random: self.remote_address_is_random,
             responder_address_is_random: self.this_address_is_random,
             pairing_data: None,
+            timer: None,
+            pairing_timeout: None,
+            timed_out: false,
         }
     }
 }
@@ -141,7 +147,11 @@ pub struct SlaveSecurityManager<'a,  C> {
     responder_address: &'a crate::BluetoothDeviceAddress,
     initiator_address_is_random: bool,
     responder_address_is_random: bool,
-    pairing_data: Option<PairingData>
+    pairing_data: Option<PairingData>,
+    timer: Option<&'a dyn Timer>,
+    /// The Security Manager Timer, this is only running while pairing
+    pairing_timeout: Option<Timeout>,
+    timed_out: bool,
 }
 
 impl<'a, C> SlaveSecurityManager<'a, C>
@@ -149,6 +159,31 @@ where C: ConnectionChannel,
 {
     pub fn set_oob_data(&mut self, val: u128) { self.oob_data = Some(val) }
 
+    /// Set the timer for the Security Manager Timer
+    ///
+    /// Without a timer, pairing never times out. With a timer, the
+    /// [`PAIRING_TIMEOUT`](super::PAIRING_TIMEOUT) is restarted every time a command is processed
+    /// while pairing, and the future returned by [`pairing_timeout`](Self::pairing_timeout)
+    /// completes when it expires. A command that returns an error does not restart the timer, but
+    /// it keeps running for as long as pairing is still in progress.
+    pub fn set_timer(&mut self, timer: &'a dyn Timer) { self.timer = Some(timer) }
+
+    /// Check if pairing timed out
+    ///
+    /// Once pairing times out, the Security Manager cannot be used for the rest of the connection
+    /// and every processed command returns `Error::Timeout`.
+    pub fn is_timed_out(&self) -> bool { self.timed_out }
+
+    /// Wait for pairing to time out
+    ///
+    /// The returned future completes once the Security Manager Timer expires. It never completes
+    /// if a timer was not set or the device is not pairing, so it is intended to be polled
+    /// alongside the future for receiving the next command. When the timer expires, the pairing
+    /// is failed and the Security Manager [is timed out](Self::is_timed_out).
+    pub fn pairing_timeout(&mut self) -> PairingTimeout<'_, 'a, C> {
+        PairingTimeout { sm: self }
+    }
+
     /// Process a request from a MasterSecurityManager
     ///
     /// This will return a response to a valid request that can be sent to the Master device.
@@ -162,7 +197,30 @@ where C: ConnectionChannel,
     /// It is recommended to always keep processing Bluetooth Security Manager packets as the
     /// responder. The host can at any point decide to restart encryption using different keys or
     /// send a `PairingFailed` to indicate that the prior pairing process failed.
+    ///
+    /// The Security Manager is unusable once pairing has [timed out](Self::is_timed_out), a
+    /// received command is not processed and `Error::Timeout` is returned.
     pub fn process_command(&mut self, received_data: &[u8] ) -> Result<Option<LazyEncrypt<'a, C>>, Error>
+    {
+        if self.timed_out {
+            return Err(Error::Timeout)
+        }
+
+        let result = self.process_received(received_data);
+
+        // The timer only runs while pairing is in progress. It is restarted by every command that
+        // pairing continues with, but a command that was not processed does not restart it.
+        self.pairing_timeout = match (&result, &self.pairing_data, self.timer) {
+            (Ok(None), Some(_), Some(timer)) => Some(timer.timeout(PAIRING_TIMEOUT)),
+            (Err(_), Some(_), Some(timer)) => self.pairing_timeout.take()
+                .or_else(|| Some(timer.timeout(PAIRING_TIMEOUT))),
+            _ => None,
+        };
+
+        result
+    }
+
+    fn process_received(&mut self, received_data: &[u8]) -> Result<Option<LazyEncrypt<'a, C>>, Error>
     {
         if received_data.len() > SecurityManager::SMALLEST_PACKET_SIZE {
 
@@ -579,6 +637,32 @@ where C: ConnectionChannel,
     }
 }
 
+/// The future returned by [`SlaveSecurityManager::pairing_timeout`]
+pub struct PairingTimeout<'z, 'a, C> {
+    sm: &'z mut SlaveSecurityManager<'a, C>,
+}
+
+impl<C> Future for PairingTimeout<'_, '_, C> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let sm = &mut *self.get_mut().sm;
+
+        match sm.pairing_timeout.as_mut().map(|timeout| timeout.as_mut().poll(cx)) {
+            Some(Poll::Ready(_)) => {
+                log::info!("(SM) Pairing timed out");
+
+                sm.pairing_timeout = None;
+                sm.pairing_data = None;
+                sm.timed_out = true;
+
+                Poll::Ready(())
+            },
+            _ => Poll::Pending,
+        }
+    }
+}
+
 // pub struct AsyncMasterSecurityManager<'a, HCI, C> {
 //     sm: &'a SecurityManager,
 //     hci: &'a HostInterface<HCI>,
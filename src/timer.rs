@@ -0,0 +1,197 @@
+//! Timers for timeouts
+//!
+//! bo-tie is not tied to any runtime, so it cannot create timers by itself. Instead a [`Timer`] is
+//! supplied by the user of the library, and it is used by the core crate to time out the
+//! procedures where the Bluetooth Specification requires a timeout (or where waiting forever is
+//! a problem).
+//!
+//! * HCI commands, once a timer is set with
+//!   [`set_timer`](crate::hci::HostInterface::set_timer) of `HostInterface`.
+//! * The `LazyEncrypt` futures of the Security Manager, they use the timer of the
+//!   `HostInterface`.
+//! * Attribute protocol transactions (30 seconds), see
+//!   [`ResponseProcessor::receive`](crate::att::client::ResponseProcessor::receive).
+//! * Security Manager pairing (30 seconds), see
+//!   [`set_timer`](crate::sm::responder::SlaveSecurityManager::set_timer) of
+//!   `SlaveSecurityManager`.
+//!
+//! A timer can be implemented with whatever provides time to the platform, such as a hardware
+//! timer on an embedded device or the timer of an async runtime. For tests there is the
+//! [`ManualClock`], a timer where time only moves forward when it is advanced by the test.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use crate::hci::spin_lock::SpinLock;
+
+/// The future returned by a [`Timer`]
+pub type Timeout = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A timer
+pub trait Timer: Send + Sync {
+    /// Create a timeout
+    ///
+    /// The returned future must complete once `duration` has elapsed since this was called.
+    fn timeout(&self, duration: Duration) -> Timeout;
+}
+
+/// The error of a future that did not complete before its timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Timed out")
+    }
+}
+
+/// A future with a timeout
+///
+/// The output is the output of the future, or `TimedOut` if the timeout completes first.
+pub struct Timed<F> {
+    future: F,
+    timeout: Timeout,
+}
+
+impl<F> Timed<F> {
+
+    /// Create a new `Timed`
+    ///
+    /// The timeout is created (and starts) when this is called, not when the `Timed` is first
+    /// polled.
+    pub fn new<T>(timer: &T, duration: Duration, future: F) -> Self
+    where T: Timer + ?Sized
+    {
+        Timed { future, timeout: timer.timeout(duration) }
+    }
+}
+
+impl<F> Future for Timed<F> where F: Future + Unpin {
+    type Output = Result<F::Output, TimedOut>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        if let Poll::Ready(output) = Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(Ok(output))
+        } else {
+            this.timeout.as_mut().poll(cx).map(|_| Err(TimedOut))
+        }
+    }
+}
+
+struct ClockState {
+    now: Duration,
+    wakers: Vec<Waker>,
+}
+
+/// A timer that is advanced by hand
+///
+/// The time of a `ManualClock` starts at zero and only changes when it is
+/// [advanced](ManualClock::advance). Advancing the clock completes every timeout that has
+/// elapsed. Clones of a `ManualClock` share the same time.
+#[derive(Clone)]
+pub struct ManualClock {
+    state: Arc<SpinLock<ClockState>>,
+}
+
+impl ManualClock {
+
+    /// Create a new `ManualClock`
+    pub fn new() -> Self {
+        ManualClock {
+            state: Arc::new(SpinLock::new(ClockState { now: Duration::default(), wakers: Vec::new() }))
+        }
+    }
+
+    /// Get the time since the clock was created
+    pub fn now(&self) -> Duration {
+        self.state.lock().now
+    }
+
+    /// Move the time of the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        let wakers = {
+            let mut state = self.state.lock();
+
+            state.now += duration;
+
+            core::mem::take(&mut state.wakers)
+        };
+
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Timer for ManualClock {
+    fn timeout(&self, duration: Duration) -> Timeout {
+        let deadline = self.now() + duration;
+
+        Box::pin(ManualTimeout { state: self.state.clone(), deadline })
+    }
+}
+
+struct ManualTimeout {
+    state: Arc<SpinLock<ClockState>>,
+    deadline: Duration,
+}
+
+impl Future for ManualTimeout {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut state = self.state.lock();
+
+        if state.now >= self.deadline {
+            Poll::Ready(())
+        } else {
+            state.wakers.push(cx.waker().clone());
+
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use futures::future::{pending, ready};
+    use futures::task::noop_waker;
+
+    #[test]
+    fn timed_test() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let clock = ManualClock::new();
+
+        let mut completes = Timed::new(&clock, Duration::from_secs(1), ready(10));
+
+        assert_eq!(Poll::Ready(Ok(10)), Pin::new(&mut completes).poll(&mut cx));
+
+        let mut times_out = Timed::new(&clock, Duration::from_secs(1), pending::<()>());
+
+        assert_eq!(Poll::Pending, Pin::new(&mut times_out).poll(&mut cx));
+
+        clock.advance(Duration::from_millis(999));
+
+        assert_eq!(Poll::Pending, Pin::new(&mut times_out).poll(&mut cx));
+
+        clock.advance(Duration::from_millis(1));
+
+        assert_eq!(Poll::Ready(Err(TimedOut)), Pin::new(&mut times_out).poll(&mut cx));
+        assert_eq!(Duration::from_secs(1), clock.now());
+    }
+}